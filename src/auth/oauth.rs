@@ -5,7 +5,13 @@ use sha2::{Digest, Sha256};
 use anyhow::{Context, Result, anyhow};
 use chrono::{DateTime, Utc};
 
-use super::token_store::{OAuthToken, TokenStore};
+use super::token_store::{OAuthToken, TokenStore, REFRESH_BUFFER};
+
+/// How long before expiry the background task refreshes a token: a minute before
+/// requests would start refreshing it themselves (`OAuthToken::needs_refresh`)
+const PROACTIVE_REFRESH_LEAD: std::time::Duration = std::time::Duration::from_secs(REFRESH_BUFFER.as_secs() + 60);
+/// How often the background task rechecks a token that isn't due (or failed to refresh)
+const PROACTIVE_REFRESH_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
 
 /// PKCE verifier for OAuth flow
 #[derive(Debug, Clone)]
@@ -137,6 +143,34 @@ impl OAuthClient {
         }
     }
 
+    /// Refresh `provider_id`'s token in the background shortly before it expires, so
+    /// requests rarely have to refresh it themselves. Tokens added later are picked up.
+    pub fn spawn_refresh_task(self, provider_id: String) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                let due_in = match self.token_store.get(&provider_id) {
+                    Some(token) => (token.expires_at - Utc::now())
+                        .to_std()
+                        .unwrap_or_default()
+                        .saturating_sub(PROACTIVE_REFRESH_LEAD),
+                    None => PROACTIVE_REFRESH_CHECK_INTERVAL,
+                };
+                if !due_in.is_zero() {
+                    tokio::time::sleep(due_in.min(PROACTIVE_REFRESH_CHECK_INTERVAL)).await;
+                    continue;
+                }
+
+                match self.refresh_if_expiring(&provider_id, PROACTIVE_REFRESH_LEAD).await {
+                    Ok(_) => tracing::info!("🔄 Refreshed OAuth token for '{}' ahead of expiry", provider_id),
+                    Err(e) => {
+                        tracing::warn!("⚠️ Background refresh of OAuth token for '{}' failed: {:#}", provider_id, e);
+                        tokio::time::sleep(PROACTIVE_REFRESH_CHECK_INTERVAL).await;
+                    }
+                }
+            }
+        })
+    }
+
     /// Generate authorization URL with PKCE
     pub fn get_authorization_url(&self) -> AuthorizationUrl {
         let pkce = PKCEVerifier::generate();
@@ -333,8 +367,42 @@ impl OAuthClient {
 
     /// Refresh an access token
     pub async fn refresh_token(&self, provider_id: &str) -> Result<OAuthToken> {
+        // Callers that saw the same stale token share one refresh
+        let seen = self.token_store.get(provider_id).map(|t| t.access_token);
+        let _lock = self.token_store.refresh_lock(provider_id).await;
+
+        let existing_token = self.token_store.get(provider_id)
+            .context("No token found for provider")?;
+        if seen.is_some_and(|seen| seen != existing_token.access_token) {
+            tracing::debug!("🔄 Token for '{}' was refreshed while waiting", provider_id);
+            return Ok(existing_token);
+        }
+        self.refresh_locked(provider_id, existing_token).await
+    }
+
+    /// Refresh the token only if it still needs it once any refresh already in flight
+    /// has finished, so concurrent requests with an expiring token share one refresh
+    pub async fn refresh_if_needed(&self, provider_id: &str) -> Result<OAuthToken> {
+        self.refresh_if_expiring(provider_id, REFRESH_BUFFER).await
+    }
+
+    /// Refresh the token if it expires within `lead`, checked again under the refresh lock
+    async fn refresh_if_expiring(&self, provider_id: &str, lead: std::time::Duration) -> Result<OAuthToken> {
+        let _lock = self.token_store.refresh_lock(provider_id).await;
+
         let existing_token = self.token_store.get(provider_id)
             .context("No token found for provider")?;
+        if !existing_token.expires_within(lead) {
+            tracing::debug!("🔄 Token for '{}' was refreshed while waiting", provider_id);
+            return Ok(existing_token);
+        }
+        self.refresh_locked(provider_id, existing_token).await
+    }
+
+    /// Exchange the refresh token; callers hold the provider's refresh lock
+    async fn refresh_locked(&self, provider_id: &str, existing_token: OAuthToken) -> Result<OAuthToken> {
+        // Many providers expiring together must not burst the token endpoints
+        let _permit = self.token_store.refresh_permit().await;
 
         #[derive(Deserialize)]
         struct TokenResponse {
@@ -428,8 +496,11 @@ impl OAuthClient {
             project_id: existing_token.project_id,  // Preserve project_id from existing token
         };
 
-        // Save refreshed token
-        self.token_store.save(token.clone())?;
+        // Save refreshed token; the new token is already in memory, so a failed
+        // write is retried by the periodic flush instead of failing the request
+        if let Err(e) = self.token_store.save(token.clone()) {
+            tracing::warn!("⚠️ Failed to persist refreshed token for {}, will retry: {:#}", provider_id, e);
+        }
 
         Ok(token)
     }
@@ -522,7 +593,7 @@ impl OAuthClient {
             .context("No token found for provider")?;
 
         if token.needs_refresh() {
-            let refreshed = self.refresh_token(provider_id).await?;
+            let refreshed = self.refresh_if_needed(provider_id).await?;
             Ok(refreshed.access_token)
         } else {
             Ok(token.access_token)
@@ -588,4 +659,143 @@ mod tests {
         assert!(auth_url.url.contains("code_challenge_method=S256"));
         assert!(auth_url.url.contains("scope="));
     }
+
+    #[tokio::test]
+    async fn test_concurrent_refreshes_limited() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        // Token endpoint recording how many refreshes are in flight
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let app = axum::Router::new().route(
+            "/token",
+            axum::routing::post({
+                let (in_flight, peak) = (in_flight.clone(), peak.clone());
+                move || async move {
+                    let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    axum::Json(serde_json::json!({ "access_token": "new", "refresh_token": "r2", "expires_in": 3600 }))
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let token_url = format!("http://{}/token", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let dir = tempfile::tempdir().unwrap();
+        let token_store = TokenStore::new(dir.path().join("tokens.json"))
+            .unwrap()
+            .with_max_concurrent_refreshes(Some(2));
+        for i in 0..5 {
+            token_store
+                .save(OAuthToken {
+                    provider_id: format!("p{}", i),
+                    access_token: "old".to_string(),
+                    refresh_token: "r1".to_string(),
+                    expires_at: Utc::now(),
+                    enterprise_url: None,
+                    project_id: None,
+                })
+                .unwrap();
+        }
+        let client = OAuthClient::new(
+            OAuthConfig {
+                token_url,
+                ..OAuthConfig::anthropic()
+            },
+            token_store.clone(),
+        );
+
+        let refreshes = (0..5).map(|i| {
+            let client = &client;
+            async move { client.refresh_token(&format!("p{}", i)).await }
+        });
+        for result in futures::future::join_all(refreshes).await {
+            assert_eq!(result.unwrap().access_token, "new");
+        }
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_shared_and_done_ahead_of_expiry() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        // Token endpoint counting refreshes
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = axum::Router::new().route(
+            "/token",
+            axum::routing::post({
+                let calls = calls.clone();
+                move || async move {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                    axum::Json(serde_json::json!({ "access_token": "new", "refresh_token": "r2", "expires_in": 3600 }))
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let token_url = format!("http://{}/token", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let dir = tempfile::tempdir().unwrap();
+        let token_store = TokenStore::new(dir.path().join("tokens.json")).unwrap();
+        let stale = |provider_id: &str, expires_in: chrono::Duration| OAuthToken {
+            provider_id: provider_id.to_string(),
+            access_token: "old".to_string(),
+            refresh_token: "r1".to_string(),
+            expires_at: Utc::now() + expires_in,
+            enterprise_url: None,
+            project_id: None,
+        };
+        let client = || {
+            OAuthClient::new(
+                OAuthConfig {
+                    token_url: token_url.clone(),
+                    ..OAuthConfig::anthropic()
+                },
+                token_store.clone(),
+            )
+        };
+
+        // Concurrent refreshes of one provider hit the token endpoint once
+        token_store.save(stale("shared", chrono::Duration::zero())).unwrap();
+        let client_a = client();
+        let refreshes = (0..5).map(|_| client_a.refresh_token("shared"));
+        for result in futures::future::join_all(refreshes).await {
+            assert_eq!(result.unwrap().access_token, "new");
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // A token expiring within the lead time is refreshed in the background
+        token_store.save(stale("background", chrono::Duration::seconds(30))).unwrap();
+        let task = client().spawn_refresh_task("background".to_string());
+        for _ in 0..40 {
+            if token_store.get("background").unwrap().access_token == "new" {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        task.abort();
+        assert_eq!(token_store.get("background").unwrap().access_token, "new");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // The task gets there before requests would consider the token stale
+        let early = stale("early", chrono::Duration::from_std(REFRESH_BUFFER).unwrap() + chrono::Duration::seconds(30));
+        assert!(!early.needs_refresh());
+        token_store.save(early).unwrap();
+        let task = client().spawn_refresh_task("early".to_string());
+        for _ in 0..40 {
+            if token_store.get("early").unwrap().access_token == "new" {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        task.abort();
+        assert_eq!(token_store.get("early").unwrap().access_token, "new");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
 }
@@ -1,10 +1,17 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
+use tokio::sync::{Mutex, OwnedMutexGuard, OwnedSemaphorePermit, Semaphore};
+
+/// How long before expiry a token counts as stale and is refreshed by the request using it
+pub const REFRESH_BUFFER: Duration = Duration::from_secs(5 * 60);
 
 /// OAuth token information
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,11 +38,14 @@ impl OAuthToken {
         Utc::now() >= self.expires_at
     }
 
-    /// Check if token will expire soon (within 5 minutes)
+    /// Check if token will expire soon (within `REFRESH_BUFFER`)
     pub fn needs_refresh(&self) -> bool {
-        let now = Utc::now();
-        let buffer = chrono::Duration::minutes(5);
-        now + buffer >= self.expires_at
+        self.expires_within(REFRESH_BUFFER)
+    }
+
+    /// Check if token will expire within `lead` from now
+    pub fn expires_within(&self, lead: Duration) -> bool {
+        Utc::now() + chrono::Duration::from_std(lead).unwrap_or_default() >= self.expires_at
     }
 }
 
@@ -46,6 +56,14 @@ pub struct TokenStore {
     file_path: PathBuf,
     /// In-memory cache of tokens
     tokens: Arc<RwLock<HashMap<String, OAuthToken>>>,
+    /// Set when in-memory tokens differ from what's on disk
+    dirty: Arc<AtomicBool>,
+    /// Caps concurrent token refreshes across all providers (unlimited if unset)
+    refresh_limiter: Option<Arc<Semaphore>>,
+    /// One lock per provider so its token is never refreshed twice at once
+    refresh_locks: Arc<std::sync::Mutex<HashMap<String, Arc<Mutex<()>>>>>,
+    /// Serializes file writes so an older snapshot never replaces a newer one
+    write_lock: Arc<std::sync::Mutex<()>>,
 }
 
 impl TokenStore {
@@ -64,9 +82,39 @@ impl TokenStore {
         Ok(Self {
             file_path,
             tokens: Arc::new(RwLock::new(tokens)),
+            dirty: Arc::new(AtomicBool::new(false)),
+            refresh_limiter: None,
+            refresh_locks: Default::default(),
+            write_lock: Default::default(),
         })
     }
 
+    /// Allow at most `max` token refreshes in flight at once (unlimited if None)
+    pub fn with_max_concurrent_refreshes(mut self, max: Option<usize>) -> Self {
+        self.refresh_limiter = max.map(|max| Arc::new(Semaphore::new(max.max(1))));
+        self
+    }
+
+    /// Wait for a refresh slot; hold the returned permit for the duration of the refresh
+    pub async fn refresh_permit(&self) -> Option<OwnedSemaphorePermit> {
+        let limiter = self.refresh_limiter.clone()?;
+        // The semaphore is never closed
+        limiter.acquire_owned().await.ok()
+    }
+
+    /// Wait until no other refresh of `provider_id` is running; hold the returned guard
+    /// for the duration of the refresh
+    pub async fn refresh_lock(&self, provider_id: &str) -> OwnedMutexGuard<()> {
+        let lock = self
+            .refresh_locks
+            .lock()
+            .unwrap()
+            .entry(provider_id.to_string())
+            .or_default()
+            .clone();
+        lock.lock_owned().await
+    }
+
     /// Get default token store path
     /// ~/.claude-code-mux/oauth_tokens.json
     pub fn default_path() -> Result<PathBuf> {
@@ -93,6 +141,7 @@ impl TokenStore {
             let mut tokens = self.tokens.write().unwrap();
             tokens.insert(provider_id, token);
         }
+        self.dirty.store(true, Ordering::SeqCst);
 
         // Persist to file
         self.persist()?;
@@ -112,6 +161,7 @@ impl TokenStore {
             let mut tokens = self.tokens.write().unwrap();
             tokens.remove(provider_id);
         }
+        self.dirty.store(true, Ordering::SeqCst);
 
         // Persist to file
         self.persist()?;
@@ -131,24 +181,74 @@ impl TokenStore {
         tokens.clone()
     }
 
-    /// Persist tokens to file
+    /// Write tokens to disk if there are changes that haven't been persisted yet.
+    /// Returns true if a write happened.
+    pub fn flush(&self) -> Result<bool> {
+        if !self.dirty.load(Ordering::SeqCst) {
+            return Ok(false);
+        }
+        self.persist()?;
+        Ok(true)
+    }
+
+    /// Periodically flush unpersisted changes (e.g. a refresh whose write failed)
+    /// so they survive a crash. Writes are skipped while nothing has changed.
+    pub fn spawn_flush_task(&self, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let store = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                match store.flush() {
+                    Ok(true) => tracing::debug!("💾 Flushed OAuth tokens to {}", store.file_path.display()),
+                    Ok(false) => {}
+                    Err(e) => tracing::warn!("⚠️ Failed to flush OAuth tokens: {:#}", e),
+                }
+            }
+        })
+    }
+
+    /// Persist tokens to file atomically (write to a temp file, then rename)
     fn persist(&self) -> Result<()> {
-        let tokens = self.tokens.read().unwrap();
-        let json = serde_json::to_string_pretty(&*tokens)
-            .context("Failed to serialize tokens")?;
+        // Clear before snapshotting so changes made during the write are picked up next flush
+        self.dirty.store(false, Ordering::SeqCst);
+        let result = self.write_snapshot();
+        if result.is_err() {
+            self.dirty.store(true, Ordering::SeqCst);
+        }
+        result
+    }
 
-        fs::write(&self.file_path, json)
-            .context("Failed to write token file")?;
+    fn write_snapshot(&self) -> Result<()> {
+        let _write = self.write_lock.lock().unwrap();
+        let json = {
+            let tokens = self.tokens.read().unwrap();
+            serde_json::to_string_pretty(&*tokens)
+                .context("Failed to serialize tokens")?
+        };
 
-        // Set file permissions to 0600 (owner read/write only)
+        // A fresh temp file next to the token file, so the rename stays on one filesystem
+        let dir = self
+            .file_path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let mut tmp = tempfile::NamedTempFile::new_in(dir)
+            .context("Failed to create temporary token file")?;
+
+        // Set file permissions to 0600 (owner read/write only) before any token is written
         #[cfg(unix)]
         {
             use std::os::unix::fs::PermissionsExt;
-            let mut perms = fs::metadata(&self.file_path)?.permissions();
-            perms.set_mode(0o600);
-            fs::set_permissions(&self.file_path, perms)?;
+            tmp.as_file().set_permissions(fs::Permissions::from_mode(0o600))?;
         }
 
+        tmp.write_all(json.as_bytes())
+            .context("Failed to write token file")?;
+        tmp.persist(&self.file_path)
+            .context("Failed to replace token file")?;
+
         Ok(())
     }
 }
@@ -209,4 +309,77 @@ mod tests {
         assert!(!valid_token.is_expired());
         assert!(!valid_token.needs_refresh());
     }
+
+    #[tokio::test]
+    async fn test_refresh_persisted_within_flush_interval() {
+        let temp_dir = TempDir::new().unwrap();
+        // Directory doesn't exist yet, so the immediate write fails
+        let token_dir = temp_dir.path().join("later");
+        let token_path = token_dir.join("tokens.json");
+        let store = TokenStore::new(token_path.clone()).unwrap();
+
+        let refreshed = OAuthToken {
+            provider_id: "test-provider".to_string(),
+            access_token: "access-refreshed".to_string(),
+            refresh_token: "refresh-rotated".to_string(),
+            expires_at: Utc::now() + chrono::Duration::hours(1),
+            enterprise_url: None,
+            project_id: None,
+        };
+        assert!(store.save(refreshed).is_err());
+        assert!(!token_path.exists());
+
+        fs::create_dir_all(&token_dir).unwrap();
+        let interval = Duration::from_millis(50);
+        let flusher = store.spawn_flush_task(interval);
+        tokio::time::sleep(interval * 3).await;
+        flusher.abort();
+
+        let reloaded = TokenStore::new(token_path).unwrap();
+        let token = reloaded.get("test-provider").unwrap();
+        assert_eq!(token.refresh_token, "refresh-rotated");
+        assert!(!store.flush().unwrap());
+    }
+
+    #[test]
+    fn test_concurrent_saves_keep_token_file_intact() {
+        let temp_dir = TempDir::new().unwrap();
+        let token_path = temp_dir.path().join("tokens.json");
+        let store = TokenStore::new(token_path.clone()).unwrap();
+
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let store = store.clone();
+                std::thread::spawn(move || {
+                    for n in 0..25 {
+                        store
+                            .save(OAuthToken {
+                                provider_id: format!("provider-{}", i),
+                                access_token: format!("access-{}", n),
+                                refresh_token: "refresh".to_string(),
+                                expires_at: Utc::now() + chrono::Duration::hours(1),
+                                enterprise_url: None,
+                                project_id: None,
+                            })
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        // The last write holds every provider's final token, and no temp files are left behind
+        let on_disk: HashMap<String, OAuthToken> = serde_json::from_str(&fs::read_to_string(&token_path).unwrap()).unwrap();
+        assert_eq!(on_disk.len(), 8);
+        assert!(on_disk.values().all(|t| t.access_token == "access-24"));
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&token_path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+    }
 }
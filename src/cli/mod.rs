@@ -1,7 +1,12 @@
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use anyhow::{Context, Result};
+use crate::models::AnthropicRequest;
 use crate::providers::ProviderConfig;
+use crate::providers::streaming::BudgetLimiter;
+use crate::providers::validation::ValidationMode;
 
 /// Application configuration
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -13,6 +18,12 @@ pub struct AppConfig {
     pub providers: Vec<ProviderConfig>,
     #[serde(default)]
     pub models: Vec<ModelConfig>,
+    /// Last-resort mappings tried for every model, after its own mappings fail
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallback_models: Vec<FallbackModel>,
+    /// Aliases that expand to the mappings of several [[models]] entries, tried in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub model_groups: Vec<ModelGroup>,
 }
 
 /// Server configuration
@@ -22,11 +33,54 @@ pub struct ServerConfig {
     pub port: u16,
     #[serde(default = "default_host")]
     pub host: String,
+    /// Addresses to listen on instead of `host`, e.g. `["0.0.0.0", "[::]:8080"]`.
+    /// Entries without a port use `port`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub listen: Vec<String>,
     pub api_key: Option<String>,
+    /// Secret for debugging features (`x-ccm-admin-key` header); they are disabled when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admin_key: Option<String>,
     #[serde(default = "default_log_level")]
     pub log_level: String,
     #[serde(default)]
     pub timeouts: TimeoutConfig,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    /// Maximum concurrent streaming responses per client (unlimited if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_streams_per_client: Option<usize>,
+    #[serde(default, skip_serializing_if = "BudgetConfig::is_unlimited")]
+    pub budget: BudgetConfig,
+    /// Forward SSE `ping` events and send keep-alive comments (default: true).
+    /// Clients can override per request with `x-ccm-sse-pings: on|off`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sse_pings: Option<bool>,
+    /// Interval for SSE keep-alive comment lines (none sent if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sse_keep_alive_secs: Option<u64>,
+    /// Drop upstream pings arriving within this many ms of the request starting;
+    /// fast responses don't need them (pings always pass through if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skip_initial_ping_ms: Option<u64>,
+    /// Remove an empty `tools` array (and `tool_choice` without tools) from incoming
+    /// requests before forwarding; some upstreams reject them (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strip_empty_tools: Option<bool>,
+    /// Reject requests missing required Anthropic fields (`model`, `messages`,
+    /// `max_tokens`) with a 400 before routing (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validate_requests: Option<bool>,
+    /// Probe every provider for auth, streaming and tool support at startup
+    /// and log the results before serving traffic (default: false)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub self_test: bool,
+    /// Maximum OAuth token refreshes in flight at once, across all providers (unlimited if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_oauth_refreshes: Option<usize>,
+    /// Cross-origin access for browser clients (none allowed by default)
+    #[serde(default, skip_serializing_if = "CorsConfig::is_disabled")]
+    pub cors: CorsConfig,
 }
 
 impl Default for ServerConfig {
@@ -34,13 +88,52 @@ impl Default for ServerConfig {
         Self {
             port: default_port(),
             host: default_host(),
+            listen: Vec::new(),
             api_key: None,
+            admin_key: None,
             log_level: default_log_level(),
             timeouts: TimeoutConfig::default(),
+            retry: RetryConfig::default(),
+            max_streams_per_client: None,
+            budget: BudgetConfig::default(),
+            sse_pings: None,
+            sse_keep_alive_secs: None,
+            skip_initial_ping_ms: None,
+            strip_empty_tools: None,
+            validate_requests: None,
+            self_test: false,
+            max_concurrent_oauth_refreshes: None,
+            cors: CorsConfig::default(),
         }
     }
 }
 
+impl ServerConfig {
+    /// `host:port` strings to bind: each `listen` entry, or `host` when none are set
+    pub fn listen_addrs(&self) -> Vec<String> {
+        let hosts: Vec<&str> = if self.listen.is_empty() {
+            vec![self.host.as_str()]
+        } else {
+            self.listen.iter().map(|l| l.trim()).collect()
+        };
+        hosts
+            .into_iter()
+            .map(|host| {
+                if host.parse::<std::net::Ipv6Addr>().is_ok() {
+                    // Bare IPv6 address
+                    format!("[{}]:{}", host, self.port)
+                } else if host.starts_with('[') && host.ends_with(']') {
+                    format!("{}:{}", host, self.port)
+                } else if host.rsplit_once(':').is_some_and(|(_, port)| port.parse::<u16>().is_ok()) {
+                    host.to_string()
+                } else {
+                    format!("{}:{}", host, self.port)
+                }
+            })
+            .collect()
+    }
+}
+
 fn default_port() -> u16 {
     3456
 }
@@ -53,6 +146,81 @@ fn default_log_level() -> String {
     "info".to_string()
 }
 
+/// CORS settings for the API and admin endpoints. With no `allowed_origins`, no CORS
+/// headers are sent and browsers block cross-origin calls.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CorsConfig {
+    /// Origins allowed to call the proxy, e.g. `["http://localhost:5173"]`; `"*"` allows any
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    #[serde(default = "default_cors_methods")]
+    pub allowed_methods: Vec<String>,
+    /// Request headers browsers may send; `"*"` allows any
+    #[serde(default = "default_cors_headers")]
+    pub allowed_headers: Vec<String>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: default_cors_methods(),
+            allowed_headers: default_cors_headers(),
+        }
+    }
+}
+
+impl CorsConfig {
+    pub fn is_disabled(&self) -> bool {
+        self.allowed_origins.is_empty()
+    }
+}
+
+fn default_cors_methods() -> Vec<String> {
+    ["GET", "POST", "OPTIONS"].map(String::from).to_vec()
+}
+
+fn default_cors_headers() -> Vec<String> {
+    ["content-type", "authorization", "x-api-key", "anthropic-version", "anthropic-beta"]
+        .map(String::from)
+        .to_vec()
+}
+
+/// Per-request spend limits, enforced on streamed responses
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct BudgetConfig {
+    /// Maximum input + output tokens per request
+    pub max_tokens: Option<u32>,
+    /// Maximum output tokens per request; the upstream stream is cut off once reached
+    pub max_output_tokens: Option<u32>,
+    /// Maximum estimated cost per request in USD (requires the prices below)
+    pub max_cost_usd: Option<f64>,
+    /// USD per million input tokens
+    pub input_cost_per_mtok: Option<f64>,
+    /// USD per million output tokens
+    pub output_cost_per_mtok: Option<f64>,
+}
+
+impl BudgetConfig {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_tokens.is_none() && self.max_output_tokens.is_none() && self.max_cost_usd.is_none()
+    }
+
+    /// Build a stream limiter for one request, if any budget is configured
+    pub fn limiter(&self) -> Option<BudgetLimiter> {
+        if self.is_unlimited() {
+            return None;
+        }
+        Some(BudgetLimiter::new(
+            self.max_tokens,
+            self.max_cost_usd,
+            self.input_cost_per_mtok.unwrap_or(0.0),
+            self.output_cost_per_mtok.unwrap_or(0.0),
+        )
+        .with_max_output_tokens(self.max_output_tokens))
+    }
+}
+
 /// Timeout configuration
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct TimeoutConfig {
@@ -60,6 +228,9 @@ pub struct TimeoutConfig {
     pub api_timeout_ms: u64,
     #[serde(default = "default_connect_timeout")]
     pub connect_timeout_ms: u64,
+    /// Upper bound for a per-request `x-ccm-timeout` override
+    #[serde(default = "default_max_timeout_override")]
+    pub max_timeout_override_ms: u64,
 }
 
 impl Default for TimeoutConfig {
@@ -67,6 +238,7 @@ impl Default for TimeoutConfig {
         Self {
             api_timeout_ms: default_api_timeout(),
             connect_timeout_ms: default_connect_timeout(),
+            max_timeout_override_ms: default_max_timeout_override(),
         }
     }
 }
@@ -79,6 +251,47 @@ fn default_connect_timeout() -> u64 {
     10_000 // 10 seconds
 }
 
+fn default_max_timeout_override() -> u64 {
+    1_800_000 // 30 minutes
+}
+
+/// Retries against the same provider when the connection is reset or dropped
+/// before a response, or its host fails to resolve, before moving on to the next mapping
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RetryConfig {
+    #[serde(default = "default_connection_retries")]
+    pub connection_retries: u32,
+    /// Retries when the provider's host name fails to resolve
+    /// (overridable per provider with `dns_retries`)
+    #[serde(default = "default_dns_retries")]
+    pub dns_retries: u32,
+    /// Delay before the first retry; doubles on each further attempt
+    #[serde(default = "default_retry_backoff")]
+    pub backoff_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            connection_retries: default_connection_retries(),
+            dns_retries: default_dns_retries(),
+            backoff_ms: default_retry_backoff(),
+        }
+    }
+}
+
+fn default_connection_retries() -> u32 {
+    2
+}
+
+fn default_dns_retries() -> u32 {
+    3
+}
+
+fn default_retry_backoff() -> u64 {
+    250
+}
+
 /// Router configuration
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RouterConfig {
@@ -92,6 +305,155 @@ pub struct RouterConfig {
     /// Regex pattern for detecting background tasks (e.g., "(?i)claude.*haiku").
     /// If empty/null, defaults to claude-haiku pattern.
     pub background_regex: Option<String>,
+    /// Content-based rules matched against the latest user message.
+    /// Evaluated in order; the first match wins.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub content_rules: Vec<ContentRule>,
+    /// Feature-based rules (images, tools, thinking), checked after content rules.
+    /// Evaluated in order; the first match wins.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub feature_rules: Vec<FeatureRule>,
+    /// Tier → model mappings (e.g., sonnet = "glm-4.6"). A request model belongs to a
+    /// tier when the tier name appears as a separate word in it (`claude-3-7-sonnet-20250219`,
+    /// `claude-sonnet-4-5`). Exact [[models]] entries take precedence.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tiers: BTreeMap<String, String>,
+    /// How the reserved `auto` model name is resolved: "default" (router default),
+    /// "cheapest" or "fastest" (by each [[models]] entry's cost_per_mtok / latency_ms)
+    #[serde(default, skip_serializing_if = "AutoPolicy::is_default")]
+    pub auto_policy: AutoPolicy,
+    /// What to do when a thinking request is routed to a provider without thinking
+    /// support: "strip" (default) drops `thinking`, "error" rejects the request,
+    /// "fallback" skips to the next thinking-capable mapping
+    #[serde(default, skip_serializing_if = "ThinkingPolicy::is_default")]
+    pub unsupported_thinking: ThinkingPolicy,
+    /// Fall back to the next mapping when a provider fails authentication
+    /// (e.g. an OAuth provider without a valid token) instead of failing the request (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback_on_auth_error: Option<bool>,
+    /// Fall back to the next mapping when a provider rejects the request as invalid,
+    /// instead of failing it with a 400 (default: true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback_on_invalid_request: Option<bool>,
+    /// Skip providers that keep failing for a cooldown (off unless set)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Client header naming the calling app, matched against mappings' `app_ids`
+    /// (default: x-ccm-app-id)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub app_id_header: Option<String>,
+}
+
+impl RouterConfig {
+    /// Header carrying the client's app identifier
+    pub fn app_id_header(&self) -> &str {
+        self.app_id_header.as_deref().unwrap_or("x-ccm-app-id")
+    }
+}
+
+/// Per-provider circuit breaker settings
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failed calls that open the circuit
+    #[serde(default = "default_circuit_failure_threshold")]
+    pub failure_threshold: u32,
+    /// Seconds an open circuit skips the provider before a probe call is let through
+    #[serde(default = "default_circuit_cooldown_secs")]
+    pub cooldown_secs: u64,
+}
+
+fn default_circuit_failure_threshold() -> u32 {
+    5
+}
+
+fn default_circuit_cooldown_secs() -> u64 {
+    30
+}
+
+/// Selection policy for requests with `model: "auto"`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AutoPolicy {
+    #[default]
+    Default,
+    Cheapest,
+    Fastest,
+}
+
+impl AutoPolicy {
+    fn is_default(&self) -> bool {
+        *self == AutoPolicy::Default
+    }
+}
+
+/// Handling of thinking requests on providers that can't think
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThinkingPolicy {
+    #[default]
+    Strip,
+    Error,
+    Fallback,
+}
+
+impl ThinkingPolicy {
+    fn is_default(&self) -> bool {
+        *self == ThinkingPolicy::Strip
+    }
+}
+
+/// Route requests whose latest user message matches to a specific model
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ContentRule {
+    /// Case-insensitive whole-word keyword (e.g., "translate")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keyword: Option<String>,
+    /// Regex pattern, used when no keyword is set (e.g., "(?i)\\btranslat(e|ion)\\b")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// Model to route to on match
+    pub model: String,
+}
+
+/// Route requests with the given features to a specific model. Every feature set
+/// to true must be present; a rule with none set never matches.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct FeatureRule {
+    /// Any message contains an image (including tool result images)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub has_images: bool,
+    /// The request defines tools
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub has_tools: bool,
+    /// Extended thinking is enabled
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub has_thinking: bool,
+    /// Model to route to on match
+    pub model: String,
+}
+
+impl FeatureRule {
+    fn is_empty(&self) -> bool {
+        !(self.has_images || self.has_tools || self.has_thinking)
+    }
+
+    /// Features this rule requires, for logging (e.g. "images+tools")
+    pub fn describe(&self) -> String {
+        [("images", self.has_images), ("tools", self.has_tools), ("thinking", self.has_thinking)]
+            .iter()
+            .filter(|(_, set)| *set)
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join("+")
+    }
+
+    /// Whether the request has every feature this rule requires
+    pub fn matches(&self, request: &AnthropicRequest) -> bool {
+        !self.is_empty()
+            && (!self.has_images || request.has_images())
+            && (!self.has_tools || request.tools.as_ref().is_some_and(|t| !t.is_empty()))
+            && (!self.has_thinking || request.thinking.as_ref().is_some_and(|t| t.r#type == "enabled"))
+    }
 }
 
 /// Model configuration with 1:N provider mappings
@@ -101,6 +463,42 @@ pub struct ModelConfig {
     pub name: String,
     /// List of provider mappings with priorities (fallback support)
     pub mappings: Vec<ModelMapping>,
+    /// Validate transformed requests/responses: "off" (default), "warn" or "strict"
+    #[serde(default, skip_serializing_if = "is_validation_off")]
+    pub schema_validation: ValidationMode,
+    /// Blended USD per million tokens, used by the "cheapest" auto policy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost_per_mtok: Option<f64>,
+    /// Typical response latency, used by the "fastest" auto policy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    /// `max_tokens` for requests that don't set one (such requests are rejected if unset)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_max_tokens: Option<u32>,
+    /// How traffic is spread across the mappings: "ordered" (default) or "weighted"
+    #[serde(default, skip_serializing_if = "MappingSelection::is_ordered")]
+    pub selection: MappingSelection,
+}
+
+/// How a model's mappings are ordered for each request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MappingSelection {
+    /// By priority: the first mapping takes all traffic, the rest are failover
+    #[default]
+    Ordered,
+    /// Weighted-random by each mapping's `weight`; the others stay as failover
+    Weighted,
+}
+
+impl MappingSelection {
+    fn is_ordered(&self) -> bool {
+        *self == MappingSelection::Ordered
+    }
+}
+
+fn is_validation_off(mode: &ValidationMode) -> bool {
+    !mode.is_enabled()
 }
 
 /// Model mapping to a specific provider
@@ -112,11 +510,195 @@ pub struct ModelMapping {
     pub provider: String,
     /// Actual model name to use with the provider
     pub actual_model: String,
+    /// API version for this model, overriding the provider default
+    /// (Anthropic-compatible: `anthropic-version` header)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_version: Option<String>,
+    /// Beta flags sent for this model, in addition to the provider's
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub betas: Vec<String>,
+    /// Ask for JSON output (`output_format: {"type": "json"}`) unless the client set a
+    /// format itself. Gemini only; enables JSON mode even without tools
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub json_mode: bool,
+    /// Only use this mapping for requests with (true) or without (false) extended thinking
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when_thinking: Option<bool>,
+    /// Only use this mapping for thinking requests with at least this `budget_tokens`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_thinking_budget: Option<u32>,
+    /// Only use this mapping for requests from these apps (see `router.app_id_header`);
+    /// such mappings are tried ahead of the model's other mappings
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub app_ids: Vec<String>,
+    /// Share of traffic under `selection = "weighted"` (default 1); 0 makes the
+    /// mapping failover only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u32>,
 }
 
-impl ModelConfig {}
+impl ModelMapping {
+    /// Whether the request meets this mapping's thinking conditions (always true without any)
+    pub fn matches_thinking(&self, request: &AnthropicRequest) -> bool {
+        let budget = request
+            .thinking
+            .as_ref()
+            .filter(|t| t.r#type == "enabled")
+            .map(|t| t.budget_tokens.unwrap_or(0));
+        if self.when_thinking.is_some_and(|wanted| wanted != budget.is_some()) {
+            return false;
+        }
+        self.min_thinking_budget
+            .is_none_or(|min| budget.is_some_and(|budget| budget >= min))
+    }
+
+    /// Whether this mapping serves requests from `app_id` (always true without `app_ids`)
+    pub fn matches_app(&self, app_id: Option<&str>) -> bool {
+        self.app_ids.is_empty() || app_id.is_some_and(|id| self.app_ids.iter().any(|a| a == id))
+    }
+
+    /// Apply this mapping's model-specific version/beta overrides to a request
+    pub fn apply_overrides(&self, request: &mut AnthropicRequest) {
+        if let Some(ref version) = self.api_version {
+            request.api_version = Some(version.clone());
+        }
+        if !self.betas.is_empty() {
+            request.betas.get_or_insert_with(Vec::new).extend(self.betas.iter().cloned());
+        }
+        if self.json_mode && request.output_format.is_none() {
+            request.output_format = Some(serde_json::json!({ "type": "json" }));
+        }
+    }
+}
+
+/// Global fallback provider/model, appended to every model's mapping chain
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FallbackModel {
+    /// Provider name
+    pub provider: String,
+    /// Actual model name to use with the provider
+    pub actual_model: String,
+}
+
+/// Named alias (e.g. "smart") for an ordered group of [[models]] entries
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ModelGroup {
+    /// Alias clients request
+    pub name: String,
+    /// [[models]] names, in fallback order
+    pub members: Vec<String>,
+}
+
+impl ModelConfig {
+    /// Mappings in the order they should be tried: this model's mappings by
+    /// priority (or weighted-random order), then the global fallbacks not already among them
+    pub fn mapping_chain(&self, fallbacks: &[FallbackModel]) -> Vec<ModelMapping> {
+        let mut chain = self.mappings.clone();
+        chain.sort_by_key(|m| m.priority);
+        if self.selection == MappingSelection::Weighted {
+            weighted_order(&mut chain, &mut rand::thread_rng());
+        }
+
+        let last_priority = chain.last().map_or(0, |m| m.priority);
+        for (idx, fallback) in fallbacks.iter().enumerate() {
+            let duplicate = chain
+                .iter()
+                .any(|m| m.provider == fallback.provider && m.actual_model == fallback.actual_model);
+            if duplicate {
+                continue;
+            }
+            chain.push(ModelMapping {
+                priority: last_priority.saturating_add(idx as u32 + 1),
+                provider: fallback.provider.clone(),
+                actual_model: fallback.actual_model.clone(),
+                api_version: None,
+                betas: Vec::new(),
+                json_mode: false,
+                when_thinking: None,
+                min_thinking_budget: None,
+                app_ids: Vec::new(),
+                weight: None,
+            });
+        }
+        chain
+    }
+}
+
+/// Reorder mappings by weighted-random sampling without replacement, so each comes
+/// first in proportion to its weight. Weight-0 mappings keep their order at the end.
+fn weighted_order(mappings: &mut Vec<ModelMapping>, rng: &mut impl rand::Rng) {
+    let mut keyed: Vec<(f64, ModelMapping)> = mappings
+        .drain(..)
+        .map(|m| {
+            let key = match m.weight.unwrap_or(1) {
+                0 => f64::INFINITY,
+                weight => -(1.0 - rng.gen::<f64>()).ln() / weight as f64,
+            };
+            (key, m)
+        })
+        .collect();
+    keyed.sort_by(|a, b| a.0.total_cmp(&b.0));
+    mappings.extend(keyed.into_iter().map(|(_, m)| m));
+}
 
 impl AppConfig {
+    /// Model config for a requested name: a [[models]] entry, or a model group expanded
+    /// into one config whose mappings are its members' mappings in order. The group
+    /// takes its other settings (validation, default_max_tokens, ...) from its first member.
+    pub fn find_model(&self, name: &str) -> Option<Cow<'_, ModelConfig>> {
+        if let Some(model) = self.models.iter().find(|m| m.name == name) {
+            return Some(Cow::Borrowed(model));
+        }
+        let group = self.model_groups.iter().find(|g| g.name == name)?;
+
+        let mut expanded: Option<ModelConfig> = None;
+        for member in &group.members {
+            let Some(model) = self.models.iter().find(|m| &m.name == member) else {
+                tracing::warn!("⚠️ Model group '{}' member '{}' is not a configured model, skipping", group.name, member);
+                continue;
+            };
+            let mut mappings = model.mappings.clone();
+            mappings.sort_by_key(|m| m.priority);
+            let expanded = expanded.get_or_insert_with(|| ModelConfig {
+                name: group.name.clone(),
+                mappings: Vec::new(),
+                ..model.clone()
+            });
+            for mapping in mappings {
+                let duplicate = expanded
+                    .mappings
+                    .iter()
+                    .any(|m| m.provider == mapping.provider && m.actual_model == mapping.actual_model);
+                if !duplicate {
+                    expanded.mappings.push(ModelMapping {
+                        priority: expanded.mappings.len() as u32 + 1,
+                        ..mapping
+                    });
+                }
+            }
+        }
+        expanded.map(Cow::Owned)
+    }
+
+    /// Retry settings for a provider: the server's, with the provider's `dns_retries` override
+    pub fn retry_for(&self, provider_name: &str) -> RetryConfig {
+        let mut retry = self.server.retry.clone();
+        if let Some(dns_retries) = self
+            .providers
+            .iter()
+            .find(|p| p.name == provider_name)
+            .and_then(|p| p.dns_retries)
+        {
+            retry.dns_retries = dns_retries;
+        }
+        retry
+    }
+
+    /// Whether `name` is a [[models]] entry or a model group
+    pub fn has_model(&self, name: &str) -> bool {
+        self.models.iter().any(|m| m.name == name) || self.model_groups.iter().any(|g| g.name == name)
+    }
+
     /// Get default config file path
     /// Returns ~/.claude-code-mux/config.toml (cross-platform)
     pub fn default_path() -> Result<PathBuf> {
@@ -143,10 +725,33 @@ impl AppConfig {
 
         // Resolve environment variables
         config.resolve_env_vars()?;
+        config.validate()?;
 
         Ok(config)
     }
 
+    /// Reject settings that can't work with the providers they're used with
+    fn validate(&self) -> Result<()> {
+        for model in &self.models {
+            for mapping in model.mappings.iter().filter(|m| m.json_mode) {
+                let provider_type = self
+                    .providers
+                    .iter()
+                    .find(|p| p.name == mapping.provider)
+                    .map(|p| p.provider_type.as_str());
+                if let Some(provider_type) = provider_type.filter(|t| !matches!(*t, "gemini" | "vertex-ai")) {
+                    anyhow::bail!(
+                        "Model {}: json_mode is only supported by Gemini and Vertex AI providers, not {} ({})",
+                        model.name,
+                        mapping.provider,
+                        provider_type
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Create a default configuration file or migrate existing one
     fn create_default_config(path: &PathBuf) -> Result<()> {
         // Create parent directory if it doesn't exist
@@ -204,6 +809,7 @@ log_level = "info"
 [server.timeouts]
 api_timeout_ms = 600000      # 10 minutes
 connect_timeout_ms = 10000   # 10 seconds
+max_timeout_override_ms = 1800000  # 30 minutes (cap for the x-ccm-timeout header)
 
 [router]
 # Default model to use when no routing conditions are met
@@ -225,6 +831,26 @@ default = "placeholder-model"
 # Optional: Regex pattern for detecting background tasks (e.g., "(?i)claude.*haiku")
 # background_regex = ""
 
+# Optional: Route by message content (first matching rule wins)
+# [[router.content_rules]]
+# keyword = "translate"
+# model = "translation-model"
+
+# Optional: Route by request features (images, tools, thinking; first matching rule wins)
+# [[router.feature_rules]]
+# has_images = true
+# model = "vision-model"
+
+# Optional: Map Claude tiers to models (applies to any dated variant)
+# [router.tiers]
+# sonnet = "glm-4.6"
+# haiku = "glm-4.5-air"
+
+# Optional: Aliases for an ordered group of models, tried in order
+# [[model_groups]]
+# name = "smart"
+# members = ["claude-sonnet-4-5", "glm-4.6"]
+
 # Providers configuration
 # Add providers via the web UI or edit this section
 # Example:
@@ -258,6 +884,9 @@ default = "placeholder-model"
                 self.server.api_key = std::env::var(env_var).ok();
             }
         }
+        if let Some(env_var) = self.server.admin_key.as_deref().and_then(|k| k.strip_prefix('$')) {
+            self.server.admin_key = std::env::var(env_var).ok();
+        }
 
         // Resolve provider API keys (only for enabled providers)
         for provider in &mut self.providers {
@@ -322,3 +951,172 @@ default = "placeholder-model"
 //         assert_eq!(config.router.default, "default");
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(priority: u32, provider: &str, actual_model: &str) -> ModelMapping {
+        ModelMapping {
+            priority,
+            provider: provider.to_string(),
+            actual_model: actual_model.to_string(),
+            api_version: None,
+            betas: Vec::new(),
+            json_mode: false,
+            when_thinking: None,
+            min_thinking_budget: None,
+            app_ids: Vec::new(),
+            weight: None,
+        }
+    }
+
+    #[test]
+    fn test_mapping_thinking_conditions() {
+        let request = |thinking: serde_json::Value| -> AnthropicRequest {
+            serde_json::from_value(serde_json::json!({
+                "model": "claude-opus",
+                "max_tokens": 32000,
+                "thinking": thinking,
+                "messages": [{ "role": "user", "content": "hi" }]
+            }))
+            .unwrap()
+        };
+        let plain = request(serde_json::Value::Null);
+        let disabled = request(serde_json::json!({ "type": "disabled" }));
+        let small = request(serde_json::json!({ "type": "enabled", "budget_tokens": 2048 }));
+        let large = request(serde_json::json!({ "type": "enabled", "budget_tokens": 16000 }));
+
+        let any = mapping(1, "gemini", "gemini-2.5-flash");
+        assert!([&plain, &disabled, &small, &large].iter().all(|r| any.matches_thinking(r)));
+
+        let fast = ModelMapping { when_thinking: Some(false), ..any.clone() };
+        assert!(fast.matches_thinking(&plain) && fast.matches_thinking(&disabled));
+        assert!(!fast.matches_thinking(&small));
+
+        let deep = ModelMapping { min_thinking_budget: Some(8000), ..any.clone() };
+        assert!(deep.matches_thinking(&large));
+        assert!(!deep.matches_thinking(&small) && !deep.matches_thinking(&plain));
+    }
+
+    #[test]
+    fn test_global_fallback_tried_after_specific_mappings() {
+        let config: AppConfig = toml::from_str(
+            r#"
+[router]
+default = "glm-4.6"
+
+[[models]]
+name = "glm-4.6"
+mappings = [
+  { priority = 2, provider = "openrouter", actual_model = "z-ai/glm-4.6" },
+  { priority = 1, provider = "zai", actual_model = "glm-4.6" },
+]
+
+[[fallback_models]]
+provider = "openrouter"
+actual_model = "z-ai/glm-4.6"
+
+[[fallback_models]]
+provider = "anthropic"
+actual_model = "claude-sonnet-4-5"
+"#,
+        )
+        .unwrap();
+
+        let chain = config.models[0].mapping_chain(&config.fallback_models);
+        let order: Vec<(&str, &str)> = chain
+            .iter()
+            .map(|m| (m.provider.as_str(), m.actual_model.as_str()))
+            .collect();
+        // Specific mappings first (by priority), then fallbacks not already present
+        assert_eq!(
+            order,
+            vec![
+                ("zai", "glm-4.6"),
+                ("openrouter", "z-ai/glm-4.6"),
+                ("anthropic", "claude-sonnet-4-5"),
+            ]
+        );
+
+        // Without global fallbacks the chain is just the model's own mappings
+        let model = ModelConfig {
+            name: "m".to_string(),
+            mappings: vec![mapping(1, "zai", "glm-4.6")],
+            schema_validation: ValidationMode::default(),
+            cost_per_mtok: None,
+            latency_ms: None,
+            default_max_tokens: None,
+            selection: MappingSelection::default(),
+        };
+        assert_eq!(model.mapping_chain(&[]).len(), 1);
+    }
+
+    #[test]
+    fn test_json_mode_rejected_for_non_gemini_providers() {
+        let config = |provider_type: &str| -> AppConfig {
+            toml::from_str(&format!(
+                r#"
+[router]
+default = "m"
+
+[[providers]]
+name = "p"
+provider_type = "{}"
+api_key = "key"
+models = []
+
+[[models]]
+name = "m"
+mappings = [{{ priority = 1, provider = "p", actual_model = "up", json_mode = true }}]
+"#,
+                provider_type
+            ))
+            .unwrap()
+        };
+
+        assert!(config("gemini").validate().is_ok());
+        assert!(config("vertex-ai").validate().is_ok());
+        let err = config("anthropic").validate().unwrap_err();
+        assert!(err.to_string().contains("json_mode is only supported by Gemini"), "{}", err);
+    }
+
+    #[test]
+    fn test_weighted_order_follows_weights() {
+        use rand::SeedableRng;
+
+        let weighted = |provider: &str, weight: u32| ModelMapping {
+            weight: Some(weight),
+            ..mapping(1, provider, "m")
+        };
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let mut first_a = 0;
+        for _ in 0..4000 {
+            let mut mappings = vec![weighted("off", 0), weighted("a", 3), mapping(2, "b", "m")];
+            weighted_order(&mut mappings, &mut rng);
+            assert_eq!(mappings.len(), 3);
+            // Weight 0 is failover only
+            assert_eq!(mappings[2].provider, "off");
+            if mappings[0].provider == "a" {
+                first_a += 1;
+            }
+        }
+        // "a" (weight 3) leads ~3/4 of the time against "b" (default weight 1)
+        assert!((2800..3200).contains(&first_a), "{}", first_a);
+    }
+
+    #[test]
+    fn test_listen_addresses() {
+        let mut server: ServerConfig = toml::from_str(r#"port = 8080"#).unwrap();
+        assert_eq!(server.listen_addrs(), vec!["127.0.0.1:8080"]);
+
+        server.listen = ["0.0.0.0", "::", "[::1]", "[::]:9000", "localhost:9001", "localhost"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(
+            server.listen_addrs(),
+            vec!["0.0.0.0:8080", "[::]:8080", "[::1]:8080", "[::]:9000", "localhost:9001", "localhost:8080"]
+        );
+    }
+}
@@ -29,6 +29,9 @@ enum Commands {
         /// Port to listen on
         #[arg(short, long)]
         port: Option<u16>,
+        /// Serve a single request read from stdin, write the response to stdout and exit
+        #[arg(long)]
+        once: bool,
     },
     /// Stop the router service
     Stop,
@@ -44,11 +47,20 @@ enum Commands {
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // Initialize tracing
-    tracing_subscriber::fmt::init();
-
     let cli = Cli::parse();
 
+    // Initialize tracing (on stderr in --once mode, where stdout carries the response)
+    // `x-ccm-debug` requests log at debug level regardless of RUST_LOG
+    let filter = tracing_subscriber::EnvFilter::from_default_env().add_directive(server::debug_log_directive());
+    if matches!(cli.command, Commands::Start { once: true, .. }) {
+        tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_writer(std::io::stderr)
+            .init();
+    } else {
+        tracing_subscriber::fmt().with_env_filter(filter).init();
+    }
+
     // Get config path (use default if not specified)
     let config_path = match &cli.config {
         Some(path) => path.clone(),
@@ -60,7 +72,10 @@ async fn main() -> anyhow::Result<()> {
     let config = cli::AppConfig::from_file(&config_path)?;
 
     match cli.command {
-        Commands::Start { port } => {
+        Commands::Start { once: true, .. } => {
+            server::serve_once(config, config_path).await?;
+        }
+        Commands::Start { port, once: false } => {
             let mut config = config;
 
             // Override port if specified
@@ -75,7 +90,7 @@ async fn main() -> anyhow::Result<()> {
 
             tracing::info!("Starting Claude Code Mux on port {}", config.server.port);
             println!("🚀 Claude Code Mux v{}", env!("CARGO_PKG_VERSION"));
-            println!("📡 Starting server on {}:{}", config.server.host, config.server.port);
+            println!("📡 Starting server on {}", config.server.listen_addrs().join(", "));
             println!();
             println!("⚡️ Rust-powered for maximum performance");
             println!("🧠 Intelligent context-aware routing");
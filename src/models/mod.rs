@@ -6,6 +6,8 @@ use std::collections::HashMap;
 pub struct AnthropicRequest {
     pub model: String,
     pub messages: Vec<Message>,
+    /// Required by the API; a client that omits it gets the model's `default_max_tokens`
+    #[serde(default)]
     pub max_tokens: u32,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub thinking: Option<ThinkingConfig>,
@@ -25,6 +27,60 @@ pub struct AnthropicRequest {
     pub system: Option<SystemPrompt>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tools: Option<Vec<Tool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<serde_json::Value>,
+    /// Anthropic beta flags; sent upstream as the `anthropic-beta` header, not in the body
+    #[serde(default, skip_serializing)]
+    pub betas: Option<Vec<String>>,
+    /// Streaming options (handled by the proxy, never forwarded upstream)
+    #[serde(default, skip_serializing)]
+    pub stream_options: Option<StreamOptions>,
+    /// API version pinned for the mapped model (set from config, never forwarded in the body)
+    #[serde(skip)]
+    pub api_version: Option<String>,
+    /// Structured output (`{"type": "json_schema", "schema": {...}}`). `{"type": "json"}`
+    /// asks providers with a JSON mode for plain JSON output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_format: Option<serde_json::Value>,
+    /// Correlation header (name, request id) for the upstream; attached per provider
+    /// by the registry, never forwarded in the body
+    #[serde(skip)]
+    pub request_id_header: Option<(String, String)>,
+    /// Client headers passed through to the upstream (provider `forward_headers`)
+    #[serde(skip)]
+    pub forwarded_headers: Vec<(String, String)>,
+    /// Keep the untransformed upstream body on the response (`x-ccm-raw-response`)
+    #[serde(skip)]
+    pub capture_raw: bool,
+}
+
+impl AnthropicRequest {
+    /// Per-request headers for the upstream: the request id and forwarded client headers
+    pub fn upstream_headers(&self) -> impl Iterator<Item = &(String, String)> {
+        self.request_id_header.iter().chain(&self.forwarded_headers)
+    }
+
+    /// Whether any message carries an image, directly or in a tool result
+    pub fn has_images(&self) -> bool {
+        self.messages.iter().any(|m| match &m.content {
+            MessageContent::Text(_) => false,
+            MessageContent::Blocks(blocks) => blocks.iter().any(|b| match b {
+                ContentBlock::Image { .. } => true,
+                ContentBlock::ToolResult { content: ToolResultContent::Blocks(blocks), .. } => {
+                    blocks.iter().any(|b| matches!(b, ToolResultBlock::Image { .. }))
+                }
+                _ => false,
+            }),
+        })
+    }
+}
+
+/// Client-requested streaming behaviour
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct StreamOptions {
+    /// Emit usage updates during the stream, not only in the final `message_delta`
+    #[serde(default)]
+    pub include_usage: bool,
 }
 
 /// Message in the conversation
@@ -50,6 +106,35 @@ pub enum SystemPrompt {
     Blocks(Vec<SystemBlock>),
 }
 
+impl SystemPrompt {
+    /// Merge blocks into as few as possible. With `preserve_cache_control`, each block
+    /// marked with `cache_control` closes a merged block carrying that marker, so cache
+    /// breakpoints stay after the same text; otherwise the blocks become plain text.
+    pub fn coalesce(&mut self, preserve_cache_control: bool) {
+        let SystemPrompt::Blocks(blocks) = self else {
+            return;
+        };
+        if !preserve_cache_control || blocks.iter().all(|b| b.cache_control.is_none()) {
+            let text = blocks.iter().map(|b| b.text.as_str()).collect::<Vec<_>>().join("\n");
+            *self = SystemPrompt::Text(text);
+            return;
+        }
+
+        let mut merged: Vec<SystemBlock> = Vec::new();
+        for block in std::mem::take(blocks) {
+            match merged.last_mut() {
+                Some(last) if last.cache_control.is_none() => {
+                    last.text.push('\n');
+                    last.text.push_str(&block.text);
+                    last.cache_control = block.cache_control;
+                }
+                _ => merged.push(block),
+            }
+        }
+        *blocks = merged;
+    }
+}
+
 /// System message block
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SystemBlock {
@@ -68,22 +153,34 @@ pub enum ToolResultContent {
     Blocks(Vec<ToolResultBlock>),
 }
 
+impl Default for ToolResultContent {
+    fn default() -> Self {
+        ToolResultContent::Blocks(Vec::new())
+    }
+}
+
 impl ToolResultContent {
-    /// Convert to string (for OpenAI compatibility)
-    pub fn to_string(&self) -> String {
+    /// Normalized block form: a bare string becomes a single text block
+    /// (an empty string becomes no blocks)
+    pub fn blocks(&self) -> Vec<ToolResultBlock> {
         match self {
-            ToolResultContent::Text(s) => s.clone(),
-            ToolResultContent::Blocks(blocks) => {
-                blocks.iter()
-                    .filter_map(|block| match block {
-                        ToolResultBlock::Text { text } => Some(text.clone()),
-                        ToolResultBlock::Image { .. } => Some("[Image]".to_string()),
-                    })
-                    .collect::<Vec<_>>()
-                    .join("\n")
-            }
+            ToolResultContent::Text(s) if s.is_empty() => Vec::new(),
+            ToolResultContent::Text(s) => vec![ToolResultBlock::Text { text: s.clone() }],
+            ToolResultContent::Blocks(blocks) => blocks.clone(),
         }
     }
+
+    /// Convert to string (for OpenAI compatibility)
+    pub fn to_string(&self) -> String {
+        self.blocks()
+            .iter()
+            .map(|block| match block {
+                ToolResultBlock::Text { text } => text.clone(),
+                ToolResultBlock::Image { .. } => "[Image]".to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
 }
 
 /// Content blocks allowed in tool results
@@ -115,13 +212,24 @@ pub enum ContentBlock {
     #[serde(rename = "tool_result")]
     ToolResult {
         tool_use_id: String,
+        /// String or array of blocks; may be omitted
+        #[serde(default)]
         content: ToolResultContent,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        is_error: Option<bool>,
     },
     #[serde(rename = "thinking")]
     Thinking {
         thinking: String,
         signature: String,
     },
+    /// Document for grounding/citations; forwarded as-is to Anthropic upstreams
+    #[serde(rename = "document")]
+    Document {
+        source: serde_json::Value,
+        #[serde(flatten)]
+        extra: serde_json::Map<String, serde_json::Value>,
+    },
 }
 
 /// Image source for vision API
@@ -174,6 +282,12 @@ pub struct CountTokensRequest {
     pub system: Option<SystemPrompt>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tools: Option<Vec<Tool>>,
+    /// Anthropic beta flags; sent upstream as the `anthropic-beta` header, not in the body
+    #[serde(default, skip_serializing)]
+    pub betas: Option<Vec<String>>,
+    /// Client headers passed through to the upstream (provider `forward_headers`)
+    #[serde(skip)]
+    pub forwarded_headers: Vec<(String, String)>,
 }
 
 /// Response for token counting
@@ -195,6 +309,8 @@ pub enum RouteType {
     WebSearch,
     Think,
     Background,
+    Content,
+    Feature,
     Default,
 }
 
@@ -204,6 +320,8 @@ impl std::fmt::Display for RouteType {
             RouteType::WebSearch => write!(f, "web-search"),
             RouteType::Think => write!(f, "think"),
             RouteType::Background => write!(f, "background"),
+            RouteType::Content => write!(f, "content"),
+            RouteType::Feature => write!(f, "feature"),
             RouteType::Default => write!(f, "default"),
         }
     }
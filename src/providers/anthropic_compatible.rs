@@ -1,4 +1,5 @@
-use super::{AnthropicProvider, ProviderResponse, error::ProviderError};
+use super::{parse_response, AnthropicProvider, PathOverrides, ProviderResponse, error::ProviderError, validation};
+use super::streaming::{check_content_type, ExpectedBody};
 use crate::models::{AnthropicRequest, CountTokensRequest, CountTokensResponse};
 use crate::auth::{TokenStore, OAuthClient, OAuthConfig};
 use async_trait::async_trait;
@@ -22,8 +23,33 @@ pub struct AnthropicCompatibleProvider {
     oauth_provider: Option<String>,
     /// Token store for OAuth authentication
     token_store: Option<TokenStore>,
+    /// Upstream path overrides
+    paths: PathOverrides,
+    /// Beta flags configured for this provider
+    betas: Vec<String>,
+    /// Organization sent as `anthropic-organization-id`
+    organization_id: Option<String>,
 }
 
+/// Default `anthropic-version` header (models can pin their own via `api_version`)
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Header selecting the organization on enterprise Anthropic accounts
+pub const ORGANIZATION_HEADER: &str = "anthropic-organization-id";
+
+/// Betas required by Claude Pro/Max OAuth tokens
+const OAUTH_BETAS: &[&str] = &[
+    "oauth-2025-04-20",
+    "claude-code-20250219",
+    "interleaved-thinking-2025-05-14",
+    "fine-grained-tool-streaming-2025-05-14",
+];
+
+/// Top-level fields of a Messages API response we expect to see
+const ANTHROPIC_RESPONSE_FIELDS: &[&str] = &[
+    "id", "type", "role", "content", "model", "stop_reason", "stop_sequence", "usage",
+];
+
 impl AnthropicCompatibleProvider {
     pub fn new(
         name: String,
@@ -37,11 +63,66 @@ impl AnthropicCompatibleProvider {
             name,
             api_key,
             base_url,
-            client: Client::new(),
+            client: super::http::shared_client(),
             models,
             custom_headers: Vec::new(),
             oauth_provider,
             token_store,
+            paths: PathOverrides::default(),
+            betas: Vec::new(),
+            organization_id: None,
+        }
+    }
+
+    /// Override the built-in endpoint paths
+    pub fn with_paths(mut self, paths: PathOverrides) -> Self {
+        self.paths = paths;
+        self
+    }
+
+    /// Beta flags to send on every request to this provider
+    pub fn with_betas(mut self, betas: Vec<String>) -> Self {
+        self.betas = betas;
+        self
+    }
+
+    /// Organization to send on every request to this provider
+    pub fn with_organization_id(mut self, organization_id: Option<String>) -> Self {
+        self.organization_id = organization_id;
+        self
+    }
+
+    /// Configured organization id, unless the client sent its own (forwarded with the request)
+    fn organization_header<'h>(&self, mut upstream_headers: impl Iterator<Item = &'h (String, String)>) -> Option<&str> {
+        if upstream_headers.any(|(name, _)| name.eq_ignore_ascii_case(ORGANIZATION_HEADER)) {
+            return None;
+        }
+        self.organization_id.as_deref()
+    }
+
+    /// Combined `anthropic-beta` header value: OAuth-required betas, then
+    /// provider-configured ones, then those from the request (deduplicated).
+    /// Entries may themselves be comma-separated header values.
+    fn beta_header(&self, request_betas: Option<&Vec<String>>) -> Option<String> {
+        let oauth_betas: &[&str] = if self.is_oauth() { OAUTH_BETAS } else { &[] };
+
+        let mut betas: Vec<&str> = Vec::new();
+        let all = oauth_betas
+            .iter()
+            .copied()
+            .chain(self.betas.iter().map(|b| b.as_str()))
+            .chain(request_betas.into_iter().flatten().map(|b| b.as_str()));
+        for beta in all.flat_map(|b| b.split(',')) {
+            let beta = beta.trim();
+            if !beta.is_empty() && !betas.contains(&beta) {
+                betas.push(beta);
+            }
+        }
+
+        if betas.is_empty() {
+            None
+        } else {
+            Some(betas.join(","))
         }
     }
 
@@ -59,11 +140,14 @@ impl AnthropicCompatibleProvider {
             name,
             api_key,
             base_url,
-            client: Client::new(),
+            client: super::http::shared_client(),
             models,
             custom_headers,
             oauth_provider,
             token_store,
+            paths: PathOverrides::default(),
+            betas: Vec::new(),
+            organization_id: None,
         }
     }
 
@@ -82,7 +166,7 @@ impl AnthropicCompatibleProvider {
                         let config = OAuthConfig::anthropic();
                         let oauth_client = OAuthClient::new(config, token_store.clone());
 
-                        match oauth_client.refresh_token(oauth_provider_id).await {
+                        match oauth_client.refresh_if_needed(oauth_provider_id).await {
                             Ok(new_token) => {
                                 tracing::info!("✅ Token refreshed successfully");
                                 return Ok(new_token.access_token);
@@ -200,7 +284,7 @@ impl AnthropicCompatibleProvider {
 #[async_trait]
 impl AnthropicProvider for AnthropicCompatibleProvider {
     async fn send_message(&self, request: AnthropicRequest) -> Result<ProviderResponse, ProviderError> {
-        let url = format!("{}/v1/messages", self.base_url);
+        let url = format!("{}{}", self.base_url, self.paths.generate_path("/v1/messages", &request.model));
 
         // Get authentication header value (API key or OAuth token)
         let auth_value = self.get_auth_header().await?;
@@ -208,26 +292,35 @@ impl AnthropicProvider for AnthropicCompatibleProvider {
         // Build request with authentication
         let mut req_builder = self.client
             .post(&url)
-            .header("anthropic-version", "2023-06-01")
+            .header("anthropic-version", request.api_version.as_deref().unwrap_or(ANTHROPIC_VERSION))
             .header("Content-Type", "application/json");
 
         // Set auth header based on OAuth vs API key
         if self.is_oauth() {
             // OAuth: Use Authorization Bearer token
-            req_builder = req_builder
-                .header("Authorization", format!("Bearer {}", auth_value))
-                .header("anthropic-beta", "oauth-2025-04-20,claude-code-20250219,interleaved-thinking-2025-05-14,fine-grained-tool-streaming-2025-05-14");
+            req_builder = req_builder.header("Authorization", format!("Bearer {}", auth_value));
             tracing::debug!("🔐 Using OAuth Bearer token for {}", self.name);
         } else {
             // API Key: Use x-api-key
             req_builder = req_builder.header("x-api-key", auth_value);
         }
 
+        if let Some(betas) = self.beta_header(request.betas.as_ref()) {
+            req_builder = req_builder.header("anthropic-beta", betas);
+        }
+        if let Some(organization_id) = self.organization_header(request.upstream_headers()) {
+            req_builder = req_builder.header(ORGANIZATION_HEADER, organization_id);
+        }
+
         // Add custom headers (for OpenRouter, etc.)
         for (key, value) in &self.custom_headers {
             req_builder = req_builder.header(key, value);
         }
 
+        for (name, value) in request.upstream_headers() {
+            req_builder = req_builder.header(name, value);
+        }
+
         // Send request (pass-through, no transformation needed!)
         let response = req_builder
             .json(&request)
@@ -250,43 +343,57 @@ impl AnthropicProvider for AnthropicCompatibleProvider {
             });
         }
 
+        check_content_type(response.headers(), ExpectedBody::Json)?;
+
         // Get response body as text for debugging
         let response_text = response.text().await?;
         tracing::debug!("{} provider response body: {}", self.name, response_text);
 
         // Try to parse the response (already in Anthropic format!)
-        let provider_response: ProviderResponse = serde_json::from_str(&response_text)
-            .map_err(|e| {
+        let provider_response: ProviderResponse =
+            parse_response(&self.name, &response_text, ANTHROPIC_RESPONSE_FIELDS).map_err(|e| {
                 tracing::error!("Failed to parse {} response: {}", self.name, e);
                 tracing::error!("Response body was: {}", response_text);
                 e
             })?;
 
-        Ok(provider_response)
+        Ok(provider_response.with_raw(request.capture_raw, &response_text))
     }
 
     async fn count_tokens(&self, request: CountTokensRequest) -> Result<CountTokensResponse, ProviderError> {
         // For Anthropic native, use their count_tokens endpoint
         if self.name == "anthropic" {
-            let url = format!("{}/v1/messages/count_tokens", self.base_url);
+            let url = format!(
+                "{}{}",
+                self.base_url,
+                self.paths.count_tokens_path("/v1/messages/count_tokens", &request.model)
+            );
 
             // Get authentication
             let auth_value = self.get_auth_header().await?;
 
             let mut req_builder = self.client
                 .post(&url)
-                .header("anthropic-version", "2023-06-01")
+                .header("anthropic-version", ANTHROPIC_VERSION)
                 .header("Content-Type", "application/json");
 
             // Set auth header
             if self.is_oauth() {
-                req_builder = req_builder
-                    .header("Authorization", format!("Bearer {}", auth_value))
-                    .header("anthropic-beta", "oauth-2025-04-20,claude-code-20250219,interleaved-thinking-2025-05-14,fine-grained-tool-streaming-2025-05-14");
+                req_builder = req_builder.header("Authorization", format!("Bearer {}", auth_value));
             } else {
                 req_builder = req_builder.header("x-api-key", auth_value);
             }
 
+            if let Some(betas) = self.beta_header(request.betas.as_ref()) {
+                req_builder = req_builder.header("anthropic-beta", betas);
+            }
+            if let Some(organization_id) = self.organization_header(request.forwarded_headers.iter()) {
+                req_builder = req_builder.header(ORGANIZATION_HEADER, organization_id);
+            }
+            for (name, value) in &request.forwarded_headers {
+                req_builder = req_builder.header(name, value);
+            }
+
             let response = req_builder
                 .json(&request)
                 .send()
@@ -356,7 +463,7 @@ impl AnthropicProvider for AnthropicCompatibleProvider {
     ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, ProviderError>> + Send>>, ProviderError> {
         use futures::stream::TryStreamExt;
 
-        let url = format!("{}/v1/messages", self.base_url);
+        let url = format!("{}{}", self.base_url, self.paths.stream_path("/v1/messages", &request.model));
 
         // Get authentication header value
         let auth_value = self.get_auth_header().await?;
@@ -364,24 +471,33 @@ impl AnthropicProvider for AnthropicCompatibleProvider {
         // Build request with authentication
         let mut req_builder = self.client
             .post(&url)
-            .header("anthropic-version", "2023-06-01")
+            .header("anthropic-version", request.api_version.as_deref().unwrap_or(ANTHROPIC_VERSION))
             .header("Content-Type", "application/json");
 
         // Set auth header based on OAuth vs API key
         if self.is_oauth() {
-            req_builder = req_builder
-                .header("Authorization", format!("Bearer {}", auth_value))
-                .header("anthropic-beta", "oauth-2025-04-20,claude-code-20250219,interleaved-thinking-2025-05-14,fine-grained-tool-streaming-2025-05-14");
+            req_builder = req_builder.header("Authorization", format!("Bearer {}", auth_value));
             tracing::debug!("🔐 Using OAuth Bearer token for streaming on {}", self.name);
         } else {
             req_builder = req_builder.header("x-api-key", auth_value);
         }
 
+        if let Some(betas) = self.beta_header(request.betas.as_ref()) {
+            req_builder = req_builder.header("anthropic-beta", betas);
+        }
+        if let Some(organization_id) = self.organization_header(request.upstream_headers()) {
+            req_builder = req_builder.header(ORGANIZATION_HEADER, organization_id);
+        }
+
         // Add custom headers
         for (key, value) in &self.custom_headers {
             req_builder = req_builder.header(key, value);
         }
 
+        for (name, value) in request.upstream_headers() {
+            req_builder = req_builder.header(name, value);
+        }
+
         // Send request with stream=true
         let response = req_builder
             .json(&request)
@@ -403,13 +519,348 @@ impl AnthropicProvider for AnthropicCompatibleProvider {
             });
         }
 
+        check_content_type(response.headers(), ExpectedBody::Sse)?;
+
         // Return the byte stream directly
         let stream = response.bytes_stream().map_err(|e| ProviderError::HttpError(e));
 
         Ok(Box::pin(stream))
     }
 
+    async fn send_passthrough(
+        &self,
+        request: AnthropicRequest,
+        body: serde_json::Value,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, ProviderError>> + Send>>, ProviderError> {
+        use futures::stream::TryStreamExt;
+
+        let stream = request.stream == Some(true);
+        let path = if stream {
+            self.paths.stream_path("/v1/messages", &request.model)
+        } else {
+            self.paths.generate_path("/v1/messages", &request.model)
+        };
+        let url = format!("{}{}", self.base_url, path);
+
+        let auth_value = self.get_auth_header().await?;
+        let mut req_builder = self.client
+            .post(&url)
+            .header("anthropic-version", request.api_version.as_deref().unwrap_or(ANTHROPIC_VERSION))
+            .header("Content-Type", "application/json");
+        if self.is_oauth() {
+            req_builder = req_builder.header("Authorization", format!("Bearer {}", auth_value));
+        } else {
+            req_builder = req_builder.header("x-api-key", auth_value);
+        }
+        if let Some(betas) = self.beta_header(request.betas.as_ref()) {
+            req_builder = req_builder.header("anthropic-beta", betas);
+        }
+        if let Some(organization_id) = self.organization_header(request.upstream_headers()) {
+            req_builder = req_builder.header(ORGANIZATION_HEADER, organization_id);
+        }
+        for (key, value) in &self.custom_headers {
+            req_builder = req_builder.header(key, value);
+        }
+        for (name, value) in request.upstream_headers() {
+            req_builder = req_builder.header(name, value);
+        }
+
+        tracing::debug!("🪞 Passthrough request to {}", self.name);
+        let response = req_builder.json(&body).send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            return Err(ProviderError::ApiError {
+                status,
+                message: format!("{} API error: {}", self.name, error_text),
+            });
+        }
+
+        check_content_type(response.headers(), if stream { ExpectedBody::Sse } else { ExpectedBody::Json })?;
+
+        Ok(Box::pin(response.bytes_stream().map_err(ProviderError::HttpError)))
+    }
+
     fn supports_model(&self, model: &str) -> bool {
         self.models.iter().any(|m| m == model)
     }
+
+    fn request_schema_violations(&self, request: &AnthropicRequest) -> Vec<String> {
+        match serde_json::to_value(request) {
+            Ok(body) => validation::validate_anthropic_request(&body),
+            Err(e) => vec![format!("failed to serialize request: {}", e)],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Message, MessageContent};
+
+    fn create_request(stream: bool) -> AnthropicRequest {
+        AnthropicRequest {
+            model: "claude-sonnet-4-5".to_string(),
+            messages: vec![Message {
+                role: "user".to_string(),
+                content: MessageContent::Text("Hello".to_string()),
+            }],
+            max_tokens: 16,
+            thinking: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
+            stream: Some(stream),
+            metadata: None,
+            system: None,
+            tools: None,
+            tool_choice: None,
+            betas: None,
+            stream_options: None,
+            api_version: None,
+            output_format: None,
+            request_id_header: None,
+            capture_raw: false,
+            forwarded_headers: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_and_provider_betas_merged() {
+        let provider = AnthropicCompatibleProvider::new(
+            "anthropic".to_string(),
+            "key".to_string(),
+            "https://api.anthropic.com".to_string(),
+            vec![],
+            None,
+            None,
+        )
+        .with_betas(vec!["context-1m-2025-08-07".to_string(), "files-api-2025-04-14".to_string()]);
+
+        let request_betas = vec!["files-api-2025-04-14".to_string(), "token-efficient-tools-2025-02-19".to_string()];
+        assert_eq!(
+            provider.beta_header(Some(&request_betas)).as_deref(),
+            Some("context-1m-2025-08-07,files-api-2025-04-14,token-efficient-tools-2025-02-19")
+        );
+        assert_eq!(provider.beta_header(None).as_deref(), Some("context-1m-2025-08-07,files-api-2025-04-14"));
+
+        // Betas never end up in the forwarded body
+        let mut request = create_request(false);
+        request.betas = Some(request_betas.clone());
+        assert!(serde_json::to_value(&request).unwrap().get("betas").is_none());
+
+        // Token counting merges them the same way
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/v1/messages/count_tokens")
+            .match_header("anthropic-beta", "context-1m-2025-08-07,files-api-2025-04-14,token-efficient-tools-2025-02-19")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"input_tokens":12}"#)
+            .create_async()
+            .await;
+        let provider = AnthropicCompatibleProvider::new("anthropic".to_string(), "key".to_string(), server.url(), vec![], None, None)
+            .with_betas(vec!["context-1m-2025-08-07".to_string(), "files-api-2025-04-14".to_string()]);
+        let request = create_request(false);
+        let count = provider
+            .count_tokens(CountTokensRequest {
+                model: request.model,
+                messages: request.messages,
+                system: None,
+                tools: None,
+                betas: Some(request_betas),
+                forwarded_headers: vec![],
+            })
+            .await
+            .unwrap();
+        assert_eq!(count.input_tokens, 12);
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_custom_path_template_used() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/api/v1/claude-sonnet-4-5/messages")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"hi"}],
+                    "model":"claude-sonnet-4-5","stop_reason":"end_turn","stop_sequence":null,
+                    "usage":{"input_tokens":1,"output_tokens":1}}"#,
+            )
+            .create_async()
+            .await;
+
+        let provider = AnthropicCompatibleProvider::new(
+            "gateway".to_string(),
+            "key".to_string(),
+            server.url(),
+            vec![],
+            None,
+            None,
+        )
+        .with_paths(PathOverrides {
+            generate: Some("/api/v1/{model}/messages".to_string()),
+            ..Default::default()
+        });
+
+        provider.send_message(create_request(false)).await.unwrap();
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_model_pinned_version_header_used() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .match_header("anthropic-version", "2024-10-22")
+            .match_header("anthropic-beta", "files-api-2025-04-14,context-1m-2025-08-07")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"hi"}],
+                    "model":"claude-sonnet-4-5","stop_reason":"end_turn","stop_sequence":null,
+                    "usage":{"input_tokens":1,"output_tokens":1}}"#,
+            )
+            .create_async()
+            .await;
+
+        let provider = AnthropicCompatibleProvider::new(
+            "anthropic".to_string(),
+            "key".to_string(),
+            server.url(),
+            vec![],
+            None,
+            None,
+        )
+        .with_betas(vec!["files-api-2025-04-14".to_string()]);
+
+        let mapping = crate::cli::ModelMapping {
+            priority: 1,
+            provider: "anthropic".to_string(),
+            actual_model: "claude-sonnet-4-5".to_string(),
+            api_version: Some("2024-10-22".to_string()),
+            betas: vec!["context-1m-2025-08-07".to_string()],
+            json_mode: false,
+            when_thinking: None,
+            min_thinking_budget: None,
+            app_ids: Vec::new(),
+            weight: None,
+        };
+        let mut request = create_request(false);
+        mapping.apply_overrides(&mut request);
+
+        provider.send_message(request).await.unwrap();
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_citation_stream_forwarded_intact() {
+        use super::super::streaming::{translate_events, UsageFinalizer};
+        use futures::StreamExt;
+
+        let citation = serde_json::json!({
+            "type": "char_location", "cited_text": "The grass is green.", "document_index": 0,
+            "document_title": "Facts", "start_char_index": 0, "end_char_index": 19
+        });
+        let upstream = [
+            serde_json::json!({"type": "message_start", "message": {"id": "msg_1", "usage": {"input_tokens": 12, "output_tokens": 0}}}),
+            serde_json::json!({"type": "content_block_start", "index": 0, "content_block": {"type": "text", "text": "", "citations": []}}),
+            serde_json::json!({"type": "content_block_delta", "index": 0, "delta": {"type": "citations_delta", "citation": citation}}),
+            serde_json::json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "the grass is green"}}),
+            serde_json::json!({"type": "content_block_stop", "index": 0}),
+            serde_json::json!({"type": "message_delta", "delta": {"stop_reason": "end_turn", "stop_sequence": null}, "usage": {"output_tokens": 6}}),
+            serde_json::json!({"type": "message_stop"}),
+        ];
+        // Some gateways omit the optional space after `data:`
+        let sse: String = upstream
+            .iter()
+            .enumerate()
+            .map(|(i, e)| {
+                let sep = if i == 2 { "" } else { " " };
+                format!("event: {}\ndata:{}{}\n\n", e["type"].as_str().unwrap(), sep, e)
+            })
+            .collect();
+
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "messages": [{"role": "user", "content": [
+                    {"type": "document", "source": {"type": "text", "media_type": "text/plain", "data": "The grass is green."},
+                     "title": "Facts", "citations": {"enabled": true}},
+                    {"type": "text", "text": "What color is the grass?"}
+                ]}]
+            })))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(sse)
+            .create_async()
+            .await;
+
+        let provider = AnthropicCompatibleProvider::new(
+            "anthropic".to_string(),
+            "key".to_string(),
+            server.url(),
+            vec![],
+            None,
+            None,
+        );
+        let mut request = create_request(true);
+        request.messages = vec![serde_json::from_value(serde_json::json!({
+            "role": "user",
+            "content": [
+                {"type": "document", "source": {"type": "text", "media_type": "text/plain", "data": "The grass is green."},
+                 "title": "Facts", "citations": {"enabled": true}},
+                {"type": "text", "text": "What color is the grass?"}
+            ]
+        }))
+        .unwrap()];
+
+        // Same pipeline the server applies to every stream
+        let stream = provider.send_message_stream(request).await.unwrap();
+        let events: Vec<serde_json::Value> = translate_events(stream, UsageFinalizer::new(false))
+            .map(|e| e.unwrap().json().unwrap())
+            .collect()
+            .await;
+
+        assert_eq!(events.len(), upstream.len());
+        assert_eq!(events[1], upstream[1]);
+        assert_eq!(events[2], upstream[2]);
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_stream_rejects_json_response() {
+        let mut server = mockito::Server::new_async().await;
+        let _mock = server
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"type":"message"}"#)
+            .create_async()
+            .await;
+
+        let provider = AnthropicCompatibleProvider::new(
+            "test".to_string(),
+            "key".to_string(),
+            server.url(),
+            vec![],
+            None,
+            None,
+        );
+
+        let result = provider.send_message_stream(create_request(true)).await;
+        match result {
+            Err(ProviderError::ContentTypeMismatch { expected, actual }) => {
+                assert!(expected.starts_with("SSE"));
+                assert!(actual.starts_with("JSON"));
+            }
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("expected a content-type mismatch"),
+        }
+    }
 }
@@ -0,0 +1,338 @@
+use super::gcp_auth::GcpTokenSource;
+use super::streaming::{check_content_type, ExpectedBody};
+use super::{parse_response, validation, AnthropicProvider, ProviderError, ProviderResponse};
+use crate::models::{AnthropicRequest, CountTokensRequest, CountTokensResponse};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::Stream;
+use reqwest::Client;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::pin::Pin;
+
+/// API version Vertex expects in the body (instead of the `anthropic-version` header)
+const VERTEX_ANTHROPIC_VERSION: &str = "vertex-2023-10-16";
+
+/// Prefix used by Model Garden style model names
+const PUBLISHER_PREFIX: &str = "publishers/anthropic/models/";
+
+/// Top-level fields of a Messages API response we expect to see
+const RESPONSE_FIELDS: &[&str] = &[
+    "id", "type", "role", "content", "model", "stop_reason", "stop_sequence", "usage",
+];
+
+/// Anthropic models served through Google Cloud Vertex AI
+///
+/// Uses the `rawPredict`/`streamRawPredict` endpoints, which accept the
+/// Anthropic Messages format with the model moved into the URL and
+/// `anthropic_version` in the body. Authenticates with ADC.
+pub struct AnthropicVertexProvider {
+    pub name: String,
+    pub base_url: String,
+    pub project_id: String,
+    pub location: String,
+    pub models: Vec<String>,
+    pub client: Client,
+    pub custom_headers: HashMap<String, String>,
+    /// Explicit access token; ADC is used when unset
+    access_token: Option<String>,
+    token_source: GcpTokenSource,
+    /// Beta flags configured for this provider
+    betas: Vec<String>,
+}
+
+impl AnthropicVertexProvider {
+    pub fn new(
+        name: String,
+        project_id: String,
+        location: String,
+        base_url: Option<String>,
+        models: Vec<String>,
+        access_token: Option<String>,
+    ) -> Self {
+        let base_url = base_url.unwrap_or_else(|| {
+            if location == "global" {
+                "https://aiplatform.googleapis.com/v1".to_string()
+            } else {
+                format!("https://{}-aiplatform.googleapis.com/v1", location)
+            }
+        });
+
+        Self {
+            name,
+            base_url,
+            project_id,
+            location,
+            models,
+            client: super::http::shared_client(),
+            custom_headers: HashMap::new(),
+            access_token,
+            token_source: GcpTokenSource::new(),
+            betas: Vec::new(),
+        }
+    }
+
+    /// Beta flags to send on every request to this provider
+    pub fn with_betas(mut self, betas: Vec<String>) -> Self {
+        self.betas = betas;
+        self
+    }
+
+    /// `{base}/projects/{project}/locations/{location}/publishers/anthropic/models/{model}:{method}`
+    fn endpoint(&self, model: &str, method: &str) -> String {
+        format!(
+            "{}/projects/{}/locations/{}/publishers/anthropic/models/{}:{}",
+            self.base_url,
+            self.project_id,
+            self.location,
+            model.trim_start_matches(PUBLISHER_PREFIX),
+            method
+        )
+    }
+
+    /// Anthropic request body as Vertex expects it: no `model`, with
+    /// `anthropic_version`, and betas as `anthropic_beta`
+    fn vertex_body(&self, request: &AnthropicRequest, stream: bool) -> Result<Value, ProviderError> {
+        let mut body = serde_json::to_value(request)?;
+        let obj = body.as_object_mut().ok_or_else(|| {
+            ProviderError::ConfigError("Request did not serialize to an object".to_string())
+        })?;
+        obj.remove("model");
+        let version = request.api_version.as_deref().unwrap_or(VERTEX_ANTHROPIC_VERSION);
+        obj.insert("anthropic_version".to_string(), version.into());
+        obj.insert("stream".to_string(), stream.into());
+
+        let mut betas: Vec<&str> = Vec::new();
+        for beta in self.betas.iter().chain(request.betas.iter().flatten()) {
+            let beta = beta.trim();
+            if !beta.is_empty() && !betas.contains(&beta) {
+                betas.push(beta);
+            }
+        }
+        if !betas.is_empty() {
+            obj.insert("anthropic_beta".to_string(), betas.into());
+        }
+
+        Ok(body)
+    }
+
+    async fn post(
+        &self,
+        url: &str,
+        body: &Value,
+        extra_headers: impl IntoIterator<Item = &(String, String)>,
+    ) -> Result<reqwest::Response, ProviderError> {
+        let token = match &self.access_token {
+            Some(token) => token.clone(),
+            None => self.token_source.access_token().await?,
+        };
+
+        let mut req_builder = self
+            .client
+            .post(url)
+            .header("Authorization", format!("Bearer {}", token))
+            .header("Content-Type", "application/json");
+
+        for (key, value) in &self.custom_headers {
+            req_builder = req_builder.header(key, value);
+        }
+
+        for (name, value) in extra_headers {
+            req_builder = req_builder.header(name, value);
+        }
+
+        let response = req_builder.json(body).send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            tracing::error!("Vertex Anthropic API error ({}): {}", status, error_text);
+            return Err(ProviderError::ApiError {
+                status,
+                message: format!("{} API error: {}", self.name, error_text),
+            });
+        }
+
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl AnthropicProvider for AnthropicVertexProvider {
+    async fn send_message(&self, request: AnthropicRequest) -> Result<ProviderResponse, ProviderError> {
+        let url = self.endpoint(&request.model, "rawPredict");
+        let body = self.vertex_body(&request, false)?;
+        tracing::debug!("📡 Using Vertex Anthropic API: {}", url);
+
+        let response = self.post(&url, &body, request.upstream_headers()).await?;
+        check_content_type(response.headers(), ExpectedBody::Json)?;
+
+        let response_text = response.text().await?;
+        tracing::debug!("{} provider response body: {}", self.name, response_text);
+
+        let provider_response: ProviderResponse =
+            parse_response(&self.name, &response_text, RESPONSE_FIELDS).map_err(|e| {
+                tracing::error!("Failed to parse {} response: {}", self.name, e);
+                tracing::error!("Response body was: {}", response_text);
+                e
+            })?;
+
+        Ok(provider_response.with_raw(request.capture_raw, &response_text))
+    }
+
+    async fn send_message_stream(
+        &self,
+        request: AnthropicRequest,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, ProviderError>> + Send>>, ProviderError> {
+        use futures::stream::TryStreamExt;
+
+        let url = self.endpoint(&request.model, "streamRawPredict");
+        let body = self.vertex_body(&request, true)?;
+        tracing::debug!("📡 Using Vertex Anthropic API (streaming): {}", url);
+
+        let response = self.post(&url, &body, request.upstream_headers()).await?;
+        check_content_type(response.headers(), ExpectedBody::Sse)?;
+
+        // Vertex streams Anthropic SSE events unchanged
+        let stream = response.bytes_stream().map_err(ProviderError::HttpError);
+        Ok(Box::pin(stream))
+    }
+
+    async fn count_tokens(&self, request: CountTokensRequest) -> Result<CountTokensResponse, ProviderError> {
+        let url = self.endpoint("count-tokens", "rawPredict");
+
+        let mut body = serde_json::to_value(&request)?;
+        if let Some(obj) = body.as_object_mut() {
+            let model = request.model.trim_start_matches(PUBLISHER_PREFIX);
+            obj.insert("model".to_string(), model.into());
+            obj.insert("anthropic_version".to_string(), VERTEX_ANTHROPIC_VERSION.into());
+        }
+
+        let response = self.post(&url, &body, []).await?;
+        Ok(response.json().await?)
+    }
+
+    fn supports_model(&self, model: &str) -> bool {
+        self.models.iter().any(|m| m == model)
+    }
+
+    fn request_schema_violations(&self, request: &AnthropicRequest) -> Vec<String> {
+        match serde_json::to_value(request) {
+            Ok(body) => validation::validate_anthropic_request(&body),
+            Err(e) => vec![format!("failed to serialize request: {}", e)],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Message, MessageContent};
+    use futures::StreamExt;
+    use mockito::Matcher;
+
+    fn create_request(stream: bool) -> AnthropicRequest {
+        AnthropicRequest {
+            model: "claude-sonnet-4@20250514".to_string(),
+            messages: vec![Message {
+                role: "user".to_string(),
+                content: MessageContent::Text("Hello".to_string()),
+            }],
+            max_tokens: 16,
+            thinking: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
+            stream: Some(stream),
+            metadata: None,
+            system: None,
+            tools: None,
+            tool_choice: None,
+            betas: None,
+            stream_options: None,
+            api_version: None,
+            output_format: None,
+            request_id_header: None,
+            capture_raw: false,
+            forwarded_headers: vec![],
+        }
+    }
+
+    fn create_provider(base_url: Option<String>) -> AnthropicVertexProvider {
+        AnthropicVertexProvider::new(
+            "vertex-claude".to_string(),
+            "my-project".to_string(),
+            "us-east5".to_string(),
+            base_url,
+            vec!["claude-sonnet-4@20250514".to_string()],
+            Some("test-token".to_string()),
+        )
+    }
+
+    #[test]
+    fn test_url_and_body_construction() {
+        let provider = create_provider(None).with_betas(vec!["context-1m-2025-08-07".to_string()]);
+
+        assert_eq!(
+            provider.endpoint("publishers/anthropic/models/claude-sonnet-4@20250514", "rawPredict"),
+            "https://us-east5-aiplatform.googleapis.com/v1/projects/my-project/locations/us-east5/publishers/anthropic/models/claude-sonnet-4@20250514:rawPredict"
+        );
+
+        let mut request = create_request(false);
+        request.betas = Some(vec!["context-1m-2025-08-07".to_string(), "files-api-2025-04-14".to_string()]);
+        let body = provider.vertex_body(&request, false).unwrap();
+        assert!(body.get("model").is_none());
+        assert_eq!(body["anthropic_version"], "vertex-2023-10-16");
+        assert_eq!(body["stream"], false);
+        assert_eq!(body["max_tokens"], 16);
+        assert_eq!(
+            body["anthropic_beta"],
+            serde_json::json!(["context-1m-2025-08-07", "files-api-2025-04-14"])
+        );
+
+        let global = AnthropicVertexProvider::new(
+            "vertex-claude".to_string(),
+            "my-project".to_string(),
+            "global".to_string(),
+            None,
+            vec![],
+            None,
+        );
+        assert!(global.endpoint("claude-sonnet-4@20250514", "streamRawPredict").starts_with(
+            "https://aiplatform.googleapis.com/v1/projects/my-project/locations/global/"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_stream_raw_predict_passes_events_through() {
+        let mut server = mockito::Server::new_async().await;
+        let sse = "event: message_start\ndata: {\"type\":\"message_start\"}\n\n\
+                   event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n";
+        let mock = server
+            .mock(
+                "POST",
+                "/projects/my-project/locations/us-east5/publishers/anthropic/models/claude-sonnet-4@20250514:streamRawPredict",
+            )
+            .match_header("authorization", "Bearer test-token")
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "anthropic_version": "vertex-2023-10-16",
+                "stream": true
+            })))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(sse)
+            .create_async()
+            .await;
+
+        let provider = create_provider(Some(server.url()));
+        let mut stream = provider.send_message_stream(create_request(true)).await.unwrap();
+
+        let mut received = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            received.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(String::from_utf8(received).unwrap(), sse);
+        mock.assert_async().await;
+    }
+}
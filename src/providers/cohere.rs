@@ -0,0 +1,786 @@
+use super::{log_stop_reason, parse_response, AnthropicProvider, ProviderResponse, Usage, error::ProviderError};
+use super::streaming::{
+    check_content_type, encode_events, estimate_tokens, translate_events, ExpectedBody, SseEvent, StreamTranslator,
+};
+use crate::models::{AnthropicRequest, ContentBlock, CountTokensRequest, CountTokensResponse, MessageContent, SystemPrompt};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::Stream;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+
+/// Cohere Chat API (v2) request
+#[derive(Debug, Serialize)]
+struct CohereRequest {
+    model: String,
+    messages: Vec<CohereMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<CohereTool>>,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    k: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_sequences: Option<Vec<String>>,
+    stream: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct CohereMessage {
+    role: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<String>,
+    /// Assistant reasoning sent alongside tool calls
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_plan: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_calls: Option<Vec<CohereToolCall>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+}
+
+impl CohereMessage {
+    fn text(role: &str, content: String) -> Self {
+        Self {
+            role: role.to_string(),
+            content: Some(content),
+            tool_plan: None,
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CohereToolCall {
+    id: String,
+    r#type: String,
+    function: CohereFunctionCall,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CohereFunctionCall {
+    name: String,
+    /// JSON-encoded arguments
+    #[serde(default)]
+    arguments: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CohereTool {
+    r#type: String,
+    function: CohereFunctionDef,
+}
+
+#[derive(Debug, Serialize)]
+struct CohereFunctionDef {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parameters: Option<serde_json::Value>,
+}
+
+/// Top-level fields of a Cohere chat response we expect to see
+const COHERE_RESPONSE_FIELDS: &[&str] = &["id", "finish_reason", "message", "usage", "logprobs"];
+
+#[derive(Debug, Deserialize)]
+struct CohereResponse {
+    id: String,
+    #[serde(default)]
+    finish_reason: Option<String>,
+    message: CohereResponseMessage,
+    #[serde(default)]
+    usage: Option<CohereUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CohereResponseMessage {
+    #[serde(default)]
+    content: Vec<CohereContentItem>,
+    #[serde(default)]
+    tool_plan: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<CohereToolCall>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+enum CohereContentItem {
+    #[serde(rename = "text")]
+    Text { text: String },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CohereUsage {
+    #[serde(default)]
+    billed_units: Option<CohereTokens>,
+    #[serde(default)]
+    tokens: Option<CohereTokens>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CohereTokens {
+    #[serde(default)]
+    input_tokens: Option<f64>,
+    #[serde(default)]
+    output_tokens: Option<f64>,
+}
+
+impl CohereUsage {
+    /// Token counts, preferring actual tokens over billed units
+    fn counts(&self) -> (u32, u32) {
+        let pick = |field: fn(&CohereTokens) -> Option<f64>| {
+            self.tokens
+                .as_ref()
+                .and_then(field)
+                .or_else(|| self.billed_units.as_ref().and_then(field))
+                .unwrap_or(0.0) as u32
+        };
+        (pick(|t| t.input_tokens), pick(|t| t.output_tokens))
+    }
+}
+
+/// Map a Cohere finish_reason to the Anthropic stop_reason
+fn map_finish_reason(reason: &str) -> &'static str {
+    match reason {
+        "MAX_TOKENS" => "max_tokens",
+        "STOP_SEQUENCE" => "stop_sequence",
+        "TOOL_CALL" => "tool_use",
+        _ => "end_turn",
+    }
+}
+
+/// Cohere chat models (Command R, Command A, ...) via the v2 Chat API
+pub struct CohereProvider {
+    pub name: String,
+    pub api_key: String,
+    pub base_url: String,
+    pub models: Vec<String>,
+    pub client: Client,
+}
+
+impl CohereProvider {
+    pub fn new(name: String, api_key: String, base_url: String, models: Vec<String>) -> Self {
+        Self {
+            name,
+            api_key,
+            base_url,
+            models,
+            client: super::http::shared_client(),
+        }
+    }
+
+    fn transform_request(&self, request: &AnthropicRequest) -> CohereRequest {
+        let mut messages = Vec::new();
+
+        if let Some(ref system) = request.system {
+            let system_text = match system {
+                SystemPrompt::Text(text) => text.clone(),
+                SystemPrompt::Blocks(blocks) => blocks.iter().map(|b| b.text.clone()).collect::<Vec<_>>().join("\n"),
+            };
+            messages.push(CohereMessage::text("system", system_text));
+        }
+
+        for msg in &request.messages {
+            let blocks = match &msg.content {
+                MessageContent::Text(text) => {
+                    messages.push(CohereMessage::text(&msg.role, text.clone()));
+                    continue;
+                }
+                MessageContent::Blocks(blocks) => blocks,
+            };
+
+            let mut texts = Vec::new();
+            let mut tool_calls = Vec::new();
+            for block in blocks {
+                match block {
+                    ContentBlock::Text { text } => texts.push(text.clone()),
+                    ContentBlock::ToolUse { id, name, input } => tool_calls.push(CohereToolCall {
+                        id: id.clone(),
+                        r#type: "function".to_string(),
+                        function: CohereFunctionCall {
+                            name: name.clone(),
+                            arguments: input.to_string(),
+                        },
+                    }),
+                    // Tool results answer the preceding assistant turn, so they go first
+                    ContentBlock::ToolResult { tool_use_id, content, .. } => messages.push(CohereMessage {
+                        role: "tool".to_string(),
+                        content: Some(content.to_string()),
+                        tool_plan: None,
+                        tool_calls: None,
+                        tool_call_id: Some(tool_use_id.clone()),
+                    }),
+                    // Cohere has no image, thinking or document blocks
+                    ContentBlock::Image { .. } | ContentBlock::Thinking { .. } | ContentBlock::Document { .. } => {}
+                }
+            }
+
+            let text = texts.join("\n");
+            if !tool_calls.is_empty() {
+                // Text next to tool calls is the assistant's plan
+                messages.push(CohereMessage {
+                    role: msg.role.clone(),
+                    content: None,
+                    tool_plan: Some(text).filter(|t| !t.is_empty()),
+                    tool_calls: Some(tool_calls),
+                    tool_call_id: None,
+                });
+            } else if !text.is_empty() {
+                messages.push(CohereMessage::text(&msg.role, text));
+            }
+        }
+
+        let tools = request.tools.as_ref().map(|tools| {
+            tools
+                .iter()
+                .filter_map(|tool| {
+                    Some(CohereTool {
+                        r#type: "function".to_string(),
+                        function: CohereFunctionDef {
+                            name: tool.name.as_ref()?.clone(),
+                            description: tool.description.clone(),
+                            parameters: tool.input_schema.clone(),
+                        },
+                    })
+                })
+                .collect()
+        });
+
+        CohereRequest {
+            model: request.model.clone(),
+            messages,
+            tools,
+            max_tokens: request.max_tokens,
+            temperature: request.temperature,
+            p: request.top_p,
+            k: request.top_k,
+            stop_sequences: request.stop_sequences.clone(),
+            stream: request.stream == Some(true),
+        }
+    }
+
+    fn transform_response(&self, response: CohereResponse, model: String) -> ProviderResponse {
+        let text: String = response
+            .message
+            .content
+            .iter()
+            .filter_map(|item| match item {
+                CohereContentItem::Text { text } => Some(text.as_str()),
+                CohereContentItem::Other => None,
+            })
+            .collect();
+
+        let mut content = Vec::new();
+        // Without other text, the tool plan explains the tool calls
+        match response.message.tool_plan {
+            Some(plan) if text.is_empty() && !response.message.tool_calls.is_empty() => {
+                content.push(ContentBlock::Text { text: plan })
+            }
+            _ if !text.is_empty() || response.message.tool_calls.is_empty() => content.push(ContentBlock::Text { text }),
+            _ => {}
+        }
+        for call in response.message.tool_calls {
+            let input = serde_json::from_str(&call.function.arguments).unwrap_or_else(|e| {
+                tracing::warn!("⚠️ Invalid tool call arguments for {}: {}", call.function.name, e);
+                serde_json::json!({})
+            });
+            content.push(ContentBlock::ToolUse {
+                id: call.id,
+                name: call.function.name,
+                input,
+            });
+        }
+
+        let stop_reason = response.finish_reason.as_deref().map(|r| map_finish_reason(r).to_string());
+        log_stop_reason(&self.name, &model, response.finish_reason.as_deref(), stop_reason.as_deref());
+
+        let (input_tokens, output_tokens) = response.usage.unwrap_or_default().counts();
+        ProviderResponse {
+            id: response.id,
+            r#type: "message".to_string(),
+            role: "assistant".to_string(),
+            content,
+            model,
+            stop_reason,
+            stop_sequence: None,
+            raw: None,
+            usage: Usage {
+                input_tokens,
+                output_tokens,
+                cache_read_input_tokens: None,
+            },
+        }
+    }
+
+    async fn post(&self, request: &AnthropicRequest, body: &CohereRequest) -> Result<reqwest::Response, ProviderError> {
+        let url = format!("{}/v2/chat", self.base_url);
+        let mut req_builder = self
+            .client
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .header("Content-Type", "application/json")
+            .header("accept", if body.stream { "text/event-stream" } else { "application/json" });
+
+        for (name, value) in request.upstream_headers() {
+            req_builder = req_builder.header(name, value);
+        }
+
+        let response = req_builder.json(body).send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            tracing::error!("Cohere API error ({}): {}", status, error_text);
+            return Err(ProviderError::ApiError {
+                status,
+                message: format!("{} API error: {}", self.name, error_text),
+            });
+        }
+        Ok(response)
+    }
+}
+
+/// Translates Cohere v2 stream events (`message-start`, `content-delta`,
+/// `tool-call-start`, ...) into Anthropic stream events
+struct CohereStreamTranslator {
+    provider: String,
+    model: String,
+    started: bool,
+    /// Index of the open content block, and whether it is a tool call
+    open_block: Option<(usize, bool)>,
+    next_index: usize,
+    upstream_reason: Option<String>,
+    saw_tool_use: bool,
+    input_tokens: u32,
+    output_tokens: Option<u32>,
+}
+
+impl CohereStreamTranslator {
+    fn new(provider: String, model: String) -> Self {
+        Self {
+            provider,
+            model,
+            started: false,
+            open_block: None,
+            next_index: 0,
+            upstream_reason: None,
+            saw_tool_use: false,
+            input_tokens: 0,
+            output_tokens: None,
+        }
+    }
+
+    fn ensure_started(&mut self, id: Option<&str>, out: &mut Vec<SseEvent>) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+        let id = id
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| format!("msg_{}", chrono::Utc::now().timestamp_millis()));
+        out.push(SseEvent::anthropic(serde_json::json!({
+            "type": "message_start",
+            "message": {
+                "id": id,
+                "type": "message",
+                "role": "assistant",
+                "content": [],
+                "model": self.model,
+                "stop_reason": null,
+                "stop_sequence": null,
+                "usage": { "input_tokens": 0, "output_tokens": 0 }
+            }
+        })));
+    }
+
+    fn close_block(&mut self, out: &mut Vec<SseEvent>) {
+        if let Some((index, _)) = self.open_block.take() {
+            out.push(SseEvent::anthropic(serde_json::json!({ "type": "content_block_stop", "index": index })));
+        }
+    }
+
+    fn open_block(&mut self, is_tool: bool, content_block: serde_json::Value, out: &mut Vec<SseEvent>) -> usize {
+        self.close_block(out);
+        let index = self.next_index;
+        self.next_index += 1;
+        self.open_block = Some((index, is_tool));
+        out.push(SseEvent::anthropic(serde_json::json!({
+            "type": "content_block_start",
+            "index": index,
+            "content_block": content_block
+        })));
+        index
+    }
+
+    fn text_delta(&mut self, text: &str, out: &mut Vec<SseEvent>) {
+        if text.is_empty() {
+            return;
+        }
+        let index = match self.open_block {
+            Some((index, false)) => index,
+            _ => self.open_block(false, serde_json::json!({ "type": "text", "text": "" }), out),
+        };
+        out.push(SseEvent::anthropic(serde_json::json!({
+            "type": "content_block_delta",
+            "index": index,
+            "delta": { "type": "text_delta", "text": text }
+        })));
+    }
+}
+
+impl StreamTranslator for CohereStreamTranslator {
+    fn translate(&mut self, event: SseEvent) -> Vec<SseEvent> {
+        let mut out = Vec::new();
+        let Some(data) = event.json() else {
+            return out;
+        };
+        let event_type = event
+            .event
+            .clone()
+            .or_else(|| data.get("type").and_then(|t| t.as_str()).map(|t| t.to_string()))
+            .unwrap_or_default();
+
+        self.ensure_started(data.get("id").and_then(|v| v.as_str()), &mut out);
+        let str_at = |pointer: &str| data.pointer(pointer).and_then(|v| v.as_str()).unwrap_or_default();
+
+        match event_type.as_str() {
+            "content-delta" => self.text_delta(str_at("/delta/message/content/text"), &mut out),
+            "tool-plan-delta" => self.text_delta(str_at("/delta/message/tool_plan"), &mut out),
+            "content-end" | "tool-call-end" => self.close_block(&mut out),
+            "tool-call-start" => {
+                self.saw_tool_use = true;
+                let index = self.open_block(
+                    true,
+                    serde_json::json!({
+                        "type": "tool_use",
+                        "id": str_at("/delta/message/tool_calls/id"),
+                        "name": str_at("/delta/message/tool_calls/function/name"),
+                        "input": {}
+                    }),
+                    &mut out,
+                );
+                let args = str_at("/delta/message/tool_calls/function/arguments");
+                if !args.is_empty() {
+                    out.push(SseEvent::anthropic(serde_json::json!({
+                        "type": "content_block_delta",
+                        "index": index,
+                        "delta": { "type": "input_json_delta", "partial_json": args }
+                    })));
+                }
+            }
+            "tool-call-delta" => {
+                let args = str_at("/delta/message/tool_calls/function/arguments");
+                if let Some((index, true)) = self.open_block {
+                    if !args.is_empty() {
+                        out.push(SseEvent::anthropic(serde_json::json!({
+                            "type": "content_block_delta",
+                            "index": index,
+                            "delta": { "type": "input_json_delta", "partial_json": args }
+                        })));
+                    }
+                }
+            }
+            "message-end" => {
+                if let Some(reason) = data.pointer("/delta/finish_reason").and_then(|v| v.as_str()) {
+                    self.upstream_reason = Some(reason.to_string());
+                }
+                if let Some(usage) = data.pointer("/delta/usage") {
+                    if let Ok(usage) = serde_json::from_value::<CohereUsage>(usage.clone()) {
+                        let (input, output) = usage.counts();
+                        self.input_tokens = input;
+                        self.output_tokens = Some(output);
+                    }
+                }
+            }
+            _ => {}
+        }
+        out
+    }
+
+    fn finish(&mut self) -> Vec<SseEvent> {
+        let mut out = Vec::new();
+        if !self.started {
+            return out;
+        }
+        self.close_block(&mut out);
+
+        let stop_reason = match self.upstream_reason.as_deref() {
+            Some(reason) => map_finish_reason(reason),
+            None if self.saw_tool_use => "tool_use",
+            None => "end_turn",
+        };
+        log_stop_reason(&self.provider, &self.model, self.upstream_reason.as_deref(), Some(stop_reason));
+
+        let mut usage = serde_json::json!({ "input_tokens": self.input_tokens });
+        if let Some(output_tokens) = self.output_tokens {
+            usage["output_tokens"] = output_tokens.into();
+        }
+        out.push(SseEvent::anthropic(serde_json::json!({
+            "type": "message_delta",
+            "delta": { "stop_reason": stop_reason, "stop_sequence": null },
+            "usage": usage
+        })));
+        out.push(SseEvent::anthropic(serde_json::json!({ "type": "message_stop" })));
+        out
+    }
+}
+
+#[async_trait]
+impl AnthropicProvider for CohereProvider {
+    async fn send_message(&self, request: AnthropicRequest) -> Result<ProviderResponse, ProviderError> {
+        let mut cohere_request = self.transform_request(&request);
+        cohere_request.stream = false;
+
+        let response = self.post(&request, &cohere_request).await?;
+        check_content_type(response.headers(), ExpectedBody::Json)?;
+
+        let response_text = response.text().await?;
+        tracing::debug!("{} provider response body: {}", self.name, response_text);
+
+        let cohere_response: CohereResponse =
+            parse_response(&self.name, &response_text, COHERE_RESPONSE_FIELDS).map_err(|e| {
+                tracing::error!("Failed to parse {} response: {}", self.name, e);
+                tracing::error!("Response body was: {}", response_text);
+                e
+            })?;
+
+        Ok(self
+            .transform_response(cohere_response, request.model.clone())
+            .with_raw(request.capture_raw, &response_text))
+    }
+
+    async fn send_message_stream(
+        &self,
+        request: AnthropicRequest,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, ProviderError>> + Send>>, ProviderError> {
+        use futures::stream::TryStreamExt;
+
+        let mut cohere_request = self.transform_request(&request);
+        cohere_request.stream = true;
+
+        let response = self.post(&request, &cohere_request).await?;
+        check_content_type(response.headers(), ExpectedBody::Sse)?;
+
+        let stream = response.bytes_stream().map_err(ProviderError::HttpError);
+        let translator = CohereStreamTranslator::new(self.name.clone(), request.model.clone());
+        Ok(encode_events(translate_events(stream, translator)))
+    }
+
+    async fn count_tokens(&self, request: CountTokensRequest) -> Result<CountTokensResponse, ProviderError> {
+        // Local estimate, as for OpenAI-compatible providers
+        let mut chars = match &request.system {
+            Some(SystemPrompt::Text(text)) => text.len(),
+            Some(SystemPrompt::Blocks(blocks)) => blocks.iter().map(|b| b.text.len()).sum(),
+            None => 0,
+        };
+        for msg in &request.messages {
+            chars += match &msg.content {
+                MessageContent::Text(text) => text.len(),
+                MessageContent::Blocks(blocks) => blocks
+                    .iter()
+                    .map(|block| match block {
+                        ContentBlock::Text { text } => text.len(),
+                        ContentBlock::ToolUse { input, .. } => input.to_string().len(),
+                        ContentBlock::ToolResult { content, .. } => content.to_string().len(),
+                        _ => 0,
+                    })
+                    .sum(),
+            };
+        }
+        Ok(CountTokensResponse {
+            input_tokens: estimate_tokens(chars),
+        })
+    }
+
+    fn supports_model(&self, model: &str) -> bool {
+        self.models.iter().any(|m| m == model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    fn provider(base_url: String) -> CohereProvider {
+        CohereProvider::new("cohere".to_string(), "test-key".to_string(), base_url, vec![])
+    }
+
+    fn tool_conversation() -> AnthropicRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": "command-a-03-2025",
+            "max_tokens": 256,
+            "top_p": 0.9,
+            "system": "You are terse.",
+            "tools": [{ "name": "get_weather", "description": "Weather by city", "input_schema": { "type": "object" } }],
+            "messages": [
+                { "role": "user", "content": "Weather in Paris?" },
+                { "role": "assistant", "content": [
+                    { "type": "text", "text": "I'll check the weather." },
+                    { "type": "tool_use", "id": "call_1", "name": "get_weather", "input": { "city": "Paris" } }
+                ]},
+                { "role": "user", "content": [
+                    { "type": "tool_result", "tool_use_id": "call_1", "content": "18°C, sunny" },
+                    { "type": "text", "text": "Thanks, and tomorrow?" }
+                ]}
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_request_translation() {
+        let body = serde_json::to_value(provider(String::new()).transform_request(&tool_conversation())).unwrap();
+
+        assert_eq!(body["model"], "command-a-03-2025");
+        assert_eq!(body["max_tokens"], 256);
+        assert_eq!(body["p"], 0.9f32 as f64);
+        assert_eq!(body["stream"], false);
+        assert_eq!(
+            body["messages"],
+            serde_json::json!([
+                { "role": "system", "content": "You are terse." },
+                { "role": "user", "content": "Weather in Paris?" },
+                {
+                    "role": "assistant",
+                    "tool_plan": "I'll check the weather.",
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": { "name": "get_weather", "arguments": "{\"city\":\"Paris\"}" }
+                    }]
+                },
+                { "role": "tool", "content": "18°C, sunny", "tool_call_id": "call_1" },
+                { "role": "user", "content": "Thanks, and tomorrow?" }
+            ])
+        );
+        assert_eq!(
+            body["tools"],
+            serde_json::json!([{
+                "type": "function",
+                "function": { "name": "get_weather", "description": "Weather by city", "parameters": { "type": "object" } }
+            }])
+        );
+    }
+
+    #[test]
+    fn test_response_translation() {
+        let response: CohereResponse = serde_json::from_value(serde_json::json!({
+            "id": "resp_1",
+            "finish_reason": "TOOL_CALL",
+            "message": {
+                "role": "assistant",
+                "tool_plan": "I will look up the weather.",
+                "tool_calls": [{
+                    "id": "call_2",
+                    "type": "function",
+                    "function": { "name": "get_weather", "arguments": "{\"city\":\"Paris\",\"day\":\"tomorrow\"}" }
+                }]
+            },
+            "usage": {
+                "billed_units": { "input_tokens": 40, "output_tokens": 12 },
+                "tokens": { "input_tokens": 52, "output_tokens": 12 }
+            }
+        }))
+        .unwrap();
+
+        let response = provider(String::new()).transform_response(response, "command-a-03-2025".to_string());
+        assert_eq!(response.stop_reason.as_deref(), Some("tool_use"));
+        assert_eq!((response.usage.input_tokens, response.usage.output_tokens), (52, 12));
+        assert!(matches!(&response.content[0], ContentBlock::Text { text } if text == "I will look up the weather."));
+        assert!(matches!(
+            &response.content[1],
+            ContentBlock::ToolUse { id, name, input }
+                if id == "call_2" && name == "get_weather" && input["day"] == "tomorrow"
+        ));
+
+        // Plain text answer
+        let response: CohereResponse = serde_json::from_value(serde_json::json!({
+            "id": "resp_2",
+            "finish_reason": "MAX_TOKENS",
+            "message": { "role": "assistant", "content": [{ "type": "text", "text": "Sunny" }] },
+            "usage": { "billed_units": { "input_tokens": 5, "output_tokens": 1 } }
+        }))
+        .unwrap();
+        let response = provider(String::new()).transform_response(response, "command-a-03-2025".to_string());
+        assert_eq!(response.stop_reason.as_deref(), Some("max_tokens"));
+        assert_eq!(response.usage.input_tokens, 5);
+        assert!(matches!(&response.content[..], [ContentBlock::Text { text }] if text == "Sunny"));
+    }
+
+    #[tokio::test]
+    async fn test_stream_fixture_translated() {
+        let fixture = [
+            r#"{"type":"message-start","id":"resp_3","delta":{"message":{"role":"assistant"}}}"#,
+            r#"{"type":"tool-plan-delta","delta":{"message":{"tool_plan":"Checking."}}}"#,
+            r#"{"type":"tool-call-start","index":0,"delta":{"message":{"tool_calls":{"id":"call_3","type":"function","function":{"name":"get_weather","arguments":""}}}}}"#,
+            r#"{"type":"tool-call-delta","index":0,"delta":{"message":{"tool_calls":{"function":{"arguments":"{\"city\":"}}}}}"#,
+            r#"{"type":"tool-call-delta","index":0,"delta":{"message":{"tool_calls":{"function":{"arguments":"\"Paris\"}"}}}}}"#,
+            r#"{"type":"tool-call-end","index":0}"#,
+            r#"{"type":"message-end","delta":{"finish_reason":"TOOL_CALL","usage":{"billed_units":{"input_tokens":30,"output_tokens":9},"tokens":{"input_tokens":41,"output_tokens":9}}}}"#,
+        ]
+        .iter()
+        .map(|data| {
+            let event_type = serde_json::from_str::<serde_json::Value>(data).unwrap()["type"].as_str().unwrap().to_string();
+            format!("event: {}\ndata: {}\n\n", event_type, data)
+        })
+        .collect::<String>();
+
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/v2/chat")
+            .match_header("authorization", "Bearer test-key")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({ "stream": true })))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(fixture)
+            .create_async()
+            .await;
+
+        let mut request = tool_conversation();
+        request.stream = Some(true);
+        let mut stream = provider(server.url()).send_message_stream(request).await.unwrap();
+        let mut body = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            body.extend_from_slice(&chunk.unwrap());
+        }
+        mock.assert_async().await;
+
+        let events: Vec<serde_json::Value> = super::super::streaming::parse_sse_events(&String::from_utf8(body).unwrap())
+            .iter()
+            .filter_map(|e| e.json())
+            .collect();
+        let types: Vec<&str> = events.iter().map(|e| e["type"].as_str().unwrap()).collect();
+        assert_eq!(
+            types,
+            vec![
+                "message_start",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop"
+            ]
+        );
+        assert_eq!(events[0]["message"]["id"], "resp_3");
+        assert_eq!(events[2]["delta"]["text"], "Checking.");
+        assert_eq!(events[4]["content_block"]["name"], "get_weather");
+        let args: String = events[5..7].iter().map(|e| e["delta"]["partial_json"].as_str().unwrap()).collect();
+        assert_eq!(args, r#"{"city":"Paris"}"#);
+        assert_eq!(events[8]["delta"]["stop_reason"], "tool_use");
+        assert_eq!(events[8]["usage"]["input_tokens"], 41);
+        assert_eq!(events[8]["usage"]["output_tokens"], 9);
+    }
+}
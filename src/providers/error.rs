@@ -20,4 +20,91 @@ pub enum ProviderError {
 
     #[error("Authentication error: {0}")]
     AuthError(String),
+
+    /// Upstream is throttling requests; worth retrying later or elsewhere
+    #[error("Rate limited: {0}")]
+    RateLimited(String),
+
+    /// Upstream quota (e.g. a daily limit) is used up; retrying soon won't help
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+
+    /// Upstream rejected the request itself (bad argument, unsupported feature)
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
+    /// Upstream answered with a body format other than the one requested
+    /// (e.g. JSON for a streaming request, or SSE for a non-streaming one)
+    #[error("Response format mismatch: expected {expected}, got {actual}")]
+    ContentTypeMismatch { expected: String, actual: String },
+
+    /// No response within the request's timeout
+    #[error("Request timed out after {0:?}")]
+    Timeout(std::time::Duration),
+
+    /// Transformed payload failed schema validation (strict mode only)
+    #[error("Schema validation failed for {target}: {}", violations.join("; "))]
+    SchemaViolation { target: String, violations: Vec<String> },
+}
+
+impl ProviderError {
+    /// The connection dropped before a response arrived (reset, broken pipe,
+    /// upstream hung up). Safe to retry against the same provider.
+    pub fn is_connection_reset(&self) -> bool {
+        let ProviderError::HttpError(e) = self else {
+            return false;
+        };
+        let mut source: Option<&(dyn std::error::Error + 'static)> = Some(e);
+        while let Some(err) = source {
+            if let Some(io) = err.downcast_ref::<std::io::Error>() {
+                use std::io::ErrorKind;
+                if matches!(
+                    io.kind(),
+                    ErrorKind::ConnectionReset
+                        | ErrorKind::ConnectionAborted
+                        | ErrorKind::BrokenPipe
+                        | ErrorKind::UnexpectedEof
+                ) {
+                    return true;
+                }
+            }
+            if err.to_string().contains("connection closed before message completed") {
+                return true;
+            }
+            source = err.source();
+        }
+        false
+    }
+
+    /// Whether the error points at the provider itself (server errors, timeouts,
+    /// connection failures, auth, rate limits) rather than at the request
+    pub fn is_upstream_fault(&self) -> bool {
+        match self {
+            ProviderError::ApiError { status, .. } => *status >= 500 || matches!(status, 401 | 403 | 408 | 429),
+            ProviderError::HttpError(_)
+            | ProviderError::Timeout(_)
+            | ProviderError::AuthError(_)
+            | ProviderError::RateLimited(_)
+            | ProviderError::QuotaExceeded(_)
+            | ProviderError::ContentTypeMismatch { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// The upstream host name could not be resolved. Often transient (e.g. while a
+    /// container's network is still coming up), so worth retrying after a delay.
+    pub fn is_dns_failure(&self) -> bool {
+        let ProviderError::HttpError(e) = self else {
+            return false;
+        };
+        let mut source: Option<&(dyn std::error::Error + 'static)> = Some(e);
+        while let Some(err) = source {
+            let message = err.to_string();
+            if message.contains("dns error") || message.contains("failed to lookup address") {
+                return true;
+            }
+            source = err.source();
+        }
+        false
+    }
 }
@@ -0,0 +1,375 @@
+use super::error::ProviderError;
+use base64::{engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}, Engine as _};
+use reqwest::Client;
+use ring::rand::SystemRandom;
+use ring::signature::{RsaKeyPair, RSA_PKCS1_SHA256};
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+const TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+
+/// Scope requested for service account and metadata server tokens
+const CLOUD_PLATFORM_SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+
+/// GCE metadata server host (override with `GCE_METADATA_HOST`)
+const METADATA_HOST: &str = "metadata.google.internal";
+
+/// How long to wait for the metadata server before assuming we're not on Google Cloud
+const METADATA_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Refresh this long before the token actually expires
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// Lifetime assumed for tokens printed by gcloud (real lifetime is 1h)
+const GCLOUD_TOKEN_LIFETIME: Duration = Duration::from_secs(45 * 60);
+
+/// Google Cloud access tokens from Application Default Credentials (ADC)
+///
+/// Resolution order:
+/// 1. `GOOGLE_OAUTH_ACCESS_TOKEN` environment variable
+/// 2. Credentials file (`GOOGLE_APPLICATION_CREDENTIALS` or gcloud's well-known path):
+///    `authorized_user` (`gcloud auth application-default login`) is refreshed and
+///    `service_account` keys sign a JWT, both against Google's token endpoint
+/// 3. GCE metadata server, when running on Google Cloud
+/// 4. `gcloud auth application-default print-access-token` (impersonation, workload identity, ...)
+#[derive(Clone)]
+pub struct GcpTokenSource {
+    client: Client,
+    cached: Arc<Mutex<Option<CachedToken>>>,
+}
+
+#[derive(Debug, Clone)]
+struct CachedToken {
+    access_token: String,
+    expires_at: Instant,
+}
+
+#[derive(Debug, Deserialize)]
+struct AdcFile {
+    #[serde(rename = "type")]
+    credential_type: String,
+    // authorized_user
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    refresh_token: Option<String>,
+    // service_account
+    client_email: Option<String>,
+    private_key: Option<String>,
+    private_key_id: Option<String>,
+    token_uri: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+impl GcpTokenSource {
+    pub fn new() -> Self {
+        Self {
+            client: super::http::shared_client(),
+            cached: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Get a valid access token, refreshing it if needed
+    pub async fn access_token(&self) -> Result<String, ProviderError> {
+        if let Ok(token) = std::env::var("GOOGLE_OAUTH_ACCESS_TOKEN") {
+            if !token.is_empty() {
+                return Ok(token);
+            }
+        }
+
+        let mut cached = self.cached.lock().await;
+        if let Some(token) = cached.as_ref() {
+            if Instant::now() + EXPIRY_MARGIN < token.expires_at {
+                return Ok(token.access_token.clone());
+            }
+        }
+
+        let token = match Self::load_adc_file()? {
+            Some(adc) if adc.credential_type == "authorized_user" => self.refresh_user_token(adc).await?,
+            Some(adc) if adc.credential_type == "service_account" => self.service_account_token(adc).await?,
+            Some(_) => Self::gcloud_token().await?,
+            None => match self.metadata_token(&Self::metadata_url()).await {
+                Ok(token) => token,
+                Err(e) => {
+                    tracing::debug!("🔑 GCE metadata server unavailable ({}), trying gcloud", e);
+                    Self::gcloud_token().await?
+                }
+            },
+        };
+        tracing::debug!("🔑 Obtained GCP access token from ADC");
+
+        let access_token = token.access_token.clone();
+        *cached = Some(token);
+        Ok(access_token)
+    }
+
+    /// Location of the ADC file: `GOOGLE_APPLICATION_CREDENTIALS`, or gcloud's well-known path
+    fn adc_path() -> Option<PathBuf> {
+        if let Ok(path) = std::env::var("GOOGLE_APPLICATION_CREDENTIALS") {
+            return Some(PathBuf::from(path));
+        }
+        let config_dir = match std::env::var("CLOUDSDK_CONFIG") {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => dirs::home_dir()?.join(".config").join("gcloud"),
+        };
+        Some(config_dir.join("application_default_credentials.json"))
+    }
+
+    fn load_adc_file() -> Result<Option<AdcFile>, ProviderError> {
+        let Some(path) = Self::adc_path().filter(|p| p.exists()) else {
+            return Ok(None);
+        };
+        let content = std::fs::read_to_string(&path).map_err(|e| {
+            ProviderError::AuthError(format!("Failed to read ADC file {}: {}", path.display(), e))
+        })?;
+        Ok(Some(serde_json::from_str(&content)?))
+    }
+
+    async fn refresh_user_token(&self, adc: AdcFile) -> Result<CachedToken, ProviderError> {
+        let (Some(client_id), Some(client_secret), Some(refresh_token)) =
+            (adc.client_id, adc.client_secret, adc.refresh_token)
+        else {
+            return Err(ProviderError::AuthError(
+                "ADC authorized_user credentials are missing client_id, client_secret or refresh_token".to_string(),
+            ));
+        };
+
+        let response = self
+            .client
+            .post(TOKEN_URL)
+            .form(&[
+                ("grant_type", "refresh_token"),
+                ("client_id", client_id.as_str()),
+                ("client_secret", client_secret.as_str()),
+                ("refresh_token", refresh_token.as_str()),
+            ])
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            return Err(ProviderError::AuthError(format!(
+                "ADC token refresh failed ({}): {}",
+                status, error_text
+            )));
+        }
+
+        let token: TokenResponse = response.json().await?;
+        Ok(CachedToken {
+            access_token: token.access_token,
+            expires_at: Instant::now() + Duration::from_secs(token.expires_in),
+        })
+    }
+
+    /// Exchange a self-signed JWT for a service account access token
+    async fn service_account_token(&self, adc: AdcFile) -> Result<CachedToken, ProviderError> {
+        let (Some(client_email), Some(private_key)) = (adc.client_email, adc.private_key) else {
+            return Err(ProviderError::AuthError(
+                "ADC service_account credentials are missing client_email or private_key".to_string(),
+            ));
+        };
+        let token_uri = adc.token_uri.unwrap_or_else(|| TOKEN_URL.to_string());
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let assertion = sign_jwt(&client_email, &private_key, adc.private_key_id.as_deref(), &token_uri, now)?;
+
+        let response = self
+            .client
+            .post(&token_uri)
+            .form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                ("assertion", assertion.as_str()),
+            ])
+            .send()
+            .await?;
+        Self::parse_token_response(response, "ADC service account token exchange").await
+    }
+
+    /// `http://<GCE_METADATA_HOST or metadata.google.internal>`
+    fn metadata_url() -> String {
+        let host = std::env::var("GCE_METADATA_HOST").unwrap_or_else(|_| METADATA_HOST.to_string());
+        format!("http://{}", host)
+    }
+
+    /// Token of the default service account attached to the GCE instance / Cloud Run service
+    async fn metadata_token(&self, base_url: &str) -> Result<CachedToken, ProviderError> {
+        let response = self
+            .client
+            .get(format!("{}/computeMetadata/v1/instance/service-accounts/default/token", base_url))
+            .header("Metadata-Flavor", "Google")
+            .timeout(METADATA_TIMEOUT)
+            .send()
+            .await?;
+        Self::parse_token_response(response, "GCE metadata server token request").await
+    }
+
+    async fn parse_token_response(response: reqwest::Response, what: &str) -> Result<CachedToken, ProviderError> {
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            return Err(ProviderError::AuthError(format!("{} failed ({}): {}", what, status, error_text)));
+        }
+
+        let token: TokenResponse = response.json().await?;
+        Ok(CachedToken {
+            access_token: token.access_token,
+            expires_at: Instant::now() + Duration::from_secs(token.expires_in),
+        })
+    }
+
+    async fn gcloud_token() -> Result<CachedToken, ProviderError> {
+        let output = tokio::process::Command::new("gcloud")
+            .args(["auth", "application-default", "print-access-token"])
+            .output()
+            .await
+            .map_err(|e| {
+                ProviderError::AuthError(format!(
+                    "No usable ADC credentials and gcloud is unavailable ({}). Run `gcloud auth application-default login`",
+                    e
+                ))
+            })?;
+
+        if !output.status.success() {
+            return Err(ProviderError::AuthError(format!(
+                "gcloud auth application-default print-access-token failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        Ok(CachedToken {
+            access_token: String::from_utf8_lossy(&output.stdout).trim().to_string(),
+            expires_at: Instant::now() + GCLOUD_TOKEN_LIFETIME,
+        })
+    }
+}
+
+impl Default for GcpTokenSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// RS256 JWT asserting the service account's identity, valid for one hour
+fn sign_jwt(
+    client_email: &str,
+    private_key: &str,
+    key_id: Option<&str>,
+    token_uri: &str,
+    now: u64,
+) -> Result<String, ProviderError> {
+    let mut header = serde_json::json!({ "alg": "RS256", "typ": "JWT" });
+    if let Some(key_id) = key_id {
+        header["kid"] = key_id.into();
+    }
+    let claims = serde_json::json!({
+        "iss": client_email,
+        "scope": CLOUD_PLATFORM_SCOPE,
+        "aud": token_uri,
+        "iat": now,
+        "exp": now + 3600,
+    });
+    let signing_input = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(header.to_string()),
+        URL_SAFE_NO_PAD.encode(claims.to_string())
+    );
+
+    let invalid_key = |reason: String| ProviderError::AuthError(format!("Invalid service account private key: {}", reason));
+    let pem_body: String = private_key.lines().filter(|line| !line.starts_with("-----")).collect();
+    let der = STANDARD.decode(pem_body.trim()).map_err(|e| invalid_key(e.to_string()))?;
+    let key_pair = RsaKeyPair::from_pkcs8(&der).map_err(|e| invalid_key(e.to_string()))?;
+
+    let mut signature = vec![0; key_pair.public().modulus_len()];
+    key_pair
+        .sign(&RSA_PKCS1_SHA256, &SystemRandom::new(), signing_input.as_bytes(), &mut signature)
+        .map_err(|_| ProviderError::AuthError("Failed to sign service account JWT".to_string()))?;
+    Ok(format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::Matcher;
+    use ring::signature::{KeyPair, UnparsedPublicKey, RSA_PKCS1_2048_8192_SHA256};
+
+    /// PKCS#8 PEM of an RSA key generated once per test run
+    fn test_key() -> &'static str {
+        static KEY: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+        KEY.get_or_init(|| {
+            let key = openssl::pkey::PKey::from_rsa(openssl::rsa::Rsa::generate(2048).unwrap()).unwrap();
+            String::from_utf8(key.private_key_to_pem_pkcs8().unwrap()).unwrap()
+        })
+    }
+
+    fn service_account(token_uri: String) -> AdcFile {
+        serde_json::from_value(serde_json::json!({
+            "type": "service_account",
+            "client_email": "ccm@project.iam.gserviceaccount.com",
+            "private_key": test_key(),
+            "private_key_id": "key1",
+            "token_uri": token_uri
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_service_account_jwt_is_signed() {
+        let jwt = sign_jwt("ccm@project.iam.gserviceaccount.com", test_key(), Some("key1"), TOKEN_URL, 1_700_000_000).unwrap();
+        let (signing_input, signature) = jwt.rsplit_once('.').unwrap();
+
+        let claims = signing_input.split('.').nth(1).unwrap();
+        let claims: serde_json::Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(claims).unwrap()).unwrap();
+        assert_eq!(claims["iss"], "ccm@project.iam.gserviceaccount.com");
+        assert_eq!(claims["aud"], TOKEN_URL);
+        assert_eq!(claims["scope"], CLOUD_PLATFORM_SCOPE);
+        assert_eq!(claims["exp"], 1_700_003_600);
+
+        let pem_body: String = test_key().lines().filter(|line| !line.starts_with("-----")).collect();
+        let key_pair = RsaKeyPair::from_pkcs8(&STANDARD.decode(pem_body).unwrap()).unwrap();
+        UnparsedPublicKey::new(&RSA_PKCS1_2048_8192_SHA256, key_pair.public_key().as_ref())
+            .verify(signing_input.as_bytes(), &URL_SAFE_NO_PAD.decode(signature).unwrap())
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_service_account_and_metadata_tokens() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/token")
+            .match_body(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("grant_type".into(), "urn:ietf:params:oauth:grant-type:jwt-bearer".into()),
+                Matcher::Regex("assertion=".into()),
+            ]))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"access_token":"ya29.sa","expires_in":3599,"token_type":"Bearer"}"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/computeMetadata/v1/instance/service-accounts/default/token")
+            .match_header("metadata-flavor", "Google")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"access_token":"ya29.gce","expires_in":3599,"token_type":"Bearer"}"#)
+            .create_async()
+            .await;
+
+        let source = GcpTokenSource::new();
+        let token = source.service_account_token(service_account(format!("{}/token", server.url()))).await.unwrap();
+        assert_eq!(token.access_token, "ya29.sa");
+        assert!(token.expires_at > Instant::now() + Duration::from_secs(3000));
+
+        let token = source.metadata_token(&server.url()).await.unwrap();
+        assert_eq!(token.access_token, "ya29.gce");
+    }
+}
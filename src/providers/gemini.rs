@@ -1,10 +1,36 @@
-use super::{AnthropicProvider, ProviderError, ProviderResponse, Usage};
+use super::{log_stop_reason, parse_response, validation::{self, StructuredOutputValidation}, AnthropicProvider, CandidateSelection, ProviderError, ProviderResponse, SafetySetting, Usage};
+use super::streaming::{
+    check_content_type, encode_events, estimate_tokens, translate_events, ExpectedBody, SseEvent, StreamTranslator,
+};
+use super::gcp_auth::GcpTokenSource;
 use crate::auth::{OAuthClient, OAuthConfig, TokenStore};
-use crate::models::{AnthropicRequest, ContentBlock, MessageContent, SystemPrompt};
+use crate::models::{AnthropicRequest, ContentBlock, ImageSource, Message, MessageContent, SystemPrompt, ToolResultBlock, ToolResultContent};
+use base64::{engine::general_purpose, Engine as _};
 use async_trait::async_trait;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Lifetime requested for system-instruction caches
+const SYSTEM_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// Default cap on images downloaded for URL image sources
+const DEFAULT_MAX_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+
+/// Default retries on 429/500/503 and the first backoff delay
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const DEFAULT_RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+/// Default longest wait before a retry; a longer `Retry-After` fails the call instead
+const DEFAULT_MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Default limit on a non-streaming call
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Model counted against by `health_check` when the provider lists none
+const HEALTH_CHECK_MODEL: &str = "gemini-2.5-flash";
 
 /// Google Gemini provider supporting three authentication methods:
 /// 1. OAuth 2.0 (Google AI Pro/Ultra) - Uses Code Assist API
@@ -23,6 +49,54 @@ pub struct GeminiProvider {
     // OAuth fields
     pub oauth_provider_id: Option<String>,
     pub token_store: Option<TokenStore>,
+    /// Client used to refresh OAuth tokens
+    oauth_config: OAuthConfig,
+    /// Application Default Credentials for Vertex AI
+    token_source: Option<GcpTokenSource>,
+    // cachedContents created for system instruction + tools, keyed by content hash
+    system_cache: Arc<Mutex<HashMap<u64, CachedSystem>>>,
+    /// Map forced single-tool choices to responseSchema structured output
+    pub structured_tool_output: bool,
+    /// Surface finishMessage and flagged safety ratings as a note in the response
+    pub safety_details: bool,
+    /// Always offer the Google Search grounding tool
+    pub google_search_grounding: bool,
+    /// `topK` used when the request doesn't set `top_k`
+    pub top_k: Option<u32>,
+    /// Return Gemini's `responseId` as the message id when present
+    pub preserve_response_id: bool,
+    /// Retries on 429/500/503 before giving up
+    pub max_retries: u32,
+    /// Backoff before the first retry, doubled for each further one
+    pub retry_base_delay: Duration,
+    /// Longest wait before a retry; upstream asking for more fails the call
+    pub max_retry_delay: Duration,
+    /// Check structured output against its schema (non-streaming only)
+    pub structured_output_validation: Option<StructuredOutputValidation>,
+    /// Retry once when a tool call comes back without its arguments (non-streaming only)
+    pub retry_empty_tool_args: bool,
+    /// Candidates requested per non-streaming call, and how to pick among them
+    pub candidate_count: Option<u32>,
+    pub candidate_selection: CandidateSelection,
+    /// Role sent with `systemInstruction`, for models that require one
+    pub system_instruction_role: Option<String>,
+    /// Harm category thresholds sent as `safetySettings`
+    pub safety_settings: Vec<SafetySetting>,
+    /// Largest image downloaded for a URL image source
+    pub max_image_bytes: usize,
+    /// Allow image URLs on loopback/private networks (refused by default)
+    pub allow_private_image_urls: bool,
+    /// Limit on a whole non-streaming call
+    pub timeout: Duration,
+    /// Limit on a whole streamed response (none if unset)
+    pub stream_timeout: Option<Duration>,
+}
+
+/// A cachedContents resource holding only the system instruction and tools
+#[derive(Debug, Clone)]
+struct CachedSystem {
+    name: String,
+    expires_at: Instant,
 }
 
 /// Remove JSON Schema metadata fields that Gemini API doesn't support
@@ -82,18 +156,335 @@ impl GeminiProvider {
             }
         });
 
+        let token_source = (oauth_provider_id.is_none() && project_id.is_some() && location.is_some())
+            .then(GcpTokenSource::new);
+
         Self {
             name,
             api_key,
             base_url,
             models,
-            client: Client::new(),
+            client: super::http::shared_client(),
             custom_headers,
             project_id,
             location,
             oauth_provider_id,
             token_store,
+            oauth_config: OAuthConfig::gemini(),
+            token_source,
+            system_cache: Arc::new(Mutex::new(HashMap::new())),
+            structured_tool_output: false,
+            safety_details: false,
+            google_search_grounding: false,
+            top_k: None,
+            preserve_response_id: false,
+            max_retries: DEFAULT_MAX_RETRIES,
+            retry_base_delay: DEFAULT_RETRY_BASE_DELAY,
+            max_retry_delay: DEFAULT_MAX_RETRY_DELAY,
+            structured_output_validation: None,
+            retry_empty_tool_args: false,
+            candidate_count: None,
+            candidate_selection: CandidateSelection::default(),
+            system_instruction_role: None,
+            safety_settings: SafetySetting::block_none(),
+            max_image_bytes: DEFAULT_MAX_IMAGE_BYTES,
+            allow_private_image_urls: false,
+            timeout: DEFAULT_TIMEOUT,
+            stream_timeout: None,
+        }
+    }
+
+    /// Enable responseSchema structured output for forced single-tool choices
+    pub fn with_structured_tool_output(mut self, enabled: bool) -> Self {
+        self.structured_tool_output = enabled;
+        self
+    }
+
+    /// Append a safety/finish note to responses (see `safety_note`)
+    pub fn with_safety_details(mut self, enabled: bool) -> Self {
+        self.safety_details = enabled;
+        self
+    }
+
+    /// Ground every request with Google Search (see `grounding_sources`)
+    pub fn with_google_search_grounding(mut self, enabled: bool) -> Self {
+        self.google_search_grounding = enabled;
+        self
+    }
+
+    /// Default `topK`; a request's own `top_k` takes precedence
+    pub fn with_top_k(mut self, top_k: Option<u32>) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    /// Use Gemini's `responseId` as the message id instead of a generated one
+    pub fn with_preserve_response_id(mut self, enabled: bool) -> Self {
+        self.preserve_response_id = enabled;
+        self
+    }
+
+    /// Retry budget and backoff for transient upstream errors (defaults when None)
+    pub fn with_retries(mut self, max_retries: Option<u32>, base_delay_ms: Option<u64>) -> Self {
+        self.max_retries = max_retries.unwrap_or(DEFAULT_MAX_RETRIES);
+        self.retry_base_delay = base_delay_ms.map_or(DEFAULT_RETRY_BASE_DELAY, Duration::from_millis);
+        self
+    }
+
+    /// Longest wait before a retry (default when None)
+    pub fn with_max_retry_delay(mut self, max_delay_ms: Option<u64>) -> Self {
+        self.max_retry_delay = max_delay_ms.map_or(DEFAULT_MAX_RETRY_DELAY, Duration::from_millis);
+        self
+    }
+
+    /// Validate structured output against the requested schema
+    pub fn with_structured_output_validation(mut self, validation: Option<StructuredOutputValidation>) -> Self {
+        self.structured_output_validation = validation;
+        self
+    }
+
+    /// Retry once with a nudge when a tool call's args are empty or malformed
+    pub fn with_empty_tool_args_retry(mut self, enabled: bool) -> Self {
+        self.retry_empty_tool_args = enabled;
+        self
+    }
+
+    /// Request several candidates and choose among them with `selection`
+    pub fn with_candidates(mut self, count: Option<u32>, selection: CandidateSelection) -> Self {
+        self.candidate_count = count;
+        self.candidate_selection = selection;
+        self
+    }
+
+    /// Send `role` with the system instruction (e.g. "user")
+    pub fn with_system_instruction_role(mut self, role: Option<String>) -> Self {
+        self.system_instruction_role = role;
+        self
+    }
+
+    /// Override the default `BLOCK_NONE` safety settings (an empty list sends none)
+    pub fn with_safety_settings(mut self, settings: Option<Vec<SafetySetting>>) -> Self {
+        if let Some(settings) = settings {
+            self.safety_settings = settings;
+        }
+        self
+    }
+
+    /// Override the call, connect and stream timeouts (in seconds)
+    pub fn with_timeouts(mut self, timeout_secs: Option<u64>, connect_timeout_secs: Option<u64>, stream_timeout_secs: Option<u64>) -> Self {
+        if let Some(secs) = timeout_secs {
+            self.timeout = Duration::from_secs(secs);
+        }
+        // Connect timeouts are per client, so a custom one needs its own pool
+        if let Some(secs) = connect_timeout_secs {
+            self.client = super::http::client_builder()
+                .connect_timeout(Duration::from_secs(secs))
+                .build()
+                .unwrap_or_else(|_| super::http::shared_client());
+        }
+        self.stream_timeout = stream_timeout_secs.map(Duration::from_secs);
+        self
+    }
+
+    /// Cap the size of images downloaded for URL image sources
+    pub fn with_max_image_bytes(mut self, max_bytes: Option<usize>) -> Self {
+        if let Some(max_bytes) = max_bytes {
+            self.max_image_bytes = max_bytes;
+        }
+        self
+    }
+
+    /// Allow URL image sources on loopback, private and link-local addresses
+    pub fn with_private_image_urls(mut self, allow: bool) -> Self {
+        self.allow_private_image_urls = allow;
+        self
+    }
+
+    /// Download URL image sources (message and tool result images) and replace them
+    /// with base64 data; Gemini only accepts inline images
+    async fn inline_remote_images(&self, request: &mut AnthropicRequest) -> Result<(), ProviderError> {
+        for message in &mut request.messages {
+            let MessageContent::Blocks(blocks) = &mut message.content else {
+                continue;
+            };
+            for block in blocks {
+                let sources: Vec<&mut ImageSource> = match block {
+                    ContentBlock::Image { source } => vec![source],
+                    ContentBlock::ToolResult { content: ToolResultContent::Blocks(blocks), .. } => blocks
+                        .iter_mut()
+                        .filter_map(|b| match b {
+                            ToolResultBlock::Image { source } => Some(source),
+                            _ => None,
+                        })
+                        .collect(),
+                    _ => vec![],
+                };
+                for source in sources {
+                    // Files API uris are sent as fileData rather than downloaded
+                    if source.data.is_some() || source.url.as_deref().is_some_and(is_file_uri) {
+                        continue;
+                    }
+                    let Some(url) = source.url.take() else {
+                        continue;
+                    };
+                    let (mime_type, data) = self.fetch_image(&url).await?;
+                    source.r#type = "base64".to_string();
+                    source.media_type = Some(source.media_type.take().unwrap_or(mime_type));
+                    source.data = Some(data);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// POST for a streamed response, bounded only by `stream_timeout`
+    fn stream_post(&self, url: &str) -> reqwest::RequestBuilder {
+        let builder = self.client.post(url);
+        match self.stream_timeout {
+            Some(timeout) => builder.timeout(timeout),
+            None => builder,
+        }
+    }
+
+    /// Report a call that ran out of time as a timeout rather than a generic HTTP error
+    fn timeout_error(&self, error: reqwest::Error) -> ProviderError {
+        if error.is_timeout() {
+            tracing::warn!("⏱️ {} call timed out after {:?}", self.name, self.timeout);
+            ProviderError::Timeout(self.timeout)
+        } else {
+            error.into()
+        }
+    }
+
+    /// Fill in usage for a response without usageMetadata, which would otherwise
+    /// be reported as zero tokens
+    fn estimate_usage(&self, request: &AnthropicRequest, response: &mut ProviderResponse) {
+        tracing::warn!("📊 {} response had no usageMetadata; estimating token usage", self.name);
+        let output_chars: usize = response
+            .content
+            .iter()
+            .map(|block| match block {
+                ContentBlock::Text { text } => text.len(),
+                ContentBlock::Thinking { thinking, .. } => thinking.len(),
+                ContentBlock::ToolUse { input, .. } => input.to_string().len(),
+                _ => 0,
+            })
+            .sum();
+        response.usage.input_tokens = estimate_input_tokens(request);
+        // Per-candidate tokenCounts, when present, are better than an estimate
+        if response.usage.output_tokens == 0 {
+            response.usage.output_tokens = estimate_tokens(output_chars);
+        }
+    }
+
+    /// Fetch an image, returning its mime type (from `Content-Type`) and base64 data.
+    /// Non-public hosts are refused unless `allow_private_image_urls` is set.
+    async fn fetch_image(&self, url: &str) -> Result<(String, String), ProviderError> {
+        let fetch_error = |reason: String| ProviderError::ApiError {
+            status: 400,
+            message: format!("Failed to fetch image {}: {}", url, reason),
+        };
+
+        let client = if self.allow_private_image_urls {
+            self.client.clone()
+        } else {
+            let parsed = reqwest::Url::parse(url).map_err(|e| fetch_error(e.to_string()))?;
+            super::http::check_public_url(&parsed).map_err(fetch_error)?;
+            super::http::public_client()
+        };
+        let mut response = client.get(url).send().await.map_err(|e| fetch_error(e.to_string()))?;
+        if !response.status().is_success() {
+            return Err(fetch_error(format!("HTTP {}", response.status())));
+        }
+        let too_large = || fetch_error(format!("larger than {} bytes", self.max_image_bytes));
+        if response.content_length().is_some_and(|len| len > self.max_image_bytes as u64) {
+            return Err(too_large());
+        }
+        let mime_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .map(|v| v.trim().to_ascii_lowercase())
+            .filter(|v| v.starts_with("image/"))
+            .ok_or_else(|| fetch_error("response is not an image".to_string()))?;
+
+        // Content-Length may be missing or wrong, so enforce the cap while reading
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|e| fetch_error(e.to_string()))? {
+            if bytes.len() + chunk.len() > self.max_image_bytes {
+                return Err(too_large());
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        tracing::debug!("🖼️ Inlined {} image ({} bytes) from {}", mime_type, bytes.len(), url);
+        Ok((mime_type, general_purpose::STANDARD.encode(bytes)))
+    }
+
+    /// The tool whose input_schema should become the responseSchema, if this request
+    /// forces a single tool and structured output is enabled
+    fn schema_tool<'a>(&self, request: &'a AnthropicRequest) -> Option<&'a crate::models::Tool> {
+        if !self.structured_tool_output {
+            return None;
         }
+        let tools = request.tools.as_ref()?;
+        let choice = request.tool_choice.as_ref()?;
+
+        let tool = match choice.get("type").and_then(|t| t.as_str()) {
+            Some("tool") => {
+                let name = choice.get("name").and_then(|n| n.as_str())?;
+                tools.iter().find(|t| t.name.as_deref() == Some(name))?
+            }
+            // "any" with a single tool is equivalent to forcing it
+            Some("any") if tools.len() == 1 => &tools[0],
+            _ => return None,
+        };
+        tool.input_schema.as_ref()?;
+        Some(tool)
+    }
+
+    /// JSON mode requested through `output_format`, with the response schema if one was given
+    fn json_output(request: &AnthropicRequest) -> Option<Option<serde_json::Value>> {
+        let format = request.output_format.as_ref()?;
+        match format.get("type").and_then(|t| t.as_str()) {
+            Some("json") => Some(None),
+            Some("json_schema") => Some(format.get("schema").cloned().map(|mut schema| {
+                clean_json_schema(&mut schema);
+                schema
+            })),
+            _ => None,
+        }
+    }
+
+    /// Schema mismatches in a response to a structured-output request (forced tool
+    /// or `output_format`); None if the request didn't ask for structured output
+    fn structured_output_violations(&self, request: &AnthropicRequest, response: &ProviderResponse) -> Option<Vec<String>> {
+        let (schema, output) = match self.schema_tool(request) {
+            Some(tool) => {
+                let input = response.content.iter().find_map(|block| match block {
+                    ContentBlock::ToolUse { input, .. } => Some(input.clone()),
+                    _ => None,
+                });
+                (tool.input_schema.clone(), input)
+            }
+            None => {
+                Self::json_output(request)?;
+                let text: String = response
+                    .content
+                    .iter()
+                    .filter_map(|block| match block {
+                        ContentBlock::Text { text } => Some(text.as_str()),
+                        _ => None,
+                    })
+                    .collect();
+                let schema = request.output_format.as_ref().and_then(|f| f.get("schema")).cloned();
+                (schema, serde_json::from_str(&text).ok())
+            }
+        };
+        let Some(output) = output else {
+            return Some(vec!["/: not valid JSON".to_string()]);
+        };
+        Some(schema.map(|schema| validation::validate_json_schema(&output, &schema)).unwrap_or_default())
     }
 
     /// Check if this provider uses OAuth (Code Assist API)
@@ -112,6 +503,14 @@ impl GeminiProvider {
         !model.contains("lite") && !model.contains("flash-lite")
     }
 
+    /// Check if the model accepts a thinkingConfig (Gemini 2.5 and later)
+    fn supports_thinking(&self, model: &str) -> bool {
+        match model.split("gemini-").nth(1) {
+            Some(version) => !version.starts_with("1.") && !version.starts_with("2.0"),
+            None => false,
+        }
+    }
+
     /// Get OAuth bearer token (with automatic refresh)
     async fn get_auth_header(&self) -> Result<Option<String>, ProviderError> {
         if let (Some(oauth_provider_id), Some(token_store)) =
@@ -122,11 +521,10 @@ impl GeminiProvider {
                 if token.needs_refresh() {
                     tracing::info!("🔄 Token for '{}' needs refresh, refreshing...", oauth_provider_id);
 
-                    // Refresh token
-                    let config = OAuthConfig::gemini();
-                    let oauth_client = OAuthClient::new(config, token_store.clone());
+                    // Refresh token (concurrent requests share one refresh)
+                    let oauth_client = OAuthClient::new(self.oauth_config.clone(), token_store.clone());
 
-                    match oauth_client.refresh_token(oauth_provider_id).await {
+                    match oauth_client.refresh_if_needed(oauth_provider_id).await {
                         Ok(new_token) => {
                             tracing::info!("✅ Token refreshed successfully");
                             return Ok(Some(format!("Bearer {}", new_token.access_token)));
@@ -149,6 +547,10 @@ impl GeminiProvider {
                 )));
             }
         }
+        // Vertex AI: Application Default Credentials
+        if let Some(token_source) = &self.token_source {
+            return Ok(Some(format!("Bearer {}", token_source.access_token().await?)));
+        }
         Ok(None)
     }
 
@@ -168,10 +570,32 @@ impl GeminiProvider {
                     .join("\n"),
             };
             GeminiSystemInstruction {
+                role: self.system_instruction_role.clone(),
                 parts: vec![GeminiPart::Text { text }],
             }
         });
 
+        // tool_result blocks only carry the tool_use id; Gemini wants the function name
+        let tool_names: HashMap<&str, &str> = request
+            .messages
+            .iter()
+            .filter_map(|msg| match &msg.content {
+                MessageContent::Blocks(blocks) => Some(blocks),
+                MessageContent::Text(_) => None,
+            })
+            .flatten()
+            .filter_map(|block| match block {
+                ContentBlock::ToolUse { id, name, .. } => Some((id.as_str(), name.as_str())),
+                _ => None,
+            })
+            .collect();
+        let tool_name = |tool_use_id: &String| {
+            tool_names
+                .get(tool_use_id.as_str())
+                .map_or_else(|| tool_use_id.clone(), |n| n.to_string())
+        };
+        let function_parts = self.supports_tools(&request.model);
+
         // Transform messages
         let mut contents = Vec::new();
         for msg in &request.messages {
@@ -207,6 +631,13 @@ impl GeminiProvider {
                                             data: data.clone(),
                                         },
                                     });
+                                } else if let Some(url) = source.url.as_deref().filter(|url| is_file_uri(url)) {
+                                    parts.push(GeminiPart::FileData {
+                                        file_data: GeminiFileData {
+                                            mime_type: source.media_type.clone(),
+                                            file_uri: url.to_string(),
+                                        },
+                                    });
                                 }
                             }
                             ContentBlock::Thinking { thinking, .. } => {
@@ -215,8 +646,54 @@ impl GeminiProvider {
                                     text: thinking.clone(),
                                 });
                             }
-                            _ => {
-                                // Skip tool use/result for now
+                            ContentBlock::ToolUse { name, input, .. } if function_parts => {
+                                parts.push(GeminiPart::FunctionCall {
+                                    function_call: GeminiFunctionCall {
+                                        name: name.clone(),
+                                        args: input.clone(),
+                                    },
+                                });
+                            }
+                            ContentBlock::ToolResult { tool_use_id, content, is_error } if function_parts => {
+                                // String and array content are handled alike via the block form
+                                let blocks = content.blocks();
+                                let key = if *is_error == Some(true) { "error" } else { "content" };
+                                parts.push(GeminiPart::FunctionResponse {
+                                    function_response: GeminiFunctionResponse {
+                                        name: tool_name(tool_use_id),
+                                        response: serde_json::json!({ key: tool_result_text(&blocks) }),
+                                    },
+                                });
+                                for block in blocks {
+                                    if let ToolResultBlock::Image { source } = block {
+                                        if let (Some(mime_type), Some(data)) = (source.media_type, source.data) {
+                                            parts.push(GeminiPart::InlineData {
+                                                inline_data: GeminiInlineData { mime_type, data },
+                                            });
+                                        }
+                                    }
+                                }
+                            }
+                            // Model without function calling: describe the tool turns in text
+                            // so the model still sees what was called and what came back
+                            ContentBlock::ToolUse { name, input, .. } => {
+                                parts.push(GeminiPart::Text {
+                                    text: format!("[Called tool {} with input {}]", name, input),
+                                });
+                            }
+                            ContentBlock::ToolResult { tool_use_id, content, is_error } => {
+                                let outcome = if *is_error == Some(true) { "Error from" } else { "Result of" };
+                                parts.push(GeminiPart::Text {
+                                    text: format!(
+                                        "[{} tool {}]\n{}",
+                                        outcome,
+                                        tool_name(tool_use_id),
+                                        tool_result_text(&content.blocks())
+                                    ),
+                                });
+                            }
+                            ContentBlock::Document { source, .. } => {
+                                parts.extend(media_part(source));
                             }
                         }
                     }
@@ -234,9 +711,23 @@ impl GeminiProvider {
         let generation_config = GeminiGenerationConfig {
             temperature: request.temperature,
             top_p: request.top_p,
-            top_k: Some(40), // Gemini default
+            // Left to the model's own default when neither request nor provider sets it
+            top_k: request.top_k.or(self.top_k).map(|k| k as i32),
             max_output_tokens: Some(request.max_tokens as i32),
             stop_sequences: request.stop_sequences.clone(),
+            response_mime_type: None,
+            response_schema: None,
+            // Streaming only ever forwards the first candidate
+            candidate_count: self.candidate_count.filter(|_| request.stream != Some(true)),
+            // Older models reject thinkingConfig, so it's left out for them
+            thinking_config: request
+                .thinking
+                .as_ref()
+                .filter(|t| t.r#type == "enabled" && self.supports_thinking(&request.model))
+                .map(|t| GeminiThinkingConfig {
+                    thinking_budget: t.budget_tokens.map(|b| b as i32),
+                    include_thoughts: true,
+                }),
         };
 
         // Transform tools if present
@@ -290,129 +781,392 @@ impl GeminiProvider {
             None // lite/flash-lite models don't support tools
         };
 
+        // Grounding requested by config, unless the client's WebSearch tool already added it
+        let tools = match tools {
+            Some(mut tools) if self.google_search_grounding => {
+                if !tools.iter().any(|t| matches!(t, GeminiTool::GoogleSearch { .. })) {
+                    tools.push(GeminiTool::GoogleSearch {
+                        google_search: GoogleSearchTool {},
+                    });
+                }
+                Some(tools)
+            }
+            None if self.google_search_grounding && self.supports_tools(&request.model) => Some(vec![GeminiTool::GoogleSearch {
+                google_search: GoogleSearchTool {},
+            }]),
+            tools => tools,
+        };
+
+        // Forced single tool: ask for JSON matching its schema instead of a function call
+        let (generation_config, tools) = match self.schema_tool(request) {
+            Some(tool) => {
+                let mut schema = tool.input_schema.clone().unwrap_or_default();
+                clean_json_schema(&mut schema);
+                let generation_config = GeminiGenerationConfig {
+                    response_mime_type: Some("application/json".to_string()),
+                    response_schema: Some(schema),
+                    ..generation_config
+                };
+                (generation_config, None)
+            }
+            None => match Self::json_output(request) {
+                // JSON mode needs no tool; Gemini rejects it alongside function declarations
+                Some(schema) if tools.as_ref().is_none_or(|t| t.is_empty()) => {
+                    let generation_config = GeminiGenerationConfig {
+                        response_mime_type: Some("application/json".to_string()),
+                        response_schema: schema,
+                        ..generation_config
+                    };
+                    (generation_config, None)
+                }
+                Some(_) => {
+                    tracing::warn!("⚠️ JSON output ignored for {}: Gemini can't combine it with tools", request.model);
+                    (generation_config, tools)
+                }
+                None => (generation_config, tools),
+            },
+        };
+
         Ok(GeminiRequest {
             contents,
             system_instruction,
             generation_config: Some(generation_config),
             tools,
+            safety_settings: self.safety_settings.clone(),
+            cached_content: None,
         })
     }
 
+    /// Whether the client marked the system prompt as cacheable (Anthropic `cache_control`)
+    fn wants_system_cache(request: &AnthropicRequest) -> bool {
+        matches!(
+            &request.system,
+            Some(SystemPrompt::Blocks(blocks)) if blocks.iter().any(|b| b.cache_control.is_some())
+        )
+    }
+
+    /// Cache key covering everything stored in the cachedContents resource
+    fn system_cache_key(model: &str, gemini_request: &GeminiRequest) -> u64 {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        model.hash(&mut hasher);
+        serde_json::to_string(&gemini_request.system_instruction)
+            .unwrap_or_default()
+            .hash(&mut hasher);
+        serde_json::to_string(&gemini_request.tools)
+            .unwrap_or_default()
+            .hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Move the system instruction (and tools) into a cachedContents resource and
+    /// reference it, so only the messages are sent each turn.
+    /// API key mode only; on any failure the request is left unchanged.
+    async fn apply_system_cache(&self, request: &AnthropicRequest, gemini_request: &mut GeminiRequest) {
+        if self.is_oauth() || self.is_vertex_ai() || gemini_request.system_instruction.is_none() {
+            return;
+        }
+        if !Self::wants_system_cache(request) {
+            return;
+        }
+        let Some(api_key) = &self.api_key else {
+            return;
+        };
+
+        let key = Self::system_cache_key(&request.model, gemini_request);
+        let cached = self
+            .system_cache
+            .lock()
+            .unwrap()
+            .get(&key)
+            .filter(|c| c.expires_at > Instant::now())
+            .map(|c| c.name.clone());
+
+        let name = match cached {
+            Some(name) => name,
+            None => {
+                let body = serde_json::json!({
+                    "model": format!("models/{}", request.model),
+                    "systemInstruction": gemini_request.system_instruction,
+                    "tools": gemini_request.tools,
+                    "ttl": format!("{}s", SYSTEM_CACHE_TTL.as_secs()),
+                });
+                let url = format!("{}/cachedContents?key={}", self.base_url, api_key);
+
+                let created = match self.client.post(&url).timeout(self.timeout).json(&body).send().await {
+                    Ok(resp) if resp.status().is_success() => resp.json::<serde_json::Value>().await.ok(),
+                    Ok(resp) => {
+                        // Commonly a 400 for prompts below the minimum cacheable size
+                        tracing::debug!("Gemini system cache not created ({}), sending inline", resp.status());
+                        None
+                    }
+                    Err(e) => {
+                        tracing::debug!("Gemini system cache request failed: {}, sending inline", e);
+                        None
+                    }
+                };
+                let Some(name) = created
+                    .as_ref()
+                    .and_then(|v| v.get("name"))
+                    .and_then(|n| n.as_str())
+                    .map(|n| n.to_string())
+                else {
+                    return;
+                };
+
+                tracing::debug!("💾 Created Gemini system cache {}", name);
+                self.system_cache.lock().unwrap().insert(
+                    key,
+                    CachedSystem {
+                        name: name.clone(),
+                        // Refresh slightly early so we never reference an expired cache
+                        expires_at: Instant::now() + SYSTEM_CACHE_TTL - Duration::from_secs(30),
+                    },
+                );
+                name
+            }
+        };
+
+        // Cached content already carries these; Gemini rejects them alongside cachedContent
+        gemini_request.system_instruction = None;
+        gemini_request.tools = None;
+        gemini_request.cached_content = Some(name);
+    }
+
+    /// After a 404 on a request referencing a system cache (expired or deleted
+    /// upstream), forget that cache and rebuild the request with a fresh one.
+    /// Returns false when the request didn't use a cache.
+    async fn recreate_system_cache(
+        &self,
+        request: &AnthropicRequest,
+        gemini_request: &mut GeminiRequest,
+    ) -> Result<bool, ProviderError> {
+        let Some(name) = gemini_request.cached_content.take() else {
+            return Ok(false);
+        };
+        tracing::info!("💾 Gemini system cache {} not found, recreating", name);
+        self.system_cache.lock().unwrap().retain(|_, cached| cached.name != name);
+        *gemini_request = self.transform_request(request)?;
+        self.apply_system_cache(request, gemini_request).await;
+        Ok(true)
+    }
+
     /// Transform Gemini response to Anthropic format
     fn transform_response(
         &self,
         response: GeminiResponse,
         model: String,
+        schema_tool: Option<&str>,
     ) -> Result<ProviderResponse, ProviderError> {
-        let candidate = response
-            .candidates
-            .first()
-            .ok_or_else(|| ProviderError::ApiError {
-                status: 500,
-                message: "No candidates in response".to_string(),
-            })?;
+        let candidate = match self.candidate_selection {
+            CandidateSelection::HighestLogprob => response.candidates.iter().reduce(|best, c| {
+                let score = |c: &GeminiCandidate| c.avg_logprobs.unwrap_or(f64::NEG_INFINITY);
+                if score(c) > score(best) { c } else { best }
+            }),
+            CandidateSelection::First | CandidateSelection::All => response.candidates.first(),
+        }
+        .ok_or_else(|| ProviderError::ApiError {
+            status: 500,
+            message: "No candidates in response".to_string(),
+        })?;
+        if response.candidates.len() > 1 {
+            tracing::info!(
+                "🎲 Gemini generated {} candidates ({:?} selection)",
+                response.candidates.len(),
+                self.candidate_selection
+            );
+        }
 
-        let content = candidate
-            .content
-            .parts
+        // Stop reason, structured output and safety notes follow the selected candidate
+        let returned: Vec<&GeminiCandidate> = match self.candidate_selection {
+            CandidateSelection::All => response.candidates.iter().collect(),
+            _ => vec![candidate],
+        };
+
+        let key = response_key(response.response_id.as_deref());
+        let mut content: Vec<ContentBlock> = returned
             .iter()
-            .map(|part| match part {
-                GeminiPart::Text { text } => ContentBlock::Text {
+            .flat_map(|c| c.content.parts.iter())
+            .enumerate()
+            .filter_map(|(index, part)| match part {
+                GeminiPart::Thought { text, thought: true } => Some(ContentBlock::Thinking {
+                    thinking: text.clone(),
+                    signature: String::new(),
+                }),
+                GeminiPart::Text { text } | GeminiPart::Thought { text, .. } => Some(ContentBlock::Text {
                     text: text.clone(),
-                },
-                _ => ContentBlock::Text {
-                    text: String::new(),
-                },
+                }),
+                GeminiPart::FunctionCall { function_call } => Some(ContentBlock::ToolUse {
+                    id: tool_use_id(&key, &function_call.name, &function_call.args, index),
+                    name: function_call.name.clone(),
+                    input: function_call.args.clone(),
+                }),
+                // Inline data and function responses never appear in model output
+                _ => None,
             })
             .collect();
 
-        let stop_reason = match candidate.finish_reason.as_deref() {
-            Some("STOP") => Some("end_turn".to_string()),
-            Some("MAX_TOKENS") => Some("max_tokens".to_string()),
-            _ => None,
+        let has_tool_use = content.iter().any(|b| matches!(b, ContentBlock::ToolUse { .. }));
+        let mut stop_reason = match candidate.finish_reason.as_deref() {
+            _ if has_tool_use => Some("tool_use".to_string()),
+            Some(reason) => Some(stop_reason_for(reason).to_string()),
+            None => None,
         };
 
+        // Structured output stands in for the forced tool call
+        if let Some(tool_name) = schema_tool {
+            let text: String = candidate
+                .content
+                .parts
+                .iter()
+                .filter_map(|part| match part {
+                    GeminiPart::Text { text } | GeminiPart::Thought { text, thought: false } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect();
+            match serde_json::from_str::<serde_json::Value>(&text) {
+                Ok(input) if input.is_object() => {
+                    content = vec![ContentBlock::ToolUse {
+                        id: tool_use_id(&key, tool_name, &input, 0),
+                        name: tool_name.to_string(),
+                        input,
+                    }];
+                    stop_reason = Some("tool_use".to_string());
+                }
+                _ => {
+                    tracing::warn!("⚠️ Gemini structured output for {} was not a JSON object, returning text", tool_name);
+                }
+            }
+        }
+
+        log_stop_reason(&self.name, &model, candidate.finish_reason.as_deref(), stop_reason.as_deref());
+
+        // A blocked response always explains itself rather than coming back empty
+        let blocked = content.is_empty() && candidate.finish_reason.as_deref().is_some_and(is_blocked_finish);
+        if self.safety_details || blocked {
+            if let Some(note) = safety_note(candidate) {
+                content.push(ContentBlock::Text { text: note });
+            }
+        }
+        if let Some(sources) = candidate.grounding_metadata.as_ref().and_then(grounding_sources) {
+            content.push(ContentBlock::Text { text: sources });
+        }
+
+        // promptTokenCount includes cached tokens, which Anthropic reports separately
+        let metadata = response.usage_metadata.as_ref();
+        let prompt_tokens = metadata.and_then(|u| u.prompt_token_count).unwrap_or(0).max(0) as u32;
+        let cached_tokens = metadata.and_then(|u| u.cached_content_token_count).map(|c| c.max(0) as u32);
         let usage = Usage {
-            input_tokens: response
-                .usage_metadata
-                .as_ref()
-                .and_then(|u| u.prompt_token_count)
-                .unwrap_or(0) as u32,
-            output_tokens: response
-                .usage_metadata
-                .as_ref()
-                .and_then(|u| u.candidates_token_count)
-                .unwrap_or(0) as u32,
+            input_tokens: prompt_tokens.saturating_sub(cached_tokens.unwrap_or(0)),
+            output_tokens: output_tokens(&response),
+            cache_read_input_tokens: cached_tokens,
         };
 
+        let id = response
+            .response_id
+            .clone()
+            .filter(|_| self.preserve_response_id)
+            .unwrap_or_else(|| format!("gemini-{}", chrono::Utc::now().timestamp_millis()));
+
         Ok(ProviderResponse {
-            id: format!("gemini-{}", chrono::Utc::now().timestamp_millis()),
+            id,
             r#type: "message".to_string(),
             role: "assistant".to_string(),
             content,
             model,
             stop_reason,
             stop_sequence: None,
+            raw: None,
             usage,
         })
     }
 
 
-    /// Handle 429 rate limit errors with automatic retry
-    async fn handle_rate_limit_retry<F, Fut>(
-        &self,
-        mut request_fn: F,
-        max_retries: u32,
-    ) -> Result<reqwest::Response, ProviderError>
+    /// Send a request, retrying 429/500/503 responses with exponential backoff.
+    /// The wait honours `Retry-After` or Gemini's RetryInfo when present, unless it is
+    /// longer than `max_retry_delay`: then the error is returned so routing can fall
+    /// back. Any other status is returned as-is for the caller to handle.
+    async fn handle_rate_limit_retry<F, Fut>(&self, mut request_fn: F) -> Result<reqwest::Response, ProviderError>
     where
         F: FnMut() -> Fut,
         Fut: std::future::Future<Output = Result<reqwest::Response, reqwest::Error>>,
     {
         let mut retries = 0;
-        
         loop {
-            let response = request_fn().await?;
-            
-            // Check if it's a 429 error
-            if response.status().as_u16() == 429 {
-                let error_text = response.text().await.unwrap_or_default();
-                
-                // Try to extract retry delay
-                if let Some(delay) = extract_retry_delay(&error_text) {
-                    if retries < max_retries {
-                        retries += 1;
-                        tracing::warn!("⏱️  Rate limit hit (attempt {}/{}), retrying after {:?}...", 
-                                      retries, max_retries, delay);
-                        tokio::time::sleep(delay).await;
-                        continue;
-                    } else {
-                        tracing::error!("❌ Rate limit retries exhausted after {} attempts", max_retries);
-                        return Err(ProviderError::ApiError {
-                            status: 429,
-                            message: error_text,
-                        });
-                    }
-                } else {
-                    // No retry delay found, return error
-                    return Err(ProviderError::ApiError {
-                        status: 429,
-                        message: error_text,
-                    });
+            let response = request_fn().await.map_err(|e| self.timeout_error(e))?;
+            let status = response.status().as_u16();
+            if !matches!(status, 429 | 500 | 503) {
+                return Ok(response);
+            }
+
+            let retry_after = retry_after(response.headers());
+            let error_text = response.text().await.unwrap_or_default();
+            if retries >= self.max_retries {
+                if self.max_retries > 0 {
+                    tracing::error!("❌ {} retries exhausted after {} attempts ({})", self.name, self.max_retries, status);
                 }
+                tracing::error!("Gemini API error ({}): {}", status, error_text);
+                return Err(gemini_error(status, error_text));
+            }
+
+            let requested = retry_after.or_else(|| extract_retry_delay(&error_text));
+            if let Some(requested) = requested.filter(|d| *d > self.max_retry_delay) {
+                tracing::warn!(
+                    "⏱️  {} asked to retry after {:?}, longer than the {:?} limit; giving up",
+                    self.name,
+                    requested,
+                    self.max_retry_delay
+                );
+                return Err(gemini_error(status, error_text));
             }
-            
-            return Ok(response);
+            let delay = requested
+                .unwrap_or(self.retry_base_delay * 2u32.saturating_pow(retries))
+                .min(self.max_retry_delay);
+            retries += 1;
+            tracing::warn!(
+                "⏱️  {} returned {} (attempt {}/{}), retrying after {:?}...",
+                self.name,
+                status,
+                retries,
+                self.max_retries,
+                delay
+            );
+            tokio::time::sleep(delay).await;
         }
     }
-}
 
-#[async_trait]
-impl AnthropicProvider for GeminiProvider {
-    async fn send_message(
+    /// `generate`, repeated once with a nudge when enabled and a tool call comes back
+    /// without usable args. Usage then covers both calls.
+    async fn generate_with_tool_args_retry(&self, request: AnthropicRequest) -> Result<ProviderResponse, ProviderError> {
+        if !self.retry_empty_tool_args {
+            return self.generate(request).await;
+        }
+
+        let first = self.generate(request.clone()).await?;
+        let incomplete = incomplete_tool_calls(&request, &first);
+        if incomplete.is_empty() {
+            return Ok(first);
+        }
+        tracing::warn!(
+            "🔁 Gemini {} returned {} without complete arguments, retrying once",
+            self.name,
+            incomplete.join(", ")
+        );
+
+        let mut retry = request;
+        nudge_for_tool_args(&mut retry, &incomplete);
+        let mut response = self.generate(retry).await?;
+        response.usage.input_tokens += first.usage.input_tokens;
+        response.usage.output_tokens += first.usage.output_tokens;
+        Ok(response)
+    }
+
+    /// One generateContent call through Code Assist, the Gemini API or Vertex AI
+    async fn generate(
         &self,
-        request: AnthropicRequest,
+        mut request: AnthropicRequest,
     ) -> Result<ProviderResponse, ProviderError> {
         let model = request.model.clone();
+        self.inline_remote_images(&mut request).await?;
 
         // Check if using OAuth (Code Assist API)
         if self.is_oauth() {
@@ -452,6 +1206,7 @@ impl AnthropicProvider for GeminiProvider {
                     system_instruction: gemini_request.system_instruction,
                     generation_config: gemini_request.generation_config,
                     tools: gemini_request.tools,
+                    safety_settings: gemini_request.safety_settings,
                     session_id: None, // Optional
                 },
             };
@@ -468,16 +1223,19 @@ impl AnthropicProvider for GeminiProvider {
 
             // Clone necessary data for the retry closure
             let client = self.client.clone();
-            let custom_headers = self.custom_headers.clone();
+            let timeout = self.timeout;
+            let mut custom_headers = self.custom_headers.clone();
+            custom_headers.extend(request.upstream_headers().cloned());
             let bearer_token = bearer_token.clone();
             let code_assist_request = code_assist_request.clone();
             let url = url.clone();
 
-            // Use retry handler for 429 errors
+            // Retry transient errors (429/500/503)
             let response = self.handle_rate_limit_retry(
                 move || {
                     let mut req_builder = client
                         .post(&url)
+                        .timeout(timeout)
                         .header("Content-Type", "application/json")
                         .header("Authorization", &bearer_token);
 
@@ -489,7 +1247,6 @@ impl AnthropicProvider for GeminiProvider {
                     // Send request
                     req_builder.json(&code_assist_request).send()
                 },
-                3, // max_retries
             ).await?;
 
             if !response.status().is_success() {
@@ -518,18 +1275,25 @@ impl AnthropicProvider for GeminiProvider {
                 }
 
                 tracing::error!("Code Assist API error ({}): {}", status, error_text);
-                return Err(ProviderError::ApiError {
-                    status,
-                    message: error_text,
-                });
+                return Err(gemini_error(status, error_text));
             }
 
+            check_content_type(response.headers(), ExpectedBody::Json)?;
+
             // Parse Code Assist response
-            let code_assist_response: CodeAssistResponse = response.json().await?;
-            self.transform_response(code_assist_response.response, model)
+            let response_text = response.text().await?;
+            let code_assist_response: CodeAssistResponse = serde_json::from_str(&response_text)?;
+            let schema_tool = self.schema_tool(&request).and_then(|t| t.name.as_deref());
+            let usage_missing = code_assist_response.response.usage_metadata.is_none();
+            let mut response = self.transform_response(code_assist_response.response, model, schema_tool)?;
+            if usage_missing {
+                self.estimate_usage(&request, &mut response);
+            }
+            Ok(response.with_raw(request.capture_raw, &response_text))
         } else {
             // Use public Gemini API or Vertex AI
-            let gemini_request = self.transform_request(&request)?;
+            let mut gemini_request = self.transform_request(&request)?;
+            self.apply_system_cache(&request, &mut gemini_request).await;
 
             // Build URL
             let url = if self.is_vertex_ai() {
@@ -555,27 +1319,49 @@ impl AnthropicProvider for GeminiProvider {
                 ));
             };
 
-            // Clone necessary data for the retry closure
-            let client = self.client.clone();
-            let custom_headers = self.custom_headers.clone();
-            let gemini_request = gemini_request.clone();
-            let url = url.clone();
+            // Vertex AI bearer token (None for API keys)
+            let auth_header = self.get_auth_header().await?;
+            let mut custom_headers = self.custom_headers.clone();
+            custom_headers.extend(request.upstream_headers().cloned());
 
-            // Use retry handler for 429 errors
-            let response = self.handle_rate_limit_retry(
-                move || {
-                    let mut req_builder = client.post(&url).header("Content-Type", "application/json");
+            let mut recreated_cache = false;
+            let response = loop {
+                // Clone necessary data for the retry closure
+                let client = self.client.clone();
+                let timeout = self.timeout;
+                let auth_header = auth_header.clone();
+                let custom_headers = custom_headers.clone();
+                let body = gemini_request.clone();
+                let url = url.clone();
 
-                    // Add custom headers
-                    for (key, value) in &custom_headers {
-                        req_builder = req_builder.header(key, value);
-                    }
+                // Retry transient errors (429/500/503)
+                let response = self.handle_rate_limit_retry(
+                    move || {
+                        let mut req_builder = client.post(&url).timeout(timeout).header("Content-Type", "application/json");
+                        if let Some(ref auth_header) = auth_header {
+                            req_builder = req_builder.header("Authorization", auth_header);
+                        }
 
-                    // Send request
-                    req_builder.json(&gemini_request).send()
-                },
-                3, // max_retries
-            ).await?;
+                        // Add custom headers
+                        for (key, value) in &custom_headers {
+                            req_builder = req_builder.header(key, value);
+                        }
+
+                        // Send request
+                        req_builder.json(&body).send()
+                    },
+                ).await?;
+
+                // The referenced cache expired: recreate it once and resend
+                if response.status().as_u16() == 404
+                    && !recreated_cache
+                    && self.recreate_system_cache(&request, &mut gemini_request).await?
+                {
+                    recreated_cache = true;
+                    continue;
+                }
+                break response;
+            };
 
             if !response.status().is_success() {
                 let status = response.status().as_u16();
@@ -584,43 +1370,449 @@ impl AnthropicProvider for GeminiProvider {
                     .await
                     .unwrap_or_else(|_| "Unknown error".to_string());
                 tracing::error!("Gemini API error ({}): {}", status, error_text);
-                return Err(ProviderError::ApiError {
-                    status,
-                    message: error_text,
-                });
+                return Err(gemini_error(status, error_text));
             }
 
-            let gemini_response: GeminiResponse = response.json().await?;
-            self.transform_response(gemini_response, model)
+            check_content_type(response.headers(), ExpectedBody::Json)?;
+
+            let response_text = response.text().await?;
+            let gemini_response: GeminiResponse =
+                parse_response("Gemini", &response_text, GEMINI_RESPONSE_FIELDS)?;
+            let schema_tool = self.schema_tool(&request).and_then(|t| t.name.as_deref());
+            let usage_missing = gemini_response.usage_metadata.is_none();
+            let mut response = self.transform_response(gemini_response, model, schema_tool)?;
+            if usage_missing {
+                self.estimate_usage(&request, &mut response);
+            }
+            Ok(response.with_raw(request.capture_raw, &response_text))
         }
     }
+}
 
-    async fn send_message_stream(
-        &self,
-        request: AnthropicRequest,
-    ) -> Result<std::pin::Pin<Box<dyn futures::stream::Stream<Item = Result<bytes::Bytes, ProviderError>> + Send>>, ProviderError> {
-        use futures::TryStreamExt;
+/// Translates Gemini `streamGenerateContent` chunks (plain or wrapped in a
+/// Code Assist `{"response": ...}` envelope) into Anthropic stream events
+struct GeminiStreamTranslator {
+    provider: String,
+    model: String,
+    started: bool,
+    /// Type of the open content block ("text" or "thinking")
+    open_block: Option<&'static str>,
+    next_index: usize,
+    stop_reason: Option<String>,
+    /// finishReason as sent by Gemini
+    upstream_reason: Option<String>,
+    input_tokens: u32,
+    output_tokens: Option<u32>,
+    cache_read_input_tokens: Option<u32>,
+    /// Forced tool answered through responseSchema: text is collected here and
+    /// emitted as that tool's input at the end
+    structured_tool: Option<String>,
+    structured_text: String,
+    /// Cited sources from groundingMetadata, emitted as a final text block
+    grounding_sources: Option<String>,
+    /// Use the first chunk's responseId as the message id
+    preserve_response_id: bool,
+    /// Mixed into tool_use ids, from the first chunk's responseId (see `response_key`)
+    response_key: String,
+    /// Whether any chunk carried usageMetadata
+    usage_reported: bool,
+    /// Input tokens reported if usageMetadata never arrives
+    estimated_input_tokens: u32,
+}
 
-        let model = request.model.clone();
+impl GeminiStreamTranslator {
+    fn new(provider: String, model: String) -> Self {
+        Self {
+            provider,
+            model,
+            started: false,
+            open_block: None,
+            next_index: 0,
+            stop_reason: None,
+            upstream_reason: None,
+            input_tokens: 0,
+            output_tokens: None,
+            cache_read_input_tokens: None,
+            structured_tool: None,
+            structured_text: String::new(),
+            grounding_sources: None,
+            preserve_response_id: false,
+            response_key: response_key(None),
+            usage_reported: false,
+            estimated_input_tokens: 0,
+        }
+    }
 
-        // Check if using OAuth (Code Assist API)
-        if self.is_oauth() {
-            // Use Code Assist API streaming endpoint
-            let gemini_request = self.transform_request(&request)?;
+    /// Input tokens to report when the stream carries no usageMetadata
+    fn with_estimated_input_tokens(mut self, tokens: u32) -> Self {
+        self.estimated_input_tokens = tokens;
+        self
+    }
 
-            // Get OAuth bearer token
-            let auth_header = self.get_auth_header().await?;
-            let bearer_token = auth_header.ok_or_else(|| {
-                ProviderError::AuthError("OAuth configured but no token available".to_string())
-            })?;
+    /// Take the message id from Gemini's responseId when present
+    fn with_preserve_response_id(mut self, enabled: bool) -> Self {
+        self.preserve_response_id = enabled;
+        self
+    }
 
-            // Get project_id from token store
-            let project_id = if let (Some(oauth_provider_id), Some(token_store)) =
-                (&self.oauth_provider_id, &self.token_store) {
-                token_store
-                    .get(oauth_provider_id)
-                    .and_then(|token| token.project_id.clone())
-            } else {
+    /// Turn the streamed JSON into a `tool_use` block for `tool` (see `schema_tool`)
+    fn with_structured_tool(mut self, tool: Option<String>) -> Self {
+        self.structured_tool = tool;
+        self
+    }
+
+    /// Emit the collected structured output: a tool_use block if it is a JSON object,
+    /// otherwise the text as-is
+    fn flush_structured(&mut self, out: &mut Vec<SseEvent>) {
+        let Some(name) = self.structured_tool.take() else {
+            return;
+        };
+        let text = std::mem::take(&mut self.structured_text);
+        if text.is_empty() {
+            return;
+        }
+        self.close_block(out);
+        let index = self.next_index;
+        self.next_index += 1;
+
+        let (content_block, delta) = match serde_json::from_str::<serde_json::Value>(&text) {
+            Ok(input) if input.is_object() => {
+                self.stop_reason = Some("tool_use".to_string());
+                (
+                    serde_json::json!({ "type": "tool_use", "id": tool_use_id(&self.response_key, &name, &input, 0), "name": name, "input": {} }),
+                    serde_json::json!({ "type": "input_json_delta", "partial_json": input.to_string() }),
+                )
+            }
+            _ => {
+                tracing::warn!("⚠️ Gemini structured output for {} was not a JSON object, returning text", name);
+                (
+                    serde_json::json!({ "type": "text", "text": "" }),
+                    serde_json::json!({ "type": "text_delta", "text": text }),
+                )
+            }
+        };
+        out.push(SseEvent::anthropic(serde_json::json!({
+            "type": "content_block_start",
+            "index": index,
+            "content_block": content_block
+        })));
+        out.push(SseEvent::anthropic(serde_json::json!({
+            "type": "content_block_delta",
+            "index": index,
+            "delta": delta
+        })));
+        out.push(SseEvent::anthropic(serde_json::json!({
+            "type": "content_block_stop",
+            "index": index
+        })));
+    }
+
+    fn close_block(&mut self, out: &mut Vec<SseEvent>) {
+        if self.open_block.take().is_some() {
+            out.push(SseEvent::anthropic(serde_json::json!({
+                "type": "content_block_stop",
+                "index": self.next_index - 1
+            })));
+        }
+    }
+
+    fn reported_input_tokens(&self) -> u32 {
+        if self.usage_reported {
+            self.input_tokens
+        } else {
+            self.estimated_input_tokens
+        }
+    }
+
+    fn usage(&self) -> serde_json::Value {
+        let mut usage = serde_json::json!({ "input_tokens": self.reported_input_tokens() });
+        if let Some(output_tokens) = self.output_tokens {
+            usage["output_tokens"] = output_tokens.into();
+        }
+        if let Some(cached) = self.cache_read_input_tokens {
+            usage["cache_read_input_tokens"] = cached.into();
+        }
+        usage
+    }
+}
+
+impl StreamTranslator for GeminiStreamTranslator {
+    fn translate(&mut self, event: SseEvent) -> Vec<SseEvent> {
+        let mut out = Vec::new();
+        let Some(mut chunk) = event.json() else {
+            return out;
+        };
+        // Code Assist wraps the GenerateContentResponse
+        if let Some(inner) = chunk.get_mut("response").map(|r| r.take()) {
+            chunk = inner;
+        }
+
+        if let Some(usage) = chunk.get("usageMetadata") {
+            self.usage_reported = true;
+            if let Some(cached) = usage.get("cachedContentTokenCount").and_then(|v| v.as_u64()) {
+                self.cache_read_input_tokens = Some(cached as u32);
+            }
+            if let Some(prompt) = usage.get("promptTokenCount").and_then(|v| v.as_u64()) {
+                self.input_tokens = (prompt as u32).saturating_sub(self.cache_read_input_tokens.unwrap_or(0));
+            }
+            if let Some(candidates) = usage.get("candidatesTokenCount").and_then(|v| v.as_u64()) {
+                self.output_tokens = Some(candidates as u32);
+            }
+        }
+
+        if !self.started {
+            self.started = true;
+            if let Some(response_id) = chunk.get("responseId").and_then(|v| v.as_str()) {
+                self.response_key = response_key(Some(response_id));
+            }
+            let id = chunk
+                .get("responseId")
+                .and_then(|v| v.as_str())
+                .filter(|_| self.preserve_response_id)
+                .map(|id| id.to_string())
+                .unwrap_or_else(|| format!("msg_{}", chrono::Utc::now().timestamp_millis()));
+            out.push(SseEvent::anthropic(serde_json::json!({
+                "type": "message_start",
+                "message": {
+                    "id": id,
+                    "type": "message",
+                    "role": "assistant",
+                    "content": [],
+                    "model": self.model,
+                    "stop_reason": null,
+                    "stop_sequence": null,
+                    "usage": { "input_tokens": self.reported_input_tokens(), "output_tokens": 0 }
+                }
+            })));
+        }
+
+        let candidate = chunk.get("candidates").and_then(|c| c.get(0));
+        let parts = candidate
+            .and_then(|c| c.pointer("/content/parts"))
+            .and_then(|p| p.as_array())
+            .cloned()
+            .unwrap_or_default();
+
+        for part in parts {
+            if let Some(text) = part.get("text").and_then(|t| t.as_str()) {
+                if text.is_empty() {
+                    continue;
+                }
+                let thought = part.get("thought").and_then(|t| t.as_bool()) == Some(true);
+                if self.structured_tool.is_some() && !thought {
+                    self.structured_text.push_str(text);
+                    continue;
+                }
+                let (block_type, delta) = if thought {
+                    ("thinking", serde_json::json!({ "type": "thinking_delta", "thinking": text }))
+                } else {
+                    ("text", serde_json::json!({ "type": "text_delta", "text": text }))
+                };
+                if self.open_block != Some(block_type) {
+                    self.close_block(&mut out);
+                    self.open_block = Some(block_type);
+                    self.next_index += 1;
+                    let content_block = if thought {
+                        serde_json::json!({ "type": "thinking", "thinking": "", "signature": "" })
+                    } else {
+                        serde_json::json!({ "type": "text", "text": "" })
+                    };
+                    out.push(SseEvent::anthropic(serde_json::json!({
+                        "type": "content_block_start",
+                        "index": self.next_index - 1,
+                        "content_block": content_block
+                    })));
+                }
+                out.push(SseEvent::anthropic(serde_json::json!({
+                    "type": "content_block_delta",
+                    "index": self.next_index - 1,
+                    "delta": delta
+                })));
+            } else if let Some(call) = part.get("functionCall") {
+                // Gemini sends complete function calls, so emit the whole block at once
+                self.close_block(&mut out);
+                let index = self.next_index;
+                self.next_index += 1;
+                let name = call.get("name").and_then(|n| n.as_str()).unwrap_or_default();
+                let args = call.get("args").cloned().unwrap_or_else(|| serde_json::json!({}));
+                out.push(SseEvent::anthropic(serde_json::json!({
+                    "type": "content_block_start",
+                    "index": index,
+                    "content_block": {
+                        "type": "tool_use",
+                        "id": tool_use_id(&self.response_key, name, &args, index),
+                        "name": name,
+                        "input": {}
+                    }
+                })));
+                out.push(SseEvent::anthropic(serde_json::json!({
+                    "type": "content_block_delta",
+                    "index": index,
+                    "delta": { "type": "input_json_delta", "partial_json": args.to_string() }
+                })));
+                out.push(SseEvent::anthropic(serde_json::json!({
+                    "type": "content_block_stop",
+                    "index": index
+                })));
+                self.stop_reason = Some("tool_use".to_string());
+            }
+        }
+
+        if let Some(sources) = candidate
+            .and_then(|c| c.get("groundingMetadata"))
+            .and_then(|m| serde_json::from_value::<GeminiGroundingMetadata>(m.clone()).ok())
+            .and_then(|m| grounding_sources(&m))
+        {
+            self.grounding_sources = Some(sources);
+        }
+
+        match candidate.and_then(|c| c.get("finishReason")).and_then(|r| r.as_str()) {
+            Some(reason) => {
+                self.upstream_reason = Some(reason.to_string());
+                // A function call already set tool_use
+                self.stop_reason.get_or_insert_with(|| stop_reason_for(reason).to_string());
+                // Nothing was streamed before the block: say why instead of ending empty
+                if self.next_index == 0 && is_blocked_finish(reason) {
+                    let note = candidate
+                        .and_then(|c| serde_json::from_value::<GeminiCandidate>(c.clone()).ok())
+                        .and_then(|c| safety_note(&c));
+                    if let Some(note) = note {
+                        self.next_index = 1;
+                        out.push(SseEvent::anthropic(serde_json::json!({
+                            "type": "content_block_start",
+                            "index": 0,
+                            "content_block": { "type": "text", "text": "" }
+                        })));
+                        out.push(SseEvent::anthropic(serde_json::json!({
+                            "type": "content_block_delta",
+                            "index": 0,
+                            "delta": { "type": "text_delta", "text": note }
+                        })));
+                        out.push(SseEvent::anthropic(serde_json::json!({
+                            "type": "content_block_stop",
+                            "index": 0
+                        })));
+                    }
+                }
+            }
+            None if chunk.get("usageMetadata").is_some() => {
+                // Intermediate usage update; the final delta is emitted in finish()
+                out.push(SseEvent::anthropic(serde_json::json!({
+                    "type": "message_delta",
+                    "delta": { "stop_reason": null, "stop_sequence": null },
+                    "usage": self.usage()
+                })));
+            }
+            None => {}
+        }
+
+        out
+    }
+
+    fn finish(&mut self) -> Vec<SseEvent> {
+        let mut out = Vec::new();
+        if !self.started {
+            return out;
+        }
+        self.flush_structured(&mut out);
+        self.close_block(&mut out);
+        if let Some(sources) = self.grounding_sources.take() {
+            let index = self.next_index;
+            self.next_index += 1;
+            out.push(SseEvent::anthropic(serde_json::json!({
+                "type": "content_block_start",
+                "index": index,
+                "content_block": { "type": "text", "text": "" }
+            })));
+            out.push(SseEvent::anthropic(serde_json::json!({
+                "type": "content_block_delta",
+                "index": index,
+                "delta": { "type": "text_delta", "text": sources }
+            })));
+            out.push(SseEvent::anthropic(serde_json::json!({
+                "type": "content_block_stop",
+                "index": index
+            })));
+        }
+        let stop_reason = self.stop_reason.clone().unwrap_or_else(|| "end_turn".to_string());
+        log_stop_reason(&self.provider, &self.model, self.upstream_reason.as_deref(), Some(&stop_reason));
+        if !self.usage_reported {
+            // Output tokens are then estimated from the streamed text downstream
+            tracing::warn!("📊 {} stream had no usageMetadata; estimating token usage", self.provider);
+        }
+        out.push(SseEvent::anthropic(serde_json::json!({
+            "type": "message_delta",
+            "delta": {
+                "stop_reason": stop_reason,
+                "stop_sequence": null
+            },
+            "usage": self.usage()
+        })));
+        out.push(SseEvent::anthropic(serde_json::json!({ "type": "message_stop" })));
+        out
+    }
+}
+
+#[async_trait]
+impl AnthropicProvider for GeminiProvider {
+    async fn send_message(&self, request: AnthropicRequest) -> Result<ProviderResponse, ProviderError> {
+        let Some(policy) = self.structured_output_validation else {
+            return self.generate_with_tool_args_retry(request).await;
+        };
+
+        let mut retried = false;
+        loop {
+            let response = self.generate_with_tool_args_retry(request.clone()).await?;
+            let violations = self.structured_output_violations(&request, &response).unwrap_or_default();
+            if violations.is_empty() {
+                return Ok(response);
+            }
+            tracing::warn!(
+                "🧪 Gemini structured output from {} doesn't match the schema: {}",
+                self.name,
+                violations.join("; ")
+            );
+            match policy {
+                StructuredOutputValidation::Pass => return Ok(response),
+                StructuredOutputValidation::Retry if !retried => retried = true,
+                _ => {
+                    return Err(ProviderError::SchemaViolation {
+                        target: "Gemini structured output".to_string(),
+                        violations,
+                    })
+                }
+            }
+        }
+    }
+
+    async fn send_message_stream(
+        &self,
+        mut request: AnthropicRequest,
+    ) -> Result<std::pin::Pin<Box<dyn futures::stream::Stream<Item = Result<bytes::Bytes, ProviderError>> + Send>>, ProviderError> {
+        use futures::TryStreamExt;
+
+        let model = request.model.clone();
+        self.inline_remote_images(&mut request).await?;
+        let structured_tool = self.schema_tool(&request).and_then(|t| t.name.clone());
+
+        // Check if using OAuth (Code Assist API)
+        if self.is_oauth() {
+            // Use Code Assist API streaming endpoint
+            let gemini_request = self.transform_request(&request)?;
+
+            // Get OAuth bearer token
+            let auth_header = self.get_auth_header().await?;
+            let bearer_token = auth_header.ok_or_else(|| {
+                ProviderError::AuthError("OAuth configured but no token available".to_string())
+            })?;
+
+            // Get project_id from token store
+            let project_id = if let (Some(oauth_provider_id), Some(token_store)) =
+                (&self.oauth_provider_id, &self.token_store) {
+                token_store
+                    .get(oauth_provider_id)
+                    .and_then(|token| token.project_id.clone())
+            } else {
                 None
             };
 
@@ -641,6 +1833,7 @@ impl AnthropicProvider for GeminiProvider {
                     system_instruction: gemini_request.system_instruction,
                     generation_config: gemini_request.generation_config,
                     tools: gemini_request.tools,
+                    safety_settings: gemini_request.safety_settings,
                     session_id: None, // Optional
                 },
             };
@@ -651,8 +1844,7 @@ impl AnthropicProvider for GeminiProvider {
             tracing::debug!("🔐 Using OAuth Code Assist API (streaming): {}", url);
 
             // Build request
-            let mut req_builder = self.client
-                .post(&url)
+            let mut req_builder = self.stream_post(&url)
                 .header("Content-Type", "application/json")
                 .header("Authorization", bearer_token);
 
@@ -661,6 +1853,10 @@ impl AnthropicProvider for GeminiProvider {
                 req_builder = req_builder.header(key, value);
             }
 
+            for (name, value) in request.upstream_headers() {
+                req_builder = req_builder.header(name, value);
+            }
+
             // Send request
             let response = req_builder.json(&code_assist_request).send().await?;
 
@@ -671,19 +1867,21 @@ impl AnthropicProvider for GeminiProvider {
                     .await
                     .unwrap_or_else(|_| "Unknown error".to_string());
                 tracing::error!("Code Assist API streaming error ({}): {}", status, error_text);
-                return Err(ProviderError::ApiError {
-                    status,
-                    message: error_text,
-                });
+                return Err(gemini_error(status, error_text));
             }
 
-            // Return the streaming response
-            // The Gemini API returns SSE format, just pass through the stream
+            check_content_type(response.headers(), ExpectedBody::Sse)?;
+
+            // Translate Gemini SSE chunks into Anthropic SSE events
             let stream = response.bytes_stream().map_err(|e| ProviderError::HttpError(e));
-            Ok(Box::pin(stream))
+            Ok(encode_events(translate_events(stream, GeminiStreamTranslator::new(self.name.clone(), model)
+                .with_structured_tool(structured_tool)
+                .with_preserve_response_id(self.preserve_response_id)
+                .with_estimated_input_tokens(estimate_input_tokens(&request)))))
         } else {
             // Use public Gemini API or Vertex AI streaming
-            let gemini_request = self.transform_request(&request)?;
+            let mut gemini_request = self.transform_request(&request)?;
+            self.apply_system_cache(&request, &mut gemini_request).await;
 
             // Build URL
             let url = if self.is_vertex_ai() {
@@ -711,16 +1909,36 @@ impl AnthropicProvider for GeminiProvider {
 
             tracing::debug!("📡 Using Gemini API (streaming): {}", url);
 
-            // Build request
-            let mut req_builder = self.client.post(&url).header("Content-Type", "application/json");
+            let mut recreated_cache = false;
+            let response = loop {
+                // Build request
+                let mut req_builder = self.stream_post(&url).header("Content-Type", "application/json");
+                if let Some(auth_header) = self.get_auth_header().await? {
+                    req_builder = req_builder.header("Authorization", auth_header);
+                }
 
-            // Add custom headers
-            for (key, value) in &self.custom_headers {
-                req_builder = req_builder.header(key, value);
-            }
+                // Add custom headers
+                for (key, value) in &self.custom_headers {
+                    req_builder = req_builder.header(key, value);
+                }
 
-            // Send request
-            let response = req_builder.json(&gemini_request).send().await?;
+                for (name, value) in request.upstream_headers() {
+                    req_builder = req_builder.header(name, value);
+                }
+
+                // Send request
+                let response = req_builder.json(&gemini_request).send().await?;
+
+                // The referenced cache expired: recreate it once and resend
+                if response.status().as_u16() == 404
+                    && !recreated_cache
+                    && self.recreate_system_cache(&request, &mut gemini_request).await?
+                {
+                    recreated_cache = true;
+                    continue;
+                }
+                break response;
+            };
 
             if !response.status().is_success() {
                 let status = response.status().as_u16();
@@ -729,31 +1947,174 @@ impl AnthropicProvider for GeminiProvider {
                     .await
                     .unwrap_or_else(|_| "Unknown error".to_string());
                 tracing::error!("Gemini API streaming error ({}): {}", status, error_text);
-                return Err(ProviderError::ApiError {
-                    status,
-                    message: error_text,
-                });
+                return Err(gemini_error(status, error_text));
             }
 
-            // Return the streaming response
+            check_content_type(response.headers(), ExpectedBody::Sse)?;
+
+            // Translate Gemini SSE chunks into Anthropic SSE events
             let stream = response.bytes_stream().map_err(|e| ProviderError::HttpError(e));
-            Ok(Box::pin(stream))
+            Ok(encode_events(translate_events(stream, GeminiStreamTranslator::new(self.name.clone(), model)
+                .with_structured_tool(structured_tool)
+                .with_preserve_response_id(self.preserve_response_id)
+                .with_estimated_input_tokens(estimate_input_tokens(&request)))))
         }
     }
 
     async fn count_tokens(
         &self,
-        _request: crate::models::CountTokensRequest,
+        request: crate::models::CountTokensRequest,
     ) -> Result<crate::models::CountTokensResponse, ProviderError> {
-        // TODO: Implement token counting for Gemini
-        Err(ProviderError::ConfigError(
-            "Token counting not yet implemented for Gemini".to_string(),
-        ))
+        // Count exactly what a real request would send
+        let mut body = serde_json::to_value(&request)?;
+        body["max_tokens"] = 1.into();
+        let mut full_request: AnthropicRequest = serde_json::from_value(body)?;
+        self.inline_remote_images(&mut full_request).await?;
+        let mut gemini_request = self.transform_request(&full_request)?;
+        gemini_request.generation_config = None;
+        gemini_request.safety_settings.clear();
+
+        let model = &request.model;
+        let (url, body, auth_header) = if self.is_oauth() {
+            // Code Assist API: https://cloudcode-pa.googleapis.com/v1internal:countTokens
+            let body = serde_json::json!({
+                "request": { "model": format!("models/{}", model), "contents": gemini_request.contents }
+            });
+            (format!("{}:countTokens", self.base_url), body, self.get_auth_header().await?)
+        } else if self.is_vertex_ai() {
+            let url = format!(
+                "{}/projects/{}/locations/{}/publishers/google/models/{}:countTokens",
+                self.base_url,
+                self.project_id.as_ref().unwrap(),
+                self.location.as_ref().unwrap(),
+                model
+            );
+            (url, serde_json::to_value(&gemini_request)?, self.get_auth_header().await?)
+        } else if let Some(api_key) = &self.api_key {
+            // System instruction and tools are only counted inside a generateContentRequest
+            let mut inner = serde_json::to_value(&gemini_request)?;
+            inner["model"] = format!("models/{}", model).into();
+            let url = format!("{}/models/{}:countTokens?key={}", self.base_url, model, api_key);
+            (url, serde_json::json!({ "generateContentRequest": inner }), None)
+        } else {
+            return Err(ProviderError::ConfigError(
+                "Gemini provider requires either api_key, OAuth, or Vertex AI configuration".to_string()
+            ));
+        };
+
+        let mut req_builder = self.client.post(&url).timeout(self.timeout).header("Content-Type", "application/json");
+        if let Some(auth_header) = auth_header {
+            req_builder = req_builder.header("Authorization", auth_header);
+        }
+        for (key, value) in &self.custom_headers {
+            req_builder = req_builder.header(key, value);
+        }
+        let response = req_builder.json(&body).send().await.map_err(|e| self.timeout_error(e))?;
+
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Unknown error".to_string());
+            tracing::error!("Gemini countTokens error ({}): {}", status, error_text);
+            return Err(gemini_error(status, error_text));
+        }
+
+        let count: GeminiCountTokensResponse = response.json().await?;
+        Ok(crate::models::CountTokensResponse {
+            input_tokens: count.total_tokens,
+        })
+    }
+
+    fn supports_embeddings(&self) -> bool {
+        !self.is_oauth()
+    }
+
+    async fn embed(&self, model: &str, text: &str) -> Result<Vec<f32>, ProviderError> {
+        let (url, body) = if self.is_oauth() {
+            return Err(ProviderError::ConfigError(
+                "Embeddings need an API key or Vertex AI; the Code Assist API used with OAuth has none".to_string()
+            ));
+        } else if self.is_vertex_ai() {
+            // Vertex AI serves embedding models through :predict
+            let url = format!(
+                "{}/projects/{}/locations/{}/publishers/google/models/{}:predict",
+                self.base_url,
+                self.project_id.as_ref().unwrap(),
+                self.location.as_ref().unwrap(),
+                model
+            );
+            (url, serde_json::json!({ "instances": [{ "content": text }] }))
+        } else if let Some(api_key) = &self.api_key {
+            let url = format!("{}/models/{}:embedContent?key={}", self.base_url, model, api_key);
+            let body = serde_json::json!({
+                "model": format!("models/{}", model),
+                "content": { "parts": [{ "text": text }] }
+            });
+            (url, body)
+        } else {
+            return Err(ProviderError::ConfigError(
+                "Gemini provider requires either api_key, OAuth, or Vertex AI configuration".to_string()
+            ));
+        };
+
+        let mut req_builder = self.client.post(&url).timeout(self.timeout).header("Content-Type", "application/json");
+        if let Some(auth_header) = self.get_auth_header().await? {
+            req_builder = req_builder.header("Authorization", auth_header);
+        }
+        for (key, value) in &self.custom_headers {
+            req_builder = req_builder.header(key, value);
+        }
+        let response = req_builder.json(&body).send().await.map_err(|e| self.timeout_error(e))?;
+
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Unknown error".to_string());
+            tracing::error!("Gemini embeddings error ({}): {}", status, error_text);
+            return Err(gemini_error(status, error_text));
+        }
+
+        let body: serde_json::Value = response.json().await?;
+        let values = body
+            .pointer("/embedding/values")
+            .or_else(|| body.pointer("/predictions/0/embeddings/values"))
+            .cloned()
+            .ok_or_else(|| ProviderError::ApiError {
+                status: 500,
+                message: format!("No embedding in Gemini response: {}", body),
+            })?;
+        Ok(serde_json::from_value(values)?)
     }
 
     fn supports_model(&self, model: &str) -> bool {
         self.models.contains(&model.to_string())
     }
+
+    /// `countTokens` on the first configured model: exercises auth (API key, OAuth
+    /// or Vertex AI) and the endpoint without generating anything
+    async fn health_check(&self) -> Result<(), ProviderError> {
+        let model = self.models.first().map(String::as_str).unwrap_or(HEALTH_CHECK_MODEL);
+        let request = serde_json::from_value(serde_json::json!({
+            "model": model,
+            "messages": [{ "role": "user", "content": "ping" }]
+        }))?;
+        self.count_tokens(request).await.map(|_| ())
+    }
+
+    fn request_schema_violations(&self, request: &AnthropicRequest) -> Vec<String> {
+        // Code Assist wraps the same body, so validating the inner request covers both
+        let body = self
+            .transform_request(request)
+            .and_then(|r| serde_json::to_value(&r).map_err(ProviderError::from));
+        match body {
+            Ok(body) => validation::validate_gemini_request(&body),
+            Err(e) => vec![format!("transform failed: {}", e)],
+        }
+    }
 }
 
 // Gemini API structures
@@ -768,9 +2129,14 @@ struct GeminiRequest {
     generation_config: Option<GeminiGenerationConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
     tools: Option<Vec<GeminiTool>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    safety_settings: Vec<SafetySetting>,
+    /// cachedContents resource name holding the system instruction and tools
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cached_content: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 struct GeminiContent {
     role: String,
     parts: Vec<GeminiPart>,
@@ -779,8 +2145,36 @@ struct GeminiContent {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 enum GeminiPart {
+    /// Thought summary (`"thought": true`), returned when includeThoughts is set
+    Thought { text: String, thought: bool },
     Text { text: String },
     InlineData { inline_data: GeminiInlineData },
+    /// Media uploaded through the Files API (or a `gs://` uri on Vertex)
+    FileData {
+        #[serde(rename = "fileData")]
+        file_data: GeminiFileData,
+    },
+    FunctionCall {
+        #[serde(rename = "functionCall")]
+        function_call: GeminiFunctionCall,
+    },
+    FunctionResponse {
+        #[serde(rename = "functionResponse")]
+        function_response: GeminiFunctionResponse,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct GeminiFunctionCall {
+    name: String,
+    #[serde(default)]
+    args: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct GeminiFunctionResponse {
+    name: String,
+    response: serde_json::Value,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -790,8 +2184,76 @@ struct GeminiInlineData {
     data: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiFileData {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mime_type: Option<String>,
+    file_uri: String,
+}
+
+/// Estimate prompt tokens from the request's text, for responses without usageMetadata
+fn estimate_input_tokens(request: &AnthropicRequest) -> u32 {
+    let mut chars = match &request.system {
+        Some(SystemPrompt::Text(text)) => text.len(),
+        Some(SystemPrompt::Blocks(blocks)) => blocks.iter().map(|b| b.text.len()).sum(),
+        None => 0,
+    };
+    for message in &request.messages {
+        chars += match &message.content {
+            MessageContent::Text(text) => text.len(),
+            MessageContent::Blocks(blocks) => blocks
+                .iter()
+                .map(|block| match block {
+                    ContentBlock::Text { text } => text.len(),
+                    ContentBlock::Thinking { thinking, .. } => thinking.len(),
+                    ContentBlock::ToolUse { input, .. } => input.to_string().len(),
+                    ContentBlock::ToolResult { content, .. } => content.to_string().len(),
+                    _ => 0,
+                })
+                .sum(),
+        };
+    }
+    estimate_tokens(chars)
+}
+
+/// Whether a url points at already-uploaded media Gemini can read directly
+fn is_file_uri(url: &str) -> bool {
+    url.starts_with("gs://") || (url.contains("generativelanguage.googleapis.com/") && url.contains("/files/"))
+}
+
+/// Gemini part for an audio or video `document` block: inline base64 data, or a
+/// Files API uri (`{"type": "file", "file_uri": ...}` or a `url` source)
+fn media_part(source: &serde_json::Value) -> Option<GeminiPart> {
+    let field = |name: &str| source.get(name).and_then(|v| v.as_str());
+    let mime_type = field("media_type").or_else(|| field("mime_type"));
+    match field("type")? {
+        "base64" => {
+            let mime_type = mime_type.filter(|m| m.starts_with("audio/") || m.starts_with("video/"))?;
+            Some(GeminiPart::InlineData {
+                inline_data: GeminiInlineData {
+                    mime_type: mime_type.to_string(),
+                    data: field("data")?.to_string(),
+                },
+            })
+        }
+        "file" | "url" => {
+            let file_uri = field("file_uri").or_else(|| field("url").filter(|url| is_file_uri(url)))?;
+            Some(GeminiPart::FileData {
+                file_data: GeminiFileData {
+                    mime_type: mime_type.map(str::to_string),
+                    file_uri: file_uri.to_string(),
+                },
+            })
+        }
+        _ => None,
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 struct GeminiSystemInstruction {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<String>,
     parts: Vec<GeminiPart>,
 }
 
@@ -808,6 +2270,22 @@ struct GeminiGenerationConfig {
     max_output_tokens: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     stop_sequences: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_mime_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_schema: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    candidate_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking_config: Option<GeminiThinkingConfig>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiThinkingConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking_budget: Option<i32>,
+    include_thoughts: bool,
 }
 
 /// Gemini Tool supports multiple tool types via protobuf oneof
@@ -844,81 +2322,336 @@ struct GoogleSearchTool {}
 #[derive(Debug, Clone, Serialize)]
 struct UrlContextTool {}
 
+/// Top-level fields of a generateContent response we expect to see
+const GEMINI_RESPONSE_FIELDS: &[&str] = &[
+    "candidates", "usageMetadata", "modelVersion", "responseId", "promptFeedback",
+];
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct GeminiResponse {
     candidates: Vec<GeminiCandidate>,
     #[serde(skip_serializing_if = "Option::is_none")]
     usage_metadata: Option<GeminiUsageMetadata>,
+    #[serde(default)]
+    response_id: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct GeminiCandidate {
+    // Omitted when the candidate was blocked
+    #[serde(default)]
     content: GeminiContent,
     #[serde(skip_serializing_if = "Option::is_none")]
     finish_reason: Option<String>,
+    #[serde(default)]
+    finish_message: Option<String>,
+    #[serde(default)]
+    safety_ratings: Vec<GeminiSafetyRating>,
+    #[serde(default)]
+    avg_logprobs: Option<f64>,
+    /// Tokens generated for this candidate (not always reported)
+    #[serde(default)]
+    token_count: Option<i32>,
+    /// Web sources used by Google Search grounding
+    #[serde(default)]
+    grounding_metadata: Option<GeminiGroundingMetadata>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct GeminiUsageMetadata {
-    prompt_token_count: Option<i32>,
-    candidates_token_count: Option<i32>,
-    total_token_count: Option<i32>,
+struct GeminiGroundingMetadata {
+    #[serde(default)]
+    grounding_chunks: Vec<GeminiGroundingChunk>,
 }
 
-// Code Assist API structures (for OAuth)
-
-#[derive(Debug, Clone, Serialize)]
-struct CodeAssistRequest {
-    model: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    project: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    user_prompt_id: Option<String>,
-    request: CodeAssistInnerRequest,
+#[derive(Debug, Deserialize)]
+struct GeminiGroundingChunk {
+    #[serde(default)]
+    web: Option<GeminiWebSource>,
 }
 
-#[derive(Debug, Clone, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct CodeAssistInnerRequest {
-    contents: Vec<GeminiContent>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    system_instruction: Option<GeminiSystemInstruction>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    generation_config: Option<GeminiGenerationConfig>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    tools: Option<Vec<GeminiTool>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    session_id: Option<String>,
+#[derive(Debug, Deserialize)]
+struct GeminiWebSource {
+    uri: String,
+    #[serde(default)]
+    title: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct CodeAssistResponse {
-    response: GeminiResponse,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    trace_id: Option<String>,
+struct GeminiSafetyRating {
+    category: String,
+    #[serde(default)]
+    probability: Option<String>,
+    #[serde(default)]
+    blocked: bool,
 }
 
-// Error response structures for rate limiting
-
-#[derive(Debug, Deserialize)]
-struct GeminiErrorResponse {
-    error: GeminiError,
+impl GeminiSafetyRating {
+    /// Blocked, or rated above NEGLIGIBLE/LOW
+    fn is_flagged(&self) -> bool {
+        self.blocked || matches!(self.probability.as_deref(), Some("MEDIUM" | "HIGH"))
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct GeminiError {
-    code: u16,
-    message: String,
-    status: String,
-    #[serde(default)]
-    details: Vec<GeminiErrorDetail>,
+/// Text of a tool result; images are sent separately
+fn tool_result_text(blocks: &[ToolResultBlock]) -> String {
+    blocks
+        .iter()
+        .filter_map(|b| match b {
+            ToolResultBlock::Text { text } => Some(text.as_str()),
+            ToolResultBlock::Image { .. } => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
 }
 
-#[derive(Debug, Deserialize)]
+/// Names of tool calls whose args are malformed (not an object), or missing/empty
+/// while the tool's schema has required properties
+fn incomplete_tool_calls(request: &AnthropicRequest, response: &ProviderResponse) -> Vec<String> {
+    let requires_args = |name: &str| {
+        request
+            .tools
+            .iter()
+            .flatten()
+            .find(|tool| tool.name.as_deref() == Some(name))
+            .and_then(|tool| tool.input_schema.as_ref()?.get("required")?.as_array())
+            .is_some_and(|required| !required.is_empty())
+    };
+
+    response
+        .content
+        .iter()
+        .filter_map(|block| match block {
+            ContentBlock::ToolUse { name, input, .. } => {
+                let empty = match input {
+                    serde_json::Value::Null => true,
+                    serde_json::Value::Object(args) => args.is_empty(),
+                    _ => return Some(name.clone()),
+                };
+                (empty && requires_args(name)).then(|| name.clone())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Ask for the named tool calls again with their arguments, in the last user turn
+fn nudge_for_tool_args(request: &mut AnthropicRequest, tools: &[String]) {
+    let nudge = format!(
+        "Your previous call to {} was missing its arguments. Call it again with every required argument filled in.",
+        tools.join(", ")
+    );
+    match request.messages.last_mut() {
+        Some(Message { role, content: MessageContent::Blocks(blocks) }) if role == "user" => {
+            blocks.push(ContentBlock::Text { text: nudge });
+        }
+        Some(Message { role, content: MessageContent::Text(text) }) if role == "user" => {
+            text.push_str("\n\n");
+            text.push_str(&nudge);
+        }
+        _ => request.messages.push(Message {
+            role: "user".to_string(),
+            content: MessageContent::Text(nudge),
+        }),
+    }
+}
+
+/// Stable Anthropic-style id for a Gemini function call, which carries no id of its own.
+/// Derived from the call and its response's `response_key` (SHA-256, so it holds across
+/// builds): a replayed response gets the same id, the same call in a later turn doesn't.
+fn tool_use_id(response_key: &str, name: &str, args: &serde_json::Value, index: usize) -> String {
+    use sha2::{Digest, Sha256};
+    let digest = Sha256::new()
+        .chain_update(response_key)
+        .chain_update([0])
+        .chain_update(name)
+        .chain_update([0])
+        .chain_update(args.to_string())
+        .chain_update([0])
+        .chain_update(index.to_string())
+        .finalize();
+    let hex: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("toolu_{}", hex)
+}
+
+/// Per-response input to `tool_use_id`: Gemini's responseId, or a random nonce without one
+fn response_key(response_id: Option<&str>) -> String {
+    response_id
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()))
+}
+
+/// Anthropic stop_reason for a Gemini finishReason (function calls are handled by
+/// the caller). Output withheld by Gemini (SAFETY, RECITATION, ...) becomes "refusal";
+/// anything other than STOP/MAX_TOKENS is logged since the output may be incomplete.
+fn stop_reason_for(reason: &str) -> &'static str {
+    match reason {
+        "STOP" | "FINISH_REASON_UNSPECIFIED" => return "end_turn",
+        "MAX_TOKENS" => return "max_tokens",
+        _ => {}
+    }
+    tracing::warn!("⚠️ Gemini finished with {}", reason);
+    if is_blocked_finish(reason) {
+        "refusal"
+    } else {
+        // MALFORMED_FUNCTION_CALL, OTHER, LANGUAGE, ...
+        "end_turn"
+    }
+}
+
+/// finishReasons for which Gemini withheld the output
+fn is_blocked_finish(reason: &str) -> bool {
+    matches!(
+        reason,
+        "SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII" | "IMAGE_SAFETY"
+    )
+}
+
+/// Markdown list of the web sources a grounded answer cites, or None without any
+fn grounding_sources(metadata: &GeminiGroundingMetadata) -> Option<String> {
+    let mut seen = std::collections::HashSet::new();
+    let sources: Vec<String> = metadata
+        .grounding_chunks
+        .iter()
+        .filter_map(|chunk| chunk.web.as_ref())
+        .filter(|web| seen.insert(web.uri.as_str()))
+        .enumerate()
+        .map(|(i, web)| format!("{}. [{}]({})", i + 1, web.title.as_deref().unwrap_or(&web.uri), web.uri))
+        .collect();
+    (!sources.is_empty()).then(|| format!("Sources:\n{}", sources.join("\n")))
+}
+
+/// Human-readable note explaining an unusual finish (block, truncation) or flagged
+/// safety ratings. None for ordinary responses.
+fn safety_note(candidate: &GeminiCandidate) -> Option<String> {
+    let unusual_finish = candidate
+        .finish_reason
+        .as_deref()
+        .filter(|r| !matches!(*r, "STOP" | "FINISH_REASON_UNSPECIFIED"));
+    let flagged: Vec<String> = candidate
+        .safety_ratings
+        .iter()
+        .filter(|r| r.is_flagged())
+        .map(|r| {
+            let category = r.category.trim_start_matches("HARM_CATEGORY_");
+            let probability = r.probability.as_deref().unwrap_or("UNKNOWN");
+            if r.blocked {
+                format!("{} ({}, blocked)", category, probability)
+            } else {
+                format!("{} ({})", category, probability)
+            }
+        })
+        .collect();
+
+    if unusual_finish.is_none() && candidate.finish_message.is_none() && flagged.is_empty() {
+        return None;
+    }
+
+    let mut parts = Vec::new();
+    if let Some(reason) = unusual_finish {
+        parts.push(format!("finish reason: {}", reason));
+    }
+    if let Some(ref message) = candidate.finish_message {
+        parts.push(message.clone());
+    }
+    if !flagged.is_empty() {
+        parts.push(format!("safety ratings: {}", flagged.join(", ")));
+    }
+    Some(format!("[Gemini {}]", parts.join("; ")))
+}
+
+/// Output tokens across every generated candidate, all of which are billed
+/// regardless of which ones are returned. `candidatesTokenCount` is the total;
+/// per-candidate `tokenCount`s stand in when it is missing or only covers the first.
+fn output_tokens(response: &GeminiResponse) -> u32 {
+    let per_candidate: i32 = response.candidates.iter().filter_map(|c| c.token_count).sum();
+    let reported = response.usage_metadata.as_ref().and_then(|u| {
+        u.candidates_token_count.or_else(|| {
+            let total = u.total_token_count?;
+            Some(total - u.prompt_token_count.unwrap_or(0) - u.thoughts_token_count.unwrap_or(0))
+        })
+    });
+    reported.unwrap_or(0).max(per_candidate).max(0) as u32
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiUsageMetadata {
+    prompt_token_count: Option<i32>,
+    candidates_token_count: Option<i32>,
+    total_token_count: Option<i32>,
+    #[serde(default)]
+    thoughts_token_count: Option<i32>,
+    /// Part of the prompt read from cachedContents
+    #[serde(default)]
+    cached_content_token_count: Option<i32>,
+}
+
+// Code Assist API structures (for OAuth)
+
+#[derive(Debug, Clone, Serialize)]
+struct CodeAssistRequest {
+    model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    project: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_prompt_id: Option<String>,
+    request: CodeAssistInnerRequest,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CodeAssistInnerRequest {
+    contents: Vec<GeminiContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<GeminiSystemInstruction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generation_config: Option<GeminiGenerationConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<GeminiTool>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    safety_settings: Vec<SafetySetting>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiCountTokensResponse {
+    #[serde(default)]
+    total_tokens: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CodeAssistResponse {
+    response: GeminiResponse,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trace_id: Option<String>,
+}
+
+// Error response structures for rate limiting
+
+#[derive(Debug, Deserialize)]
+struct GeminiErrorResponse {
+    error: GeminiError,
+}
+
+#[derive(Debug, Deserialize)]
+struct GeminiError {
+    #[serde(default)]
+    code: u16,
+    message: String,
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    details: Vec<GeminiErrorDetail>,
+}
+
+#[derive(Debug, Deserialize)]
 #[serde(tag = "@type")]
 enum GeminiErrorDetail {
     #[serde(rename = "type.googleapis.com/google.rpc.RetryInfo")]
@@ -933,10 +2666,60 @@ enum GeminiErrorDetail {
         #[serde(default)]
         metadata: HashMap<String, String>,
     },
+    #[serde(rename = "type.googleapis.com/google.rpc.QuotaFailure")]
+    QuotaFailure {
+        #[serde(default)]
+        violations: Vec<GeminiQuotaViolation>,
+    },
     #[serde(other)]
     Unknown,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiQuotaViolation {
+    #[serde(default)]
+    quota_id: String,
+}
+
+/// Typed error for a failed Gemini call, from the `{"error": {"status": ...}}` body.
+/// Bodies that aren't a Google API error stay a plain `ApiError`.
+fn gemini_error(status: u16, error_text: String) -> ProviderError {
+    // Code Assist sometimes wraps the error in an array
+    let parsed = serde_json::from_str::<GeminiErrorResponse>(&error_text)
+        .ok()
+        .or_else(|| serde_json::from_str::<Vec<GeminiErrorResponse>>(&error_text).ok()?.into_iter().next());
+    let Some(GeminiErrorResponse { error }) = parsed else {
+        return ProviderError::ApiError { status, message: error_text };
+    };
+
+    let code = if error.code == 0 { status } else { error.code };
+    let kind = match (error.status.as_str(), code) {
+        ("", 429) => "RESOURCE_EXHAUSTED",
+        ("", 400) => "INVALID_ARGUMENT",
+        ("", 401) => "UNAUTHENTICATED",
+        ("", 403) => "PERMISSION_DENIED",
+        (kind, _) => kind,
+    };
+    match kind {
+        "RESOURCE_EXHAUSTED" => {
+            // Per-day quotas don't recover by waiting a few seconds
+            let daily = error.details.iter().any(|detail| match detail {
+                GeminiErrorDetail::QuotaFailure { violations } => violations.iter().any(|v| v.quota_id.contains("PerDay")),
+                _ => false,
+            });
+            if daily {
+                ProviderError::QuotaExceeded(error.message)
+            } else {
+                ProviderError::RateLimited(error.message)
+            }
+        }
+        "INVALID_ARGUMENT" | "FAILED_PRECONDITION" | "OUT_OF_RANGE" => ProviderError::InvalidRequest(error.message),
+        "UNAUTHENTICATED" | "PERMISSION_DENIED" => ProviderError::AuthError(error.message),
+        _ => ProviderError::ApiError { status: code, message: error.message },
+    }
+}
+
 /// Parse retry delay from Google's duration format (e.g., "3.020317815s", "60s", "900ms")
 fn parse_retry_delay(duration: &str) -> Option<std::time::Duration> {
     if let Some(ms_str) = duration.strip_suffix("ms") {
@@ -948,6 +2731,16 @@ fn parse_retry_delay(duration: &str) -> Option<std::time::Duration> {
     }
 }
 
+/// Delay requested by a `Retry-After` header (seconds or an HTTP date)
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (at.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().ok()
+}
+
 /// Extract retry delay from 429 error response
 fn extract_retry_delay(error_text: &str) -> Option<std::time::Duration> {
     // Try to parse as JSON error response
@@ -982,3 +2775,1627 @@ fn extract_retry_delay(error_text: &str) -> Option<std::time::Duration> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Message, SystemBlock};
+    use mockito::Matcher;
+
+    fn create_request(user_text: &str) -> AnthropicRequest {
+        AnthropicRequest {
+            model: "gemini-2.5-pro".to_string(),
+            messages: vec![Message {
+                role: "user".to_string(),
+                content: MessageContent::Text(user_text.to_string()),
+            }],
+            max_tokens: 16,
+            thinking: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
+            stream: None,
+            metadata: None,
+            system: Some(SystemPrompt::Blocks(vec![SystemBlock {
+                r#type: "text".to_string(),
+                text: "You are a very long system prompt.".to_string(),
+                cache_control: Some(serde_json::json!({ "type": "ephemeral" })),
+            }])),
+            tools: None,
+            tool_choice: None,
+            betas: None,
+            stream_options: None,
+            api_version: None,
+            output_format: None,
+            request_id_header: None,
+            capture_raw: false,
+            forwarded_headers: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_url_images_fetched_and_inlined() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/cat.png")
+            .with_status(200)
+            .with_header("content-type", "image/png; charset=binary")
+            .with_body(b"\x89PNG")
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/huge.png")
+            .with_status(200)
+            .with_header("content-type", "image/png")
+            .with_body(vec![0u8; 64])
+            .create_async()
+            .await;
+        server.mock("GET", "/missing.png").with_status(404).create_async().await;
+        server
+            .mock("GET", "/page.png")
+            .with_status(200)
+            .with_header("content-type", "text/html")
+            .with_body("<html></html>")
+            .create_async()
+            .await;
+
+        let provider = GeminiProvider::new(
+            "gemini".to_string(),
+            Some("key".to_string()),
+            None,
+            vec![],
+            HashMap::new(),
+            None,
+            None,
+            None,
+            None,
+        )
+        .with_max_image_bytes(Some(32));
+        let request = |path: &str| -> AnthropicRequest {
+            serde_json::from_value(serde_json::json!({
+                "model": "gemini-2.5-pro",
+                "max_tokens": 16,
+                "messages": [{ "role": "user", "content": [
+                    { "type": "image", "source": { "type": "url", "url": format!("{}{}", server.url(), path) } },
+                    { "type": "text", "text": "What is this?" }
+                ] }]
+            }))
+            .unwrap()
+        };
+
+        // The mock server is on loopback, which is refused by default
+        match provider.inline_remote_images(&mut request("/cat.png")).await {
+            Err(ProviderError::ApiError { message, .. }) => assert!(message.contains("not a public address"), "{}", message),
+            other => panic!("expected loopback to be refused, got {:?}", other),
+        }
+        let provider = provider.with_private_image_urls(true);
+
+        let mut inlined = request("/cat.png");
+        provider.inline_remote_images(&mut inlined).await.unwrap();
+        let body = serde_json::to_value(provider.transform_request(&inlined).unwrap()).unwrap();
+        assert_eq!(
+            body["contents"][0]["parts"][0],
+            serde_json::json!({ "inline_data": { "mimeType": "image/png", "data": "iVBORw==" } })
+        );
+
+        for path in ["/huge.png", "/missing.png", "/page.png"] {
+            match provider.inline_remote_images(&mut request(path)).await {
+                Err(ProviderError::ApiError { message, .. }) => assert!(message.contains("Failed to fetch image")),
+                other => panic!("expected a fetch error for {}, got {:?}", path, other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_audio_video_and_file_parts() {
+        let provider = GeminiProvider::new(
+            "gemini".to_string(),
+            Some("key".to_string()),
+            None,
+            vec![],
+            HashMap::new(),
+            None,
+            None,
+            None,
+            None,
+        );
+        let file_uri = "https://generativelanguage.googleapis.com/v1beta/files/abc123";
+        let mut request: AnthropicRequest = serde_json::from_value(serde_json::json!({
+            "model": "gemini-2.5-pro",
+            "max_tokens": 16,
+            "messages": [{ "role": "user", "content": [
+                { "type": "document", "source": { "type": "base64", "media_type": "audio/wav", "data": "UklGRg==" } },
+                { "type": "document", "source": { "type": "file", "file_uri": "gs://bucket/clip.mp4", "media_type": "video/mp4" } },
+                { "type": "image", "source": { "type": "url", "url": file_uri } },
+                // Not audio/video: still ignored
+                { "type": "document", "source": { "type": "base64", "media_type": "application/pdf", "data": "JVBERg==" } },
+                { "type": "text", "text": "Describe these" }
+            ] }]
+        }))
+        .unwrap();
+
+        // Files API uris are left for Gemini to read, not downloaded
+        provider.inline_remote_images(&mut request).await.unwrap();
+        let body = serde_json::to_value(provider.transform_request(&request).unwrap()).unwrap();
+        assert_eq!(
+            body["contents"][0]["parts"],
+            serde_json::json!([
+                { "inline_data": { "mimeType": "audio/wav", "data": "UklGRg==" } },
+                { "fileData": { "mimeType": "video/mp4", "fileUri": "gs://bucket/clip.mp4" } },
+                { "fileData": { "fileUri": file_uri } },
+                { "text": "Describe these" }
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_system_cache_reference_stable_across_messages() {
+        let mut server = mockito::Server::new_async().await;
+        let cache_mock = server
+            .mock("POST", "/cachedContents")
+            .match_query(Matcher::Any)
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "model": "models/gemini-2.5-pro",
+                "systemInstruction": { "parts": [{ "text": "You are a very long system prompt." }] }
+            })))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"name":"cachedContents/sys123"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let generate_mock = server
+            .mock("POST", "/models/gemini-2.5-pro:generateContent")
+            .match_query(Matcher::Any)
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "cachedContent": "cachedContents/sys123"
+            })))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"ok"}]},"finishReason":"STOP"}],
+                    "usageMetadata":{"promptTokenCount":5,"candidatesTokenCount":1,"totalTokenCount":6}}"#,
+            )
+            .expect(2)
+            .create_async()
+            .await;
+
+        let provider = GeminiProvider::new(
+            "gemini".to_string(),
+            Some("key".to_string()),
+            Some(server.url()),
+            vec!["gemini-2.5-pro".to_string()],
+            HashMap::new(),
+            None,
+            None,
+            None,
+            None,
+        );
+
+        // Different messages each turn, same system prompt
+        provider.send_message(create_request("first question")).await.unwrap();
+        provider.send_message(create_request("second question")).await.unwrap();
+
+        cache_mock.assert_async().await;
+        generate_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_expired_system_cache_recreated() {
+        let mut server = mockito::Server::new_async().await;
+        let first_cache = server
+            .mock("POST", "/cachedContents")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"name":"cachedContents/old"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let second_cache = server
+            .mock("POST", "/cachedContents")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"name":"cachedContents/new"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        // The first cache has expired upstream
+        let expired = server
+            .mock("POST", "/models/gemini-2.5-pro:generateContent")
+            .match_query(Matcher::Any)
+            .match_body(Matcher::PartialJson(serde_json::json!({ "cachedContent": "cachedContents/old" })))
+            .with_status(404)
+            .with_body(r#"{"error":{"code":404,"message":"CachedContent not found","status":"NOT_FOUND"}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let generate = server
+            .mock("POST", "/models/gemini-2.5-pro:generateContent")
+            .match_query(Matcher::Any)
+            .match_body(Matcher::PartialJson(serde_json::json!({ "cachedContent": "cachedContents/new" })))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"ok"}]},"finishReason":"STOP"}],
+                    "usageMetadata":{"promptTokenCount":5,"candidatesTokenCount":1,"totalTokenCount":6}}"#,
+            )
+            .expect(2)
+            .create_async()
+            .await;
+
+        let provider = GeminiProvider::new(
+            "gemini".to_string(),
+            Some("key".to_string()),
+            Some(server.url()),
+            vec!["gemini-2.5-pro".to_string()],
+            HashMap::new(),
+            None,
+            None,
+            None,
+            None,
+        );
+
+        provider.send_message(create_request("first question")).await.unwrap();
+        // The recreated cache is reused afterwards
+        provider.send_message(create_request("second question")).await.unwrap();
+
+        first_cache.assert_async().await;
+        second_cache.assert_async().await;
+        expired.assert_async().await;
+        generate.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_empty_tool_args_retried_once() {
+        let mut server = mockito::Server::new_async().await;
+        let empty_args = server
+            .mock("POST", "/models/gemini-2.5-pro:generateContent")
+            .match_query(Matcher::Any)
+            .match_body(Matcher::Regex(r#""text":"Weather in Paris\?""#.to_string()))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"candidates":[{"content":{"role":"model","parts":[{"functionCall":{"name":"get_weather","args":{}}}]},"finishReason":"STOP"}],
+                    "usageMetadata":{"promptTokenCount":20,"candidatesTokenCount":3,"totalTokenCount":23}}"#,
+            )
+            .expect(1)
+            .create_async()
+            .await;
+        let nudged = server
+            .mock("POST", "/models/gemini-2.5-pro:generateContent")
+            .match_query(Matcher::Any)
+            .match_body(Matcher::Regex("previous call to get_weather was missing its arguments".to_string()))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"candidates":[{"content":{"role":"model","parts":[{"functionCall":{"name":"get_weather","args":{"city":"Paris"}}}]},"finishReason":"STOP"}],
+                    "usageMetadata":{"promptTokenCount":40,"candidatesTokenCount":5,"totalTokenCount":45}}"#,
+            )
+            .expect(1)
+            .create_async()
+            .await;
+
+        let provider = GeminiProvider::new(
+            "gemini".to_string(),
+            Some("key".to_string()),
+            Some(server.url()),
+            vec!["gemini-2.5-pro".to_string()],
+            HashMap::new(),
+            None,
+            None,
+            None,
+            None,
+        )
+        .with_empty_tool_args_retry(true);
+        let request: AnthropicRequest = serde_json::from_value(serde_json::json!({
+            "model": "gemini-2.5-pro",
+            "max_tokens": 64,
+            "messages": [{ "role": "user", "content": "Weather in Paris?" }],
+            "tools": [{
+                "name": "get_weather",
+                "description": "Current weather for a city",
+                "input_schema": {
+                    "type": "object",
+                    "properties": { "city": { "type": "string" } },
+                    "required": ["city"]
+                }
+            }]
+        }))
+        .unwrap();
+
+        let response = provider.send_message(request).await.unwrap();
+
+        empty_args.assert_async().await;
+        nudged.assert_async().await;
+        let [ContentBlock::ToolUse { name, input, .. }] = response.content.as_slice() else {
+            panic!("expected a single tool call, got {:?}", response.content);
+        };
+        assert_eq!(name, "get_weather");
+        assert_eq!(input["city"], "Paris");
+        assert_eq!(response.usage.input_tokens, 60);
+        assert_eq!(response.usage.output_tokens, 8);
+    }
+
+    #[tokio::test]
+    async fn test_structured_output_schema_mismatch_flagged() {
+        let mut server = mockito::Server::new_async().await;
+        // Valid JSON, but `population` should be an integer and `country` is required
+        let generate = server
+            .mock("POST", "/models/gemini-2.5-pro:generateContent")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                serde_json::json!({
+                    "candidates": [{
+                        "content": { "role": "model", "parts": [{ "text": "{\"city\":\"Seoul\",\"population\":\"9.4M\"}" }] },
+                        "finishReason": "STOP"
+                    }]
+                })
+                .to_string(),
+            )
+            .expect(4)
+            .create_async()
+            .await;
+
+        let provider = |validation: StructuredOutputValidation| {
+            GeminiProvider::new(
+                "gemini".to_string(),
+                Some("key".to_string()),
+                Some(server.url()),
+                vec!["gemini-2.5-pro".to_string()],
+                HashMap::new(),
+                None,
+                None,
+                None,
+                None,
+            )
+            .with_structured_output_validation(Some(validation))
+        };
+        let mut request = create_request("Describe Seoul");
+        request.output_format = Some(serde_json::json!({
+            "type": "json_schema",
+            "schema": {
+                "type": "object",
+                "properties": {
+                    "city": { "type": "string" },
+                    "country": { "type": "string" },
+                    "population": { "type": "integer" }
+                },
+                "required": ["city", "country", "population"]
+            }
+        }));
+
+        let err = provider(StructuredOutputValidation::Error).send_message(request.clone()).await.unwrap_err();
+        match err {
+            ProviderError::SchemaViolation { violations, .. } => {
+                assert_eq!(violations, vec!["/country: missing", "/population: expected integer"]);
+            }
+            other => panic!("expected a schema violation, got {}", other),
+        }
+
+        // Retry asks once more before failing
+        let err = provider(StructuredOutputValidation::Retry).send_message(request.clone()).await.unwrap_err();
+        assert!(matches!(err, ProviderError::SchemaViolation { .. }));
+
+        let response = provider(StructuredOutputValidation::Pass).send_message(request).await.unwrap();
+        assert!(matches!(&response.content[0], ContentBlock::Text { text } if text.contains("9.4M")));
+        generate.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_transient_errors_retried_with_backoff() {
+        let mut server = mockito::Server::new_async().await;
+        let ok_body = serde_json::json!({
+            "candidates": [{ "content": { "role": "model", "parts": [{ "text": "ok" }] }, "finishReason": "STOP" }]
+        })
+        .to_string();
+        let path = |model: &str| format!("/models/{}:generateContent", model);
+
+        // Two 503s (one with Retry-After), then success
+        let unavailable = server
+            .mock("POST", path("gemini-2.5-pro").as_str())
+            .match_query(Matcher::Any)
+            .with_status(503)
+            .with_header("retry-after", "0")
+            .with_body(r#"{"error":{"code":503,"message":"The model is overloaded"}}"#)
+            .expect(2)
+            .create_async()
+            .await;
+        let recovered = server
+            .mock("POST", path("gemini-2.5-pro").as_str())
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(&ok_body)
+            .expect(1)
+            .create_async()
+            .await;
+        let bad_request = server
+            .mock("POST", path("gemini-2.5-flash").as_str())
+            .match_query(Matcher::Any)
+            .with_status(400)
+            .with_body(r#"{"error":{"code":400,"message":"Invalid argument"}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let rate_limited = server
+            .mock("POST", path("gemini-2.0-flash").as_str())
+            .match_query(Matcher::Any)
+            .with_status(429)
+            .with_body(r#"{"error":{"code":429,"message":"Resource exhausted"}}"#)
+            .expect(3)
+            .create_async()
+            .await;
+        let long_wait = server
+            .mock("POST", path("gemini-1.5-pro").as_str())
+            .match_query(Matcher::Any)
+            .with_status(429)
+            .with_header("retry-after", "3600")
+            .with_body(r#"{"error":{"code":429,"message":"Resource exhausted"}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let provider = GeminiProvider::new(
+            "gemini".to_string(),
+            Some("key".to_string()),
+            Some(server.url()),
+            vec![],
+            HashMap::new(),
+            None,
+            None,
+            None,
+            None,
+        )
+        .with_retries(Some(2), Some(1));
+        let request = |model: &str| AnthropicRequest {
+            model: model.to_string(),
+            ..create_request("hi")
+        };
+
+        let response = provider.send_message(request("gemini-2.5-pro")).await.unwrap();
+        assert!(matches!(&response.content[0], ContentBlock::Text { text } if text == "ok"));
+        unavailable.assert_async().await;
+        recovered.assert_async().await;
+
+        // Client errors fail fast
+        let err = provider.send_message(request("gemini-2.5-flash")).await.unwrap_err();
+        assert!(matches!(err, ProviderError::InvalidRequest(ref m) if m == "Invalid argument"));
+        bad_request.assert_async().await;
+
+        // Retries run out: the last error is returned
+        let err = provider.send_message(request("gemini-2.0-flash")).await.unwrap_err();
+        assert!(matches!(err, ProviderError::RateLimited(_)));
+        rate_limited.assert_async().await;
+
+        // A Retry-After over the limit fails right away instead of waiting
+        let started = std::time::Instant::now();
+        let err = provider.send_message(request("gemini-1.5-pro")).await.unwrap_err();
+        assert!(matches!(err, ProviderError::RateLimited(_)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        long_wait.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_call_timeout_spares_streams() {
+        // Accepts connections but never answers
+        let silent = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let silent_url = format!("http://{}", silent.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((socket, _)) = silent.accept().await {
+                held.push(socket);
+            }
+        });
+        let provider = |base_url: String| {
+            let mut provider = GeminiProvider::new(
+                "gemini".to_string(),
+                Some("key".to_string()),
+                Some(base_url),
+                vec![],
+                HashMap::new(),
+                None,
+                None,
+                None,
+                None,
+            )
+            .with_retries(Some(0), None);
+            provider.timeout = Duration::from_millis(200);
+            provider
+        };
+        let mut request = create_request("hi");
+        request.system = None;
+
+        let err = provider(silent_url).send_message(request.clone()).await.unwrap_err();
+        assert!(matches!(err, ProviderError::Timeout(_)), "{:?}", err);
+
+        // A stream taking longer than the call timeout still completes
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/models/gemini-2.5-pro:streamGenerateContent")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_chunked_body(|w| {
+                let chunk = |text: &str| {
+                    format!("data: {{\"candidates\":[{{\"content\":{{\"role\":\"model\",\"parts\":[{{\"text\":\"{}\"}}]}}}}]}}\n\n", text)
+                };
+                w.write_all(chunk("slow").as_bytes())?;
+                w.flush()?;
+                std::thread::sleep(Duration::from_millis(400));
+                w.write_all(chunk(" answer").as_bytes())
+            })
+            .create_async()
+            .await;
+        request.stream = Some(true);
+        let stream = provider(server.url()).send_message_stream(request).await.unwrap();
+        let chunks: Vec<bytes::Bytes> = futures::TryStreamExt::try_collect(stream).await.unwrap();
+        assert!(String::from_utf8(chunks.concat()).unwrap().contains(" answer"));
+    }
+
+    #[test]
+    fn test_tool_use_id_fixed_across_builds() {
+        let args = serde_json::json!({ "city": "Paris" });
+        // Pinned: ids replayed from earlier responses must keep matching
+        assert_eq!(tool_use_id("resp-1", "get_weather", &args, 0), "toolu_fa2139d4e0a1d651");
+        assert_ne!(tool_use_id("resp-1", "get_weather", &args, 1), tool_use_id("resp-1", "get_weather", &args, 0));
+        // The same call in a later response gets a new id
+        assert_ne!(tool_use_id("resp-2", "get_weather", &args, 0), tool_use_id("resp-1", "get_weather", &args, 0));
+        assert_ne!(response_key(None), response_key(None));
+    }
+
+    #[test]
+    fn test_structured_errors_typed() {
+        let error = |status: u16, body: &str| gemini_error(status, body.to_string());
+
+        let per_minute = r#"{"error":{"code":429,"status":"RESOURCE_EXHAUSTED","message":"Too many requests",
+            "details":[{"@type":"type.googleapis.com/google.rpc.QuotaFailure","violations":[{"quotaId":"GenerateRequestsPerMinutePerProjectPerModel"}]}]}}"#;
+        assert!(matches!(error(429, per_minute), ProviderError::RateLimited(m) if m == "Too many requests"));
+        let per_day = per_minute.replace("PerMinute", "PerDay");
+        assert!(matches!(error(429, &per_day), ProviderError::QuotaExceeded(_)));
+
+        let invalid = r#"{"error":{"code":400,"status":"INVALID_ARGUMENT","message":"Unknown field"}}"#;
+        assert!(matches!(error(400, invalid), ProviderError::InvalidRequest(m) if m == "Unknown field"));
+        let denied = r#"[{"error":{"code":403,"status":"PERMISSION_DENIED","message":"No access"}}]"#;
+        assert!(matches!(error(403, denied), ProviderError::AuthError(_)));
+        let internal = r#"{"error":{"code":500,"status":"INTERNAL","message":"Oops"}}"#;
+        assert!(matches!(error(500, internal), ProviderError::ApiError { status: 500, message } if message == "Oops"));
+
+        // Not a Google API error: the raw body is kept
+        assert!(matches!(error(502, "<html>Bad Gateway</html>"), ProviderError::ApiError { status: 502, message } if message.contains("html")));
+    }
+
+    #[tokio::test]
+    async fn test_count_tokens_uses_count_endpoint() {
+        let mut server = mockito::Server::new_async().await;
+        let count_mock = server
+            .mock("POST", "/models/gemini-2.5-pro:countTokens")
+            .match_query(Matcher::UrlEncoded("key".to_string(), "key".to_string()))
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "generateContentRequest": {
+                    "model": "models/gemini-2.5-pro",
+                    "contents": [{ "role": "user", "parts": [{ "text": "How many tokens is this?" }] }],
+                    "systemInstruction": { "parts": [{ "text": "You are a very long system prompt." }] }
+                }
+            })))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"totalTokens":14,"promptTokensDetails":[{"modality":"TEXT","tokenCount":14}]}"#)
+            .create_async()
+            .await;
+
+        let provider = GeminiProvider::new(
+            "gemini".to_string(),
+            Some("key".to_string()),
+            Some(server.url()),
+            vec!["gemini-2.5-pro".to_string()],
+            HashMap::new(),
+            None,
+            None,
+            None,
+            None,
+        );
+        let request = create_request("How many tokens is this?");
+        let count = provider
+            .count_tokens(crate::models::CountTokensRequest {
+                model: request.model,
+                messages: request.messages,
+                system: request.system,
+                tools: None,
+                betas: None,
+                forwarded_headers: vec![],
+            })
+            .await
+            .unwrap();
+
+        count_mock.assert_async().await;
+        assert!(count.input_tokens > 0);
+        assert_eq!(count.input_tokens, 14);
+    }
+
+    #[tokio::test]
+    async fn test_forced_tool_uses_response_schema() {
+        let mut server = mockito::Server::new_async().await;
+        let generate_mock = server
+            .mock("POST", "/models/gemini-2.5-pro:generateContent")
+            .match_query(Matcher::Any)
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "generationConfig": {
+                    "responseMimeType": "application/json",
+                    "responseSchema": { "type": "object", "properties": { "city": { "type": "string" } } }
+                }
+            })))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"{\"city\":\"Seoul\"}"}]},"finishReason":"STOP"}]}"#,
+            )
+            .create_async()
+            .await;
+
+        let provider = GeminiProvider::new(
+            "gemini".to_string(),
+            Some("key".to_string()),
+            Some(server.url()),
+            vec![],
+            HashMap::new(),
+            None,
+            None,
+            None,
+            None,
+        )
+        .with_structured_tool_output(true);
+
+        let mut request = create_request("Where is the capital of Korea?");
+        request.system = None;
+        request.tools = Some(vec![crate::models::Tool {
+            r#type: None,
+            name: Some("record_city".to_string()),
+            description: Some("Record a city".to_string()),
+            input_schema: Some(serde_json::json!({
+                "$schema": "http://json-schema.org/draft-07/schema#",
+                "type": "object",
+                "properties": { "city": { "type": "string" } }
+            })),
+        }]);
+        request.tool_choice = Some(serde_json::json!({ "type": "tool", "name": "record_city" }));
+
+        // No function declarations are sent alongside the schema
+        let body = serde_json::to_value(provider.transform_request(&request).unwrap()).unwrap();
+        assert!(body.get("tools").is_none());
+
+        let response = provider.send_message(request).await.unwrap();
+        generate_mock.assert_async().await;
+
+        assert_eq!(response.stop_reason.as_deref(), Some("tool_use"));
+        match &response.content[..] {
+            [ContentBlock::ToolUse { name, input, .. }] => {
+                assert_eq!(name, "record_city");
+                assert_eq!(input["city"], "Seoul");
+            }
+            other => panic!("expected a single tool_use block, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_streamed_structured_output_becomes_tool_use() {
+        let run = |texts: &[&str]| -> Vec<serde_json::Value> {
+            let mut translator = GeminiStreamTranslator::new("gemini".to_string(), "gemini-2.5-pro".to_string())
+                .with_structured_tool(Some("record_city".to_string()));
+            let mut events = Vec::new();
+            for text in texts {
+                let chunk = serde_json::json!({ "candidates": [{ "content": { "role": "model", "parts": [{ "text": text }] } }] });
+                events.extend(translator.translate(SseEvent { event: None, data: chunk.to_string() }));
+            }
+            let stop = serde_json::json!({ "candidates": [{ "content": { "role": "model", "parts": [] }, "finishReason": "STOP" }] });
+            events.extend(translator.translate(SseEvent { event: None, data: stop.to_string() }));
+            events.extend(translator.finish());
+            events.iter().filter_map(|e| e.json()).collect()
+        };
+
+        // JSON split across chunks arrives as one tool_use block
+        let events = run(&["{\"city\":", "\"Seoul\"}"]);
+        let starts: Vec<&serde_json::Value> = events.iter().filter(|e| e["type"] == "content_block_start").collect();
+        assert_eq!(starts.len(), 1);
+        assert_eq!(starts[0]["content_block"]["type"], "tool_use");
+        assert_eq!(starts[0]["content_block"]["name"], "record_city");
+        let delta = events.iter().find(|e| e["type"] == "content_block_delta").unwrap();
+        assert_eq!(delta["delta"]["partial_json"], r#"{"city":"Seoul"}"#);
+        let final_delta = events.iter().rev().find(|e| e["type"] == "message_delta").unwrap();
+        assert_eq!(final_delta["delta"]["stop_reason"], "tool_use");
+
+        // Anything else stays text
+        let events = run(&["Seoul"]);
+        let start = events.iter().find(|e| e["type"] == "content_block_start").unwrap();
+        assert_eq!(start["content_block"]["type"], "text");
+        let final_delta = events.iter().rev().find(|e| e["type"] == "message_delta").unwrap();
+        assert_eq!(final_delta["delta"]["stop_reason"], "end_turn");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_requests_share_one_token_refresh() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // Token endpoint counting refreshes
+        let refreshes = Arc::new(AtomicUsize::new(0));
+        let app = axum::Router::new().route(
+            "/token",
+            axum::routing::post({
+                let refreshes = refreshes.clone();
+                move || async move {
+                    refreshes.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    axum::Json(serde_json::json!({ "access_token": "fresh", "expires_in": 3600 }))
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let token_url = format!("http://{}/token", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let dir = tempfile::tempdir().unwrap();
+        let token_store = TokenStore::new(dir.path().join("tokens.json")).unwrap();
+        token_store
+            .save(crate::auth::OAuthToken {
+                provider_id: "gemini-oauth".to_string(),
+                access_token: "expired".to_string(),
+                refresh_token: "refresh".to_string(),
+                expires_at: chrono::Utc::now(),
+                enterprise_url: None,
+                project_id: None,
+            })
+            .unwrap();
+        let mut provider = GeminiProvider::new(
+            "gemini".to_string(),
+            None,
+            None,
+            vec![],
+            HashMap::new(),
+            Some("gemini-oauth".to_string()),
+            Some(token_store),
+            None,
+            None,
+        );
+        provider.oauth_config.token_url = token_url;
+        let provider = Arc::new(provider);
+
+        let requests = (0..50).map(|_| {
+            let provider = provider.clone();
+            tokio::spawn(async move { provider.get_auth_header().await })
+        });
+        for header in futures::future::join_all(requests).await {
+            assert_eq!(header.unwrap().unwrap().as_deref(), Some("Bearer fresh"));
+        }
+        assert_eq!(refreshes.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_system_cache_strips_inline_system() {
+        let provider = GeminiProvider::new(
+            "gemini".to_string(),
+            Some("key".to_string()),
+            None,
+            vec![],
+            HashMap::new(),
+            None,
+            None,
+            None,
+            None,
+        );
+        provider.system_cache.lock().unwrap().insert(
+            GeminiProvider::system_cache_key(
+                "gemini-2.5-pro",
+                &provider.transform_request(&create_request("hi")).unwrap(),
+            ),
+            CachedSystem {
+                name: "cachedContents/sys123".to_string(),
+                expires_at: Instant::now() + SYSTEM_CACHE_TTL,
+            },
+        );
+
+        let request = create_request("a different message");
+        let mut gemini_request = provider.transform_request(&request).unwrap();
+        provider.apply_system_cache(&request, &mut gemini_request).await;
+
+        let body = serde_json::to_value(&gemini_request).unwrap();
+        assert_eq!(body["cachedContent"], "cachedContents/sys123");
+        assert!(body.get("systemInstruction").is_none());
+        assert_eq!(body["contents"][0]["parts"][0]["text"], "a different message");
+    }
+
+    #[test]
+    fn test_response_with_unknown_fields_deserializes() {
+        let body = serde_json::json!({
+            "candidates": [{
+                "content": {"role": "model", "parts": [{"text": "hello"}]},
+                "finishReason": "STOP",
+                "citationMetadata": {"citations": []}
+            }],
+            "usageMetadata": {"promptTokenCount": 3, "candidatesTokenCount": 1, "thoughtsTokenCount": 0},
+            "modelVersion": "gemini-2.5-pro",
+            "groundingReport": {"sources": []}
+        })
+        .to_string();
+
+        let response: GeminiResponse =
+            parse_response("Gemini", &body, GEMINI_RESPONSE_FIELDS).unwrap();
+        assert_eq!(response.candidates.len(), 1);
+
+        let value: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            crate::providers::unknown_fields(&value, GEMINI_RESPONSE_FIELDS),
+            vec!["groundingReport".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_safety_ratings_surfaced_when_enabled() {
+        let response = || -> GeminiResponse {
+            serde_json::from_value(serde_json::json!({
+                "candidates": [{
+                    "finishReason": "SAFETY",
+                    "finishMessage": "Response was blocked due to dangerous content",
+                    "safetyRatings": [
+                        {"category": "HARM_CATEGORY_HARASSMENT", "probability": "NEGLIGIBLE"},
+                        {"category": "HARM_CATEGORY_DANGEROUS_CONTENT", "probability": "HIGH", "blocked": true}
+                    ]
+                }]
+            }))
+            .unwrap()
+        };
+        let provider = GeminiProvider::new(
+            "gemini".to_string(),
+            Some("key".to_string()),
+            None,
+            vec![],
+            HashMap::new(),
+            None,
+            None,
+            None,
+            None,
+        );
+
+        // A blocked candidate is explained even without safety details
+        let plain = provider.transform_response(response(), "gemini-2.5-pro".to_string(), None).unwrap();
+        assert_eq!(plain.stop_reason.as_deref(), Some("refusal"));
+        assert_eq!(plain.content.len(), 1);
+
+        let provider = provider.with_safety_details(true);
+        let detailed = provider.transform_response(response(), "gemini-2.5-pro".to_string(), None).unwrap();
+        match detailed.content.as_slice() {
+            [ContentBlock::Text { text }] => {
+                assert_eq!(
+                    text,
+                    "[Gemini finish reason: SAFETY; Response was blocked due to dangerous content; \
+                     safety ratings: DANGEROUS_CONTENT (HIGH, blocked)]"
+                );
+            }
+            other => panic!("expected a single note block, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_safety_settings_default_to_block_none() {
+        let provider = GeminiProvider::new(
+            "gemini".to_string(),
+            Some("key".to_string()),
+            None,
+            vec![],
+            HashMap::new(),
+            None,
+            None,
+            None,
+            None,
+        );
+        let request: AnthropicRequest = serde_json::from_value(serde_json::json!({
+            "model": "gemini-2.5-pro",
+            "max_tokens": 16,
+            "messages": [{ "role": "user", "content": "Write a port scanner" }]
+        }))
+        .unwrap();
+
+        let body = serde_json::to_value(provider.transform_request(&request).unwrap()).unwrap();
+        let settings = body["safetySettings"].as_array().unwrap();
+        assert_eq!(settings.len(), 4);
+        assert!(settings.iter().all(|s| s["threshold"] == "BLOCK_NONE"));
+
+        let custom = provider.with_safety_settings(Some(vec![SafetySetting {
+            category: "HARM_CATEGORY_HARASSMENT".to_string(),
+            threshold: "BLOCK_ONLY_HIGH".to_string(),
+        }]));
+        let body = serde_json::to_value(custom.transform_request(&request).unwrap()).unwrap();
+        assert_eq!(
+            body["safetySettings"],
+            serde_json::json!([{ "category": "HARM_CATEGORY_HARASSMENT", "threshold": "BLOCK_ONLY_HIGH" }])
+        );
+
+        // An empty list leaves Gemini's defaults in place
+        let none = custom.with_safety_settings(Some(vec![]));
+        let body = serde_json::to_value(none.transform_request(&request).unwrap()).unwrap();
+        assert!(body.get("safetySettings").is_none());
+    }
+
+    #[test]
+    fn test_google_search_grounding_adds_tool_and_sources() {
+        let provider = GeminiProvider::new(
+            "gemini".to_string(),
+            Some("key".to_string()),
+            None,
+            vec![],
+            HashMap::new(),
+            None,
+            None,
+            None,
+            None,
+        );
+        let request: AnthropicRequest = serde_json::from_value(serde_json::json!({
+            "model": "gemini-2.5-pro",
+            "max_tokens": 16,
+            "messages": [{ "role": "user", "content": "Who won the match yesterday?" }]
+        }))
+        .unwrap();
+
+        let body = serde_json::to_value(provider.transform_request(&request).unwrap()).unwrap();
+        assert!(body.get("tools").is_none());
+
+        let provider = provider.with_google_search_grounding(true);
+        let body = serde_json::to_value(provider.transform_request(&request).unwrap()).unwrap();
+        assert_eq!(body["tools"], serde_json::json!([{ "googleSearch": {} }]));
+
+        let response: GeminiResponse = serde_json::from_value(serde_json::json!({
+            "candidates": [{
+                "content": { "role": "model", "parts": [{ "text": "Korea won 2-1." }] },
+                "finishReason": "STOP",
+                "groundingMetadata": {
+                    "webSearchQueries": ["match result yesterday"],
+                    "groundingChunks": [
+                        { "web": { "uri": "https://news.example/a", "title": "Match report" } },
+                        { "web": { "uri": "https://news.example/a", "title": "Match report" } },
+                        { "web": { "uri": "https://scores.example/b" } }
+                    ]
+                }
+            }]
+        }))
+        .unwrap();
+        let message = provider.transform_response(response, "gemini-2.5-pro".to_string(), None).unwrap();
+        match message.content.as_slice() {
+            [ContentBlock::Text { text: answer }, ContentBlock::Text { text: sources }] => {
+                assert_eq!(answer, "Korea won 2-1.");
+                assert_eq!(
+                    sources,
+                    "Sources:\n1. [Match report](https://news.example/a)\n\
+                     2. [https://scores.example/b](https://scores.example/b)"
+                );
+            }
+            other => panic!("expected answer and sources, got {:?}", other),
+        }
+
+        // Streaming: sources arrive as a final text block
+        let mut translator = GeminiStreamTranslator::new("gemini".to_string(), "gemini-2.5-pro".to_string());
+        let chunk = serde_json::json!({ "candidates": [{
+            "content": { "role": "model", "parts": [{ "text": "Korea won." }] },
+            "finishReason": "STOP",
+            "groundingMetadata": { "groundingChunks": [{ "web": { "uri": "https://news.example/a", "title": "Match report" } }] }
+        }] });
+        let mut events = translator.translate(SseEvent { event: None, data: chunk.to_string() });
+        events.extend(translator.finish());
+        let texts: Vec<String> = events
+            .iter()
+            .filter_map(|e| e.json())
+            .filter(|e| e["type"] == "content_block_delta")
+            .map(|e| e["delta"]["text"].as_str().unwrap_or_default().to_string())
+            .collect();
+        assert_eq!(texts, vec!["Korea won.", "Sources:\n1. [Match report](https://news.example/a)"]);
+    }
+
+    #[test]
+    fn test_upstream_response_id_echoed() {
+        let provider = GeminiProvider::new(
+            "gemini".to_string(),
+            Some("key".to_string()),
+            None,
+            vec![],
+            HashMap::new(),
+            None,
+            None,
+            None,
+            None,
+        );
+        let chunk = serde_json::json!({
+            "candidates": [{ "content": { "role": "model", "parts": [{ "text": "hi" }] }, "finishReason": "STOP" }],
+            "responseId": "mQ5oaPLlBZ-3nvgP5KTq0Ac"
+        });
+        let response = || serde_json::from_value::<GeminiResponse>(chunk.clone()).unwrap();
+        let stream_id = |preserve: bool| {
+            let mut translator = GeminiStreamTranslator::new("gemini".to_string(), "gemini-2.5-pro".to_string())
+                .with_preserve_response_id(preserve);
+            let events = translator.translate(SseEvent { event: None, data: chunk.to_string() });
+            events[0].json().unwrap()["message"]["id"].as_str().unwrap().to_string()
+        };
+
+        // Default: the proxy generates its own id
+        let message = provider.transform_response(response(), "gemini-2.5-pro".to_string(), None).unwrap();
+        assert!(message.id.starts_with("gemini-"));
+        assert!(stream_id(false).starts_with("msg_"));
+
+        // Enabled: Gemini's responseId is returned as-is
+        let provider = provider.with_preserve_response_id(true);
+        let message = provider.transform_response(response(), "gemini-2.5-pro".to_string(), None).unwrap();
+        assert_eq!(message.id, "mQ5oaPLlBZ-3nvgP5KTq0Ac");
+        assert_eq!(stream_id(true), "mQ5oaPLlBZ-3nvgP5KTq0Ac");
+
+        // Enabled but absent: still generated
+        let without_id: GeminiResponse = serde_json::from_value(serde_json::json!({ "candidates": chunk["candidates"] })).unwrap();
+        let message = provider.transform_response(without_id, "gemini-2.5-pro".to_string(), None).unwrap();
+        assert!(message.id.starts_with("gemini-"));
+    }
+
+    #[tokio::test]
+    async fn test_missing_usage_metadata_estimated() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/models/gemini-2.5-pro:generateContent")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"candidates":[{"content":{"role":"model","parts":[{"text":"twelve chars"}]},"finishReason":"STOP"}]}"#)
+            .create_async()
+            .await;
+        let provider = GeminiProvider::new(
+            "gemini".to_string(),
+            Some("key".to_string()),
+            Some(server.url()),
+            vec![],
+            HashMap::new(),
+            None,
+            None,
+            None,
+            None,
+        );
+        let mut request = create_request(&"x".repeat(400));
+        request.system = None;
+
+        // No usageMetadata: estimated from the text rather than reported as zero
+        let usage = provider.send_message(request.clone()).await.unwrap().usage;
+        assert_eq!((usage.input_tokens, usage.output_tokens), (100, 3));
+
+        // Streaming: the estimate stands in until usageMetadata arrives
+        let chunk = |usage: Option<serde_json::Value>| {
+            let mut chunk = serde_json::json!({
+                "candidates": [{ "content": { "role": "model", "parts": [{ "text": "hi" }] }, "finishReason": "STOP" }]
+            });
+            if let Some(usage) = usage {
+                chunk["usageMetadata"] = usage;
+            }
+            SseEvent { event: None, data: chunk.to_string() }
+        };
+        let final_input = |usage: Option<serde_json::Value>| {
+            let mut translator = GeminiStreamTranslator::new("gemini".to_string(), "gemini-2.5-pro".to_string())
+                .with_estimated_input_tokens(estimate_input_tokens(&request));
+            let start = translator.translate(chunk(usage))[0].json().unwrap();
+            let end = translator.finish().iter().rev().nth(1).unwrap().json().unwrap();
+            (start["message"]["usage"]["input_tokens"].clone(), end["usage"]["input_tokens"].clone())
+        };
+        assert_eq!(final_input(None), (100.into(), 100.into()));
+        let reported = serde_json::json!({ "promptTokenCount": 7, "candidatesTokenCount": 1 });
+        assert_eq!(final_input(Some(reported)), (7.into(), 7.into()));
+    }
+
+    #[test]
+    fn test_tool_result_string_and_array_content_match() {
+        let provider = GeminiProvider::new(
+            "gemini".to_string(),
+            Some("key".to_string()),
+            None,
+            vec![],
+            HashMap::new(),
+            None,
+            None,
+            None,
+            None,
+        );
+        let turn = |result: serde_json::Value| -> serde_json::Value {
+            let mut request = create_request("What's the weather?");
+            request.system = None;
+            request.messages.push(serde_json::from_value(serde_json::json!({
+                "role": "assistant",
+                "content": [{"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {"city": "Paris"}}]
+            })).unwrap());
+            request.messages.push(serde_json::from_value(serde_json::json!({
+                "role": "user",
+                "content": [result]
+            })).unwrap());
+            let body = serde_json::to_value(provider.transform_request(&request).unwrap()).unwrap();
+            body["contents"].clone()
+        };
+
+        let string_form = turn(serde_json::json!({"type": "tool_result", "tool_use_id": "toolu_1", "content": "Sunny"}));
+        let array_form = turn(serde_json::json!({
+            "type": "tool_result", "tool_use_id": "toolu_1", "content": [{"type": "text", "text": "Sunny"}]
+        }));
+        assert_eq!(string_form, array_form);
+        assert_eq!(
+            string_form[1]["parts"][0]["functionCall"],
+            serde_json::json!({"name": "get_weather", "args": {"city": "Paris"}})
+        );
+        assert_eq!(
+            string_form[2]["parts"][0]["functionResponse"],
+            serde_json::json!({"name": "get_weather", "response": {"content": "Sunny"}})
+        );
+
+        // Missing content and is_error are both accepted
+        let error_form = turn(serde_json::json!({"type": "tool_result", "tool_use_id": "toolu_1", "is_error": true}));
+        assert_eq!(
+            error_form[2]["parts"][0]["functionResponse"]["response"],
+            serde_json::json!({"error": ""})
+        );
+
+        // Without function calling the tool turns are kept as text
+        let mut request = create_request("What's the weather?");
+        request.model = "gemini-2.0-flash-lite".to_string();
+        request.messages.push(serde_json::from_value(serde_json::json!({
+            "role": "assistant",
+            "content": [{"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {"city": "Paris"}}]
+        })).unwrap());
+        request.messages.push(serde_json::from_value(serde_json::json!({
+            "role": "user",
+            "content": [{"type": "tool_result", "tool_use_id": "toolu_1", "content": "Sunny"}]
+        })).unwrap());
+        let contents = serde_json::to_value(provider.transform_request(&request).unwrap()).unwrap()["contents"].clone();
+        assert_eq!(contents[1]["parts"][0]["text"], r#"[Called tool get_weather with input {"city":"Paris"}]"#);
+        assert_eq!(contents[2]["parts"][0]["text"], "[Result of tool get_weather]\nSunny");
+    }
+
+    #[test]
+    fn test_multiple_candidates_selected_by_policy() {
+        let response = || -> GeminiResponse {
+            serde_json::from_value(serde_json::json!({
+                "candidates": [
+                    {"index": 0, "content": {"role": "model", "parts": [{"text": "first"}]}, "finishReason": "STOP", "avgLogprobs": -0.9},
+                    {"index": 1, "content": {"role": "model", "parts": [{"text": "second"}]}, "finishReason": "MAX_TOKENS", "avgLogprobs": -0.2},
+                    {"index": 2, "content": {"role": "model", "parts": [{"text": "third"}]}, "finishReason": "STOP"}
+                ]
+            }))
+            .unwrap()
+        };
+        let provider = |selection: CandidateSelection| {
+            GeminiProvider::new(
+                "gemini".to_string(),
+                Some("key".to_string()),
+                None,
+                vec![],
+                HashMap::new(),
+                None,
+                None,
+                None,
+                None,
+            )
+            .with_candidates(Some(3), selection)
+        };
+        let texts = |response: ProviderResponse| -> Vec<String> {
+            response
+                .content
+                .into_iter()
+                .filter_map(|b| match b {
+                    ContentBlock::Text { text } => Some(text),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        let first = provider(CandidateSelection::First)
+            .transform_response(response(), "gemini-2.5-pro".to_string(), None)
+            .unwrap();
+        assert_eq!(texts(first), vec!["first"]);
+
+        let best = provider(CandidateSelection::HighestLogprob)
+            .transform_response(response(), "gemini-2.5-pro".to_string(), None)
+            .unwrap();
+        assert_eq!(best.stop_reason.as_deref(), Some("max_tokens"));
+        assert_eq!(texts(best), vec!["second"]);
+
+        let all = provider(CandidateSelection::All)
+            .transform_response(response(), "gemini-2.5-pro".to_string(), None)
+            .unwrap();
+        assert_eq!(texts(all), vec!["first", "second", "third"]);
+
+        // candidateCount is only requested for non-streaming calls
+        let body = |stream: bool| {
+            let mut request = create_request("hi");
+            request.stream = Some(stream);
+            let gemini_request = provider(CandidateSelection::All).transform_request(&request).unwrap();
+            serde_json::to_value(gemini_request).unwrap()["generationConfig"]["candidateCount"].clone()
+        };
+        assert_eq!(body(false), 3);
+        assert!(body(true).is_null());
+    }
+
+    #[test]
+    fn test_top_k_request_over_provider_default() {
+        let provider = GeminiProvider::new(
+            "gemini".to_string(),
+            Some("key".to_string()),
+            None,
+            vec![],
+            HashMap::new(),
+            None,
+            None,
+            None,
+            None,
+        );
+        let top_k = |provider: &GeminiProvider, request_top_k: Option<u32>| {
+            let mut request = create_request("hi");
+            request.top_k = request_top_k;
+            serde_json::to_value(provider.transform_request(&request).unwrap()).unwrap()["generationConfig"]["topK"].clone()
+        };
+
+        // Nothing configured: the model's own default applies
+        assert!(top_k(&provider, None).is_null());
+        assert_eq!(top_k(&provider, Some(5)), 5);
+
+        let provider = provider.with_top_k(Some(20));
+        assert_eq!(top_k(&provider, None), 20);
+        assert_eq!(top_k(&provider, Some(5)), 5);
+    }
+
+    #[test]
+    fn test_cached_content_tokens_reported() {
+        let response: GeminiResponse = serde_json::from_str(
+            r#"{
+              "candidates": [{
+                "content": { "parts": [{ "text": "The file defines a tokenizer." }], "role": "model" },
+                "finishReason": "STOP",
+                "avgLogprobs": -0.0412
+              }],
+              "usageMetadata": {
+                "promptTokenCount": 12013,
+                "candidatesTokenCount": 21,
+                "totalTokenCount": 12034,
+                "cachedContentTokenCount": 11862,
+                "promptTokensDetails": [{ "modality": "TEXT", "tokenCount": 12013 }],
+                "cacheTokensDetails": [{ "modality": "TEXT", "tokenCount": 11862 }],
+                "candidatesTokensDetails": [{ "modality": "TEXT", "tokenCount": 21 }]
+              },
+              "modelVersion": "gemini-2.5-pro",
+              "responseId": "mQ5oaPLlBZ-3nvgP5KTq0Ac"
+            }"#,
+        )
+        .unwrap();
+        let provider = GeminiProvider::new(
+            "gemini".to_string(),
+            Some("key".to_string()),
+            None,
+            vec![],
+            HashMap::new(),
+            None,
+            None,
+            None,
+            None,
+        );
+
+        let usage = provider.transform_response(response, "gemini-2.5-pro".to_string(), None).unwrap().usage;
+        assert_eq!(usage.input_tokens, 151);
+        assert_eq!(usage.output_tokens, 21);
+        assert_eq!(usage.cache_read_input_tokens, Some(11862));
+        assert_eq!(serde_json::to_value(&usage).unwrap()["cache_read_input_tokens"], 11862);
+    }
+
+    #[test]
+    fn test_multi_candidate_usage_accounting() {
+        let response = |usage: serde_json::Value, token_counts: [Option<i32>; 3]| -> GeminiResponse {
+            let candidates: Vec<serde_json::Value> = token_counts
+                .iter()
+                .enumerate()
+                .map(|(i, count)| {
+                    serde_json::json!({
+                        "index": i,
+                        "content": {"role": "model", "parts": [{"text": format!("answer {}", i)}]},
+                        "finishReason": "STOP",
+                        "tokenCount": count
+                    })
+                })
+                .collect();
+            serde_json::from_value(serde_json::json!({ "candidates": candidates, "usageMetadata": usage })).unwrap()
+        };
+        let usage_for = |response: GeminiResponse| -> Usage {
+            GeminiProvider::new(
+                "gemini".to_string(),
+                Some("key".to_string()),
+                None,
+                vec![],
+                HashMap::new(),
+                None,
+                None,
+                None,
+                None,
+            )
+            .with_candidates(Some(3), CandidateSelection::First)
+            .transform_response(response, "gemini-2.5-pro".to_string(), None)
+            .unwrap()
+            .usage
+        };
+
+        // candidatesTokenCount already totals all candidates, even though only the first is returned
+        let usage = usage_for(response(
+            serde_json::json!({ "promptTokenCount": 20, "candidatesTokenCount": 30, "totalTokenCount": 50 }),
+            [None, None, None],
+        ));
+        assert_eq!((usage.input_tokens, usage.output_tokens), (20, 30));
+
+        // Only the first candidate counted upstream: per-candidate counts are summed
+        let usage = usage_for(response(
+            serde_json::json!({ "promptTokenCount": 20, "candidatesTokenCount": 10 }),
+            [Some(10), Some(12), Some(8)],
+        ));
+        assert_eq!(usage.output_tokens, 30);
+
+        // No candidate total: derived from the overall total
+        let usage = usage_for(response(
+            serde_json::json!({ "promptTokenCount": 20, "totalTokenCount": 65, "thoughtsTokenCount": 5 }),
+            [None, None, None],
+        ));
+        assert_eq!(usage.output_tokens, 40);
+    }
+
+    #[test]
+    fn test_function_call_becomes_tool_use() {
+        let provider = GeminiProvider::new(
+            "gemini".to_string(),
+            Some("key".to_string()),
+            None,
+            vec![],
+            HashMap::new(),
+            None,
+            None,
+            None,
+            None,
+        );
+        let body = r#"{
+            "candidates": [{
+                "content": {"role": "model", "parts": [
+                    {"text": "Checking the weather."},
+                    {"functionCall": {"name": "get_weather", "args": {"city": "Paris"}}}
+                ]},
+                "finishReason": "STOP"
+            }],
+            "usageMetadata": {"promptTokenCount": 20, "candidatesTokenCount": 8},
+            "responseId": "resp-1"
+        }"#;
+        let translate = |body: &str| {
+            let response: GeminiResponse = serde_json::from_str(body).unwrap();
+            provider.transform_response(response, "gemini-2.5-pro".to_string(), None).unwrap()
+        };
+
+        let response = translate(body);
+        assert_eq!(response.stop_reason.as_deref(), Some("tool_use"));
+        assert_eq!(response.content.len(), 2);
+        assert!(matches!(&response.content[0], ContentBlock::Text { text } if text == "Checking the weather."));
+        let ContentBlock::ToolUse { id, name, input } = &response.content[1] else {
+            panic!("expected tool_use, got {:?}", response.content[1]);
+        };
+        assert!(id.starts_with("toolu_"));
+        assert_eq!(name, "get_weather");
+        assert_eq!(input["city"], "Paris");
+
+        // Same response replayed, same id; the same call in another response, a new one
+        assert!(matches!(&translate(body).content[1], ContentBlock::ToolUse { id: again, .. } if again == id));
+        let later = body.replace("resp-1", "resp-2");
+        assert!(matches!(&translate(&later).content[1], ContentBlock::ToolUse { id: other, .. } if other != id));
+    }
+
+    #[test]
+    fn test_json_mode_without_tools() {
+        let provider = GeminiProvider::new(
+            "gemini".to_string(),
+            Some("key".to_string()),
+            None,
+            vec![],
+            HashMap::new(),
+            None,
+            None,
+            None,
+            None,
+        );
+        let generation_config = |request: &AnthropicRequest| {
+            let body = serde_json::to_value(provider.transform_request(request).unwrap()).unwrap();
+            assert!(body.get("tools").is_none());
+            body["generationConfig"].clone()
+        };
+
+        // Enabled from config: the mapping's json_mode sets a plain JSON output format
+        let mapping = crate::cli::ModelMapping {
+            priority: 1,
+            provider: "gemini".to_string(),
+            actual_model: "gemini-2.5-pro".to_string(),
+            api_version: None,
+            betas: vec![],
+            json_mode: true,
+            when_thinking: None,
+            min_thinking_budget: None,
+            app_ids: Vec::new(),
+            weight: None,
+        };
+        let mut request = create_request("List three colors as JSON");
+        assert!(request.tools.is_none());
+        mapping.apply_overrides(&mut request);
+        let config = generation_config(&request);
+        assert_eq!(config["responseMimeType"], "application/json");
+        assert!(config.get("responseSchema").is_none());
+
+        // Requested by the client with a schema
+        let mut request = create_request("List three colors as JSON");
+        request.output_format = Some(serde_json::json!({
+            "type": "json_schema",
+            "schema": {
+                "type": "object",
+                "$schema": "http://json-schema.org/draft-07/schema#",
+                "properties": {"colors": {"type": "array", "items": {"type": "string"}}}
+            }
+        }));
+        let config = generation_config(&request);
+        assert_eq!(config["responseMimeType"], "application/json");
+        assert_eq!(config["responseSchema"]["properties"]["colors"]["type"], "array");
+        assert!(config["responseSchema"].get("$schema").is_none());
+
+        // Not requested: plain text
+        assert!(generation_config(&create_request("hi")).get("responseMimeType").is_none());
+    }
+
+    #[test]
+    fn test_stream_terminal_stop_reason() {
+        let terminal = |chunks: Vec<serde_json::Value>| {
+            let mut translator = GeminiStreamTranslator::new("gemini".to_string(), "gemini-2.5-pro".to_string());
+            let mut out = Vec::new();
+            for chunk in chunks {
+                out.extend(translator.translate(SseEvent { event: None, data: chunk.to_string() }));
+            }
+            out.extend(translator.finish());
+            let final_delta = out.iter().rev().find(|e| e.event.as_deref() == Some("message_delta")).unwrap();
+            final_delta.json().unwrap()["delta"]["stop_reason"].clone()
+        };
+
+        let text = serde_json::json!({ "candidates": [{ "content": { "role": "model", "parts": [{ "text": "Hi" }] } }] });
+        let call = serde_json::json!({ "candidates": [{
+            "content": { "role": "model", "parts": [{ "functionCall": { "name": "get_weather", "args": {} } }] },
+            "finishReason": "STOP"
+        }] });
+        let stop = |reason: &str| serde_json::json!({
+            "candidates": [{ "content": { "role": "model", "parts": [] }, "finishReason": reason }],
+            "usageMetadata": { "promptTokenCount": 3, "candidatesTokenCount": 2 }
+        });
+
+        assert_eq!(terminal(vec![text.clone(), call]), "tool_use");
+        assert_eq!(terminal(vec![text.clone(), stop("MAX_TOKENS")]), "max_tokens");
+        assert_eq!(terminal(vec![text, stop("STOP")]), "end_turn");
+        assert_eq!(terminal(vec![stop("SAFETY")]), "refusal");
+    }
+
+    #[test]
+    fn test_finish_reasons_map_to_stop_reasons() {
+        for (reason, expected) in [
+            ("STOP", "end_turn"),
+            ("FINISH_REASON_UNSPECIFIED", "end_turn"),
+            ("MAX_TOKENS", "max_tokens"),
+            ("SAFETY", "refusal"),
+            ("RECITATION", "refusal"),
+            ("BLOCKLIST", "refusal"),
+            ("PROHIBITED_CONTENT", "refusal"),
+            ("SPII", "refusal"),
+            ("IMAGE_SAFETY", "refusal"),
+            ("MALFORMED_FUNCTION_CALL", "end_turn"),
+            ("OTHER", "end_turn"),
+        ] {
+            assert_eq!(stop_reason_for(reason), expected, "finishReason {}", reason);
+        }
+    }
+
+    #[test]
+    fn test_thinking_budget_and_thought_parts() {
+        let provider = GeminiProvider::new(
+            "gemini".to_string(),
+            Some("key".to_string()),
+            None,
+            vec![],
+            HashMap::new(),
+            None,
+            None,
+            None,
+            None,
+        );
+        let thinking_config = |model: &str| {
+            let mut request = create_request("Prove it");
+            request.model = model.to_string();
+            request.max_tokens = 8192;
+            request.thinking = Some(crate::models::ThinkingConfig {
+                r#type: "enabled".to_string(),
+                budget_tokens: Some(2048),
+            });
+            let body = serde_json::to_value(provider.transform_request(&request).unwrap()).unwrap();
+            body["generationConfig"].get("thinkingConfig").cloned()
+        };
+        assert_eq!(
+            thinking_config("gemini-2.5-pro"),
+            Some(serde_json::json!({ "thinkingBudget": 2048, "includeThoughts": true }))
+        );
+        // Not supported by the model: silently left out
+        assert_eq!(thinking_config("gemini-2.0-flash"), None);
+
+        let response: GeminiResponse = serde_json::from_value(serde_json::json!({
+            "candidates": [{
+                "content": { "role": "model", "parts": [
+                    { "text": "Consider small cases first.", "thought": true },
+                    { "text": "It holds for all n." }
+                ]},
+                "finishReason": "STOP"
+            }]
+        }))
+        .unwrap();
+        let response = provider.transform_response(response, "gemini-2.5-pro".to_string(), None).unwrap();
+        assert!(matches!(
+            &response.content[..],
+            [ContentBlock::Thinking { thinking, .. }, ContentBlock::Text { text }]
+                if thinking == "Consider small cases first." && text == "It holds for all n."
+        ));
+
+        // Streamed thoughts get their own thinking block
+        let mut translator = GeminiStreamTranslator::new("gemini".to_string(), "gemini-2.5-pro".to_string());
+        let mut events = Vec::new();
+        for part in [
+            serde_json::json!({ "text": "Consider", "thought": true }),
+            serde_json::json!({ "text": " small cases.", "thought": true }),
+            serde_json::json!({ "text": "It holds." }),
+        ] {
+            let chunk = serde_json::json!({ "candidates": [{ "content": { "role": "model", "parts": [part] } }] });
+            events.extend(translator.translate(SseEvent { event: None, data: chunk.to_string() }));
+        }
+        events.extend(translator.finish());
+        let events: Vec<serde_json::Value> = events.iter().filter_map(|e| e.json()).collect();
+        let blocks: Vec<(&str, &str)> = events
+            .iter()
+            .filter(|e| e["type"] == "content_block_start")
+            .map(|e| (e["content_block"]["type"].as_str().unwrap(), e["content_block"]["signature"].as_str().unwrap_or("")))
+            .collect();
+        assert_eq!(blocks, vec![("thinking", ""), ("text", "")]);
+        let thinking: String = events
+            .iter()
+            .filter(|e| e["delta"]["type"] == "thinking_delta")
+            .map(|e| e["delta"]["thinking"].as_str().unwrap())
+            .collect();
+        assert_eq!(thinking, "Consider small cases.");
+    }
+
+    #[test]
+    fn test_system_instruction_role_when_configured() {
+        let provider = |role: Option<&str>| {
+            GeminiProvider::new(
+                "gemini".to_string(),
+                Some("key".to_string()),
+                None,
+                vec![],
+                HashMap::new(),
+                None,
+                None,
+                None,
+                None,
+            )
+            .with_system_instruction_role(role.map(|r| r.to_string()))
+        };
+        let mut request = create_request("Hello");
+        request.system = Some(SystemPrompt::Text("Be brief.".to_string()));
+
+        let body = serde_json::to_value(provider(Some("user")).transform_request(&request).unwrap()).unwrap();
+        assert_eq!(
+            body["systemInstruction"],
+            serde_json::json!({"role": "user", "parts": [{"text": "Be brief."}]})
+        );
+
+        // Not configured: no role key at all
+        let body = serde_json::to_value(provider(None).transform_request(&request).unwrap()).unwrap();
+        assert!(body["systemInstruction"].get("role").is_none());
+    }
+}
+
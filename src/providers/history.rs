@@ -0,0 +1,204 @@
+use super::error::ProviderError;
+use crate::models::{AnthropicRequest, Message, MessageContent};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Provider-level cap on the size of the conversation history
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct HistoryLimit {
+    /// Maximum serialized size of `messages` in bytes. When exceeded, the oldest
+    /// turns are replaced by a summary.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_history_bytes: Option<usize>,
+    /// External summarizer: receives `{"messages": [...]}` and returns `{"summary": "..."}`.
+    /// Old turns are simply dropped (with a note) when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_summarizer_url: Option<String>,
+}
+
+/// Turns a run of old messages into a short text replacing them
+#[async_trait]
+pub trait HistorySummarizer: Send + Sync {
+    async fn summarize(&self, messages: &[Message]) -> Result<String, ProviderError>;
+}
+
+/// Built-in summarizer: drops the old turns, leaving only a note that they existed
+pub struct TruncatingSummarizer;
+
+#[async_trait]
+impl HistorySummarizer for TruncatingSummarizer {
+    async fn summarize(&self, messages: &[Message]) -> Result<String, ProviderError> {
+        Ok(format!(
+            "{} earlier messages were omitted to fit the context limit.",
+            messages.len()
+        ))
+    }
+}
+
+/// Summarizer backed by an HTTP endpoint
+pub struct HttpSummarizer {
+    url: String,
+    client: Client,
+}
+
+impl HttpSummarizer {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            client: super::http::shared_client(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SummaryResponse {
+    summary: String,
+}
+
+#[async_trait]
+impl HistorySummarizer for HttpSummarizer {
+    async fn summarize(&self, messages: &[Message]) -> Result<String, ProviderError> {
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&serde_json::json!({ "messages": messages }))
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            return Err(ProviderError::ApiError {
+                status,
+                message: format!("History summarizer error: {}", error_text),
+            });
+        }
+        Ok(response.json::<SummaryResponse>().await?.summary)
+    }
+}
+
+/// Applies a [`HistoryLimit`] using a summarizer
+#[derive(Clone)]
+pub struct HistoryCompactor {
+    max_bytes: usize,
+    summarizer: Arc<dyn HistorySummarizer>,
+}
+
+impl HistoryCompactor {
+    pub fn new(max_bytes: usize, summarizer: Arc<dyn HistorySummarizer>) -> Self {
+        Self { max_bytes, summarizer }
+    }
+
+    /// Compactor for a provider's config, if it sets a limit
+    pub fn from_limit(limit: &HistoryLimit) -> Option<Self> {
+        let max_bytes = limit.max_history_bytes?;
+        let summarizer: Arc<dyn HistorySummarizer> = match &limit.history_summarizer_url {
+            Some(url) => Arc::new(HttpSummarizer::new(url.clone())),
+            None => Arc::new(TruncatingSummarizer),
+        };
+        Some(Self::new(max_bytes, summarizer))
+    }
+
+    /// Replace the oldest turns with a summary when the history is over the limit.
+    /// Returns how many messages were summarized.
+    ///
+    /// The kept history starts at an assistant turn, so the summary (sent as a user
+    /// message) keeps roles alternating and no tool result loses its tool use.
+    pub async fn compact(&self, request: &mut AnthropicRequest) -> usize {
+        let sizes: Vec<usize> = request.messages.iter().map(message_bytes).collect();
+        let total: usize = sizes.iter().sum();
+        if total <= self.max_bytes {
+            return 0;
+        }
+
+        // Earliest split whose tail fits; otherwise the latest possible one
+        let mut split = None;
+        let mut tail = total;
+        for (i, message) in request.messages.iter().enumerate().skip(1) {
+            tail -= sizes[i - 1];
+            if message.role == "assistant" {
+                split = Some(i);
+                if tail <= self.max_bytes {
+                    break;
+                }
+            }
+        }
+        let Some(split) = split else {
+            tracing::warn!("📜 History is {} bytes (limit {}) but has no turn to summarize", total, self.max_bytes);
+            return 0;
+        };
+
+        let summary = match self.summarizer.summarize(&request.messages[..split]).await {
+            Ok(summary) => summary,
+            Err(e) => {
+                tracing::warn!("📜 History summarizer failed, dropping old turns instead: {}", e);
+                TruncatingSummarizer.summarize(&request.messages[..split]).await.unwrap_or_default()
+            }
+        };
+
+        request.messages.splice(
+            ..split,
+            [Message {
+                role: "user".to_string(),
+                content: MessageContent::Text(format!("Summary of the earlier conversation:\n{}", summary)),
+            }],
+        );
+        split
+    }
+}
+
+fn message_bytes(message: &Message) -> usize {
+    serde_json::to_vec(message).map(|v| v.len()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Records what it was asked to summarize
+    #[derive(Default)]
+    struct RecordingSummarizer {
+        calls: Mutex<Vec<usize>>,
+    }
+
+    #[async_trait]
+    impl HistorySummarizer for RecordingSummarizer {
+        async fn summarize(&self, messages: &[Message]) -> Result<String, ProviderError> {
+            self.calls.lock().unwrap().push(messages.len());
+            Ok("the user asked about rust".to_string())
+        }
+    }
+
+    fn request(turns: usize) -> AnthropicRequest {
+        let messages: Vec<serde_json::Value> = (0..turns)
+            .map(|i| {
+                let role = if i % 2 == 0 { "user" } else { "assistant" };
+                serde_json::json!({ "role": role, "content": "x".repeat(100) })
+            })
+            .collect();
+        serde_json::from_value(serde_json::json!({ "model": "m", "max_tokens": 16, "messages": messages })).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_summarizer_invoked_over_limit() {
+        let summarizer = Arc::new(RecordingSummarizer::default());
+        let compactor = HistoryCompactor::new(400, summarizer.clone());
+
+        // Under the limit: untouched, summarizer not called
+        let mut short = request(3);
+        assert_eq!(compactor.compact(&mut short).await, 0);
+        assert_eq!(short.messages.len(), 3);
+        assert!(summarizer.calls.lock().unwrap().is_empty());
+
+        // Over the limit: the oldest turns become one summary message
+        let mut long = request(7);
+        assert_eq!(compactor.compact(&mut long).await, 5);
+        assert_eq!(*summarizer.calls.lock().unwrap(), vec![5]);
+        assert_eq!(long.messages.len(), 3);
+        assert_eq!(long.messages[0].role, "user");
+        assert!(matches!(&long.messages[0].content, MessageContent::Text(t) if t.contains("the user asked about rust")));
+        assert_eq!(long.messages[1].role, "assistant");
+    }
+}
@@ -2,13 +2,19 @@ pub mod error;
 pub mod openai;
 pub mod anthropic_compatible;
 pub mod gemini;
+pub mod anthropic_vertex;
+pub mod cohere;
+pub mod gcp_auth;
+pub mod history;
+pub mod http;
 pub mod registry;
 pub mod streaming;
+pub mod validation;
 
 use async_trait::async_trait;
-use crate::models::{AnthropicRequest, CountTokensRequest, CountTokensResponse, ContentBlock};
+use crate::models::{AnthropicRequest, CountTokensRequest, CountTokensResponse, ContentBlock, Message, MessageContent, Tool};
 use error::ProviderError;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use bytes::Bytes;
 use futures::stream::Stream;
 use std::pin::Pin;
@@ -24,12 +30,40 @@ pub struct ProviderResponse {
     pub stop_reason: Option<String>,
     pub stop_sequence: Option<String>,
     pub usage: Usage,
+    /// Untransformed upstream body, when the request asked for it
+    #[serde(skip)]
+    pub raw: Option<serde_json::Value>,
+}
+
+impl ProviderResponse {
+    /// Attach the upstream body if `capture` is set (as JSON when it parses, else as a string)
+    pub fn with_raw(mut self, capture: bool, body: &str) -> Self {
+        if capture {
+            self.raw = Some(serde_json::from_str(body).unwrap_or_else(|_| body.into()));
+        }
+        self
+    }
+
+    /// Strip leading/trailing whitespace from text blocks
+    pub fn trim_text(&mut self) {
+        for block in &mut self.content {
+            if let ContentBlock::Text { text } = block {
+                let trimmed = text.trim();
+                if trimmed.len() != text.len() {
+                    *text = trimmed.to_string();
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Usage {
     pub input_tokens: u32,
     pub output_tokens: u32,
+    /// Prompt tokens served from a cache; not included in `input_tokens`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<u32>,
 }
 
 /// Main provider trait - all providers must implement this
@@ -53,6 +87,42 @@ pub trait AnthropicProvider: Send + Sync {
 
     /// Check if provider supports a specific model
     fn supports_model(&self, model: &str) -> bool;
+
+    /// Schema violations in the request body this provider would send upstream
+    /// Only called when schema validation is enabled for the model
+    fn request_schema_violations(&self, _request: &AnthropicRequest) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Send `body` upstream as-is and return the raw response body (passthrough mode).
+    /// `request` only supplies the endpoint, headers and stream flag.
+    async fn send_passthrough(
+        &self,
+        _request: AnthropicRequest,
+        _body: serde_json::Value,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<Bytes, ProviderError>> + Send>>, ProviderError> {
+        Err(ProviderError::ConfigError(
+            "Passthrough is only supported by Anthropic-compatible providers".to_string(),
+        ))
+    }
+
+    /// Whether `embed` is implemented; routing skips providers without it
+    fn supports_embeddings(&self) -> bool {
+        false
+    }
+
+    /// Embedding vector for `text` from `model`
+    async fn embed(&self, _model: &str, _text: &str) -> Result<Vec<f32>, ProviderError> {
+        Err(ProviderError::ConfigError(
+            "Embeddings are only supported by Gemini and Vertex AI providers".to_string(),
+        ))
+    }
+
+    /// Cheap upstream call confirming credentials and endpoint work.
+    /// Providers without a probe report healthy.
+    async fn health_check(&self) -> Result<(), ProviderError> {
+        Ok(())
+    }
 }
 
 /// Authentication type for providers
@@ -90,17 +160,481 @@ pub struct ProviderConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub oauth_provider: Option<String>,
 
-    /// Google Cloud Project ID (for Vertex AI provider)
+    /// Google Cloud Project ID (for Vertex AI provider, defaults to `GOOGLE_CLOUD_PROJECT`)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub project_id: Option<String>,
 
-    /// Location/Region (for Vertex AI provider)
+    /// Location/Region (for Vertex AI provider, defaults to `GOOGLE_CLOUD_REGION`)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub location: Option<String>,
 
     pub base_url: Option<String>,
     pub models: Vec<String>,
     pub enabled: Option<bool>,
+
+    /// Model used by the `/health?probe=true` check (default: first configured model)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probe_model: Option<String>,
+
+    /// Prompt used by the `/health?probe=true` check (default: "hi")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probe_prompt: Option<String>,
+
+    /// Gemini only: send a forced single-tool choice as `responseSchema` structured
+    /// output instead of function calling, and map the JSON back to a tool_use block
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub structured_tool_output: bool,
+
+    /// Gemini only: append a note with `finishMessage` and flagged safety ratings
+    /// when a response is blocked, truncated or rated as potentially harmful
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub safety_details: bool,
+
+    /// Gemini only: add the Google Search grounding tool to every request; cited
+    /// sources are appended to the response
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub google_search_grounding: bool,
+
+    /// Gemini only: check structured output against the requested schema and
+    /// pass, error or retry on a mismatch (non-streaming)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub structured_output_validation: Option<validation::StructuredOutputValidation>,
+
+    /// Gemini only: retry once, asking for complete arguments, when a tool call
+    /// comes back with empty or malformed args (non-streaming)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub retry_empty_tool_args: bool,
+
+    /// Gemini only: retries on 429/500/503 responses (default 3)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_retries: Option<u32>,
+
+    /// Gemini only: first backoff delay, doubled per retry, unless the response
+    /// says how long to wait (default 1000)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_base_delay_ms: Option<u64>,
+
+    /// Gemini only: longest wait before a retry (default 30000); when upstream asks
+    /// for longer, the call fails so routing can fall back
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_retry_delay_ms: Option<u64>,
+
+    /// Gemini only: `topK` for requests that don't set `top_k` themselves
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+
+    /// Gemini only: use Gemini's `responseId` as the message id instead of generating one
+    /// (other providers already return the upstream id)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub preserve_response_id: bool,
+
+    /// Gemini only: number of candidates to request (`candidateCount`, non-streaming)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub candidate_count: Option<u32>,
+
+    /// Gemini only: how to pick among multiple candidates
+    #[serde(default, skip_serializing_if = "CandidateSelection::is_default")]
+    pub candidate_selection: CandidateSelection,
+
+    /// Gemini only: role sent with `systemInstruction` (e.g. "user") for models that
+    /// require one; omitted if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_instruction_role: Option<String>,
+
+    /// Gemini only: `safetySettings` sent with every request. Defaults to `BLOCK_NONE`
+    /// for all harm categories; `[]` leaves Gemini's own thresholds in place
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub safety_settings: Option<Vec<SafetySetting>>,
+
+    /// Gemini only: largest image fetched for URL image sources, in bytes (default 20MB)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_image_bytes: Option<usize>,
+
+    /// Gemini only: allow image URLs on loopback, private and link-local addresses
+    /// (refused by default so clients can't reach the proxy's internal network)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_private_image_urls: bool,
+
+    /// Gemini only: limit on a whole non-streaming call, in seconds (default 120)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+
+    /// Gemini only: limit on establishing a connection, in seconds (default 10)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout_secs: Option<u64>,
+
+    /// Gemini only: limit on a whole streamed response, in seconds (none by default,
+    /// so long generations aren't cut off)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream_timeout_secs: Option<u64>,
+
+    /// Trim leading/trailing whitespace from response text blocks (streamed too)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub trim_response_text: bool,
+
+    /// When a stream fails before any content, retry the same provider non-streaming
+    /// and replay the response to the client as a stream
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub non_streaming_retry: bool,
+
+    /// Forward the client's request body and the upstream response untouched
+    /// (Anthropic-compatible providers only); only `model` is replaced
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub passthrough: bool,
+
+    /// Upstream path overrides for gateways with non-standard routes
+    #[serde(default, skip_serializing_if = "PathOverrides::is_empty")]
+    pub paths: PathOverrides,
+
+    /// Whether the provider accepts extended thinking requests
+    /// (default: true for Anthropic-format providers, false otherwise)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub supports_thinking: Option<bool>,
+
+    /// Merge system prompt blocks before sending. Providers that honour `cache_control`
+    /// keep one block per cache breakpoint; others get plain text.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub coalesce_system_blocks: bool,
+
+    /// Whether the provider honours `cache_control` on system blocks
+    /// (default: true for Anthropic, Anthropic on Vertex AI and Gemini)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub supports_cache_control: Option<bool>,
+
+    /// Header used to forward the proxy's request id upstream (e.g. "x-request-id");
+    /// not forwarded if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id_header: Option<String>,
+
+    /// Client request headers passed through to this provider (e.g. "x-feature-flags").
+    /// Only `x-` headers qualify; matched case-insensitively
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub forward_headers: Vec<String>,
+
+    /// Retries when this provider's host fails to resolve, overriding `server.retry.dns_retries`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns_retries: Option<u32>,
+
+    /// Anthropic beta flags always sent to this provider (Anthropic-compatible only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub betas: Vec<String>,
+
+    /// Sent as `anthropic-organization-id` (Anthropic-compatible only). A client's own
+    /// `anthropic-organization-id` header takes precedence.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub organization_id: Option<String>,
+
+    /// Restrict which models this provider may serve
+    #[serde(flatten)]
+    pub model_filter: ModelFilter,
+
+    /// Cap or narrow the tool list forwarded to this provider
+    #[serde(flatten)]
+    pub tool_limit: ToolLimit,
+
+    /// Summarize old turns once the history exceeds a byte limit
+    #[serde(flatten)]
+    pub history_limit: history::HistoryLimit,
+
+    /// Take the provider out of routing once too many of its calls fail
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_threshold: Option<FailureThreshold>,
+
+    /// Normalize mapped model names before sending (strip/add prefixes and suffixes)
+    #[serde(default, skip_serializing_if = "ModelRewrite::is_empty")]
+    pub model_rewrite: ModelRewrite,
+}
+
+/// How to turn multiple Gemini candidates into one Anthropic response
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CandidateSelection {
+    /// The first candidate (Gemini's own ranking)
+    #[default]
+    First,
+    /// The candidate with the highest `avgLogprobs`
+    HighestLogprob,
+    /// Content of every candidate, in order
+    All,
+}
+
+impl CandidateSelection {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// A Gemini harm category threshold (e.g. `HARM_CATEGORY_HARASSMENT` / `BLOCK_ONLY_HIGH`)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SafetySetting {
+    pub category: String,
+    pub threshold: String,
+}
+
+impl SafetySetting {
+    /// `BLOCK_NONE` for every category, so ordinary coding prompts aren't cut off
+    pub fn block_none() -> Vec<Self> {
+        [
+            "HARM_CATEGORY_HARASSMENT",
+            "HARM_CATEGORY_HATE_SPEECH",
+            "HARM_CATEGORY_SEXUALLY_EXPLICIT",
+            "HARM_CATEGORY_DANGEROUS_CONTENT",
+        ]
+        .into_iter()
+        .map(|category| Self {
+            category: category.to_string(),
+            threshold: "BLOCK_NONE".to_string(),
+        })
+        .collect()
+    }
+}
+
+/// Provider-level model allowlist/denylist. Entries are exact model names or
+/// `*` wildcard patterns (e.g. `gpt-4o*`). The denylist wins over the allowlist;
+/// an empty allowlist allows everything.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ModelFilter {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_models: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub denied_models: Vec<String>,
+}
+
+impl ModelFilter {
+    pub fn is_empty(&self) -> bool {
+        self.allowed_models.is_empty() && self.denied_models.is_empty()
+    }
+
+    /// Whether `model` may be served
+    pub fn permits(&self, model: &str) -> bool {
+        if self.denied_models.iter().any(|p| glob_match(p, model)) {
+            return false;
+        }
+        self.allowed_models.is_empty() || self.allowed_models.iter().any(|p| glob_match(p, model))
+    }
+}
+
+/// Failure rate at which a provider is disabled until re-enabled by an admin
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FailureThreshold {
+    /// Fraction of failed calls (0.0-1.0) above which the provider is disabled
+    pub max_failure_rate: f64,
+    /// Only calls from the last `window_secs` count
+    #[serde(default = "default_failure_window_secs")]
+    pub window_secs: u64,
+    /// Calls needed in the window before the rate is judged
+    #[serde(default = "default_failure_min_requests")]
+    pub min_requests: usize,
+}
+
+fn default_failure_window_secs() -> u64 {
+    300
+}
+
+fn default_failure_min_requests() -> usize {
+    10
+}
+
+/// Provider-level limits on the tools forwarded with each request
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ToolLimit {
+    /// Maximum number of tools forwarded. Recently used tools (and a tool forced by
+    /// `tool_choice`) are kept first; the rest fill up in request order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tools: Option<usize>,
+    /// Forward only tools used in the last N messages. All tools are kept
+    /// until the conversation has used one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recent_tool_turns: Option<usize>,
+}
+
+impl ToolLimit {
+    pub fn is_empty(&self) -> bool {
+        self.max_tools.is_none() && self.recent_tool_turns.is_none()
+    }
+
+    /// Trim `request.tools` in place, keeping the original order. Returns how many were dropped.
+    pub fn apply(&self, request: &mut AnthropicRequest) -> usize {
+        let Some(tools) = request.tools.as_mut() else {
+            return 0;
+        };
+        let before = tools.len();
+
+        // Tools that must survive: the forced one and those used recently (most recent first)
+        let mut preferred: Vec<String> = Vec::new();
+        if let Some(name) = request
+            .tool_choice
+            .as_ref()
+            .filter(|c| c.get("type").and_then(|t| t.as_str()) == Some("tool"))
+            .and_then(|c| c.get("name"))
+            .and_then(|n| n.as_str())
+        {
+            preferred.push(name.to_string());
+        }
+        let window = self.recent_tool_turns.unwrap_or(request.messages.len());
+        for message in request.messages.iter().rev().take(window) {
+            if let MessageContent::Blocks(blocks) = &message.content {
+                for block in blocks.iter().rev() {
+                    if let ContentBlock::ToolUse { name, .. } = block {
+                        if !preferred.contains(name) {
+                            preferred.push(name.clone());
+                        }
+                    }
+                }
+            }
+        }
+
+        let is_preferred = |tool: &Tool| tool.name.as_ref().is_some_and(|n| preferred.contains(n));
+        let rank = |tool: &Tool| {
+            tool.name
+                .as_ref()
+                .and_then(|n| preferred.iter().position(|p| p == n))
+                .unwrap_or(usize::MAX)
+        };
+
+        let mut keep: Vec<usize> = (0..tools.len()).collect();
+        if self.recent_tool_turns.is_some() && tools.iter().any(is_preferred) {
+            keep.retain(|&i| is_preferred(&tools[i]));
+        }
+        if let Some(max) = self.max_tools.filter(|max| keep.len() > *max) {
+            // Stable sort: preferred tools by rank, then the rest in request order
+            keep.sort_by_key(|&i| rank(&tools[i]));
+            keep.truncate(max);
+            keep.sort_unstable();
+        }
+
+        if keep.len() < before {
+            let mut index = 0;
+            tools.retain(|_| {
+                let kept = keep.contains(&index);
+                index += 1;
+                kept
+            });
+        }
+        before - tools.len()
+    }
+}
+
+/// Rewrite rules applied to `actual_model` before it is sent to the provider,
+/// e.g. OpenRouter-style `vendor/model:variant` → `model`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ModelRewrite {
+    /// Prefixes removed when present (first match only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub strip_prefixes: Vec<String>,
+    /// Suffixes removed when present (first match only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub strip_suffixes: Vec<String>,
+    /// Prefix added after stripping, unless already present
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub add_prefix: Option<String>,
+    /// Suffix added after stripping, unless already present
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub add_suffix: Option<String>,
+}
+
+impl ModelRewrite {
+    pub fn is_empty(&self) -> bool {
+        self.strip_prefixes.is_empty()
+            && self.strip_suffixes.is_empty()
+            && self.add_prefix.is_none()
+            && self.add_suffix.is_none()
+    }
+
+    pub fn apply(&self, model: &str) -> String {
+        let mut name = model;
+        if let Some(rest) = self.strip_prefixes.iter().find_map(|p| name.strip_prefix(p.as_str())) {
+            name = rest;
+        }
+        if let Some(rest) = self.strip_suffixes.iter().find_map(|s| name.strip_suffix(s.as_str())) {
+            name = rest;
+        }
+
+        let mut name = name.to_string();
+        if let Some(prefix) = self.add_prefix.as_deref().filter(|p| !name.starts_with(p)) {
+            name.insert_str(0, prefix);
+        }
+        if let Some(suffix) = self.add_suffix.as_deref().filter(|s| !name.ends_with(s)) {
+            name.push_str(suffix);
+        }
+        name
+    }
+}
+
+/// Match `name` against a pattern where `*` matches any run of characters
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // No wildcard: exact match
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// Per-provider path templates, appended to base_url in place of the built-in
+/// suffix (e.g. `/api/v1/messages`). `{model}` is replaced with the model name,
+/// percent-encoded as a path segment.
+/// Applies to Anthropic-compatible and OpenAI-compatible providers.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct PathOverrides {
+    /// Non-streaming generation endpoint
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generate: Option<String>,
+    /// Streaming endpoint (defaults to `generate` when only that is set)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream: Option<String>,
+    /// Token counting endpoint
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count_tokens: Option<String>,
+}
+
+impl PathOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.generate.is_none() && self.stream.is_none() && self.count_tokens.is_none()
+    }
+
+    pub fn generate_path(&self, default: &str, model: &str) -> String {
+        Self::resolve(self.generate.as_deref(), default, model)
+    }
+
+    pub fn stream_path(&self, default: &str, model: &str) -> String {
+        Self::resolve(self.stream.as_deref().or(self.generate.as_deref()), default, model)
+    }
+
+    pub fn count_tokens_path(&self, default: &str, model: &str) -> String {
+        Self::resolve(self.count_tokens.as_deref(), default, model)
+    }
+
+    fn resolve(template: Option<&str>, default: &str, model: &str) -> String {
+        template.unwrap_or(default).replace("{model}", &encode_path_segment(model))
+    }
+}
+
+/// Percent-encode everything but unreserved characters, `:` and `@`, so the value
+/// stays within one path segment
+fn encode_path_segment(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b':' | b'@' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn env_default(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.trim().is_empty())
 }
 
 impl ProviderConfig {
@@ -108,6 +642,57 @@ impl ProviderConfig {
         self.enabled.unwrap_or(true)
     }
 
+    /// Whether the provider speaks the Anthropic Messages API natively
+    pub fn is_anthropic_compatible(&self) -> bool {
+        matches!(self.provider_type.as_str(), "anthropic" | "z.ai" | "minimax" | "zenmux" | "kimi-coding")
+    }
+
+    /// Whether thinking requests can be sent to this provider as-is
+    pub fn supports_thinking(&self) -> bool {
+        self.supports_thinking
+            .unwrap_or(self.is_anthropic_compatible() || matches!(self.provider_type.as_str(), "anthropic-vertex" | "gemini" | "vertex-ai"))
+    }
+
+    /// Whether `cache_control` markers on system blocks take effect with this provider
+    pub fn supports_cache_control(&self) -> bool {
+        self.supports_cache_control.unwrap_or(matches!(
+            self.provider_type.as_str(),
+            "anthropic" | "anthropic-vertex" | "gemini" | "vertex-ai"
+        ))
+    }
+
+    /// Configured `model_rewrite`, or the provider type's default when none is set
+    /// (Groq drops LiteLLM-style `groq/` prefixes)
+    pub fn model_rewrite(&self) -> ModelRewrite {
+        match self.provider_type.as_str() {
+            "groq" if self.model_rewrite.is_empty() => ModelRewrite {
+                strip_prefixes: vec!["groq/".to_string()],
+                ..Default::default()
+            },
+            _ => self.model_rewrite.clone(),
+        }
+    }
+
+    /// GCP project for Vertex providers; defaults to `GOOGLE_CLOUD_PROJECT`
+    pub fn gcp_project_id(&self) -> Option<String> {
+        self.gcp_project_id_from(env_default)
+    }
+
+    /// GCP location for Vertex providers; defaults to `GOOGLE_CLOUD_REGION`
+    pub fn gcp_location(&self) -> Option<String> {
+        self.gcp_location_from(env_default)
+    }
+
+    /// `gcp_project_id`, reading environment variables through `env`
+    fn gcp_project_id_from(&self, env: impl Fn(&str) -> Option<String>) -> Option<String> {
+        self.project_id.clone().or_else(|| env("GOOGLE_CLOUD_PROJECT"))
+    }
+
+    /// `gcp_location`, reading environment variables through `env`
+    fn gcp_location_from(&self, env: impl Fn(&str) -> Option<String>) -> Option<String> {
+        self.location.clone().or_else(|| env("GOOGLE_CLOUD_REGION"))
+    }
+
     /// Get the API key or OAuth provider ID
     pub fn get_auth_credential(&self) -> Option<String> {
         match self.auth_type {
@@ -115,9 +700,242 @@ impl ProviderConfig {
             AuthType::OAuth => self.oauth_provider.clone(),
         }
     }
+
+    /// Build the cheap 1-token request used to probe this provider
+    /// Returns None if no probe model is configured and the provider lists no models
+    pub fn probe_request(&self) -> Option<AnthropicRequest> {
+        let model = self
+            .probe_model
+            .clone()
+            .or_else(|| self.models.first().cloned())?;
+        let prompt = self.probe_prompt.clone().unwrap_or_else(|| "hi".to_string());
+
+        Some(AnthropicRequest {
+            model,
+            messages: vec![Message {
+                role: "user".to_string(),
+                content: MessageContent::Text(prompt),
+            }],
+            max_tokens: 1,
+            thinking: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            stop_sequences: None,
+            stream: Some(false),
+            metadata: None,
+            system: None,
+            tools: None,
+            tool_choice: None,
+            betas: None,
+            stream_options: None,
+            api_version: None,
+            output_format: None,
+            request_id_header: None,
+            capture_raw: false,
+            forwarded_headers: vec![],
+        })
+    }
+}
+
+/// Deserialize a provider response body. Unknown fields are ignored, but unexpected
+/// top-level ones are logged so new provider capabilities get noticed.
+pub fn parse_response<T: DeserializeOwned>(
+    provider: &str,
+    body: &str,
+    known_fields: &[&str],
+) -> Result<T, serde_json::Error> {
+    let value: serde_json::Value = serde_json::from_str(body)?;
+    let unknown = unknown_fields(&value, known_fields);
+    if !unknown.is_empty() {
+        tracing::debug!("🆕 {} response has unrecognized fields: {}", provider, unknown.join(", "));
+    }
+    serde_json::from_value(value)
+}
+
+/// Log the raw upstream finish reason next to the Anthropic stop_reason it was mapped to
+pub fn log_stop_reason(provider: &str, model: &str, upstream_reason: Option<&str>, stop_reason: Option<&str>) {
+    tracing::info!(
+        provider,
+        model,
+        upstream_finish_reason = upstream_reason.unwrap_or("none"),
+        stop_reason = stop_reason.unwrap_or("none"),
+        "🏁 Response finished"
+    );
+}
+
+/// Top-level object keys not in `known_fields`
+pub fn unknown_fields(value: &serde_json::Value, known_fields: &[&str]) -> Vec<String> {
+    value
+        .as_object()
+        .map(|obj| {
+            obj.keys()
+                .filter(|k| !known_fields.contains(&k.as_str()))
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default()
 }
 
 // Re-export provider implementations
 pub use openai::OpenAIProvider;
 pub use anthropic_compatible::AnthropicCompatibleProvider;
 pub use registry::ProviderRegistry;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(probe_model: Option<&str>) -> ProviderConfig {
+        ProviderConfig {
+            name: "openrouter".to_string(),
+            provider_type: "openrouter".to_string(),
+            auth_type: AuthType::ApiKey,
+            api_key: Some("key".to_string()),
+            oauth_provider: None,
+            project_id: None,
+            location: None,
+            base_url: None,
+            models: vec!["anthropic/claude-opus-4".to_string(), "openai/gpt-4o-mini".to_string()],
+            enabled: None,
+            probe_model: probe_model.map(|m| m.to_string()),
+            probe_prompt: None,
+            structured_tool_output: false,
+            safety_details: false,
+            google_search_grounding: false,
+            structured_output_validation: None,
+            retry_empty_tool_args: false,
+            max_retries: None,
+            retry_base_delay_ms: None,
+            max_retry_delay_ms: None,
+            top_k: None,
+            preserve_response_id: false,
+            candidate_count: None,
+            candidate_selection: CandidateSelection::default(),
+            system_instruction_role: None,
+            safety_settings: None,
+            max_image_bytes: None,
+            allow_private_image_urls: false,
+            timeout_secs: None,
+            connect_timeout_secs: None,
+            stream_timeout_secs: None,
+            trim_response_text: false,
+            non_streaming_retry: false,
+            passthrough: false,
+            paths: PathOverrides::default(),
+            betas: Vec::new(),
+            organization_id: None,
+            model_filter: ModelFilter::default(),
+            tool_limit: ToolLimit::default(),
+            model_rewrite: ModelRewrite::default(),
+            history_limit: history::HistoryLimit::default(),
+            failure_threshold: None,
+            supports_thinking: None,
+            coalesce_system_blocks: false,
+            supports_cache_control: None,
+            request_id_header: None,
+            forward_headers: vec![],
+            dns_retries: None,
+        }
+    }
+
+    #[test]
+    fn test_probe_request_uses_configured_model() {
+        let request = config(Some("openai/gpt-4o-mini")).probe_request().unwrap();
+        assert_eq!(request.model, "openai/gpt-4o-mini");
+        assert_eq!(request.max_tokens, 1);
+
+        // Falls back to the first configured model
+        let request = config(None).probe_request().unwrap();
+        assert_eq!(request.model, "anthropic/claude-opus-4");
+    }
+
+    #[test]
+    fn test_tool_list_capped() {
+        let request = || -> AnthropicRequest {
+            let tools: Vec<serde_json::Value> = ["read", "write", "grep", "glob", "bash"]
+                .iter()
+                .map(|name| serde_json::json!({ "name": name, "input_schema": { "type": "object" } }))
+                .collect();
+            serde_json::from_value(serde_json::json!({
+                "model": "m",
+                "max_tokens": 16,
+                "tools": tools,
+                "messages": [
+                    { "role": "user", "content": "find the bug" },
+                    { "role": "assistant", "content": [{ "type": "tool_use", "id": "t1", "name": "bash", "input": {} }] },
+                    { "role": "user", "content": [{ "type": "tool_result", "tool_use_id": "t1", "content": "ok" }] },
+                    { "role": "assistant", "content": [{ "type": "tool_use", "id": "t2", "name": "grep", "input": {} }] },
+                    { "role": "user", "content": [{ "type": "tool_result", "tool_use_id": "t2", "content": "ok" }] }
+                ]
+            }))
+            .unwrap()
+        };
+        let names = |request: &AnthropicRequest| -> Vec<String> {
+            request.tools.iter().flatten().filter_map(|t| t.name.clone()).collect()
+        };
+
+        // Configured from TOML alongside the rest of the provider settings
+        let limit: ToolLimit = toml::from_str("max_tools = 3").unwrap();
+
+        // Used tools survive the cap; the rest fill up in request order
+        let mut capped = request();
+        assert_eq!(limit.apply(&mut capped), 2);
+        assert_eq!(names(&capped), vec!["read", "grep", "bash"]);
+
+        // Only tools used within the last two messages
+        let recent = ToolLimit { max_tools: None, recent_tool_turns: Some(2) };
+        let mut narrowed = request();
+        assert_eq!(recent.apply(&mut narrowed), 4);
+        assert_eq!(names(&narrowed), vec!["grep"]);
+
+        // No limits: untouched
+        let mut untouched = request();
+        assert_eq!(ToolLimit::default().apply(&mut untouched), 0);
+        assert_eq!(names(&untouched).len(), 5);
+    }
+
+    #[test]
+    fn test_model_rewrite_normalizes_names() {
+        let provider: ProviderConfig = toml::from_str(
+            r#"
+name = "moonshot"
+provider_type = "openai"
+auth_type = "apikey"
+models = []
+model_rewrite = { strip_prefixes = ["moonshotai/"], strip_suffixes = [":nitro", ":free"] }
+"#,
+        )
+        .unwrap();
+        assert_eq!(provider.model_rewrite.apply("moonshotai/kimi-k2-thinking:nitro"), "kimi-k2-thinking");
+        assert_eq!(provider.model_rewrite.apply("kimi-k2-thinking"), "kimi-k2-thinking");
+
+        // Prefixes and suffixes are only added when missing
+        let add = ModelRewrite {
+            add_prefix: Some("moonshotai/".to_string()),
+            add_suffix: Some(":nitro".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(add.apply("kimi-k2-thinking"), "moonshotai/kimi-k2-thinking:nitro");
+        assert_eq!(add.apply("moonshotai/kimi-k2-thinking:nitro"), "moonshotai/kimi-k2-thinking:nitro");
+
+        // No rules: untouched
+        let untouched = ModelRewrite::default().apply("moonshotai/kimi-k2-thinking:nitro");
+        assert_eq!(untouched, "moonshotai/kimi-k2-thinking:nitro");
+    }
+
+    #[test]
+    fn test_path_template_encodes_model() {
+        let paths = PathOverrides {
+            generate: Some("/api/{model}/messages".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(paths.generate_path("/v1/messages", "claude-sonnet-4-5"), "/api/claude-sonnet-4-5/messages");
+        assert_eq!(
+            paths.generate_path("/v1/messages", "vendor/model:tag?x#y"),
+            "/api/vendor%2Fmodel:tag%3Fx%23y/messages"
+        );
+        // Defaults without a template are left alone
+        assert_eq!(paths.count_tokens_path("/v1/messages/count_tokens", "vendor/model"), "/v1/messages/count_tokens");
+    }
+}
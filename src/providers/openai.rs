@@ -1,4 +1,7 @@
-use super::{AnthropicProvider, ProviderResponse, ContentBlock, Usage, error::ProviderError};
+use super::{log_stop_reason, parse_response, AnthropicProvider, PathOverrides, ProviderResponse, ContentBlock, Usage, error::ProviderError, validation};
+use super::streaming::{
+    check_content_type, encode_events, translate_events, ExpectedBody, SseEvent, StreamTranslator,
+};
 use crate::models::{AnthropicRequest, CountTokensRequest, CountTokensResponse, MessageContent};
 use crate::auth::{OAuthClient, OAuthConfig, TokenStore};
 use async_trait::async_trait;
@@ -32,6 +35,8 @@ struct OpenAIRequest {
     tools: Option<Vec<OpenAITool>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     tool_choice: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<serde_json::Value>,
 }
 
 /// OpenAI Responses API request format (for Codex models)
@@ -92,6 +97,7 @@ struct OpenAIImageUrl {
 #[derive(Debug, Serialize, Deserialize)]
 struct OpenAIToolCall {
     id: String,
+    #[serde(default)]
     r#type: String, // "function"
     function: OpenAIFunctionCall,
 }
@@ -133,6 +139,13 @@ struct OpenAIMessage {
     tool_call_id: Option<String>,
 }
 
+/// Top-level fields of a chat completion we expect to see
+const OPENAI_RESPONSE_FIELDS: &[&str] = &[
+    "id", "object", "created", "model", "choices", "usage", "system_fingerprint", "service_tier",
+    // Groq extensions
+    "x_groq", "usage_breakdown",
+];
+
 /// OpenAI Chat Completions response format
 #[derive(Debug, Deserialize)]
 struct OpenAIResponse {
@@ -141,6 +154,8 @@ struct OpenAIResponse {
     _object: String,
     model: String,
     choices: Vec<OpenAIChoice>,
+    /// Some OpenAI-compatible upstreams leave it out
+    #[serde(default)]
     usage: OpenAIUsage,
 }
 
@@ -150,7 +165,7 @@ struct OpenAIChoice {
     finish_reason: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 struct OpenAIUsage {
     prompt_tokens: u32,
     completion_tokens: u32,
@@ -201,6 +216,10 @@ pub struct OpenAIProvider {
     oauth_provider: Option<String>,
     /// Token store for OAuth authentication
     token_store: Option<TokenStore>,
+    /// Upstream path overrides
+    paths: PathOverrides,
+    /// Most stop sequences the upstream accepts; extras are dropped
+    max_stop_sequences: Option<usize>,
 }
 
 impl OpenAIProvider {
@@ -216,14 +235,36 @@ impl OpenAIProvider {
             name,
             api_key,
             base_url,
-            client: Client::new(),
+            client: super::http::shared_client(),
             models,
             custom_headers: Vec::new(),
             oauth_provider,
             token_store,
+            paths: PathOverrides::default(),
+            max_stop_sequences: None,
         }
     }
 
+    /// Override the built-in endpoint paths
+    pub fn with_paths(mut self, paths: PathOverrides) -> Self {
+        self.paths = paths;
+        self
+    }
+
+    /// Use `base_url` instead of the built-in endpoint, when set
+    pub fn with_base_url(mut self, base_url: Option<String>) -> Self {
+        if let Some(base_url) = base_url {
+            self.base_url = base_url;
+        }
+        self
+    }
+
+    /// Send at most `max` stop sequences
+    pub fn with_max_stop_sequences(mut self, max: Option<usize>) -> Self {
+        self.max_stop_sequences = max;
+        self
+    }
+
     /// Check if the model is a Codex model that requires /v1/responses endpoint
     fn is_codex_model(model: &str) -> bool {
         model.to_lowercase().contains("codex")
@@ -369,11 +410,13 @@ impl OpenAIProvider {
             name,
             api_key,
             base_url,
-            client: Client::new(),
+            client: super::http::shared_client(),
             models,
             custom_headers,
             oauth_provider,
             token_store,
+            paths: PathOverrides::default(),
+            max_stop_sequences: None,
         }
     }
 
@@ -454,7 +497,7 @@ impl OpenAIProvider {
         )
     }
 
-    /// Groq - Fast OpenAI-compatible inference
+    /// Groq - Fast OpenAI-compatible inference (rejects more than 4 stop sequences)
     pub fn groq(name: String, api_key: String, models: Vec<String>) -> Self {
         Self::new(
             name,
@@ -464,6 +507,7 @@ impl OpenAIProvider {
             None,
             None,
         )
+        .with_max_stop_sequences(Some(4))
     }
 
     /// Nebius - OpenAI-compatible
@@ -516,7 +560,7 @@ impl OpenAIProvider {
                         let config = OAuthConfig::openai_codex();
                         let oauth_client = OAuthClient::new(config, token_store.clone());
 
-                        match oauth_client.refresh_token(oauth_provider_id).await {
+                        match oauth_client.refresh_if_needed(oauth_provider_id).await {
                             Ok(new_token) => {
                                 tracing::info!("✅ Token refreshed successfully");
                                 return Ok(new_token.access_token);
@@ -616,7 +660,7 @@ impl OpenAIProvider {
                     // Check if we have any tool results - they need separate messages
                     let tool_results: Vec<_> = blocks.iter()
                         .filter_map(|block| {
-                            if let crate::models::ContentBlock::ToolResult { tool_use_id, content } = block {
+                            if let crate::models::ContentBlock::ToolResult { tool_use_id, content, .. } = block {
                                 Some((tool_use_id.clone(), content.to_string()))
                             } else {
                                 None
@@ -681,6 +725,9 @@ impl OpenAIProvider {
                             crate::models::ContentBlock::Thinking { .. } => {
                                 // OpenAI doesn't have thinking blocks, skip
                             }
+                            crate::models::ContentBlock::Document { .. } => {
+                                // OpenAI doesn't have document blocks, skip
+                            }
                         }
                     }
 
@@ -745,10 +792,22 @@ impl OpenAIProvider {
             max_tokens: Some(request.max_tokens),
             temperature: request.temperature,
             top_p: request.top_p,
-            stop: request.stop_sequences.clone(),
+            stop: request.stop_sequences.clone().map(|mut stop| {
+                if let Some(max) = self.max_stop_sequences.filter(|&max| stop.len() > max) {
+                    tracing::warn!("⚠️ {} accepts at most {} stop sequences, dropping {}", self.name, max, stop.len() - max);
+                    stop.truncate(max);
+                }
+                stop
+            }),
             stream: request.stream,
             tools,
             tool_choice: None, // TODO: Add tool_choice support if needed
+            // Ask for the trailing usage chunk so the final message_delta can report it
+            stream_options: if request.stream == Some(true) {
+                Some(serde_json::json!({ "include_usage": true }))
+            } else {
+                None
+            },
         })
     }
 
@@ -781,19 +840,41 @@ impl OpenAIProvider {
             String::new()
         };
 
+        let tool_calls = choice.message.tool_calls.unwrap_or_default();
+
+        // Text first, then tool calls in the order OpenAI returned them
+        let mut content = Vec::with_capacity(tool_calls.len() + 1);
+        if !text.is_empty() || tool_calls.is_empty() {
+            content.push(ContentBlock::Text { text });
+        }
+        for call in tool_calls {
+            let input = serde_json::from_str(&call.function.arguments).unwrap_or_else(|e| {
+                tracing::warn!("⚠️ Invalid tool call arguments for {}: {}", call.function.name, e);
+                serde_json::json!({})
+            });
+            content.push(ContentBlock::ToolUse {
+                id: call.id,
+                name: call.function.name,
+                input,
+            });
+        }
+
+        let stop_reason = choice.finish_reason.as_deref().map(|r| map_finish_reason(r).to_string());
+        log_stop_reason(&self.name, &response.model, choice.finish_reason.as_deref(), stop_reason.as_deref());
+
         ProviderResponse {
             id: response.id,
             r#type: "message".to_string(),
             role: "assistant".to_string(),
-            content: vec![ContentBlock::Text {
-                text,
-            }],
+            content,
             model: response.model,
-            stop_reason: choice.finish_reason,
+            stop_reason,
             stop_sequence: None,
+            raw: None,
             usage: Usage {
                 input_tokens: response.usage.prompt_tokens,
                 output_tokens: response.usage.completion_tokens,
+                cache_read_input_tokens: None,
             },
         }
     }
@@ -822,14 +903,348 @@ impl OpenAIProvider {
             model: response.model,
             stop_reason: Some("end_turn".to_string()),
             stop_sequence: None,
+            raw: None,
             usage: Usage {
                 input_tokens: response.usage.input_tokens,
                 output_tokens: response.usage.output_tokens,
+                cache_read_input_tokens: None,
             },
         }
     }
 }
 
+/// Rate-limit headers worth surfacing with a 429 (OpenAI and Groq both send these)
+const RATE_LIMIT_HEADERS: &[&str] = &[
+    "retry-after",
+    "x-ratelimit-remaining-requests",
+    "x-ratelimit-remaining-tokens",
+    "x-ratelimit-reset-requests",
+    "x-ratelimit-reset-tokens",
+];
+
+/// Error for a failed response: 429 becomes `RateLimited` carrying the upstream's
+/// rate-limit headers, anything else an `ApiError`
+fn openai_error(status: u16, headers: &reqwest::header::HeaderMap, error_text: String) -> ProviderError {
+    if status != 429 {
+        return ProviderError::ApiError { status, message: error_text };
+    }
+    let limits: Vec<String> = RATE_LIMIT_HEADERS
+        .iter()
+        .filter_map(|&name| Some(format!("{}: {}", name, headers.get(name)?.to_str().ok()?)))
+        .collect();
+    if limits.is_empty() {
+        ProviderError::RateLimited(error_text)
+    } else {
+        ProviderError::RateLimited(format!("{} ({})", error_text, limits.join(", ")))
+    }
+}
+
+/// Map an OpenAI finish_reason to the Anthropic stop_reason
+fn map_finish_reason(reason: &str) -> &'static str {
+    match reason {
+        "length" => "max_tokens",
+        "tool_calls" | "function_call" => "tool_use",
+        _ => "end_turn",
+    }
+}
+
+/// Translates OpenAI streaming chunks (Chat Completions `chat.completion.chunk`
+/// objects or Responses API `response.*` events) into Anthropic stream events
+struct OpenAIStreamTranslator {
+    provider: String,
+    model: String,
+    started: bool,
+    /// Index and kind of the currently open content block
+    open_block: Option<(usize, OpenBlock)>,
+    next_index: usize,
+    stop_reason: Option<String>,
+    /// finish_reason (or Responses API status/incomplete reason) as sent upstream
+    upstream_reason: Option<String>,
+    /// Some OpenAI-compatible upstreams report "stop" even after tool calls
+    saw_tool_use: bool,
+    input_tokens: u32,
+    output_tokens: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OpenBlock {
+    Text,
+    /// Tool call with the OpenAI tool_calls index (Chat Completions)
+    /// or output_index (Responses API)
+    Tool(u64),
+}
+
+impl OpenAIStreamTranslator {
+    fn new(provider: String, model: String) -> Self {
+        Self {
+            provider,
+            model,
+            started: false,
+            open_block: None,
+            next_index: 0,
+            stop_reason: None,
+            upstream_reason: None,
+            saw_tool_use: false,
+            input_tokens: 0,
+            output_tokens: None,
+        }
+    }
+
+    /// stop_reason for the terminal message_delta
+    fn final_stop_reason(&self) -> String {
+        match self.stop_reason.as_deref() {
+            None | Some("end_turn") if self.saw_tool_use => "tool_use".to_string(),
+            Some(reason) => reason.to_string(),
+            None => "end_turn".to_string(),
+        }
+    }
+
+    fn ensure_started(&mut self, id: Option<&str>, out: &mut Vec<SseEvent>) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+        let id = id
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| format!("msg_{}", chrono::Utc::now().timestamp_millis()));
+        out.push(SseEvent::anthropic(serde_json::json!({
+            "type": "message_start",
+            "message": {
+                "id": id,
+                "type": "message",
+                "role": "assistant",
+                "content": [],
+                "model": self.model,
+                "stop_reason": null,
+                "stop_sequence": null,
+                "usage": { "input_tokens": self.input_tokens, "output_tokens": 0 }
+            }
+        })));
+    }
+
+    fn close_block(&mut self, out: &mut Vec<SseEvent>) {
+        if let Some((index, _)) = self.open_block.take() {
+            out.push(SseEvent::anthropic(serde_json::json!({
+                "type": "content_block_stop",
+                "index": index
+            })));
+        }
+    }
+
+    /// Make sure a block of the given kind is open, starting it if necessary
+    fn open(&mut self, kind: OpenBlock, content_block: serde_json::Value, out: &mut Vec<SseEvent>) -> usize {
+        if let Some((index, open_kind)) = self.open_block {
+            if open_kind == kind {
+                return index;
+            }
+        }
+        self.close_block(out);
+        let index = self.next_index;
+        self.next_index += 1;
+        self.open_block = Some((index, kind));
+        out.push(SseEvent::anthropic(serde_json::json!({
+            "type": "content_block_start",
+            "index": index,
+            "content_block": content_block
+        })));
+        index
+    }
+
+    fn text_delta(&mut self, text: &str, out: &mut Vec<SseEvent>) {
+        if text.is_empty() {
+            return;
+        }
+        let index = self.open(OpenBlock::Text, serde_json::json!({ "type": "text", "text": "" }), out);
+        out.push(SseEvent::anthropic(serde_json::json!({
+            "type": "content_block_delta",
+            "index": index,
+            "delta": { "type": "text_delta", "text": text }
+        })));
+    }
+
+    fn translate_chat_chunk(&mut self, chunk: serde_json::Value, out: &mut Vec<SseEvent>) {
+        self.ensure_started(chunk.get("id").and_then(|v| v.as_str()), out);
+
+        // Groq reports streamed usage under `x_groq` in the last chunk
+        let usage = chunk
+            .get("usage")
+            .filter(|u| !u.is_null())
+            .or_else(|| chunk.pointer("/x_groq/usage"));
+        if let Some(usage) = usage {
+            if let Some(prompt) = usage.get("prompt_tokens").and_then(|v| v.as_u64()) {
+                self.input_tokens = prompt as u32;
+            }
+            if let Some(completion) = usage.get("completion_tokens").and_then(|v| v.as_u64()) {
+                self.output_tokens = Some(completion as u32);
+            }
+        }
+
+        let Some(choice) = chunk.get("choices").and_then(|c| c.get(0)) else {
+            return;
+        };
+
+        if let Some(delta) = choice.get("delta") {
+            if let Some(text) = delta.get("content").and_then(|v| v.as_str()) {
+                self.text_delta(text, out);
+            }
+
+            if let Some(tool_calls) = delta.get("tool_calls").and_then(|v| v.as_array()) {
+                for call in tool_calls {
+                    self.saw_tool_use = true;
+                    let tool_index = call.get("index").and_then(|v| v.as_u64()).unwrap_or(0);
+                    let function = call.get("function");
+                    let index = self.open(
+                        OpenBlock::Tool(tool_index),
+                        serde_json::json!({
+                            "type": "tool_use",
+                            "id": call.get("id").and_then(|v| v.as_str()).unwrap_or_default(),
+                            "name": function
+                                .and_then(|f| f.get("name"))
+                                .and_then(|v| v.as_str())
+                                .unwrap_or_default(),
+                            "input": {}
+                        }),
+                        out,
+                    );
+
+                    if let Some(args) = function
+                        .and_then(|f| f.get("arguments"))
+                        .and_then(|v| v.as_str())
+                        .filter(|a| !a.is_empty())
+                    {
+                        out.push(SseEvent::anthropic(serde_json::json!({
+                            "type": "content_block_delta",
+                            "index": index,
+                            "delta": { "type": "input_json_delta", "partial_json": args }
+                        })));
+                    }
+                }
+            }
+        }
+
+        if let Some(reason) = choice.get("finish_reason").and_then(|v| v.as_str()) {
+            self.stop_reason = Some(map_finish_reason(reason).to_string());
+            self.upstream_reason = Some(reason.to_string());
+        }
+    }
+
+    fn translate_responses_event(&mut self, event_type: &str, data: serde_json::Value, out: &mut Vec<SseEvent>) {
+        let response_id = data.pointer("/response/id").and_then(|v| v.as_str()).map(|s| s.to_string());
+        self.ensure_started(response_id.as_deref(), out);
+
+        match event_type {
+            "response.output_text.delta" => {
+                if let Some(text) = data.get("delta").and_then(|v| v.as_str()) {
+                    self.text_delta(text, out);
+                }
+            }
+            "response.output_item.added" => {
+                let item = data.get("item");
+                if item.and_then(|i| i.get("type")).and_then(|t| t.as_str()) == Some("function_call") {
+                    self.saw_tool_use = true;
+                    let output_index = data.get("output_index").and_then(|v| v.as_u64()).unwrap_or(0);
+                    let field = |key: &str| {
+                        item.and_then(|i| i.get(key)).and_then(|v| v.as_str()).unwrap_or_default().to_string()
+                    };
+                    self.open(
+                        OpenBlock::Tool(output_index),
+                        serde_json::json!({
+                            "type": "tool_use",
+                            "id": field("call_id"),
+                            "name": field("name"),
+                            "input": {}
+                        }),
+                        out,
+                    );
+                }
+            }
+            "response.function_call_arguments.delta" => {
+                let output_index = data.get("output_index").and_then(|v| v.as_u64()).unwrap_or(0);
+                let args = data.get("delta").and_then(|v| v.as_str()).unwrap_or_default();
+                if let Some((index, OpenBlock::Tool(open_index))) = self.open_block {
+                    if open_index == output_index && !args.is_empty() {
+                        out.push(SseEvent::anthropic(serde_json::json!({
+                            "type": "content_block_delta",
+                            "index": index,
+                            "delta": { "type": "input_json_delta", "partial_json": args }
+                        })));
+                    }
+                }
+            }
+            "response.completed" | "response.incomplete" => {
+                if let Some(usage) = data.pointer("/response/usage") {
+                    if let Some(input) = usage.get("input_tokens").and_then(|v| v.as_u64()) {
+                        self.input_tokens = input as u32;
+                    }
+                    if let Some(output) = usage.get("output_tokens").and_then(|v| v.as_u64()) {
+                        self.output_tokens = Some(output as u32);
+                    }
+                }
+                let incomplete_reason = data
+                    .pointer("/response/incomplete_details/reason")
+                    .and_then(|v| v.as_str());
+                self.upstream_reason = Some(
+                    incomplete_reason.unwrap_or(event_type.trim_start_matches("response.")).to_string(),
+                );
+                self.stop_reason = Some(match (event_type, incomplete_reason) {
+                    ("response.incomplete", None | Some("max_output_tokens")) => "max_tokens".to_string(),
+                    _ => "end_turn".to_string(),
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+impl StreamTranslator for OpenAIStreamTranslator {
+    fn translate(&mut self, event: SseEvent) -> Vec<SseEvent> {
+        let mut out = Vec::new();
+        if event.data.trim() == "[DONE]" {
+            return out;
+        }
+        let Some(data) = event.json() else {
+            return out;
+        };
+
+        // Responses API events carry their type in the event name or payload
+        let event_type = event
+            .event
+            .clone()
+            .or_else(|| data.get("type").and_then(|t| t.as_str()).map(|t| t.to_string()));
+
+        match event_type {
+            Some(t) if t.starts_with("response.") => self.translate_responses_event(&t, data, &mut out),
+            _ => self.translate_chat_chunk(data, &mut out),
+        }
+        out
+    }
+
+    fn finish(&mut self) -> Vec<SseEvent> {
+        let mut out = Vec::new();
+        if !self.started {
+            return out;
+        }
+        self.close_block(&mut out);
+
+        let mut usage = serde_json::json!({ "input_tokens": self.input_tokens });
+        if let Some(output_tokens) = self.output_tokens {
+            usage["output_tokens"] = output_tokens.into();
+        }
+        let stop_reason = self.final_stop_reason();
+        log_stop_reason(&self.provider, &self.model, self.upstream_reason.as_deref(), Some(&stop_reason));
+        out.push(SseEvent::anthropic(serde_json::json!({
+            "type": "message_delta",
+            "delta": {
+                "stop_reason": stop_reason,
+                "stop_sequence": null
+            },
+            "usage": usage
+        })));
+        out.push(SseEvent::anthropic(serde_json::json!({ "type": "message_stop" })));
+        out
+    }
+}
+
 #[async_trait]
 impl AnthropicProvider for OpenAIProvider {
     async fn send_message(&self, request: AnthropicRequest) -> Result<ProviderResponse, ProviderError> {
@@ -898,6 +1313,10 @@ impl AnthropicProvider for OpenAIProvider {
                 req_builder = req_builder.header(key, value);
             }
 
+            for (name, value) in request.upstream_headers() {
+                req_builder = req_builder.header(name, value);
+            }
+
             let response = req_builder
                 .json(&responses_request)
                 .send()
@@ -905,14 +1324,14 @@ impl AnthropicProvider for OpenAIProvider {
 
             if !response.status().is_success() {
                 let status = response.status().as_u16();
+                let headers = response.headers().clone();
                 let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
                 tracing::error!("Responses API error ({}): {}", status, error_text);
-                return Err(ProviderError::ApiError {
-                    status,
-                    message: error_text,
-                });
+                return Err(openai_error(status, &headers, error_text));
             }
 
+            check_content_type(response.headers(), ExpectedBody::Sse)?;
+
             let response_text = response.text().await?;
             tracing::debug!("Responses API response body: {}", response_text);
 
@@ -930,15 +1349,18 @@ impl AnthropicProvider for OpenAIProvider {
                 model: request.model.clone(),
                 stop_reason: Some("end_turn".to_string()),
                 stop_sequence: None,
+                raw: None,
                 usage: Usage {
                     input_tokens: 0,  // SSE doesn't provide token counts
                     output_tokens: 0,
+                    cache_read_input_tokens: None,
                 },
-            })
+            }
+            .with_raw(request.capture_raw, &response_text))
         } else {
             // Use standard /v1/chat/completions endpoint for non-Codex models
             let openai_request = self.transform_request(&request)?;
-            let url = format!("{}/chat/completions", base_url);
+            let url = format!("{}{}", base_url, self.paths.generate_path("/chat/completions", &request.model));
 
             let mut req_builder = self.client
                 .post(&url)
@@ -969,6 +1391,10 @@ impl AnthropicProvider for OpenAIProvider {
                 req_builder = req_builder.header(key, value);
             }
 
+            for (name, value) in request.upstream_headers() {
+                req_builder = req_builder.header(name, value);
+            }
+
             let response = req_builder
                 .json(&openai_request)
                 .send()
@@ -976,26 +1402,26 @@ impl AnthropicProvider for OpenAIProvider {
 
             if !response.status().is_success() {
                 let status = response.status().as_u16();
+                let headers = response.headers().clone();
                 let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-                return Err(ProviderError::ApiError {
-                    status,
-                    message: error_text,
-                });
+                return Err(openai_error(status, &headers, error_text));
             }
 
+            check_content_type(response.headers(), ExpectedBody::Json)?;
+
             // Get response body as text for debugging
             let response_text = response.text().await?;
             tracing::debug!("OpenAI provider response body: {}", response_text);
 
             // Try to parse the response
-            let openai_response: OpenAIResponse = serde_json::from_str(&response_text)
-                .map_err(|e| {
+            let openai_response: OpenAIResponse =
+                parse_response("OpenAI", &response_text, OPENAI_RESPONSE_FIELDS).map_err(|e| {
                     tracing::error!("Failed to parse OpenAI response: {}", e);
                     tracing::error!("Response body was: {}", response_text);
                     e
                 })?;
 
-            Ok(self.transform_response(openai_response))
+            Ok(self.transform_response(openai_response).with_raw(request.capture_raw, &response_text))
         }
     }
 
@@ -1078,7 +1504,8 @@ impl AnthropicProvider for OpenAIProvider {
             let openai_request = self.transform_request(&request)?;
             let body = serde_json::to_value(&openai_request)
                 .map_err(|e| ProviderError::SerializationError(e))?;
-            (format!("{}/chat/completions", base_url), body)
+            let path = self.paths.stream_path("/chat/completions", &request.model);
+            (format!("{}{}", base_url, path), body)
         };
 
         // Send streaming request
@@ -1113,21 +1540,376 @@ impl AnthropicProvider for OpenAIProvider {
         // Check for errors
         if !response.status().is_success() {
             let status = response.status().as_u16();
+            let headers = response.headers().clone();
             let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(ProviderError::ApiError {
-                status,
-                message: error_text,
-            });
+            return Err(openai_error(status, &headers, error_text));
         }
 
-        // TODO: Transform OpenAI SSE format to Anthropic SSE format
-        // For now, just pass through the stream
+        check_content_type(response.headers(), ExpectedBody::Sse)?;
+
+        // Translate OpenAI SSE (Chat Completions or Responses API) into Anthropic SSE
         let stream = response.bytes_stream().map_err(|e| ProviderError::HttpError(e));
+        let translator = OpenAIStreamTranslator::new(self.name.clone(), request.model.clone());
 
-        Ok(Box::pin(stream))
+        Ok(encode_events(translate_events(stream, translator)))
     }
 
     fn supports_model(&self, model: &str) -> bool {
         self.models.iter().any(|m| m == model)
     }
+
+    fn request_schema_violations(&self, request: &AnthropicRequest) -> Vec<String> {
+        // Mirror the endpoint selection in send_message
+        let result = if self.is_oauth() || Self::is_codex_model(&request.model) {
+            self.transform_to_responses_request(request)
+                .and_then(|r| serde_json::to_value(&r).map_err(ProviderError::from))
+                .map(|body| validation::validate_openai_responses_request(&body))
+        } else {
+            self.transform_request(request)
+                .and_then(|r| serde_json::to_value(&r).map_err(ProviderError::from))
+                .map(|body| validation::validate_openai_chat_request(&body))
+        };
+        result.unwrap_or_else(|e| vec![format!("transform failed: {}", e)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(data: serde_json::Value) -> SseEvent {
+        SseEvent { event: None, data: data.to_string() }
+    }
+
+    #[test]
+    fn test_transform_response_text_and_tool_calls() {
+        let provider = OpenAIProvider::new(
+            "openai".to_string(),
+            "key".to_string(),
+            "https://api.openai.com/v1".to_string(),
+            vec![],
+            None,
+            None,
+        );
+        let response: OpenAIResponse = serde_json::from_value(serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "model": "gpt-4o",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": "Let me check both files.",
+                    "tool_calls": [
+                        { "id": "call_a", "type": "function", "function": { "name": "read", "arguments": "{\"path\":\"a.rs\"}" } },
+                        { "id": "call_b", "type": "function", "function": { "name": "read", "arguments": "{\"path\":\"b.rs\"}" } }
+                    ]
+                },
+                "finish_reason": "tool_calls"
+            }],
+            "usage": { "prompt_tokens": 10, "completion_tokens": 20, "total_tokens": 30 }
+        }))
+        .unwrap();
+
+        let result = provider.transform_response(response);
+        assert_eq!(result.content.len(), 3);
+        assert!(matches!(&result.content[0], ContentBlock::Text { text } if text == "Let me check both files."));
+        match (&result.content[1], &result.content[2]) {
+            (
+                ContentBlock::ToolUse { id: first, input: first_input, .. },
+                ContentBlock::ToolUse { id: second, input: second_input, .. },
+            ) => {
+                assert_eq!(first, "call_a");
+                assert_eq!(first_input["path"], "a.rs");
+                assert_eq!(second, "call_b");
+                assert_eq!(second_input["path"], "b.rs");
+            }
+            other => panic!("expected two tool_use blocks, got {:?}", other),
+        }
+        assert_eq!(result.stop_reason.as_deref(), Some("tool_use"));
+    }
+
+    #[test]
+    fn test_stop_reason_logged_with_upstream_finish_reason() {
+        #[derive(Clone, Default)]
+        struct Capture(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+        impl std::io::Write for Capture {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let capture = Capture::default();
+        let writer = capture.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let provider = OpenAIProvider::new(
+                "openai".to_string(),
+                "key".to_string(),
+                "https://api.openai.com/v1".to_string(),
+                vec![],
+                None,
+                None,
+            );
+            let response: OpenAIResponse = serde_json::from_value(serde_json::json!({
+                "id": "chatcmpl-1",
+                "model": "gpt-4o",
+                "choices": [{ "index": 0, "message": { "role": "assistant", "content": "Hi" }, "finish_reason": "length" }],
+                "usage": { "prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2 }
+            }))
+            .unwrap();
+            provider.transform_response(response);
+
+            let mut translator = OpenAIStreamTranslator::new("openrouter".to_string(), "gpt-4o".to_string());
+            translator.translate(chunk(serde_json::json!({
+                "id": "chatcmpl-2",
+                "choices": [{ "index": 0, "delta": { "content": "Hi" }, "finish_reason": "content_filter" }]
+            })));
+            translator.finish();
+        });
+
+        let logs = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = logs.lines().filter(|l| l.contains("Response finished")).collect();
+        assert_eq!(lines.len(), 2, "logs: {}", logs);
+        assert!(lines[0].contains(r#"provider="openai""#) && lines[0].contains(r#"upstream_finish_reason="length""#));
+        assert!(lines[0].contains(r#"stop_reason="max_tokens""#));
+        assert!(lines[1].contains(r#"provider="openrouter""#) && lines[1].contains(r#"upstream_finish_reason="content_filter""#));
+        assert!(lines[1].contains(r#"stop_reason="end_turn""#));
+    }
+
+    #[test]
+    fn test_stream_translator_reports_usage_in_final_delta() {
+        let mut translator = OpenAIStreamTranslator::new("openai".to_string(), "gpt-4o".to_string());
+        let mut events = Vec::new();
+        events.extend(translator.translate(chunk(serde_json::json!({
+            "id": "chatcmpl-1",
+            "choices": [{ "index": 0, "delta": { "role": "assistant", "content": "Hi" }, "finish_reason": null }]
+        }))));
+        events.extend(translator.translate(chunk(serde_json::json!({
+            "id": "chatcmpl-1",
+            "choices": [{ "index": 0, "delta": {}, "finish_reason": "stop" }]
+        }))));
+        events.extend(translator.translate(chunk(serde_json::json!({
+            "id": "chatcmpl-1",
+            "choices": [],
+            "usage": { "prompt_tokens": 9, "completion_tokens": 3, "total_tokens": 12 }
+        }))));
+        events.extend(translator.translate(SseEvent { event: None, data: "[DONE]".to_string() }));
+        events.extend(translator.finish());
+
+        let names: Vec<_> = events.iter().map(|e| e.event.clone().unwrap()).collect();
+        assert_eq!(
+            names,
+            vec!["message_start", "content_block_start", "content_block_delta", "content_block_stop", "message_delta", "message_stop"]
+        );
+
+        let final_delta = events[4].json().unwrap();
+        assert_eq!(final_delta["delta"]["stop_reason"], "end_turn");
+        assert_eq!(final_delta["usage"]["input_tokens"], 9);
+        assert_eq!(final_delta["usage"]["output_tokens"], 3);
+    }
+
+    fn terminal_stop_reason(events: Vec<serde_json::Value>) -> serde_json::Value {
+        let mut translator = OpenAIStreamTranslator::new("openai".to_string(), "gpt-4o".to_string());
+        let mut out = Vec::new();
+        for event in events {
+            let name = event.get("type").and_then(|t| t.as_str()).map(|t| t.to_string());
+            out.extend(translator.translate(SseEvent { event: name, data: event.to_string() }));
+        }
+        out.extend(translator.finish());
+        let final_delta = out.iter().rev().find(|e| e.event.as_deref() == Some("message_delta")).unwrap();
+        final_delta.json().unwrap()["delta"]["stop_reason"].clone()
+    }
+
+    #[test]
+    fn test_stream_terminal_stop_reason() {
+        // Tool calls reported with "stop" by some compatible upstreams
+        let tool_call = serde_json::json!({
+            "id": "chatcmpl-1",
+            "choices": [{ "index": 0, "delta": { "tool_calls": [{
+                "index": 0, "id": "call_1", "function": { "name": "get_weather", "arguments": "{}" }
+            }] }, "finish_reason": null }]
+        });
+        let stop = serde_json::json!({ "id": "chatcmpl-1", "choices": [{ "index": 0, "delta": {}, "finish_reason": "stop" }] });
+        assert_eq!(terminal_stop_reason(vec![tool_call.clone(), stop]), "tool_use");
+
+        let tool_calls = serde_json::json!({ "id": "chatcmpl-1", "choices": [{ "index": 0, "delta": {}, "finish_reason": "tool_calls" }] });
+        assert_eq!(terminal_stop_reason(vec![tool_call, tool_calls]), "tool_use");
+
+        let text = serde_json::json!({ "id": "chatcmpl-1", "choices": [{ "index": 0, "delta": { "content": "Hi" }, "finish_reason": null }] });
+        let length = serde_json::json!({ "id": "chatcmpl-1", "choices": [{ "index": 0, "delta": {}, "finish_reason": "length" }] });
+        assert_eq!(terminal_stop_reason(vec![text.clone(), length]), "max_tokens");
+
+        // Responses API
+        let completed = serde_json::json!({ "type": "response.completed", "response": { "id": "resp_1" } });
+        assert_eq!(
+            terminal_stop_reason(vec![
+                serde_json::json!({ "type": "response.output_item.added", "output_index": 0,
+                    "item": { "type": "function_call", "call_id": "call_1", "name": "get_weather" } }),
+                serde_json::json!({ "type": "response.function_call_arguments.delta", "output_index": 0, "delta": "{}" }),
+                completed.clone(),
+            ]),
+            "tool_use"
+        );
+        assert_eq!(
+            terminal_stop_reason(vec![serde_json::json!({
+                "type": "response.incomplete",
+                "response": { "id": "resp_1", "incomplete_details": { "reason": "max_output_tokens" } }
+            })]),
+            "max_tokens"
+        );
+        assert_eq!(
+            terminal_stop_reason(vec![
+                serde_json::json!({ "type": "response.output_text.delta", "delta": "Hi" }),
+                completed,
+            ]),
+            "end_turn"
+        );
+    }
+
+    fn groq(base_url: String) -> OpenAIProvider {
+        OpenAIProvider::groq("groq".to_string(), "gsk-key".to_string(), vec!["llama-3.3-70b-versatile".to_string()])
+            .with_base_url(Some(base_url))
+    }
+
+    fn groq_request(stream: bool) -> AnthropicRequest {
+        serde_json::from_value(serde_json::json!({
+            "model": "llama-3.3-70b-versatile",
+            "max_tokens": 256,
+            "stream": stream,
+            "stop_sequences": ["a", "b", "c", "d", "e", "f"],
+            "messages": [{ "role": "user", "content": "Weather in Paris?" }],
+            "tools": [{
+                "name": "get_weather",
+                "input_schema": { "type": "object", "properties": { "city": { "type": "string" } } }
+            }]
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_groq_stream_translated() {
+        use futures::StreamExt;
+
+        // Groq sends usage under `x_groq` in the final chunk
+        let fixture = [
+            r#"{"id":"chatcmpl-g1","object":"chat.completion.chunk","created":1,"model":"llama-3.3-70b-versatile","system_fingerprint":"fp_1","choices":[{"index":0,"delta":{"role":"assistant","content":""},"logprobs":null,"finish_reason":null}],"x_groq":{"id":"req_01"}}"#,
+            r#"{"id":"chatcmpl-g1","object":"chat.completion.chunk","created":1,"model":"llama-3.3-70b-versatile","choices":[{"index":0,"delta":{"content":"Checking."},"logprobs":null,"finish_reason":null}]}"#,
+            r#"{"id":"chatcmpl-g1","object":"chat.completion.chunk","created":1,"model":"llama-3.3-70b-versatile","choices":[{"index":0,"delta":{"tool_calls":[{"id":"call_g1","type":"function","function":{"name":"get_weather","arguments":"{\"city\":\"Paris\"}"},"index":0}]},"logprobs":null,"finish_reason":null}]}"#,
+            r#"{"id":"chatcmpl-g1","object":"chat.completion.chunk","created":1,"model":"llama-3.3-70b-versatile","choices":[{"index":0,"delta":{},"logprobs":null,"finish_reason":"tool_calls"}],"x_groq":{"id":"req_01","usage":{"queue_time":0.01,"prompt_tokens":42,"prompt_time":0.002,"completion_tokens":11,"completion_time":0.03,"total_tokens":53,"total_time":0.032}}}"#,
+            "[DONE]",
+        ]
+        .iter()
+        .map(|data| format!("data: {}\n\n", data))
+        .collect::<String>();
+
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/chat/completions")
+            .match_header("authorization", "Bearer gsk-key")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "model": "llama-3.3-70b-versatile",
+                "stream": true,
+                "stop": ["a", "b", "c", "d"]
+            })))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(fixture)
+            .create_async()
+            .await;
+
+        let mut stream = groq(server.url()).send_message_stream(groq_request(true)).await.unwrap();
+        let mut body = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            body.extend_from_slice(&chunk.unwrap());
+        }
+        mock.assert_async().await;
+
+        let events: Vec<serde_json::Value> = super::super::streaming::parse_sse_events(&String::from_utf8(body).unwrap())
+            .iter()
+            .filter_map(|e| e.json())
+            .collect();
+        let types: Vec<&str> = events.iter().map(|e| e["type"].as_str().unwrap()).collect();
+        assert_eq!(
+            types,
+            vec![
+                "message_start",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop"
+            ]
+        );
+        assert_eq!(events[0]["message"]["id"], "chatcmpl-g1");
+        assert_eq!(events[2]["delta"]["text"], "Checking.");
+        assert_eq!(events[4]["content_block"]["name"], "get_weather");
+        assert_eq!(events[5]["delta"]["partial_json"], r#"{"city":"Paris"}"#);
+        assert_eq!(events[7]["delta"]["stop_reason"], "tool_use");
+        assert_eq!(events[7]["usage"]["input_tokens"], 42);
+        assert_eq!(events[7]["usage"]["output_tokens"], 11);
+    }
+
+    #[tokio::test]
+    async fn test_groq_rate_limit_and_missing_fields() {
+        let mut server = mockito::Server::new_async().await;
+        let limited = server
+            .mock("POST", "/chat/completions")
+            .with_status(429)
+            .with_header("content-type", "application/json")
+            .with_header("retry-after", "7")
+            .with_header("x-ratelimit-remaining-tokens", "0")
+            .with_header("x-ratelimit-reset-tokens", "7.66s")
+            .with_body(r#"{"error":{"message":"Rate limit reached for model `llama-3.3-70b-versatile`","type":"tokens","code":"rate_limit_exceeded"}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let error = groq(server.url()).send_message(groq_request(false)).await.unwrap_err();
+        limited.assert_async().await;
+        let ProviderError::RateLimited(message) = error else {
+            panic!("expected RateLimited, got {:?}", error);
+        };
+        assert!(message.contains("Rate limit reached"));
+        assert!(message.contains("retry-after: 7"));
+        assert!(message.contains("x-ratelimit-reset-tokens: 7.66s"));
+
+        // No usage, a tool call without `type`, and Groq's own top-level fields
+        limited.remove_async().await;
+        server
+            .mock("POST", "/chat/completions")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"id":"chatcmpl-g2","object":"chat.completion","created":1,"model":"llama-3.3-70b-versatile",
+                    "choices":[{"index":0,"message":{"role":"assistant","content":null,"tool_calls":[{"id":"call_g2","function":{"name":"get_weather","arguments":"{\"city\":\"Paris\"}"}}]},"finish_reason":"tool_calls"}],
+                    "x_groq":{"id":"req_02"}}"#,
+            )
+            .create_async()
+            .await;
+
+        let response = groq(server.url()).send_message(groq_request(false)).await.unwrap();
+        assert_eq!(response.stop_reason.as_deref(), Some("tool_use"));
+        assert_eq!(response.usage.input_tokens, 0);
+        assert!(matches!(
+            &response.content[..],
+            [ContentBlock::ToolUse { name, input, .. }] if name == "get_weather" && input["city"] == "Paris"
+        ));
+
+        // LiteLLM-style model names are accepted
+        let config: super::super::ProviderConfig =
+            toml::from_str("name = \"groq\"\nprovider_type = \"groq\"\napi_key = \"k\"\nmodels = []").unwrap();
+        assert_eq!(config.model_rewrite().apply("groq/llama-3.3-70b-versatile"), "llama-3.3-70b-versatile");
+    }
 }
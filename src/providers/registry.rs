@@ -1,7 +1,13 @@
-use super::{AnthropicProvider, ProviderConfig, OpenAIProvider, AnthropicCompatibleProvider, error::ProviderError};
+use super::{AnthropicProvider, ModelFilter, ModelRewrite, ProviderConfig, ToolLimit, OpenAIProvider, AnthropicCompatibleProvider, error::ProviderError};
+use crate::models::AnthropicRequest;
 use super::gemini::GeminiProvider;
+use super::anthropic_vertex::AnthropicVertexProvider;
+use super::anthropic_compatible::ORGANIZATION_HEADER;
+use super::cohere::CohereProvider;
+use super::history::HistoryCompactor;
 use crate::auth::TokenStore;
-use std::collections::HashMap;
+use reqwest::header::HeaderMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 /// Provider registry that manages all configured providers
@@ -10,6 +16,28 @@ pub struct ProviderRegistry {
     providers: HashMap<String, Arc<Box<dyn AnthropicProvider>>>,
     /// Map of model name -> provider name for fast lookup
     model_to_provider: HashMap<String, String>,
+    /// Map of provider name -> allow/deny list (only providers that configure one)
+    model_filters: HashMap<String, ModelFilter>,
+    /// Map of provider name -> forwarded tool limits
+    tool_limits: HashMap<String, ToolLimit>,
+    /// Providers that can't handle extended thinking
+    no_thinking: HashSet<String>,
+    /// Map of provider name -> header carrying the request id upstream
+    request_id_headers: HashMap<String, String>,
+    /// Map of provider name -> client headers (lowercase) passed through upstream
+    forward_headers: HashMap<String, Vec<String>>,
+    /// Providers whose response text is trimmed of surrounding whitespace
+    trim_text: HashSet<String>,
+    /// Providers whose early stream failures are retried non-streaming
+    non_streaming_retry: HashSet<String>,
+    /// Map of provider name -> model name rewrite rules
+    model_rewrites: HashMap<String, ModelRewrite>,
+    /// Map of provider name -> history size limit and summarizer
+    history_compactors: HashMap<String, HistoryCompactor>,
+    /// Providers forwarding requests and responses without transformation
+    passthrough: HashSet<String>,
+    /// Map of provider name -> whether coalesced system blocks keep `cache_control`
+    coalesce_system: HashMap<String, bool>,
 }
 
 impl ProviderRegistry {
@@ -18,6 +46,17 @@ impl ProviderRegistry {
         Self {
             providers: HashMap::new(),
             model_to_provider: HashMap::new(),
+            model_filters: HashMap::new(),
+            tool_limits: HashMap::new(),
+            no_thinking: HashSet::new(),
+            request_id_headers: HashMap::new(),
+            forward_headers: HashMap::new(),
+            trim_text: HashSet::new(),
+            non_streaming_retry: HashSet::new(),
+            model_rewrites: HashMap::new(),
+            history_compactors: HashMap::new(),
+            passthrough: HashSet::new(),
+            coalesce_system: HashMap::new(),
         }
     }
 
@@ -33,6 +72,10 @@ impl ProviderRegistry {
 
             // Get API key - required for API key auth, skipped for OAuth
             let api_key = match &config.auth_type {
+                // Vertex-hosted Anthropic authenticates with ADC; api_key is an optional access token
+                super::AuthType::ApiKey if config.provider_type == "anthropic-vertex" => {
+                    config.api_key.clone().unwrap_or_default()
+                }
                 super::AuthType::ApiKey => {
                     config.api_key.clone().ok_or_else(|| {
                         ProviderError::ConfigError(
@@ -57,7 +100,7 @@ impl ProviderRegistry {
                     config.models.clone(),
                     config.oauth_provider.clone(),
                     token_store.clone(),
-                )),
+                ).with_paths(config.paths.clone())),
 
                 // Anthropic-compatible providers
                 "anthropic" => Box::new(AnthropicCompatibleProvider::new(
@@ -67,78 +110,97 @@ impl ProviderRegistry {
                     config.models.clone(),
                     config.oauth_provider.clone(),
                     token_store.clone(),
-                )),
+                ).with_paths(config.paths.clone())
+                    .with_betas(config.betas.clone())
+                    .with_organization_id(config.organization_id.clone())),
                 "z.ai" => Box::new(AnthropicCompatibleProvider::zai(
                     api_key,
                     config.models.clone(),
                     token_store.clone(),
-                )),
+                ).with_paths(config.paths.clone())
+                    .with_betas(config.betas.clone())
+                    .with_organization_id(config.organization_id.clone())),
                 "minimax" => Box::new(AnthropicCompatibleProvider::minimax(
                     api_key,
                     config.models.clone(),
                     token_store.clone(),
-                )),
+                ).with_paths(config.paths.clone())
+                    .with_betas(config.betas.clone())
+                    .with_organization_id(config.organization_id.clone())),
                 "zenmux" => Box::new(AnthropicCompatibleProvider::zenmux(
                     api_key,
                     config.models.clone(),
                     token_store.clone(),
-                )),
+                ).with_paths(config.paths.clone())
+                    .with_betas(config.betas.clone())
+                    .with_organization_id(config.organization_id.clone())),
                 "kimi-coding" => Box::new(AnthropicCompatibleProvider::kimi_coding(
                     api_key,
                     config.models.clone(),
                     token_store.clone(),
-                )),
+                ).with_paths(config.paths.clone())
+                    .with_betas(config.betas.clone())
+                    .with_organization_id(config.organization_id.clone())),
 
                 // OpenAI-compatible providers
                 "openrouter" => Box::new(OpenAIProvider::openrouter(
                     config.name.clone(),
                     api_key,
                     config.models.clone(),
-                )),
+                ).with_paths(config.paths.clone())),
                 "deepinfra" => Box::new(OpenAIProvider::deepinfra(
                     config.name.clone(),
                     api_key,
                     config.models.clone(),
-                )),
+                ).with_paths(config.paths.clone())),
                 "novita" => Box::new(OpenAIProvider::novita(
                     config.name.clone(),
                     api_key,
                     config.models.clone(),
-                )),
+                ).with_paths(config.paths.clone())),
                 "baseten" => Box::new(OpenAIProvider::baseten(
                     config.name.clone(),
                     api_key,
                     config.models.clone(),
-                )),
+                ).with_paths(config.paths.clone())),
                 "together" => Box::new(OpenAIProvider::together(
                     config.name.clone(),
                     api_key,
                     config.models.clone(),
-                )),
+                ).with_paths(config.paths.clone())),
                 "fireworks" => Box::new(OpenAIProvider::fireworks(
                     config.name.clone(),
                     api_key,
                     config.models.clone(),
-                )),
+                ).with_paths(config.paths.clone())),
                 "groq" => Box::new(OpenAIProvider::groq(
                     config.name.clone(),
                     api_key,
                     config.models.clone(),
-                )),
+                ).with_base_url(config.base_url.clone())
+                    .with_paths(config.paths.clone())),
                 "nebius" => Box::new(OpenAIProvider::nebius(
                     config.name.clone(),
                     api_key,
                     config.models.clone(),
-                )),
+                ).with_paths(config.paths.clone())),
                 "cerebras" => Box::new(OpenAIProvider::cerebras(
                     config.name.clone(),
                     api_key,
                     config.models.clone(),
-                )),
+                ).with_paths(config.paths.clone())),
                 "moonshot" => Box::new(OpenAIProvider::moonshot(
                     config.name.clone(),
                     api_key,
                     config.models.clone(),
+                ).with_paths(config.paths.clone())),
+
+                // Cohere (Command models)
+                "cohere" => Box::new(CohereProvider::new(
+                    config.name.clone(),
+                    api_key,
+                    config.base_url.clone().unwrap_or_else(|| "https://api.cohere.com".to_string()),
+                    config.models.clone(),
                 )),
 
                 // Google Gemini (supports OAuth, API Key, Vertex AI)
@@ -159,7 +221,21 @@ impl ProviderRegistry {
                         token_store.clone(),
                         None, // No project_id/location for Gemini (AI Studio/OAuth only)
                         None,
-                    ))
+                    ).with_structured_tool_output(config.structured_tool_output)
+                        .with_safety_details(config.safety_details)
+                        .with_google_search_grounding(config.google_search_grounding)
+                        .with_top_k(config.top_k)
+                        .with_preserve_response_id(config.preserve_response_id)
+                        .with_retries(config.max_retries, config.retry_base_delay_ms)
+                        .with_max_retry_delay(config.max_retry_delay_ms)
+                        .with_structured_output_validation(config.structured_output_validation)
+                        .with_empty_tool_args_retry(config.retry_empty_tool_args)
+                        .with_candidates(config.candidate_count, config.candidate_selection)
+                        .with_system_instruction_role(config.system_instruction_role.clone())
+                        .with_safety_settings(config.safety_settings.clone())
+                        .with_max_image_bytes(config.max_image_bytes)
+                        .with_private_image_urls(config.allow_private_image_urls)
+                        .with_timeouts(config.timeout_secs, config.connect_timeout_secs, config.stream_timeout_secs))
                 }
 
                 "vertex-ai" => {
@@ -173,9 +249,43 @@ impl ProviderRegistry {
                         HashMap::new(), // custom headers
                         None, // No OAuth for Vertex AI
                         token_store.clone(),
-                        config.project_id.clone(), // GCP project ID
-                        config.location.clone(),   // GCP location
-                    ))
+                        config.gcp_project_id(), // GCP project ID
+                        config.gcp_location(),   // GCP location
+                    ).with_structured_tool_output(config.structured_tool_output)
+                        .with_safety_details(config.safety_details)
+                        .with_google_search_grounding(config.google_search_grounding)
+                        .with_top_k(config.top_k)
+                        .with_preserve_response_id(config.preserve_response_id)
+                        .with_retries(config.max_retries, config.retry_base_delay_ms)
+                        .with_max_retry_delay(config.max_retry_delay_ms)
+                        .with_structured_output_validation(config.structured_output_validation)
+                        .with_empty_tool_args_retry(config.retry_empty_tool_args)
+                        .with_candidates(config.candidate_count, config.candidate_selection)
+                        .with_system_instruction_role(config.system_instruction_role.clone())
+                        .with_safety_settings(config.safety_settings.clone())
+                        .with_max_image_bytes(config.max_image_bytes)
+                        .with_private_image_urls(config.allow_private_image_urls)
+                        .with_timeouts(config.timeout_secs, config.connect_timeout_secs, config.stream_timeout_secs))
+                }
+
+                "anthropic-vertex" => {
+                    let (Some(project_id), Some(location)) = (config.gcp_project_id(), config.gcp_location()) else {
+                        return Err(ProviderError::ConfigError(
+                            format!(
+                                "Provider '{}' requires project_id and location (or GOOGLE_CLOUD_PROJECT and GOOGLE_CLOUD_REGION)",
+                                config.name
+                            )
+                        ));
+                    };
+
+                    Box::new(AnthropicVertexProvider::new(
+                        config.name.clone(),
+                        project_id,
+                        location,
+                        config.base_url.clone(),
+                        config.models.clone(),
+                        Some(api_key).filter(|k| !k.is_empty()),
+                    ).with_betas(config.betas.clone()))
                 }
 
                 other => {
@@ -189,6 +299,62 @@ impl ProviderRegistry {
             // Model mappings are now defined in [[models]] section
             // We only register the provider by name
 
+            if !config.model_filter.is_empty() {
+                registry.model_filters.insert(config.name.clone(), config.model_filter.clone());
+            }
+            if !config.tool_limit.is_empty() {
+                registry.tool_limits.insert(config.name.clone(), config.tool_limit.clone());
+            }
+            if !config.supports_thinking() {
+                registry.no_thinking.insert(config.name.clone());
+            }
+            if config.coalesce_system_blocks {
+                registry.coalesce_system.insert(config.name.clone(), config.supports_cache_control());
+            }
+            if let Some(compactor) = HistoryCompactor::from_limit(&config.history_limit) {
+                registry.history_compactors.insert(config.name.clone(), compactor);
+            }
+            let model_rewrite = config.model_rewrite();
+            if !model_rewrite.is_empty() {
+                registry.model_rewrites.insert(config.name.clone(), model_rewrite);
+            }
+            if config.trim_response_text {
+                registry.trim_text.insert(config.name.clone());
+            }
+            if config.non_streaming_retry {
+                registry.non_streaming_retry.insert(config.name.clone());
+            }
+            if config.passthrough {
+                if !config.is_anthropic_compatible() {
+                    return Err(ProviderError::ConfigError(format!(
+                        "Provider '{}': passthrough requires an Anthropic-compatible provider_type, not '{}'",
+                        config.name, config.provider_type
+                    )));
+                }
+                registry.passthrough.insert(config.name.clone());
+            }
+            if let Some(header) = &config.request_id_header {
+                registry.request_id_headers.insert(config.name.clone(), header.clone());
+            }
+            let mut forward: Vec<String> = config
+                .forward_headers
+                .iter()
+                .map(|h| h.to_ascii_lowercase())
+                .filter(|h| {
+                    let allowed = h.starts_with("x-");
+                    if !allowed {
+                        tracing::warn!("⚠️ Provider {}: not forwarding '{}' (only x- headers can be forwarded)", config.name, h);
+                    }
+                    allowed
+                })
+                .collect();
+            if config.is_anthropic_compatible() {
+                forward.push(ORGANIZATION_HEADER.to_string());
+            }
+            if !forward.is_empty() {
+                registry.forward_headers.insert(config.name.clone(), forward);
+            }
+
             // Add provider to registry
             registry.providers.insert(config.name.clone(), Arc::new(provider));
         }
@@ -201,23 +367,132 @@ impl ProviderRegistry {
         self.providers.get(name).cloned()
     }
 
+    /// Whether the provider's allow/deny list permits serving `model`
+    pub fn is_model_allowed(&self, provider_name: &str, model: &str) -> bool {
+        self.model_filters
+            .get(provider_name)
+            .is_none_or(|filter| filter.permits(model))
+    }
+
+    /// Whether the provider accepts extended thinking requests
+    pub fn supports_thinking(&self, provider_name: &str) -> bool {
+        !self.no_thinking.contains(provider_name)
+    }
+
+    /// Summarize old turns if the history is over this provider's byte limit
+    pub async fn compact_history(&self, provider_name: &str, request: &mut AnthropicRequest) {
+        let Some(compactor) = self.history_compactors.get(provider_name) else {
+            return;
+        };
+        let summarized = compactor.compact(request).await;
+        if summarized > 0 {
+            tracing::info!(
+                "📜 Summarized {} old messages for provider {}",
+                summarized,
+                provider_name
+            );
+        }
+    }
+
+    /// Model name to send to the provider for a mapping's `actual_model`
+    pub fn upstream_model(&self, provider_name: &str, model: &str) -> String {
+        match self.model_rewrites.get(provider_name) {
+            Some(rewrite) => {
+                let rewritten = rewrite.apply(model);
+                if rewritten != model {
+                    tracing::debug!("🏷️ Rewrote model for provider {}: {} → {}", provider_name, model, rewritten);
+                }
+                rewritten
+            }
+            None => model.to_string(),
+        }
+    }
+
+    /// Whether response text from this provider should be trimmed
+    pub fn trims_response_text(&self, provider_name: &str) -> bool {
+        self.trim_text.contains(provider_name)
+    }
+
+    /// Whether a stream from this provider that fails before any content is
+    /// retried as a non-streaming request
+    pub fn retries_non_streaming(&self, provider_name: &str) -> bool {
+        self.non_streaming_retry.contains(provider_name)
+    }
+
+    /// Whether requests to this provider skip request/response transformation
+    pub fn is_passthrough(&self, provider_name: &str) -> bool {
+        self.passthrough.contains(provider_name)
+    }
+
+    /// Attach the request id header if the provider is configured to receive one
+    pub fn attach_request_id(&self, provider_name: &str, request_id: &str, request: &mut AnthropicRequest) {
+        request.request_id_header = self
+            .request_id_headers
+            .get(provider_name)
+            .map(|header| (header.clone(), request_id.to_string()));
+    }
+
+    /// Copy the client headers the provider is configured to receive onto the request
+    pub fn attach_forwarded_headers(&self, provider_name: &str, headers: &HeaderMap, request: &mut AnthropicRequest) {
+        request.forwarded_headers = self.forwarded_headers(provider_name, headers);
+    }
+
+    /// The client headers this provider forwards upstream, with their values
+    pub fn forwarded_headers(&self, provider_name: &str, headers: &HeaderMap) -> Vec<(String, String)> {
+        self.forward_headers
+            .get(provider_name)
+            .into_iter()
+            .flatten()
+            .filter_map(|name| Some((name.clone(), headers.get(name)?.to_str().ok()?.to_string())))
+            .collect()
+    }
+
+    /// Merge the system blocks of a request about to be sent to the provider, if it
+    /// asks for that (see `ProviderConfig::coalesce_system_blocks`)
+    pub fn coalesce_system(&self, provider_name: &str, request: &mut AnthropicRequest) {
+        if let (Some(&preserve_cache_control), Some(system)) = (self.coalesce_system.get(provider_name), request.system.as_mut()) {
+            system.coalesce(preserve_cache_control);
+        }
+    }
+
+    /// Apply the provider's tool limits to a request about to be sent to it
+    pub fn trim_tools(&self, provider_name: &str, request: &mut AnthropicRequest) {
+        let Some(limit) = self.tool_limits.get(provider_name) else {
+            return;
+        };
+        let before = request.tools.as_ref().map_or(0, |t| t.len());
+        let dropped = limit.apply(request);
+        if dropped > 0 {
+            tracing::info!(
+                "✂️ Trimmed tools for provider {}: {} → {}",
+                provider_name,
+                before,
+                before - dropped
+            );
+        }
+    }
+
     /// Get a provider for a specific model
     pub fn get_provider_for_model(&self, model: &str) -> Result<Arc<Box<dyn AnthropicProvider>>, ProviderError> {
+        self.provider_name_for_model(model)
+            .and_then(|name| self.providers.get(name).cloned())
+            .ok_or_else(|| ProviderError::ModelNotSupported(model.to_string()))
+    }
+
+    /// Name of the provider `get_provider_for_model` picks for a model
+    pub fn provider_name_for_model(&self, model: &str) -> Option<&str> {
         // First, check if we have a direct model → provider mapping
         if let Some(provider_name) = self.model_to_provider.get(model) {
-            if let Some(provider) = self.providers.get(provider_name) {
-                return Ok(provider.clone());
+            if self.providers.contains_key(provider_name) && self.is_model_allowed(provider_name, model) {
+                return Some(provider_name);
             }
         }
 
         // If no direct mapping, search through all providers
-        for provider in self.providers.values() {
-            if provider.supports_model(model) {
-                return Ok(provider.clone());
-            }
-        }
-
-        Err(ProviderError::ModelNotSupported(model.to_string()))
+        self.providers
+            .iter()
+            .find(|(name, provider)| provider.supports_model(model) && self.is_model_allowed(name, model))
+            .map(|(name, _)| name.as_str())
     }
 
     /// List all available models
@@ -254,4 +529,236 @@ mod tests {
         let result = registry.get_provider_for_model("gpt-4");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_denylisted_model_skipped() {
+        let config: ProviderConfig = serde_json::from_value(serde_json::json!({
+            "name": "openrouter",
+            "provider_type": "openrouter",
+            "api_key": "key",
+            "models": ["openai/gpt-4o", "openai/o1-pro"],
+            "allowed_models": ["openai/*"],
+            "denied_models": ["*o1-pro"]
+        }))
+        .unwrap();
+        let registry = ProviderRegistry::from_configs(&[config], None).unwrap();
+
+        assert!(registry.is_model_allowed("openrouter", "openai/gpt-4o"));
+        assert!(!registry.is_model_allowed("openrouter", "openai/o1-pro"));
+        assert!(!registry.is_model_allowed("openrouter", "anthropic/claude-opus-4"));
+        // Providers without lists allow everything
+        assert!(registry.is_model_allowed("other", "anything"));
+
+        assert!(registry.get_provider_for_model("openai/gpt-4o").is_ok());
+        assert!(registry.get_provider_for_model("openai/o1-pro").is_err());
+        assert_eq!(registry.provider_name_for_model("openai/gpt-4o"), Some("openrouter"));
+        assert_eq!(registry.provider_name_for_model("openai/o1-pro"), None);
+    }
+
+    #[test]
+    fn test_thinking_support_defaults() {
+        let provider = |name: &str, provider_type: &str| -> ProviderConfig {
+            serde_json::from_value(serde_json::json!({
+                "name": name,
+                "provider_type": provider_type,
+                "api_key": "key",
+                "models": []
+            }))
+            .unwrap()
+        };
+        let registry = ProviderRegistry::from_configs(
+            &[provider("kimi", "kimi-coding"), provider("vertex", "vertex-ai"), provider("groq", "groq")],
+            None,
+        )
+        .unwrap();
+
+        // Anthropic-format and Gemini providers keep thinking; others have it stripped by default
+        assert!(registry.supports_thinking("kimi"));
+        assert!(registry.supports_thinking("vertex"));
+        assert!(!registry.supports_thinking("groq"));
+    }
+
+    #[tokio::test]
+    async fn test_vertex_project_and_location_from_env() {
+        let env = |name: &str| match name {
+            "GOOGLE_CLOUD_PROJECT" => Some("env-project".to_string()),
+            "GOOGLE_CLOUD_REGION" => Some("europe-west1".to_string()),
+            _ => None,
+        };
+
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock(
+                "POST",
+                "/projects/env-project/locations/europe-west1/publishers/anthropic/models/claude-sonnet-4@20250514:rawPredict",
+            )
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"hi"}],
+                    "model":"claude-sonnet-4","stop_reason":"end_turn","stop_sequence":null,
+                    "usage":{"input_tokens":1,"output_tokens":1}}"#,
+            )
+            .create_async()
+            .await;
+
+        let vertex = |project_id: Option<&str>, location: Option<&str>| -> ProviderConfig {
+            serde_json::from_value(serde_json::json!({
+                "name": "vertex-claude",
+                "provider_type": "anthropic-vertex",
+                "api_key": "test-token",
+                "base_url": server.url(),
+                "project_id": project_id,
+                "location": location,
+                "models": []
+            }))
+            .unwrap()
+        };
+
+        // Neither project_id nor location configured: both come from the environment
+        let config = vertex(None, None);
+        assert_eq!(config.gcp_project_id_from(env).as_deref(), Some("env-project"));
+        assert_eq!(config.gcp_location_from(env).as_deref(), Some("europe-west1"));
+
+        // The registry builds the Vertex endpoint from them
+        let config = vertex(Some("env-project"), Some("europe-west1"));
+        let registry = ProviderRegistry::from_configs(&[config], None).unwrap();
+        let provider = registry.get_provider("vertex-claude").unwrap();
+        let request = serde_json::from_value(serde_json::json!({
+            "model": "claude-sonnet-4@20250514",
+            "max_tokens": 16,
+            "messages": [{ "role": "user", "content": "hello" }]
+        }))
+        .unwrap();
+        provider.send_message(request).await.unwrap();
+        mock.assert_async().await;
+
+        // Explicit per-provider settings take precedence
+        let config = vertex(Some("own-project"), None);
+        assert_eq!(config.gcp_project_id_from(env).as_deref(), Some("own-project"));
+        assert_eq!(config.gcp_location_from(env).as_deref(), Some("europe-west1"));
+    }
+
+    #[test]
+    fn test_system_blocks_coalesced_per_provider() {
+        let provider = |name: &str, provider_type: &str| -> ProviderConfig {
+            serde_json::from_value(serde_json::json!({
+                "name": name,
+                "provider_type": provider_type,
+                "api_key": "key",
+                "models": [],
+                "coalesce_system_blocks": name != "untouched"
+            }))
+            .unwrap()
+        };
+        let registry = ProviderRegistry::from_configs(
+            &[provider("claude", "anthropic"), provider("groq", "groq"), provider("untouched", "anthropic")],
+            None,
+        )
+        .unwrap();
+        let request = || -> AnthropicRequest {
+            serde_json::from_value(serde_json::json!({
+                "model": "m",
+                "max_tokens": 16,
+                "messages": [{ "role": "user", "content": "hi" }],
+                "system": [
+                    { "type": "text", "text": "a" },
+                    { "type": "text", "text": "b", "cache_control": { "type": "ephemeral" } },
+                    { "type": "text", "text": "c" },
+                    { "type": "text", "text": "d" }
+                ]
+            }))
+            .unwrap()
+        };
+        let system = |provider: &str| {
+            let mut request = request();
+            registry.coalesce_system(provider, &mut request);
+            serde_json::to_value(request.system).unwrap()
+        };
+
+        // Caching provider: one block per breakpoint, cache_control kept
+        assert_eq!(
+            system("claude"),
+            serde_json::json!([
+                { "type": "text", "text": "a\nb", "cache_control": { "type": "ephemeral" } },
+                { "type": "text", "text": "c\nd" }
+            ])
+        );
+        // No cache_control support: flattened to text
+        assert_eq!(system("groq"), serde_json::json!("a\nb\nc\nd"));
+        // Not enabled: left alone
+        assert_eq!(system("untouched").as_array().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_providers_share_connection_pool() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Minimal keep-alive HTTP server counting the connections it accepts
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let body = r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"ok"}],"model":"up","stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":1,"output_tokens":1}}"#;
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 4096];
+                    loop {
+                        let Ok(n) = socket.read(&mut buf).await else { return };
+                        if n == 0 {
+                            return;
+                        }
+                        request.extend_from_slice(&buf[..n]);
+                        let text = String::from_utf8_lossy(&request).to_string();
+                        let Some(header_end) = text.find("\r\n\r\n") else { continue };
+                        let length: usize = text[..header_end]
+                            .lines()
+                            .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse().unwrap()))
+                            .unwrap_or(0);
+                        if request.len() < header_end + 4 + length {
+                            continue;
+                        }
+                        request.clear();
+                        let response = format!(
+                            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                            body.len(),
+                            body
+                        );
+                        if socket.write_all(response.as_bytes()).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+
+        let provider = |name: &str| -> ProviderConfig {
+            serde_json::from_value(serde_json::json!({
+                "name": name,
+                "provider_type": "anthropic",
+                "api_key": "key",
+                "base_url": base_url,
+                "models": []
+            }))
+            .unwrap()
+        };
+        let registry = ProviderRegistry::from_configs(&[provider("first"), provider("second")], None).unwrap();
+        let request = || -> AnthropicRequest {
+            serde_json::from_value(serde_json::json!({
+                "model": "up",
+                "max_tokens": 16,
+                "messages": [{ "role": "user", "content": "hi" }]
+            }))
+            .unwrap()
+        };
+
+        for name in ["first", "second", "first", "second"] {
+            registry.get_provider(name).unwrap().send_message(request()).await.unwrap();
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
 }
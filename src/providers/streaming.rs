@@ -1,8 +1,72 @@
+use super::error::ProviderError;
+use super::ProviderResponse;
+use crate::models::ContentBlock;
 use bytes::Bytes;
-use futures::stream::Stream;
+use futures::stream::{Stream, StreamExt};
 use pin_project::pin_project;
+use serde_json::json;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// Boxed stream of provider output, as returned by `send_message_stream`
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, ProviderError>> + Send>>;
+
+/// Boxed stream of parsed SSE events
+pub type EventStream = Pin<Box<dyn Stream<Item = Result<SseEvent, ProviderError>> + Send>>;
+
+/// Body format a provider response is expected to carry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpectedBody {
+    /// Server-Sent Events (`text/event-stream`)
+    Sse,
+    /// Plain JSON document
+    Json,
+}
+
+impl std::fmt::Display for ExpectedBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExpectedBody::Sse => write!(f, "SSE stream (text/event-stream)"),
+            ExpectedBody::Json => write!(f, "JSON (application/json)"),
+        }
+    }
+}
+
+/// Check that a successful upstream response has the body format we asked for.
+/// Responses without a Content-Type are accepted as-is.
+pub fn check_content_type(
+    headers: &reqwest::header::HeaderMap,
+    expected: ExpectedBody,
+) -> Result<(), ProviderError> {
+    let Some(content_type) = headers
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return Ok(());
+    };
+
+    let content_type = content_type.to_ascii_lowercase();
+    let actual = if content_type.starts_with("text/event-stream") {
+        ExpectedBody::Sse
+    } else if content_type.contains("json") {
+        ExpectedBody::Json
+    } else {
+        // Unknown formats (text/plain, etc.) are left to the body parser
+        return Ok(());
+    };
+
+    if actual == expected {
+        Ok(())
+    } else {
+        tracing::warn!("⚠️ Upstream returned {} but {} was expected", actual, expected);
+        Err(ProviderError::ContentTypeMismatch {
+            expected: expected.to_string(),
+            actual: actual.to_string(),
+        })
+    }
+}
 
 /// SSE event from provider
 #[derive(Debug, Clone)]
@@ -12,6 +76,21 @@ pub struct SseEvent {
 }
 
 impl SseEvent {
+    /// Build an Anthropic Messages stream event; the SSE event name is taken
+    /// from the payload's `type` field
+    pub fn anthropic(data: serde_json::Value) -> Self {
+        let event = data.get("type").and_then(|t| t.as_str()).map(|t| t.to_string());
+        Self {
+            event,
+            data: data.to_string(),
+        }
+    }
+
+    /// Parse the data payload as JSON
+    pub fn json(&self) -> Option<serde_json::Value> {
+        serde_json::from_str(&self.data).ok()
+    }
+
     /// Format as SSE output for client
     pub fn to_sse_string(&self) -> String {
         let mut output = String::new();
@@ -41,13 +120,15 @@ pub fn parse_sse_events(input: &str) -> Vec<SseEvent> {
                 });
                 current_data.clear();
             }
-        } else if let Some(data) = line.strip_prefix("data: ") {
+        } else if let Some(data) = line.strip_prefix("data:") {
+            // The space after the colon is optional
+            let data = data.strip_prefix(' ').unwrap_or(data);
             if !current_data.is_empty() {
                 current_data.push('\n');
             }
             current_data.push_str(data);
-        } else if let Some(event) = line.strip_prefix("event: ") {
-            current_event = Some(event.to_string());
+        } else if let Some(event) = line.strip_prefix("event:") {
+            current_event = Some(event.strip_prefix(' ').unwrap_or(event).to_string());
         }
         // Ignore other fields like "id:", "retry:", etc.
     }
@@ -63,6 +144,725 @@ pub fn parse_sse_events(input: &str) -> Vec<SseEvent> {
     events
 }
 
+/// Incremental SSE parser that only yields events once their terminating
+/// blank line has arrived, keeping partial events buffered across chunks
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buffer: String,
+    /// Trailing bytes of a UTF-8 sequence split across chunks
+    partial: Vec<u8>,
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed a chunk of bytes and return all events completed by it
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.partial.extend_from_slice(chunk);
+        // Decode up to an incomplete trailing sequence; invalid bytes become U+FFFD
+        let complete = match std::str::from_utf8(&self.partial) {
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            _ => self.partial.len(),
+        };
+        let bytes: Vec<u8> = self.partial.drain(..complete).collect();
+        self.buffer.push_str(&String::from_utf8_lossy(&bytes));
+        if self.buffer.contains('\r') {
+            self.buffer = self.buffer.replace("\r\n", "\n");
+        }
+
+        match self.buffer.rfind("\n\n") {
+            Some(end) => {
+                let complete: String = self.buffer.drain(..end + 2).collect();
+                parse_sse_events(&complete)
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Flush whatever is left in the buffer at end of stream
+    pub fn finish(&mut self) -> Vec<SseEvent> {
+        let partial = std::mem::take(&mut self.partial);
+        self.buffer.push_str(&String::from_utf8_lossy(&partial));
+        let rest = std::mem::take(&mut self.buffer);
+        parse_sse_events(&rest)
+    }
+}
+
+/// Converts a sequence of upstream SSE events into Anthropic Messages SSE events
+pub trait StreamTranslator: Send {
+    /// Translate one upstream event into zero or more output events
+    fn translate(&mut self, event: SseEvent) -> Vec<SseEvent>;
+
+    /// Called once the upstream stream has ended
+    fn finish(&mut self) -> Vec<SseEvent> {
+        Vec::new()
+    }
+
+    /// True once the translator has ended the message itself; the upstream
+    /// stream is then dropped (closing the connection) instead of drained
+    fn is_done(&self) -> bool {
+        false
+    }
+}
+
+/// Decode a byte stream into SSE events and run them through a translator
+pub fn translate_events<S, T>(inner: S, translator: T) -> EventStream
+where
+    S: Stream<Item = Result<Bytes, ProviderError>> + Send + 'static,
+    T: StreamTranslator + 'static,
+{
+    struct State<S, T> {
+        inner: Pin<Box<S>>,
+        decoder: SseDecoder,
+        translator: T,
+        pending: VecDeque<SseEvent>,
+        done: bool,
+    }
+
+    let state = State {
+        inner: Box::pin(inner),
+        decoder: SseDecoder::new(),
+        translator,
+        pending: VecDeque::new(),
+        done: false,
+    };
+
+    Box::pin(futures::stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(event) = state.pending.pop_front() {
+                return Some((Ok(event), state));
+            }
+            if state.done || state.translator.is_done() {
+                return None;
+            }
+
+            match state.inner.next().await {
+                Some(Ok(bytes)) => {
+                    for event in state.decoder.push(&bytes) {
+                        state.pending.extend(state.translator.translate(event));
+                        if state.translator.is_done() {
+                            break;
+                        }
+                    }
+                }
+                Some(Err(e)) => {
+                    state.done = true;
+                    return Some((Err(e), state));
+                }
+                None => {
+                    state.done = true;
+                    for event in state.decoder.finish() {
+                        state.pending.extend(state.translator.translate(event));
+                    }
+                    state.pending.extend(state.translator.finish());
+                }
+            }
+        }
+    }))
+}
+
+/// Run an already-decoded event stream through another translator
+pub fn map_events<T>(inner: EventStream, translator: T) -> EventStream
+where
+    T: StreamTranslator + 'static,
+{
+    struct State<T> {
+        inner: EventStream,
+        translator: T,
+        pending: VecDeque<SseEvent>,
+        done: bool,
+    }
+
+    let state = State {
+        inner,
+        translator,
+        pending: VecDeque::new(),
+        done: false,
+    };
+
+    Box::pin(futures::stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(event) = state.pending.pop_front() {
+                return Some((Ok(event), state));
+            }
+            if state.done || state.translator.is_done() {
+                return None;
+            }
+
+            match state.inner.next().await {
+                Some(Ok(event)) => state.pending.extend(state.translator.translate(event)),
+                Some(Err(e)) => {
+                    state.done = true;
+                    return Some((Err(e), state));
+                }
+                None => {
+                    state.done = true;
+                    state.pending.extend(state.translator.finish());
+                }
+            }
+        }
+    }))
+}
+
+/// Serialize an event stream back into SSE bytes
+pub fn encode_events(events: EventStream) -> ByteStream {
+    Box::pin(events.map(|result| result.map(|event| Bytes::from(event.to_sse_string()))))
+}
+
+/// End an Anthropic event stream cleanly when the upstream fails part-way through.
+///
+/// Events already produced are kept; instead of the error cutting the connection,
+/// any open content block is closed and an `error` event ends the stream, so the
+/// client still has the partial message and knows it is incomplete.
+pub fn end_on_error(events: EventStream) -> EventStream {
+    struct State {
+        inner: EventStream,
+        open_block: Option<serde_json::Value>,
+        message_stopped: bool,
+        pending: VecDeque<SseEvent>,
+        done: bool,
+    }
+
+    let state = State {
+        inner: events,
+        open_block: None,
+        message_stopped: false,
+        pending: VecDeque::new(),
+        done: false,
+    };
+
+    Box::pin(futures::stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(event) = state.pending.pop_front() {
+                return Some((Ok(event), state));
+            }
+            if state.done {
+                return None;
+            }
+
+            match state.inner.next().await {
+                Some(Ok(event)) => {
+                    if let Some(data) = event.json() {
+                        match data.get("type").and_then(|t| t.as_str()) {
+                            Some("content_block_start") => state.open_block = data.get("index").cloned(),
+                            Some("content_block_stop") => state.open_block = None,
+                            Some("message_stop") => state.message_stopped = true,
+                            _ => {}
+                        }
+                    }
+                    return Some((Ok(event), state));
+                }
+                Some(Err(e)) => {
+                    state.done = true;
+                    if state.message_stopped {
+                        tracing::debug!("Stream error after message_stop: {}", e);
+                        continue;
+                    }
+                    tracing::warn!("⚠️ Upstream stream failed mid-response, ending it with an error event: {}", e);
+                    if let Some(index) = state.open_block.take() {
+                        state.pending.push_back(SseEvent::anthropic(serde_json::json!({
+                            "type": "content_block_stop",
+                            "index": index
+                        })));
+                    }
+                    state.pending.push_back(SseEvent::anthropic(serde_json::json!({
+                        "type": "error",
+                        "error": { "type": "api_error", "message": e.to_string() }
+                    })));
+                }
+                None => return None,
+            }
+        }
+    }))
+}
+
+/// Wait for a stream's first content block, so a failure before any content
+/// can be told apart from one part-way through.
+///
+/// Events up to the first `content_block_start` (or `message_stop`) are buffered;
+/// an error, or an Anthropic `error` event, among them is returned as `Err`.
+/// Otherwise the buffered events are replayed ahead of the rest of the stream.
+pub async fn first_content(mut events: EventStream) -> Result<EventStream, ProviderError> {
+    let mut buffered = Vec::new();
+    while let Some(item) = events.next().await {
+        let event = item?;
+        let data = event.json();
+        match data.as_ref().and_then(|d| d.get("type")).and_then(|t| t.as_str()) {
+            Some("error") => {
+                let data = data.unwrap_or_default();
+                let message = data
+                    .pointer("/error/message")
+                    .and_then(|m| m.as_str())
+                    .unwrap_or("stream error event");
+                return Err(ProviderError::ApiError {
+                    status: if data.pointer("/error/type") == Some(&json!("overloaded_error")) { 529 } else { 500 },
+                    message: message.to_string(),
+                });
+            }
+            Some("content_block_start") | Some("message_stop") => {
+                buffered.push(Ok(event));
+                break;
+            }
+            _ => buffered.push(Ok(event)),
+        }
+    }
+    Ok(Box::pin(futures::stream::iter(buffered).chain(events)))
+}
+
+/// Anthropic stream events for a complete response: each content block is sent as
+/// a single delta. Used to replay a non-streaming response to a streaming client.
+pub fn response_events(response: &ProviderResponse) -> Vec<SseEvent> {
+    let mut events = vec![SseEvent::anthropic(json!({
+        "type": "message_start",
+        "message": {
+            "id": response.id,
+            "type": "message",
+            "role": "assistant",
+            "content": [],
+            "model": response.model,
+            "stop_reason": null,
+            "stop_sequence": null,
+            "usage": { "input_tokens": response.usage.input_tokens, "output_tokens": 0 }
+        }
+    }))];
+
+    for (index, block) in response.content.iter().enumerate() {
+        let (start, deltas) = match block {
+            ContentBlock::Text { text } => (
+                json!({ "type": "text", "text": "" }),
+                vec![json!({ "type": "text_delta", "text": text })],
+            ),
+            ContentBlock::ToolUse { id, name, input } => (
+                json!({ "type": "tool_use", "id": id, "name": name, "input": {} }),
+                vec![json!({ "type": "input_json_delta", "partial_json": input.to_string() })],
+            ),
+            ContentBlock::Thinking { thinking, signature } => {
+                let mut deltas = vec![json!({ "type": "thinking_delta", "thinking": thinking })];
+                if !signature.is_empty() {
+                    deltas.push(json!({ "type": "signature_delta", "signature": signature }));
+                }
+                (json!({ "type": "thinking", "thinking": "" }), deltas)
+            }
+            // Responses don't carry other block types
+            _ => continue,
+        };
+        events.push(SseEvent::anthropic(json!({
+            "type": "content_block_start",
+            "index": index,
+            "content_block": start
+        })));
+        for delta in deltas {
+            events.push(SseEvent::anthropic(json!({
+                "type": "content_block_delta",
+                "index": index,
+                "delta": delta
+            })));
+        }
+        events.push(SseEvent::anthropic(json!({ "type": "content_block_stop", "index": index })));
+    }
+
+    let mut usage = json!({
+        "input_tokens": response.usage.input_tokens,
+        "output_tokens": response.usage.output_tokens
+    });
+    if let Some(cached) = response.usage.cache_read_input_tokens {
+        usage["cache_read_input_tokens"] = json!(cached);
+    }
+    events.push(SseEvent::anthropic(json!({
+        "type": "message_delta",
+        "delta": { "stop_reason": response.stop_reason, "stop_sequence": response.stop_sequence },
+        "usage": usage
+    })));
+    events.push(SseEvent::anthropic(json!({ "type": "message_stop" })));
+    events
+}
+
+/// Estimate tokens from text length (~4 chars per token), used when an
+/// upstream does not report usage
+pub fn estimate_tokens(chars: usize) -> u32 {
+    chars.div_ceil(4) as u32
+}
+
+/// Ensures every Anthropic stream ends with a usage-bearing `message_delta`.
+///
+/// Upstreams (or our own translators) may report usage part-way through a
+/// stream via `message_delta` events without a `stop_reason`. Those are only
+/// forwarded when the client asked for `stream_options.include_usage`; the
+/// final delta always carries the accumulated usage, estimated from the
+/// streamed text if the upstream never reported any.
+pub struct UsageFinalizer {
+    include_usage: bool,
+    input_tokens: u32,
+    output_tokens: Option<u32>,
+    streamed_chars: usize,
+    stop_reason: Option<serde_json::Value>,
+    saw_tool_use: bool,
+    final_delta_sent: bool,
+}
+
+impl UsageFinalizer {
+    pub fn new(include_usage: bool) -> Self {
+        Self {
+            include_usage,
+            input_tokens: 0,
+            output_tokens: None,
+            streamed_chars: 0,
+            stop_reason: None,
+            saw_tool_use: false,
+            final_delta_sent: false,
+        }
+    }
+
+    fn record_usage(&mut self, usage: &serde_json::Value) {
+        if let Some(input) = usage.get("input_tokens").and_then(|v| v.as_u64()) {
+            if input > 0 {
+                self.input_tokens = input as u32;
+            }
+        }
+        if let Some(output) = usage.get("output_tokens").and_then(|v| v.as_u64()) {
+            self.output_tokens = Some(output as u32);
+        }
+    }
+
+    fn usage(&self) -> serde_json::Value {
+        json!({
+            "input_tokens": self.input_tokens,
+            "output_tokens": self
+                .output_tokens
+                .unwrap_or_else(|| estimate_tokens(self.streamed_chars)),
+        })
+    }
+}
+
+impl StreamTranslator for UsageFinalizer {
+    fn translate(&mut self, event: SseEvent) -> Vec<SseEvent> {
+        let Some(mut data) = event.json() else {
+            return vec![event];
+        };
+
+        match data.get("type").and_then(|t| t.as_str()) {
+            Some("message_start") => {
+                if let Some(usage) = data.pointer("/message/usage") {
+                    let usage = usage.clone();
+                    self.record_usage(&usage);
+                }
+                vec![event]
+            }
+            Some("content_block_start") => {
+                if data.pointer("/content_block/type").and_then(|t| t.as_str()) == Some("tool_use") {
+                    self.saw_tool_use = true;
+                }
+                vec![event]
+            }
+            Some("content_block_delta") => {
+                if let Some(delta) = data.get("delta") {
+                    for key in ["text", "partial_json", "thinking"] {
+                        if let Some(text) = delta.get(key).and_then(|v| v.as_str()) {
+                            self.streamed_chars += text.len();
+                        }
+                    }
+                }
+                vec![event]
+            }
+            Some("message_delta") => {
+                if let Some(usage) = data.get("usage") {
+                    let usage = usage.clone();
+                    self.record_usage(&usage);
+                }
+
+                let stop_reason = data
+                    .pointer("/delta/stop_reason")
+                    .filter(|v| !v.is_null())
+                    .cloned();
+
+                match stop_reason {
+                    Some(reason) => {
+                        self.stop_reason = Some(reason);
+                        self.final_delta_sent = true;
+                        data["usage"] = self.usage();
+                        vec![SseEvent::anthropic(data)]
+                    }
+                    // Intermediate usage update
+                    None if self.include_usage => {
+                        data["usage"] = self.usage();
+                        vec![SseEvent::anthropic(data)]
+                    }
+                    None => Vec::new(),
+                }
+            }
+            Some("message_stop") if !self.final_delta_sent => {
+                self.final_delta_sent = true;
+                // Upstream never sent a stop_reason; infer it from what was streamed
+                let stop_reason = self.stop_reason.clone().unwrap_or_else(|| {
+                    if self.saw_tool_use {
+                        json!("tool_use")
+                    } else {
+                        json!("end_turn")
+                    }
+                });
+                vec![
+                    SseEvent::anthropic(json!({
+                        "type": "message_delta",
+                        "delta": { "stop_reason": stop_reason, "stop_sequence": null },
+                        "usage": self.usage(),
+                    })),
+                    event,
+                ]
+            }
+            _ => vec![event],
+        }
+    }
+}
+
+/// Ends a stream early once a per-request token or cost budget is exceeded.
+///
+/// Output tokens come from upstream usage when reported, otherwise they are
+/// estimated from the streamed text. When the budget is hit the open content
+/// block is closed and the message ends with `stop_reason: "max_tokens"`.
+pub struct BudgetLimiter {
+    max_tokens: Option<u32>,
+    max_output_tokens: Option<u32>,
+    max_cost_usd: Option<f64>,
+    input_cost_per_mtok: f64,
+    output_cost_per_mtok: f64,
+    input_tokens: u32,
+    reported_output_tokens: Option<u32>,
+    streamed_chars: usize,
+    open_block: Option<u64>,
+    exhausted: bool,
+}
+
+impl BudgetLimiter {
+    /// `max_cost_usd` is only enforced when prices (USD per million tokens) are known
+    pub fn new(
+        max_tokens: Option<u32>,
+        max_cost_usd: Option<f64>,
+        input_cost_per_mtok: f64,
+        output_cost_per_mtok: f64,
+    ) -> Self {
+        Self {
+            max_tokens,
+            max_output_tokens: None,
+            max_cost_usd,
+            input_cost_per_mtok,
+            output_cost_per_mtok,
+            input_tokens: 0,
+            reported_output_tokens: None,
+            streamed_chars: 0,
+            open_block: None,
+            exhausted: false,
+        }
+    }
+
+    /// Stop as soon as output tokens reach `max`, regardless of input size
+    pub fn with_max_output_tokens(mut self, max: Option<u32>) -> Self {
+        self.max_output_tokens = max;
+        self
+    }
+
+    fn output_tokens(&self) -> u32 {
+        self.reported_output_tokens
+            .unwrap_or(0)
+            .max(estimate_tokens(self.streamed_chars))
+    }
+
+    fn over_budget(&self) -> bool {
+        let output_tokens = self.output_tokens();
+        if self
+            .max_tokens
+            .is_some_and(|max| self.input_tokens + output_tokens > max)
+        {
+            return true;
+        }
+        if self.max_output_tokens.is_some_and(|max| output_tokens >= max) {
+            return true;
+        }
+        let cost = (self.input_tokens as f64 * self.input_cost_per_mtok
+            + output_tokens as f64 * self.output_cost_per_mtok)
+            / 1_000_000.0;
+        self.max_cost_usd.is_some_and(|max| cost > max)
+    }
+
+    /// Close the message with a max_tokens stop
+    fn stop(&mut self) -> Vec<SseEvent> {
+        self.exhausted = true;
+        tracing::info!(
+            "💸 Request budget exceeded ({} input + {} output tokens), ending stream",
+            self.input_tokens,
+            self.output_tokens()
+        );
+
+        let mut out = Vec::new();
+        if let Some(index) = self.open_block.take() {
+            out.push(SseEvent::anthropic(json!({ "type": "content_block_stop", "index": index })));
+        }
+        out.push(SseEvent::anthropic(json!({
+            "type": "message_delta",
+            "delta": { "stop_reason": "max_tokens", "stop_sequence": null },
+            "usage": { "input_tokens": self.input_tokens, "output_tokens": self.output_tokens() },
+        })));
+        out.push(SseEvent::anthropic(json!({ "type": "message_stop" })));
+        out
+    }
+}
+
+impl StreamTranslator for BudgetLimiter {
+    fn translate(&mut self, event: SseEvent) -> Vec<SseEvent> {
+        if self.exhausted {
+            return Vec::new();
+        }
+        let Some(data) = event.json() else {
+            return vec![event];
+        };
+
+        match data.get("type").and_then(|t| t.as_str()) {
+            Some("message_start") => {
+                if let Some(input) = data.pointer("/message/usage/input_tokens").and_then(|v| v.as_u64()) {
+                    self.input_tokens = input as u32;
+                }
+            }
+            Some("content_block_start") => {
+                self.open_block = data.get("index").and_then(|i| i.as_u64());
+            }
+            Some("content_block_stop") => {
+                self.open_block = None;
+            }
+            Some("content_block_delta") => {
+                if let Some(delta) = data.get("delta") {
+                    for key in ["text", "partial_json", "thinking"] {
+                        if let Some(text) = delta.get(key).and_then(|v| v.as_str()) {
+                            self.streamed_chars += text.len();
+                        }
+                    }
+                }
+            }
+            Some("message_delta") => {
+                if let Some(usage) = data.get("usage") {
+                    if let Some(input) = usage.get("input_tokens").and_then(|v| v.as_u64()) {
+                        self.input_tokens = self.input_tokens.max(input as u32);
+                    }
+                    if let Some(output) = usage.get("output_tokens").and_then(|v| v.as_u64()) {
+                        self.reported_output_tokens = Some(output as u32);
+                    }
+                }
+                // The message is already ending; let the upstream stop through
+                if data.pointer("/delta/stop_reason").is_some_and(|r| !r.is_null()) {
+                    return vec![event];
+                }
+            }
+            _ => {}
+        }
+
+        let mut out = vec![event];
+        if self.over_budget() {
+            out.extend(self.stop());
+        }
+        out
+    }
+
+    fn is_done(&self) -> bool {
+        self.exhausted
+    }
+}
+
+fn is_ping(event: &SseEvent) -> bool {
+    event.event.as_deref() == Some("ping")
+        || event.json().is_some_and(|data| data.get("type").and_then(|t| t.as_str()) == Some("ping"))
+}
+
+/// Drops `ping` events for clients that can't handle them
+pub struct PingFilter;
+
+impl StreamTranslator for PingFilter {
+    fn translate(&mut self, event: SseEvent) -> Vec<SseEvent> {
+        if is_ping(&event) {
+            Vec::new()
+        } else {
+            vec![event]
+        }
+    }
+}
+
+/// Drops `ping` events arriving within `threshold` of the request starting.
+/// A fast upstream doesn't need its initial pings to keep the connection alive;
+/// pings from slow upstreams (after the threshold) still get through.
+pub struct EarlyPingFilter {
+    started: Instant,
+    threshold: Duration,
+}
+
+impl EarlyPingFilter {
+    pub fn new(started: Instant, threshold: Duration) -> Self {
+        Self { started, threshold }
+    }
+}
+
+impl StreamTranslator for EarlyPingFilter {
+    fn translate(&mut self, event: SseEvent) -> Vec<SseEvent> {
+        if is_ping(&event) && self.started.elapsed() < self.threshold {
+            Vec::new()
+        } else {
+            vec![event]
+        }
+    }
+}
+
+/// Trims leading and trailing whitespace from streamed text blocks. Trailing
+/// whitespace is held back until more text follows and dropped at block end.
+#[derive(Default)]
+pub struct TextTrimmer {
+    /// Text blocks (by index) that have emitted non-whitespace text
+    started: HashSet<u64>,
+    /// Whitespace held back per block
+    pending: HashMap<u64, String>,
+}
+
+impl TextTrimmer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StreamTranslator for TextTrimmer {
+    fn translate(&mut self, event: SseEvent) -> Vec<SseEvent> {
+        let Some(mut data) = event.json() else {
+            return vec![event];
+        };
+        let index = data.get("index").and_then(|i| i.as_u64()).unwrap_or(0);
+
+        match data.get("type").and_then(|t| t.as_str()) {
+            Some("content_block_delta") if data.pointer("/delta/type").and_then(|t| t.as_str()) == Some("text_delta") => {
+                let text = data.pointer("/delta/text").and_then(|t| t.as_str()).unwrap_or("");
+                let text = if self.started.contains(&index) { text } else { text.trim_start() };
+                if text.is_empty() {
+                    return Vec::new();
+                }
+                self.started.insert(index);
+
+                let body = text.trim_end();
+                let tail = text[body.len()..].to_string();
+                let mut out = self.pending.remove(&index).unwrap_or_default();
+                if body.is_empty() {
+                    out.push_str(&tail);
+                    self.pending.insert(index, out);
+                    return Vec::new();
+                }
+                out.push_str(body);
+                self.pending.insert(index, tail);
+                data["delta"]["text"] = out.into();
+                vec![SseEvent { event: event.event, data: data.to_string() }]
+            }
+            Some("content_block_stop") => {
+                self.pending.remove(&index);
+                vec![event]
+            }
+            _ => vec![event],
+        }
+    }
+}
+
 /// Stream adapter that converts a reqwest Response stream into SSE events
 #[pin_project]
 pub struct SseStream<S> {
@@ -151,6 +951,267 @@ mod tests {
         assert_eq!(events[1].event.as_deref(), Some("delta"));
     }
 
+    #[test]
+    fn test_check_content_type_sse_expected_json_returned() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(reqwest::header::CONTENT_TYPE, "application/json; charset=utf-8".parse().unwrap());
+
+        let err = check_content_type(&headers, ExpectedBody::Sse).unwrap_err();
+        assert!(matches!(err, ProviderError::ContentTypeMismatch { .. }));
+        assert!(err.to_string().contains("expected SSE"));
+
+        assert!(check_content_type(&headers, ExpectedBody::Json).is_ok());
+        assert!(check_content_type(&reqwest::header::HeaderMap::new(), ExpectedBody::Sse).is_ok());
+    }
+
+    fn collect(events: Vec<SseEvent>, translator: impl StreamTranslator + 'static) -> Vec<serde_json::Value> {
+        let bytes: Vec<Result<Bytes, ProviderError>> = events
+            .into_iter()
+            .map(|e| Ok(Bytes::from(e.to_sse_string())))
+            .collect();
+        let stream = translate_events(futures::stream::iter(bytes), translator);
+        futures::executor::block_on(stream.collect::<Vec<_>>())
+            .into_iter()
+            .map(|e| e.unwrap().json().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_mid_stream_error_keeps_content_and_terminates() {
+        let sent = [
+            serde_json::json!({ "type": "message_start", "message": { "id": "msg_1", "content": [] } }),
+            serde_json::json!({ "type": "content_block_start", "index": 0, "content_block": { "type": "text", "text": "" } }),
+            serde_json::json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "text_delta", "text": "Hello" } }),
+            serde_json::json!({ "type": "content_block_delta", "index": 0, "delta": { "type": "text_delta", "text": " wor" } }),
+        ];
+        let mut upstream: Vec<Result<Bytes, ProviderError>> =
+            sent.iter().map(|e| Ok(Bytes::from(SseEvent::anthropic(e.clone()).to_sse_string()))).collect();
+        upstream.push(Err(ProviderError::ApiError { status: 502, message: "connection reset".to_string() }));
+
+        let events = end_on_error(translate_events(futures::stream::iter(upstream), PingFilter));
+        let out: Vec<SseEvent> = futures::executor::block_on(events.collect::<Vec<_>>())
+            .into_iter()
+            .map(|e| e.expect("errors are turned into events"))
+            .collect();
+
+        // Everything already received is passed through unchanged
+        let json: Vec<serde_json::Value> = out.iter().map(|e| e.json().unwrap()).collect();
+        assert_eq!(json[..4], sent[..]);
+        // ...followed by a terminator for the open block and the stream
+        assert_eq!(json.len(), 6);
+        assert_eq!(json[4], serde_json::json!({ "type": "content_block_stop", "index": 0 }));
+        assert_eq!(out[5].event.as_deref(), Some("error"));
+        assert_eq!(json[5]["error"]["type"], "api_error");
+        assert!(json[5]["error"]["message"].as_str().unwrap().contains("connection reset"));
+    }
+
+    #[test]
+    fn test_sse_decoder_buffers_partial_events() {
+        let mut decoder = SseDecoder::new();
+        assert!(decoder.push(b"event: ping\ndata: {\"type\"").is_empty());
+
+        let events = decoder.push(b":\"ping\"}\n\nevent: next\n");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].data, "{\"type\":\"ping\"}");
+
+        let rest = decoder.finish();
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn test_sse_decoder_joins_utf8_split_across_chunks() {
+        let event = "data: {\"text\":\"안녕 👋\"}\n\n".as_bytes();
+        // Split inside the 3-byte '안' and again inside the 4-byte emoji
+        let first = event.iter().position(|&b| b >= 0x80).unwrap() + 1;
+        let second = event.len() - 5;
+
+        let mut decoder = SseDecoder::new();
+        assert!(decoder.push(&event[..first]).is_empty());
+        assert!(decoder.push(&event[first..second]).is_empty());
+        let events = decoder.push(&event[second..]);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].data, "{\"text\":\"안녕 👋\"}");
+    }
+
+    #[test]
+    fn test_usage_in_final_stream_event() {
+        let events = vec![
+            SseEvent::anthropic(json!({
+                "type": "message_start",
+                "message": { "id": "msg_1", "usage": { "input_tokens": 12, "output_tokens": 1 } }
+            })),
+            SseEvent::anthropic(json!({
+                "type": "content_block_delta", "index": 0,
+                "delta": { "type": "text_delta", "text": "Hello world!" }
+            })),
+            // Intermediate usage-only delta
+            SseEvent::anthropic(json!({
+                "type": "message_delta",
+                "delta": { "stop_reason": null },
+                "usage": { "output_tokens": 2 }
+            })),
+            SseEvent::anthropic(json!({ "type": "message_stop" })),
+        ];
+
+        let out = collect(events.clone(), UsageFinalizer::new(false));
+        let types: Vec<_> = out.iter().map(|e| e["type"].as_str().unwrap().to_string()).collect();
+        assert_eq!(types, vec!["message_start", "content_block_delta", "message_delta", "message_stop"]);
+
+        let final_delta = &out[2];
+        assert_eq!(final_delta["delta"]["stop_reason"], "end_turn");
+        assert_eq!(final_delta["usage"]["input_tokens"], 12);
+        assert_eq!(final_delta["usage"]["output_tokens"], 2);
+
+        // With include_usage the intermediate delta is forwarded as well
+        let out = collect(events, UsageFinalizer::new(true));
+        assert_eq!(out.iter().filter(|e| e["type"] == "message_delta").count(), 2);
+        assert_eq!(out.last().unwrap()["type"], "message_stop");
+    }
+
+    #[test]
+    fn test_low_budget_truncates_stream() {
+        let mut events = vec![
+            SseEvent::anthropic(json!({
+                "type": "message_start",
+                "message": { "id": "msg_1", "usage": { "input_tokens": 10, "output_tokens": 0 } }
+            })),
+            SseEvent::anthropic(json!({
+                "type": "content_block_start", "index": 0,
+                "content_block": { "type": "text", "text": "" }
+            })),
+        ];
+        // Each delta is ~10 tokens
+        for _ in 0..5 {
+            events.push(SseEvent::anthropic(json!({
+                "type": "content_block_delta", "index": 0,
+                "delta": { "type": "text_delta", "text": "x".repeat(40) }
+            })));
+        }
+        events.push(SseEvent::anthropic(json!({ "type": "content_block_stop", "index": 0 })));
+        events.push(SseEvent::anthropic(json!({ "type": "message_stop" })));
+
+        let out = collect(events, BudgetLimiter::new(Some(25), None, 0.0, 0.0));
+        let types: Vec<_> = out.iter().map(|e| e["type"].as_str().unwrap().to_string()).collect();
+        assert_eq!(
+            types,
+            vec![
+                "message_start",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop"
+            ]
+        );
+        assert_eq!(out[5]["delta"]["stop_reason"], "max_tokens");
+        assert_eq!(out[5]["usage"]["output_tokens"], 20);
+    }
+
+    #[test]
+    fn test_output_token_cap_stops_generation() {
+        let mut events = vec![
+            SseEvent::anthropic(json!({
+                "type": "message_start",
+                "message": { "id": "msg_1", "usage": { "input_tokens": 500, "output_tokens": 0 } }
+            })),
+            SseEvent::anthropic(json!({
+                "type": "content_block_start", "index": 0,
+                "content_block": { "type": "text", "text": "" }
+            })),
+        ];
+        // Each delta is ~10 tokens
+        for _ in 0..5 {
+            events.push(SseEvent::anthropic(json!({
+                "type": "content_block_delta", "index": 0,
+                "delta": { "type": "text_delta", "text": "x".repeat(40) }
+            })));
+        }
+        events.push(SseEvent::anthropic(json!({ "type": "content_block_stop", "index": 0 })));
+        events.push(SseEvent::anthropic(json!({
+            "type": "message_delta",
+            "delta": { "stop_reason": "end_turn", "stop_sequence": null },
+            "usage": { "output_tokens": 50 }
+        })));
+        events.push(SseEvent::anthropic(json!({ "type": "message_stop" })));
+
+        // Input tokens don't count towards the output cap
+        let limiter = BudgetLimiter::new(None, None, 0.0, 0.0).with_max_output_tokens(Some(30));
+        let out = collect(events, limiter);
+        let deltas = out.iter().filter(|e| e["type"] == "content_block_delta").count();
+        assert_eq!(deltas, 3);
+
+        let stops: Vec<_> = out.iter().filter(|e| e["type"] == "message_delta").collect();
+        assert_eq!(stops.len(), 1);
+        assert_eq!(stops[0]["delta"]["stop_reason"], "max_tokens");
+        assert_eq!(stops[0]["usage"]["output_tokens"], 30);
+        assert_eq!(out.last().unwrap()["type"], "message_stop");
+    }
+
+    #[test]
+    fn test_passthrough_terminal_stop_reason() {
+        let stream = |blocks: Vec<serde_json::Value>, final_delta: Option<serde_json::Value>| {
+            let mut events = vec![SseEvent::anthropic(json!({
+                "type": "message_start",
+                "message": { "id": "msg_1", "usage": { "input_tokens": 5, "output_tokens": 0 } }
+            }))];
+            for block in blocks {
+                events.push(SseEvent::anthropic(json!({ "type": "content_block_start", "index": 0, "content_block": block })));
+                events.push(SseEvent::anthropic(json!({ "type": "content_block_stop", "index": 0 })));
+            }
+            events.extend(final_delta.map(SseEvent::anthropic));
+            events.push(SseEvent::anthropic(json!({ "type": "message_stop" })));
+            let out = collect(events, UsageFinalizer::new(false));
+            out.iter().find(|e| e["type"] == "message_delta").unwrap()["delta"]["stop_reason"].clone()
+        };
+        let tool = json!({ "type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {} });
+        let text = json!({ "type": "text", "text": "" });
+
+        // Upstream reported stop_reasons are kept
+        for reason in ["end_turn", "max_tokens", "tool_use"] {
+            let delta = json!({ "type": "message_delta", "delta": { "stop_reason": reason }, "usage": { "output_tokens": 1 } });
+            assert_eq!(stream(vec![text.clone()], Some(delta)), reason);
+        }
+        // Missing final delta: inferred from the streamed blocks
+        assert_eq!(stream(vec![text.clone(), tool], None), "tool_use");
+        assert_eq!(stream(vec![text], None), "end_turn");
+    }
+
+    #[test]
+    fn test_pings_suppressed_when_disabled() {
+        let events = vec![
+            SseEvent::anthropic(json!({ "type": "message_start", "message": { "id": "msg_1" } })),
+            SseEvent::anthropic(json!({ "type": "ping" })),
+            // Untyped ping payload still recognized
+            SseEvent { event: None, data: json!({ "type": "ping" }).to_string() },
+            SseEvent::anthropic(json!({ "type": "message_stop" })),
+        ];
+
+        let out = collect(events, PingFilter);
+        let types: Vec<_> = out.iter().map(|e| e["type"].as_str().unwrap().to_string()).collect();
+        assert_eq!(types, vec!["message_start", "message_stop"]);
+    }
+
+    #[test]
+    fn test_no_pings_for_fast_upstream() {
+        let events = || {
+            vec![
+                SseEvent::anthropic(json!({ "type": "message_start", "message": { "id": "msg_1" } })),
+                SseEvent::anthropic(json!({ "type": "ping" })),
+                SseEvent::anthropic(json!({ "type": "message_stop" })),
+            ]
+        };
+
+        // Upstream answered well within the threshold
+        let fast = EarlyPingFilter::new(Instant::now(), Duration::from_secs(5));
+        let types: Vec<_> = collect(events(), fast).iter().map(|e| e["type"].as_str().unwrap().to_string()).collect();
+        assert_eq!(types, vec!["message_start", "message_stop"]);
+
+        // Slow upstream: threshold already passed, pings are kept
+        let slow = EarlyPingFilter::new(Instant::now() - Duration::from_secs(10), Duration::from_secs(5));
+        assert_eq!(collect(events(), slow).len(), 3);
+    }
+
     #[test]
     fn test_parse_sse_no_event_type() {
         let input = "data: plain data\n\n";
@@ -0,0 +1,463 @@
+//! Optional schema validation for transformed payloads
+//!
+//! Enabled per model via `schema_validation = "warn" | "strict"` in `[[models]]`.
+//! These are structural checks meant to catch transform bugs while debugging,
+//! not a full implementation of each provider's JSON schema.
+
+use super::error::ProviderError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// How schema violations are handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValidationMode {
+    /// No validation (default)
+    #[default]
+    Off,
+    /// Log violations and continue
+    Warn,
+    /// Fail the attempt on violations (falls through to the next mapping)
+    Strict,
+}
+
+impl ValidationMode {
+    pub fn is_enabled(&self) -> bool {
+        *self != ValidationMode::Off
+    }
+
+    /// Apply the mode to a list of violations found in `target`
+    pub fn enforce(&self, target: &str, violations: Vec<String>) -> Result<(), ProviderError> {
+        if violations.is_empty() || *self == ValidationMode::Off {
+            return Ok(());
+        }
+
+        tracing::warn!("🧪 Schema violations in {}: {}", target, violations.join("; "));
+
+        if *self == ValidationMode::Strict {
+            return Err(ProviderError::SchemaViolation {
+                target: target.to_string(),
+                violations,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// What to do when structured output doesn't match the requested schema
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StructuredOutputValidation {
+    /// Log the mismatch and return the response anyway
+    Pass,
+    /// Fail the attempt (falls through to the next mapping)
+    Error,
+    /// Ask the model once more, then fail
+    Retry,
+}
+
+/// Collects violations with a JSON-pointer-ish path prefix
+struct Checker {
+    violations: Vec<String>,
+}
+
+impl Checker {
+    fn new() -> Self {
+        Self { violations: Vec::new() }
+    }
+
+    fn fail(&mut self, path: &str, msg: impl std::fmt::Display) {
+        self.violations.push(format!("{}: {}", path, msg));
+    }
+
+    fn string<'a>(&mut self, value: &'a Value, path: &str, key: &str) -> Option<&'a str> {
+        match value.get(key) {
+            Some(Value::String(s)) => Some(s),
+            Some(_) => {
+                self.fail(&format!("{}/{}", path, key), "expected string");
+                None
+            }
+            None => {
+                self.fail(&format!("{}/{}", path, key), "missing");
+                None
+            }
+        }
+    }
+
+    fn non_empty_string(&mut self, value: &Value, path: &str, key: &str) {
+        if let Some(s) = self.string(value, path, key) {
+            if s.is_empty() {
+                self.fail(&format!("{}/{}", path, key), "must not be empty");
+            }
+        }
+    }
+
+    fn array<'a>(&mut self, value: &'a Value, path: &str, key: &str) -> Option<&'a Vec<Value>> {
+        match value.get(key) {
+            Some(Value::Array(items)) => {
+                if items.is_empty() {
+                    self.fail(&format!("{}/{}", path, key), "must not be empty");
+                }
+                Some(items)
+            }
+            Some(_) => {
+                self.fail(&format!("{}/{}", path, key), "expected array");
+                None
+            }
+            None => {
+                self.fail(&format!("{}/{}", path, key), "missing");
+                None
+            }
+        }
+    }
+
+    fn one_of(&mut self, value: &Value, path: &str, key: &str, allowed: &[&str]) {
+        if let Some(s) = self.string(value, path, key) {
+            if !allowed.contains(&s) {
+                self.fail(&format!("{}/{}", path, key), format!("unexpected value '{}'", s));
+            }
+        }
+    }
+
+    fn positive_int(&mut self, value: &Value, path: &str, key: &str) {
+        if let Some(v) = value.get(key) {
+            if matches!(v.as_u64(), None | Some(0)) {
+                self.fail(&format!("{}/{}", path, key), "expected positive integer");
+            }
+        }
+    }
+
+    fn finish(self) -> Vec<String> {
+        self.violations
+    }
+}
+
+/// Check a value against the JSON Schema subset used for tool inputs and output
+/// formats: `type`, `enum`, `properties`, `required`, `additionalProperties: false`
+/// and `items`. Other keywords are ignored.
+pub fn validate_json_schema(value: &Value, schema: &Value) -> Vec<String> {
+    let mut c = Checker::new();
+    check_schema(&mut c, value, schema, "");
+    c.finish()
+}
+
+fn check_schema(c: &mut Checker, value: &Value, schema: &Value, path: &str) {
+    let at = if path.is_empty() { "/" } else { path };
+    let types: Vec<&str> = match schema.get("type") {
+        Some(Value::String(t)) => vec![t.as_str()],
+        Some(Value::Array(ts)) => ts.iter().filter_map(|t| t.as_str()).collect(),
+        _ => Vec::new(),
+    };
+    if !types.is_empty() && !types.iter().any(|t| json_type_matches(value, t)) {
+        c.fail(at, format!("expected {}", types.join(" or ")));
+        return;
+    }
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(value) {
+            c.fail(at, format!("{} is not an allowed value", value));
+        }
+    }
+
+    match value {
+        Value::Object(fields) => {
+            for key in schema.get("required").and_then(|r| r.as_array()).into_iter().flatten().filter_map(|k| k.as_str()) {
+                if !fields.contains_key(key) {
+                    c.fail(&format!("{}/{}", path, key), "missing");
+                }
+            }
+            let properties = schema.get("properties").and_then(|p| p.as_object());
+            for (key, field) in fields {
+                match properties.and_then(|p| p.get(key)) {
+                    Some(field_schema) => check_schema(c, field, field_schema, &format!("{}/{}", path, key)),
+                    None if schema.get("additionalProperties") == Some(&Value::Bool(false)) => {
+                        c.fail(&format!("{}/{}", path, key), "unexpected property");
+                    }
+                    None => {}
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    check_schema(c, item, item_schema, &format!("{}/{}", path, i));
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn json_type_matches(value: &Value, json_type: &str) -> bool {
+    match json_type.to_ascii_lowercase().as_str() {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+/// Validate an Anthropic Messages API request (as forwarded to Anthropic-compatible providers)
+pub fn validate_anthropic_request(body: &Value) -> Vec<String> {
+    let mut c = Checker::new();
+    c.non_empty_string(body, "", "model");
+    c.positive_int(body, "", "max_tokens");
+    if body.get("max_tokens").is_none() {
+        c.fail("/max_tokens", "missing");
+    }
+
+    if let Some(messages) = c.array(body, "", "messages") {
+        for (i, msg) in messages.iter().enumerate() {
+            let path = format!("/messages/{}", i);
+            c.one_of(msg, &path, "role", &["user", "assistant"]);
+            match msg.get("content") {
+                Some(Value::String(_)) => {}
+                Some(Value::Array(blocks)) => {
+                    for (j, block) in blocks.iter().enumerate() {
+                        check_anthropic_block(&mut c, block, &format!("{}/content/{}", path, j));
+                    }
+                }
+                _ => c.fail(&format!("{}/content", path), "expected string or array"),
+            }
+        }
+    }
+    c.finish()
+}
+
+/// Validate an Anthropic Messages API response body
+pub fn validate_anthropic_response(body: &Value) -> Vec<String> {
+    let mut c = Checker::new();
+    c.non_empty_string(body, "", "id");
+    c.one_of(body, "", "type", &["message"]);
+    c.one_of(body, "", "role", &["assistant"]);
+    c.non_empty_string(body, "", "model");
+
+    match body.get("content") {
+        Some(Value::Array(blocks)) => {
+            for (i, block) in blocks.iter().enumerate() {
+                check_anthropic_block(&mut c, block, &format!("/content/{}", i));
+            }
+        }
+        _ => c.fail("/content", "expected array"),
+    }
+
+    match body.get("stop_reason") {
+        None | Some(Value::Null) => {}
+        Some(_) => c.one_of(
+            body,
+            "",
+            "stop_reason",
+            &["end_turn", "max_tokens", "stop_sequence", "tool_use", "pause_turn", "refusal"],
+        ),
+    }
+
+    match body.get("usage") {
+        Some(usage @ Value::Object(_)) => {
+            for key in ["input_tokens", "output_tokens"] {
+                if !usage.get(key).is_some_and(|v| v.is_u64()) {
+                    c.fail(&format!("/usage/{}", key), "expected non-negative integer");
+                }
+            }
+        }
+        _ => c.fail("/usage", "expected object"),
+    }
+    c.finish()
+}
+
+fn check_anthropic_block(c: &mut Checker, block: &Value, path: &str) {
+    let Some(block_type) = c.string(block, path, "type") else {
+        return;
+    };
+    match block_type {
+        "text" => {
+            c.string(block, path, "text");
+        }
+        "tool_use" => {
+            c.non_empty_string(block, path, "id");
+            c.non_empty_string(block, path, "name");
+            if !block.get("input").is_some_and(|v| v.is_object()) {
+                c.fail(&format!("{}/input", path), "expected object");
+            }
+        }
+        "tool_result" => c.non_empty_string(block, path, "tool_use_id"),
+        "thinking" => {
+            c.string(block, path, "thinking");
+        }
+        "image" => {
+            if !block.get("source").is_some_and(|v| v.is_object()) {
+                c.fail(&format!("{}/source", path), "expected object");
+            }
+        }
+        "redacted_thinking" | "document" | "server_tool_use" | "web_search_tool_result" => {}
+        other => c.fail(&format!("{}/type", path), format!("unknown block type '{}'", other)),
+    }
+}
+
+/// Validate an OpenAI Chat Completions request body
+pub fn validate_openai_chat_request(body: &Value) -> Vec<String> {
+    let mut c = Checker::new();
+    c.non_empty_string(body, "", "model");
+    c.positive_int(body, "", "max_tokens");
+    c.positive_int(body, "", "max_completion_tokens");
+
+    if let Some(messages) = c.array(body, "", "messages") {
+        for (i, msg) in messages.iter().enumerate() {
+            let path = format!("/messages/{}", i);
+            c.one_of(msg, &path, "role", &["system", "developer", "user", "assistant", "tool"]);
+
+            let role = msg.get("role").and_then(|r| r.as_str()).unwrap_or_default();
+            let has_tool_calls = msg.get("tool_calls").is_some_and(|t| !t.is_null());
+            match msg.get("content") {
+                Some(Value::String(_)) | Some(Value::Array(_)) => {}
+                // Assistant messages may carry only tool calls
+                None | Some(Value::Null) if role == "assistant" && has_tool_calls => {}
+                _ => c.fail(&format!("{}/content", path), "expected string or array"),
+            }
+
+            if role == "tool" {
+                c.non_empty_string(msg, &path, "tool_call_id");
+            }
+
+            if let Some(tool_calls) = msg.get("tool_calls").and_then(|t| t.as_array()) {
+                for (j, call) in tool_calls.iter().enumerate() {
+                    let call_path = format!("{}/tool_calls/{}", path, j);
+                    c.non_empty_string(call, &call_path, "id");
+                    c.one_of(call, &call_path, "type", &["function"]);
+                    match call.get("function") {
+                        Some(function) => {
+                            let fn_path = format!("{}/function", call_path);
+                            c.non_empty_string(function, &fn_path, "name");
+                            c.string(function, &fn_path, "arguments");
+                        }
+                        None => c.fail(&format!("{}/function", call_path), "missing"),
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(tools) = body.get("tools").and_then(|t| t.as_array()) {
+        for (i, tool) in tools.iter().enumerate() {
+            let path = format!("/tools/{}", i);
+            c.one_of(tool, &path, "type", &["function"]);
+            match tool.get("function") {
+                Some(function) => c.non_empty_string(function, &format!("{}/function", path), "name"),
+                None => c.fail(&format!("{}/function", path), "missing"),
+            }
+        }
+    }
+    c.finish()
+}
+
+/// Validate an OpenAI Responses API request body
+pub fn validate_openai_responses_request(body: &Value) -> Vec<String> {
+    let mut c = Checker::new();
+    c.non_empty_string(body, "", "model");
+    if let Some(input) = c.array(body, "", "input") {
+        for (i, item) in input.iter().enumerate() {
+            if item.get("role").is_some() {
+                c.one_of(item, &format!("/input/{}", i), "role", &["system", "developer", "user", "assistant"]);
+            }
+        }
+    }
+    c.finish()
+}
+
+/// Validate a Gemini generateContent request body
+pub fn validate_gemini_request(body: &Value) -> Vec<String> {
+    let mut c = Checker::new();
+    if let Some(contents) = c.array(body, "", "contents") {
+        for (i, content) in contents.iter().enumerate() {
+            let path = format!("/contents/{}", i);
+            c.one_of(content, &path, "role", &["user", "model"]);
+            if let Some(parts) = c.array(content, &path, "parts") {
+                for (j, part) in parts.iter().enumerate() {
+                    check_gemini_part(&mut c, part, &format!("{}/parts/{}", path, j));
+                }
+            }
+        }
+    }
+
+    if let Some(system) = body.get("systemInstruction") {
+        if let Some(parts) = c.array(system, "/systemInstruction", "parts") {
+            for (j, part) in parts.iter().enumerate() {
+                check_gemini_part(&mut c, part, &format!("/systemInstruction/parts/{}", j));
+            }
+        }
+    }
+
+    if let Some(config) = body.get("generationConfig") {
+        c.positive_int(config, "/generationConfig", "maxOutputTokens");
+    }
+    c.finish()
+}
+
+fn check_gemini_part(c: &mut Checker, part: &Value, path: &str) {
+    const PART_KEYS: &[&str] = &[
+        "text",
+        "inline_data",
+        "inlineData",
+        "file_data",
+        "fileData",
+        "functionCall",
+        "functionResponse",
+    ];
+    let Some(obj) = part.as_object() else {
+        c.fail(path, "expected object");
+        return;
+    };
+    if !obj.keys().any(|k| PART_KEYS.contains(&k.as_str())) {
+        c.fail(path, "part has no data field");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_malformed_transform_is_caught() {
+        // A tool result transformed without its tool_call_id, and a tool call whose
+        // arguments were left as an object instead of a JSON string
+        let body = json!({
+            "model": "gpt-4o",
+            "messages": [
+                { "role": "user", "content": "hi" },
+                { "role": "assistant", "content": null, "tool_calls": [
+                    { "id": "call_1", "type": "function", "function": { "name": "ls", "arguments": {} } }
+                ]},
+                { "role": "tool", "content": "ok" }
+            ]
+        });
+
+        let violations = validate_openai_chat_request(&body);
+        assert_eq!(violations.len(), 2, "{:?}", violations);
+        assert!(violations.iter().any(|v| v.starts_with("/messages/1/tool_calls/0/function/arguments")));
+        assert!(violations.iter().any(|v| v.starts_with("/messages/2/tool_call_id")));
+
+        assert!(ValidationMode::Warn.enforce("openai request", violations.clone()).is_ok());
+        assert!(matches!(
+            ValidationMode::Strict.enforce("openai request", violations),
+            Err(ProviderError::SchemaViolation { .. })
+        ));
+    }
+
+    #[test]
+    fn test_valid_anthropic_response_passes() {
+        let body = json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "content": [{ "type": "text", "text": "Hello" }],
+            "model": "claude-sonnet-4-5",
+            "stop_reason": "end_turn",
+            "stop_sequence": null,
+            "usage": { "input_tokens": 3, "output_tokens": 1 }
+        });
+        assert!(validate_anthropic_response(&body).is_empty());
+    }
+}
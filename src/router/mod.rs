@@ -1,8 +1,11 @@
-use crate::cli::AppConfig;
-use crate::models::{AnthropicRequest, RouteDecision, RouteType, SystemPrompt};
+use crate::cli::{AppConfig, AutoPolicy, FeatureRule};
+use crate::models::{AnthropicRequest, ContentBlock, MessageContent, RouteDecision, RouteType, SystemPrompt};
 use anyhow::Result;
 use regex::Regex;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
+
+/// Reserved model name asking the router to pick a model
+const AUTO_MODEL: &str = "auto";
 
 /// Router for intelligently selecting models based on request characteristics
 #[derive(Clone)]
@@ -10,6 +13,12 @@ pub struct Router {
     config: AppConfig,
     auto_map_regex: Option<Regex>,
     background_regex: Option<Regex>,
+    /// Compiled content rules (pattern, target model), in config order
+    content_rules: Vec<(Regex, String)>,
+    /// Feature rules that require at least one feature, in config order
+    feature_rules: Vec<FeatureRule>,
+    /// Compiled tier rules (tier, pattern, target model)
+    tier_rules: Vec<(String, Regex, String)>,
 }
 
 impl Router {
@@ -78,22 +87,85 @@ impl Router {
                 Some(Regex::new(r"(?i)claude.*haiku").expect("Invalid default background regex"))
             });
 
+        // Compile content-based routing rules, skipping invalid ones
+        let content_rules = config
+            .router
+            .content_rules
+            .iter()
+            .filter_map(|rule| {
+                let pattern = match (&rule.keyword, &rule.pattern) {
+                    (Some(keyword), _) => format!(r"(?i)\b{}\b", regex::escape(keyword)),
+                    (None, Some(pattern)) => pattern.clone(),
+                    (None, None) => {
+                        eprintln!("Warning: content rule for '{}' has no keyword or pattern, ignoring", rule.model);
+                        return None;
+                    }
+                };
+                match Regex::new(&pattern) {
+                    Ok(regex) => Some((regex, rule.model.clone())),
+                    Err(e) => {
+                        eprintln!("Warning: Invalid content rule pattern '{}': {}", pattern, e);
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        let feature_rules = config
+            .router
+            .feature_rules
+            .iter()
+            .filter(|rule| {
+                let usable = !rule.describe().is_empty();
+                if !usable {
+                    eprintln!("Warning: feature rule for '{}' requires no features, ignoring", rule.model);
+                }
+                usable
+            })
+            .cloned()
+            .collect();
+
+        // Tier names match as a separate word in the model name
+        let tier_rules = config
+            .router
+            .tiers
+            .iter()
+            .filter_map(|(tier, model)| {
+                let pattern = format!(r"(?i)(^|[^a-z0-9]){}([^a-z0-9]|$)", regex::escape(tier));
+                Regex::new(&pattern).ok().map(|regex| (tier.clone(), regex, model.clone()))
+            })
+            .collect();
+
         Self {
             config,
             auto_map_regex,
             background_regex,
+            content_rules,
+            feature_rules,
+            tier_rules,
         }
     }
 
     /// Route an incoming request to the appropriate model
-    /// Priority: websearch > subagent > think > background > auto-map > default
+    /// Priority: websearch > subagent > content rules > feature rules > think > background > auto-map > default
     pub fn route(&self, request: &mut AnthropicRequest) -> Result<RouteDecision> {
+        // Reserved `auto` model: pick one by the configured policy, then route as usual
+        if request.model == AUTO_MODEL {
+            request.model = self.resolve_auto().to_string();
+        }
+
         // Save original model for background task detection
         let original_model = request.model.clone();
 
-        // 0. Auto-mapping (model name transformation FIRST)
-        // Transform model name if it matches auto_map_regex
-        if let Some(ref regex) = self.auto_map_regex {
+        // 0. Tier mapping, then auto-mapping (model name transformation FIRST)
+        // Exact [[models]] entries and model groups win over tier rules
+        let has_exact_mapping = self.config.has_model(&request.model);
+        let tier = if has_exact_mapping { None } else { self.classify_tier(&request.model) };
+        if let Some((tier, model)) = tier {
+            debug!("🎚️ Tier '{}' mapped model '{}' → '{}'", tier, request.model, model);
+            request.model = model.to_string();
+        } else if let Some(ref regex) = self.auto_map_regex {
+            // Transform model name if it matches auto_map_regex
             if regex.is_match(&request.model) {
                 let old = request.model.clone();
                 request.model = self.config.router.default.clone();
@@ -113,7 +185,8 @@ impl Router {
         }
 
         // 2. Subagent Model (system prompt tag)
-        if let Some(model) = self.extract_subagent_model(request) {
+        if let Some(tag) = self.extract_subagent_model(request) {
+            let model = self.resolve_subagent_model(&tag);
             info!(
                 "🤖 Routing to subagent model (CCM-SUBAGENT-MODEL tag): {}",
                 model
@@ -124,7 +197,25 @@ impl Router {
             });
         }
 
-        // 3. Think mode (Plan Mode / Reasoning)
+        // 3. Content rules (keyword/regex on the latest user message)
+        if let Some((pattern, model)) = self.match_content_rule(request) {
+            info!("🏷️ Routing to {} (content rule '{}' matched)", model, pattern);
+            return Ok(RouteDecision {
+                model_name: model.to_string(),
+                route_type: RouteType::Content,
+            });
+        }
+
+        // 4. Feature rules (images, tools, thinking)
+        if let Some(rule) = self.feature_rules.iter().find(|rule| rule.matches(request)) {
+            info!("🧩 Routing to {} (feature rule '{}' matched)", rule.model, rule.describe());
+            return Ok(RouteDecision {
+                model_name: rule.model.clone(),
+                route_type: RouteType::Feature,
+            });
+        }
+
+        // 5. Think mode (Plan Mode / Reasoning)
         if let Some(ref think_model) = self.config.router.think {
             if self.is_plan_mode(request) {
                 info!("🧠 Routing to think model (Plan Mode detected)");
@@ -135,7 +226,7 @@ impl Router {
             }
         }
 
-        // 4. Background tasks (check against ORIGINAL model name, before auto-mapping)
+        // 6. Background tasks (check against ORIGINAL model name, before auto-mapping)
         if let Some(ref background_model) = self.config.router.background {
             if self.is_background_task(&original_model) {
                 debug!("🔄 Routing to background model");
@@ -146,7 +237,7 @@ impl Router {
             }
         }
 
-        // 5. Default fallback
+        // 7. Default fallback
         // Use the transformed model name (from auto-mapping) or original if no mapping
         debug!("✅ Using model: {}", request.model);
         Ok(RouteDecision {
@@ -155,6 +246,35 @@ impl Router {
         })
     }
 
+    /// Model selected for `model: "auto"` by the configured policy. Models without the
+    /// metric the policy needs are skipped; the router default is used if none have it.
+    fn resolve_auto(&self) -> &str {
+        let policy = self.config.router.auto_policy;
+        let models = self.config.models.iter();
+        let selected = match policy {
+            AutoPolicy::Default => None,
+            AutoPolicy::Cheapest => models
+                .filter_map(|m| m.cost_per_mtok.map(|cost| (cost, m)))
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+                .map(|(_, m)| m.name.as_str()),
+            AutoPolicy::Fastest => models
+                .filter_map(|m| m.latency_ms.map(|latency| (latency, m)))
+                .min_by_key(|(latency, _)| *latency)
+                .map(|(_, m)| m.name.as_str()),
+        };
+        let model = selected.unwrap_or(&self.config.router.default);
+        info!("🎲 Resolved model 'auto' → '{}' ({:?} policy)", model, policy);
+        model
+    }
+
+    /// Tier rule matching the model name: (tier, target model)
+    fn classify_tier(&self, model: &str) -> Option<(&str, &str)> {
+        self.tier_rules
+            .iter()
+            .find(|(_, regex, _)| regex.is_match(model))
+            .map(|(tier, _, target)| (tier.as_str(), target.as_str()))
+    }
+
     /// Check if request has web_search tool (tool-based detection)
     /// Following claude-code-router pattern: checks if tools array contains web_search type
     fn has_web_search_tool(&self, request: &AnthropicRequest) -> bool {
@@ -170,6 +290,39 @@ impl Router {
         }
     }
 
+    /// First content rule matching the latest user message that has text
+    /// (tool-result-only turns keep the routing of the turn that started them)
+    fn match_content_rule(&self, request: &AnthropicRequest) -> Option<(&str, &str)> {
+        if self.content_rules.is_empty() {
+            return None;
+        }
+
+        let text = request
+            .messages
+            .iter()
+            .rev()
+            .filter(|m| m.role == "user")
+            .find_map(|m| {
+                let text = match &m.content {
+                    MessageContent::Text(text) => text.clone(),
+                    MessageContent::Blocks(blocks) => blocks
+                        .iter()
+                        .filter_map(|b| match b {
+                            ContentBlock::Text { text } => Some(text.as_str()),
+                            _ => None,
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                };
+                (!text.is_empty()).then_some(text)
+            })?;
+
+        self.content_rules
+            .iter()
+            .find(|(regex, _)| regex.is_match(&text))
+            .map(|(regex, model)| (regex.as_str(), model.as_str()))
+    }
+
     /// Check if request is Plan Mode by detecting thinking field
     fn is_plan_mode(&self, request: &AnthropicRequest) -> bool {
         request
@@ -189,6 +342,33 @@ impl Router {
         }
     }
 
+    /// Configured model (a [[models]] `name` or model group) for a subagent tag, whether or
+    /// not the router references it. A tag holding a mapping's `actual_model` resolves to
+    /// the model that maps to it.
+    fn resolve_subagent_model(&self, tag: &str) -> String {
+        if self.config.has_model(tag) {
+            return tag.to_string();
+        }
+        let by_actual_model = self
+            .config
+            .models
+            .iter()
+            .find(|m| m.mappings.iter().any(|mapping| mapping.actual_model == tag));
+        match by_actual_model {
+            Some(model) => {
+                info!(
+                    "🤖 Subagent tag '{}' is an actual_model of model '{}'; tag the model name instead",
+                    tag, model.name
+                );
+                model.name.clone()
+            }
+            None => {
+                warn!("⚠️ Subagent tag '{}' doesn't name a configured model", tag);
+                tag.to_string()
+            }
+        }
+    }
+
     /// Extract subagent model from system prompt tag
     /// Checks for <CCM-SUBAGENT-MODEL>model-name</CCM-SUBAGENT-MODEL> in system[1].text
     /// and removes the tag after extraction
@@ -208,12 +388,12 @@ impl Router {
             }
 
             // Extract model name using regex
-            let re = Regex::new(r"<CCM-SUBAGENT-MODEL>(.*?)</CCM-SUBAGENT-MODEL>")
+            let re = Regex::new(r"(?s)<CCM-SUBAGENT-MODEL>(.*?)</CCM-SUBAGENT-MODEL>")
                 .expect("Invalid regex pattern");
 
             if let Some(captures) = re.captures(&second_block.text) {
                 if let Some(model_match) = captures.get(1) {
-                    let model_name = model_match.as_str().to_string();
+                    let model_name = model_match.as_str().trim().to_string();
 
                     // Remove the tag from the text
                     second_block.text = re.replace_all(&second_block.text, "").to_string();
@@ -243,9 +423,20 @@ mod tests {
                 websearch: Some("websearch.model".to_string()),
                 auto_map_regex: None,   // Use default Claude pattern
                 background_regex: None, // Use default claude-haiku pattern
+                content_rules: vec![],
+                feature_rules: vec![],
+                tiers: Default::default(),
+                auto_policy: Default::default(),
+                unsupported_thinking: Default::default(),
+                fallback_on_auth_error: None,
+                fallback_on_invalid_request: None,
+                circuit_breaker: None,
+                app_id_header: None,
             },
             providers: vec![],
             models: vec![],
+            fallback_models: vec![],
+            model_groups: vec![],
         }
     }
 
@@ -266,6 +457,14 @@ mod tests {
             metadata: None,
             system: None,
             tools: None,
+            tool_choice: None,
+            betas: None,
+            stream_options: None,
+            api_version: None,
+            output_format: None,
+            request_id_header: None,
+            capture_raw: false,
+            forwarded_headers: vec![],
         }
     }
 
@@ -414,4 +613,210 @@ mod tests {
         assert_eq!(decision.route_type, RouteType::Default);
         assert_eq!(decision.model_name, "glm-4.6"); // Uses original model name (no auto-mapping)
     }
+
+    #[test]
+    fn test_content_keyword_reroutes() {
+        let mut config = create_test_config();
+        config.router.content_rules = vec![
+            crate::cli::ContentRule {
+                keyword: Some("translate".to_string()),
+                pattern: None,
+                model: "translation.model".to_string(),
+            },
+            crate::cli::ContentRule {
+                keyword: None,
+                pattern: Some("(?i)sql|database".to_string()),
+                model: "sql.model".to_string(),
+            },
+        ];
+        let router = Router::new(config);
+
+        let mut request = create_simple_request("Please TRANSLATE this into French");
+        let decision = router.route(&mut request).unwrap();
+        assert_eq!(decision.route_type, RouteType::Content);
+        assert_eq!(decision.model_name, "translation.model");
+
+        // Content rules take precedence over think mode
+        let mut request = create_simple_request("Optimize this SQL query");
+        request.thinking = Some(ThinkingConfig {
+            r#type: "enabled".to_string(),
+            budget_tokens: Some(10_000),
+        });
+        let decision = router.route(&mut request).unwrap();
+        assert_eq!(decision.model_name, "sql.model");
+
+        // Whole-word keyword matching only
+        let mut request = create_simple_request("The translated text looks fine");
+        let decision = router.route(&mut request).unwrap();
+        assert_eq!(decision.route_type, RouteType::Default);
+    }
+
+    #[test]
+    fn test_feature_rules_route_images_and_tools() {
+        let mut config = create_test_config();
+        config.router.feature_rules = vec![
+            crate::cli::FeatureRule {
+                has_images: true,
+                model: "vision.model".to_string(),
+                ..Default::default()
+            },
+            crate::cli::FeatureRule {
+                has_tools: true,
+                model: "tools.model".to_string(),
+                ..Default::default()
+            },
+        ];
+        let router = Router::new(config);
+
+        let mut request = create_simple_request("What is in this picture?");
+        request.messages[0].content = MessageContent::Blocks(vec![
+            ContentBlock::Image {
+                source: crate::models::ImageSource {
+                    r#type: "url".to_string(),
+                    media_type: None,
+                    data: None,
+                    url: Some("https://example.com/cat.png".to_string()),
+                },
+            },
+            ContentBlock::Text { text: "What is in this picture?".to_string() },
+        ]);
+        let decision = router.route(&mut request).unwrap();
+        assert_eq!(decision.route_type, RouteType::Feature);
+        assert_eq!(decision.model_name, "vision.model");
+
+        let mut request = create_simple_request("What time is it?");
+        request.tools = Some(vec![crate::models::Tool {
+            r#type: None,
+            name: Some("get_time".to_string()),
+            description: None,
+            input_schema: Some(serde_json::json!({ "type": "object" })),
+        }]);
+        let decision = router.route(&mut request).unwrap();
+        assert_eq!(decision.route_type, RouteType::Feature);
+        assert_eq!(decision.model_name, "tools.model");
+
+        // No features: the usual chain applies
+        let mut request = create_simple_request("Hello");
+        let decision = router.route(&mut request).unwrap();
+        assert_eq!(decision.route_type, RouteType::Default);
+    }
+
+    #[test]
+    fn test_sonnet_tier_maps_dated_variants() {
+        let mut config = create_test_config();
+        config.router.tiers.insert("sonnet".to_string(), "sonnet.model".to_string());
+        config.router.tiers.insert("opus".to_string(), "opus.model".to_string());
+        config.models = vec![crate::cli::ModelConfig {
+            name: "claude-3-5-sonnet-20241022".to_string(),
+            mappings: vec![],
+            schema_validation: Default::default(),
+            cost_per_mtok: None,
+            latency_ms: None,
+            default_max_tokens: None,
+            selection: Default::default(),
+        }];
+        let router = Router::new(config);
+
+        for model in ["claude-3-7-sonnet-20250219", "claude-sonnet-4-5-20250929", "claude-sonnet-4"] {
+            let mut request = create_simple_request("Hello");
+            request.model = model.to_string();
+            let decision = router.route(&mut request).unwrap();
+            assert_eq!(decision.route_type, RouteType::Default);
+            assert_eq!(decision.model_name, "sonnet.model", "{}", model);
+        }
+
+        // Exact mappings bypass the tier rule (auto-mapping still applies as before)
+        let mut request = create_simple_request("Hello");
+        request.model = "claude-3-5-sonnet-20241022".to_string();
+        assert_eq!(router.route(&mut request).unwrap().model_name, "default.model");
+
+        // No tier rule: falls back to auto-mapping
+        let mut request = create_simple_request("Hello");
+        request.model = "claude-3-5-haiku-20241022".to_string();
+        let decision = router.route(&mut request).unwrap();
+        assert_eq!(decision.model_name, "background.model");
+    }
+
+    #[test]
+    fn test_auto_model_resolves_by_policy() {
+        let model = |name: &str, cost: f64, latency: u64| crate::cli::ModelConfig {
+            name: name.to_string(),
+            mappings: vec![],
+            schema_validation: Default::default(),
+            cost_per_mtok: Some(cost),
+            latency_ms: Some(latency),
+            default_max_tokens: None,
+            selection: Default::default(),
+        };
+        let mut config = create_test_config();
+        config.models = vec![model("premium", 15.0, 900), model("budget", 0.4, 2500), model("quick", 3.0, 300)];
+
+        for (policy, expected) in [
+            (AutoPolicy::Default, "default.model"),
+            (AutoPolicy::Cheapest, "budget"),
+            (AutoPolicy::Fastest, "quick"),
+        ] {
+            config.router.auto_policy = policy;
+            let router = Router::new(config.clone());
+            let mut request = create_simple_request("Hello");
+            request.model = "auto".to_string();
+            let decision = router.route(&mut request).unwrap();
+            assert_eq!(decision.model_name, expected, "{:?}", policy);
+        }
+    }
+
+    fn subagent_request(tag: &str) -> AnthropicRequest {
+        let block = |text: &str| crate::models::SystemBlock {
+            r#type: "text".to_string(),
+            text: text.to_string(),
+            cache_control: None,
+        };
+        let mut request = create_simple_request("Hello");
+        request.system = Some(SystemPrompt::Blocks(vec![
+            block("You are Claude Code."),
+            block(&format!("<CCM-SUBAGENT-MODEL>{}</CCM-SUBAGENT-MODEL>You review code.", tag)),
+        ]));
+        request
+    }
+
+    #[test]
+    fn test_subagent_tag_matches_model_name() {
+        let mut config = create_test_config();
+        // Not referenced anywhere in [router]
+        config.models = vec![crate::cli::ModelConfig {
+            name: "kimi-k2-thinking".to_string(),
+            mappings: vec![serde_json::from_value(serde_json::json!({
+                "priority": 1,
+                "provider": "openrouter",
+                "actual_model": "moonshotai/kimi-k2-thinking:nitro"
+            }))
+            .unwrap()],
+            schema_validation: Default::default(),
+            cost_per_mtok: None,
+            latency_ms: None,
+            default_max_tokens: None,
+            selection: Default::default(),
+        }];
+        let router = Router::new(config.clone());
+
+        for tag in ["kimi-k2-thinking", " kimi-k2-thinking\n"] {
+            let mut request = subagent_request(tag);
+            let decision = router.route(&mut request).unwrap();
+            assert_eq!(decision.model_name, "kimi-k2-thinking", "{:?}", tag);
+            let Some(SystemPrompt::Blocks(blocks)) = &request.system else { unreachable!() };
+            assert_eq!(blocks[1].text, "You review code.");
+
+            let model = config.find_model(&decision.model_name).unwrap();
+            assert_eq!(model.mappings[0].provider, "openrouter");
+            assert_eq!(model.mappings[0].actual_model, "moonshotai/kimi-k2-thinking:nitro");
+        }
+
+        // An upstream identifier still finds the model mapping to it
+        let mut request = subagent_request("moonshotai/kimi-k2-thinking:nitro");
+        assert_eq!(router.route(&mut request).unwrap().model_name, "kimi-k2-thinking");
+
+        // Unknown names pass through unchanged
+        let mut request = subagent_request("unknown-model");
+        assert_eq!(router.route(&mut request).unwrap().model_name, "unknown-model");
+    }
 }
@@ -0,0 +1,174 @@
+use crate::cli::CircuitBreakerConfig;
+use crate::providers::error::ProviderError;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Skips providers that keep failing. After `failure_threshold` consecutive failures a
+/// provider's circuit opens and routing skips it for `cooldown_secs`; then one probe call
+/// is let through (half-open), which either closes the circuit or opens it again.
+#[derive(Clone, Default)]
+pub struct CircuitBreaker {
+    config: Option<CircuitBreakerConfig>,
+    circuits: Arc<Mutex<HashMap<String, Circuit>>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Circuit {
+    /// Routing normally, counting consecutive failures
+    Closed { failures: u32 },
+    /// Skipped until `until`
+    Open { until: Instant },
+    /// A probe call went out at `since`; other calls are skipped until it reports back
+    HalfOpen { since: Instant },
+}
+
+impl CircuitBreaker {
+    pub fn new(config: Option<CircuitBreakerConfig>) -> Self {
+        Self {
+            config,
+            circuits: Default::default(),
+        }
+    }
+
+    /// Whether a call to `provider` may go ahead. Once an open circuit's cooldown is
+    /// over this lets a single probe through; a probe that never reports back (e.g. the
+    /// attempt was skipped for another reason) is replaced after another cooldown.
+    pub fn allow(&self, provider: &str) -> bool {
+        let Some(config) = &self.config else {
+            return true;
+        };
+        let mut circuits = self.circuits.lock().unwrap();
+        let Some(circuit) = circuits.get_mut(provider) else {
+            return true;
+        };
+
+        let now = Instant::now();
+        let cooldown = Duration::from_secs(config.cooldown_secs);
+        match *circuit {
+            Circuit::Closed { .. } => true,
+            Circuit::Open { until } if now < until => false,
+            Circuit::HalfOpen { since } if now.duration_since(since) < cooldown => false,
+            Circuit::Open { .. } | Circuit::HalfOpen { .. } => {
+                *circuit = Circuit::HalfOpen { since: now };
+                tracing::info!("🟡 Circuit for provider {} half-open: sending a probe request", provider);
+                true
+            }
+        }
+    }
+
+    /// Record a finished call. Upstream faults count towards opening the circuit and a
+    /// success closes it; errors caused by the request (e.g. a 400) leave it unchanged.
+    pub fn record(&self, provider: &str, error: Option<&ProviderError>) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        if error.is_some_and(|e| !e.is_upstream_fault()) {
+            return;
+        }
+        let failed = error.is_some();
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits
+            .entry(provider.to_string())
+            .or_insert(Circuit::Closed { failures: 0 });
+
+        let open_until = Instant::now() + Duration::from_secs(config.cooldown_secs);
+        *circuit = match (*circuit, failed) {
+            (Circuit::Closed { .. }, false) => Circuit::Closed { failures: 0 },
+            (_, false) => {
+                tracing::info!("🟢 Circuit for provider {} closed: call succeeded", provider);
+                Circuit::Closed { failures: 0 }
+            }
+            (Circuit::Closed { failures }, true) if failures + 1 < config.failure_threshold => {
+                Circuit::Closed { failures: failures + 1 }
+            }
+            (Circuit::Closed { failures }, true) => {
+                tracing::warn!(
+                    "🔴 Circuit for provider {} open after {} consecutive failures, skipping it for {}s",
+                    provider,
+                    failures + 1,
+                    config.cooldown_secs
+                );
+                Circuit::Open { until: open_until }
+            }
+            (Circuit::HalfOpen { .. }, true) => {
+                tracing::warn!(
+                    "🔴 Circuit for provider {} open again: probe failed, skipping it for {}s",
+                    provider,
+                    config.cooldown_secs
+                );
+                Circuit::Open { until: open_until }
+            }
+            // A call started before the circuit opened
+            (open @ Circuit::Open { .. }, true) => open,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_half_open_probe_closes_or_reopens() {
+        let breaker = CircuitBreaker::new(Some(CircuitBreakerConfig {
+            failure_threshold: 2,
+            cooldown_secs: 1,
+        }));
+        let failure = ProviderError::AuthError("bad token".to_string());
+        let cooldown = Duration::from_millis(1100);
+
+        // Invalid requests are the caller's fault and don't count
+        breaker.record("p", Some(&ProviderError::InvalidRequest("bad".to_string())));
+        breaker.record("p", Some(&failure));
+        assert!(breaker.allow("p"));
+        breaker.record("p", Some(&failure));
+        assert!(!breaker.allow("p"));
+        assert!(breaker.allow("other"));
+
+        // After the cooldown exactly one probe goes through; it fails and reopens
+        std::thread::sleep(cooldown);
+        assert!(breaker.allow("p"));
+        assert!(!breaker.allow("p"));
+        breaker.record("p", Some(&failure));
+        assert!(!breaker.allow("p"));
+
+        // The next probe succeeds and closes the circuit
+        std::thread::sleep(cooldown);
+        assert!(breaker.allow("p"));
+        breaker.record("p", None);
+        assert!(breaker.allow("p"));
+        assert!(breaker.allow("p"));
+    }
+
+    #[test]
+    fn test_client_errors_leave_circuit_unchanged() {
+        let breaker = CircuitBreaker::new(Some(CircuitBreakerConfig {
+            failure_threshold: 2,
+            cooldown_secs: 60,
+        }));
+        let failure = ProviderError::ApiError { status: 503, message: "down".to_string() };
+        let client_errors = [
+            ProviderError::ApiError { status: 400, message: "bad".to_string() },
+            ProviderError::ApiError { status: 422, message: "bad".to_string() },
+            ProviderError::InvalidRequest("bad".to_string()),
+        ];
+
+        // Bad requests don't open the circuit...
+        for error in &client_errors {
+            breaker.record("p", Some(error));
+            breaker.record("p", Some(error));
+        }
+        assert!(breaker.allow("p"));
+
+        // ...nor reset the failure count or close an open circuit
+        breaker.record("p", Some(&failure));
+        breaker.record("p", Some(&client_errors[0]));
+        breaker.record("p", Some(&failure));
+        assert!(!breaker.allow("p"));
+        for error in &client_errors {
+            breaker.record("p", Some(error));
+        }
+        assert!(!breaker.allow("p"));
+    }
+}
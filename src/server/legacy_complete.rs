@@ -0,0 +1,210 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use crate::models::{AnthropicRequest, ContentBlock, Message, MessageContent, SystemPrompt};
+use crate::providers::streaming::{SseEvent, StreamTranslator};
+use crate::providers::ProviderResponse;
+
+const HUMAN_PROMPT: &str = "\n\nHuman:";
+const AI_PROMPT: &str = "\n\nAssistant:";
+
+/// Legacy Text Completions request format (`/v1/complete`)
+#[derive(Debug, Deserialize)]
+pub struct LegacyCompletionRequest {
+    pub model: String,
+    pub prompt: String,
+    pub max_tokens_to_sample: u32,
+    #[serde(default)]
+    pub stop_sequences: Option<Vec<String>>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub top_k: Option<u32>,
+    #[serde(default)]
+    pub stream: Option<bool>,
+    #[serde(default)]
+    pub metadata: Option<HashMap<String, serde_json::Value>>,
+}
+
+/// Legacy Text Completions response format
+#[derive(Debug, Serialize)]
+pub struct LegacyCompletionResponse {
+    #[serde(rename = "type")]
+    pub r#type: String,
+    pub id: String,
+    pub completion: String,
+    pub stop_reason: Option<String>,
+    pub model: String,
+    /// The stop sequence that ended generation, if any
+    pub stop: Option<String>,
+}
+
+/// Transform a `\n\nHuman: ... \n\nAssistant:` prompt into a Messages request.
+/// Text before the first Human turn becomes the system prompt; a non-empty final
+/// Assistant turn is kept as a prefill.
+pub fn transform_legacy_to_anthropic(legacy_req: LegacyCompletionRequest) -> Result<AnthropicRequest, String> {
+    let prompt = if legacy_req.prompt.starts_with("Human:") {
+        format!("\n\n{}", legacy_req.prompt)
+    } else {
+        legacy_req.prompt
+    };
+
+    let mut turns: Vec<(usize, &str, &str)> = prompt
+        .match_indices(HUMAN_PROMPT)
+        .map(|(idx, marker)| (idx, "user", marker))
+        .chain(prompt.match_indices(AI_PROMPT).map(|(idx, marker)| (idx, "assistant", marker)))
+        .collect();
+    turns.sort_by_key(|(idx, _, _)| *idx);
+
+    let Some(&(first, "user", _)) = turns.first() else {
+        return Err("prompt must start with a \"\\n\\nHuman:\" turn".to_string());
+    };
+
+    let system = prompt[..first].trim();
+    let mut messages: Vec<Message> = Vec::new();
+    for (i, (start, role, marker)) in turns.iter().enumerate() {
+        let end = turns.get(i + 1).map(|(idx, _, _)| *idx).unwrap_or(prompt.len());
+        let text = prompt[start + marker.len()..end].trim();
+        if text.is_empty() {
+            continue;
+        }
+        match messages.last_mut() {
+            // Adjacent turns of the same role are merged
+            Some(last) if last.role == *role => {
+                if let MessageContent::Text(existing) = &mut last.content {
+                    existing.push_str("\n\n");
+                    existing.push_str(text);
+                }
+            }
+            _ => messages.push(Message {
+                role: role.to_string(),
+                content: MessageContent::Text(text.to_string()),
+            }),
+        }
+    }
+
+    if messages.is_empty() {
+        return Err("prompt has no Human turn content".to_string());
+    }
+
+    Ok(AnthropicRequest {
+        model: legacy_req.model,
+        messages,
+        max_tokens: legacy_req.max_tokens_to_sample,
+        thinking: None,
+        temperature: legacy_req.temperature,
+        top_p: legacy_req.top_p,
+        top_k: legacy_req.top_k,
+        stop_sequences: legacy_req.stop_sequences,
+        stream: legacy_req.stream,
+        metadata: legacy_req.metadata,
+        system: (!system.is_empty()).then(|| SystemPrompt::Text(system.to_string())),
+        tools: None,
+        tool_choice: None,
+        betas: None,
+        stream_options: None,
+        api_version: None,
+        output_format: None,
+        request_id_header: None,
+        capture_raw: false,
+        forwarded_headers: vec![],
+    })
+}
+
+/// Legacy stop reason and stop sequence for a Messages stop reason
+fn legacy_stop(stop_reason: Option<&str>, stop_sequence: Option<&str>) -> (Option<String>, Option<String>) {
+    match stop_reason {
+        Some("max_tokens") => (Some("max_tokens".to_string()), None),
+        Some("stop_sequence") => (Some("stop_sequence".to_string()), stop_sequence.map(|s| s.to_string())),
+        // A natural end of turn is reported as hitting the Human prompt
+        Some(_) => (Some("stop_sequence".to_string()), Some(HUMAN_PROMPT.to_string())),
+        None => (None, None),
+    }
+}
+
+/// Transform a Messages response into a legacy completion
+pub fn transform_anthropic_to_legacy(response: ProviderResponse, model: String) -> LegacyCompletionResponse {
+    let completion = response
+        .content
+        .iter()
+        .filter_map(|block| match block {
+            ContentBlock::Text { text } => Some(text.as_str()),
+            _ => None,
+        })
+        .collect::<String>();
+    let (stop_reason, stop) = legacy_stop(response.stop_reason.as_deref(), response.stop_sequence.as_deref());
+
+    LegacyCompletionResponse {
+        r#type: "completion".to_string(),
+        id: response.id,
+        completion,
+        stop_reason,
+        model,
+        stop,
+    }
+}
+
+/// Re-emits a Messages event stream as legacy `completion` events
+pub struct LegacyStreamTranslator {
+    model: String,
+    id: String,
+}
+
+impl LegacyStreamTranslator {
+    pub fn new(model: String) -> Self {
+        Self {
+            model,
+            id: String::new(),
+        }
+    }
+
+    fn completion(&self, completion: &str, stop_reason: Option<String>, stop: Option<String>) -> SseEvent {
+        SseEvent {
+            event: Some("completion".to_string()),
+            data: serde_json::json!({
+                "type": "completion",
+                "id": self.id,
+                "completion": completion,
+                "stop_reason": stop_reason,
+                "model": self.model,
+                "stop": stop,
+            })
+            .to_string(),
+        }
+    }
+}
+
+impl StreamTranslator for LegacyStreamTranslator {
+    fn translate(&mut self, event: SseEvent) -> Vec<SseEvent> {
+        let Some(data) = event.json() else {
+            return Vec::new();
+        };
+
+        match data.get("type").and_then(|t| t.as_str()) {
+            Some("message_start") => {
+                if let Some(id) = data.pointer("/message/id").and_then(|v| v.as_str()) {
+                    self.id = id.to_string();
+                }
+                Vec::new()
+            }
+            Some("content_block_delta") => match data.pointer("/delta/text").and_then(|t| t.as_str()) {
+                Some(text) if !text.is_empty() => vec![self.completion(text, None, None)],
+                _ => Vec::new(),
+            },
+            Some("message_delta") => {
+                let (stop_reason, stop) = legacy_stop(
+                    data.pointer("/delta/stop_reason").and_then(|v| v.as_str()),
+                    data.pointer("/delta/stop_sequence").and_then(|v| v.as_str()),
+                );
+                if stop_reason.is_some() {
+                    vec![self.completion("", stop_reason, stop)]
+                } else {
+                    Vec::new()
+                }
+            }
+            Some("ping") | Some("error") => vec![event],
+            _ => Vec::new(),
+        }
+    }
+}
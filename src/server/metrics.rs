@@ -0,0 +1,180 @@
+use crate::providers::streaming::{SseEvent, StreamTranslator};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// In-process request metrics shared by all handlers
+#[derive(Clone)]
+pub struct Metrics {
+    started: Instant,
+    inner: Arc<Mutex<Counters>>,
+}
+
+#[derive(Default)]
+struct Counters {
+    requests: u64,
+    errors: u64,
+    fallbacks: u64,
+    input_tokens: u64,
+    output_tokens: u64,
+    providers: BTreeMap<String, ProviderCounters>,
+}
+
+#[derive(Default)]
+struct ProviderCounters {
+    requests: u64,
+    errors: u64,
+    total_latency: Duration,
+    max_latency: Duration,
+}
+
+/// Point-in-time view of the metrics, as served by `/api/metrics`
+#[derive(Debug, Serialize)]
+pub struct MetricsSnapshot {
+    pub uptime_secs: u64,
+    pub requests_total: u64,
+    pub errors_total: u64,
+    pub fallbacks_total: u64,
+    pub input_tokens_total: u64,
+    pub output_tokens_total: u64,
+    pub providers: BTreeMap<String, ProviderSnapshot>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProviderSnapshot {
+    pub requests: u64,
+    pub errors: u64,
+    pub avg_latency_ms: u64,
+    pub max_latency_ms: u64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            inner: Arc::new(Mutex::new(Counters::default())),
+        }
+    }
+
+    /// An API request was received; `failed` if it ended with a non-2xx response
+    pub fn record_request(&self, failed: bool) {
+        let mut counters = self.inner.lock().unwrap();
+        counters.requests += 1;
+        if failed {
+            counters.errors += 1;
+        }
+    }
+
+    /// A request moved on to its next mapping
+    pub fn record_fallback(&self) {
+        self.inner.lock().unwrap().fallbacks += 1;
+    }
+
+    /// One upstream call to `provider` finished after `latency`
+    pub fn record_provider(&self, provider: &str, latency: Duration, success: bool) {
+        let mut counters = self.inner.lock().unwrap();
+        let entry = counters.providers.entry(provider.to_string()).or_default();
+        entry.requests += 1;
+        if !success {
+            entry.errors += 1;
+        }
+        entry.total_latency += latency;
+        entry.max_latency = entry.max_latency.max(latency);
+    }
+
+    pub fn record_usage(&self, input_tokens: u32, output_tokens: u32) {
+        let mut counters = self.inner.lock().unwrap();
+        counters.input_tokens += u64::from(input_tokens);
+        counters.output_tokens += u64::from(output_tokens);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let counters = self.inner.lock().unwrap();
+        MetricsSnapshot {
+            uptime_secs: self.started.elapsed().as_secs(),
+            requests_total: counters.requests,
+            errors_total: counters.errors,
+            fallbacks_total: counters.fallbacks,
+            input_tokens_total: counters.input_tokens,
+            output_tokens_total: counters.output_tokens,
+            providers: counters
+                .providers
+                .iter()
+                .map(|(name, p)| {
+                    let avg = p.total_latency.as_millis() / u128::from(p.requests.max(1));
+                    (
+                        name.clone(),
+                        ProviderSnapshot {
+                            requests: p.requests,
+                            errors: p.errors,
+                            avg_latency_ms: avg as u64,
+                            max_latency_ms: p.max_latency.as_millis() as u64,
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Middleware counting API requests and failed responses
+pub async fn track_requests(State(metrics): State<Metrics>, request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    metrics.record_request(!response.status().is_success());
+    response
+}
+
+/// Adds the token usage of a streamed response to the metrics once it ends
+pub struct UsageRecorder {
+    metrics: Metrics,
+    input_tokens: u32,
+    output_tokens: u32,
+}
+
+impl UsageRecorder {
+    pub fn new(metrics: Metrics) -> Self {
+        Self {
+            metrics,
+            input_tokens: 0,
+            output_tokens: 0,
+        }
+    }
+}
+
+impl StreamTranslator for UsageRecorder {
+    fn translate(&mut self, event: SseEvent) -> Vec<SseEvent> {
+        if let Some(data) = event.json() {
+            let usage = match data.get("type").and_then(|t| t.as_str()) {
+                Some("message_start") => data.pointer("/message/usage"),
+                Some("message_delta") => data.get("usage"),
+                _ => None,
+            };
+            if let Some(usage) = usage {
+                if let Some(input) = usage.get("input_tokens").and_then(|v| v.as_u64()) {
+                    self.input_tokens = input as u32;
+                }
+                if let Some(output) = usage.get("output_tokens").and_then(|v| v.as_u64()) {
+                    self.output_tokens = output as u32;
+                }
+            }
+        }
+        vec![event]
+    }
+
+    fn finish(&mut self) -> Vec<SseEvent> {
+        self.metrics.record_usage(self.input_tokens, self.output_tokens);
+        Vec::new()
+    }
+}
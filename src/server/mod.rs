@@ -1,21 +1,38 @@
+mod circuit_breaker;
+mod legacy_complete;
+mod metrics;
 mod openai_compat;
 mod oauth_handlers;
+mod provider_health;
+mod self_test;
+mod stream_limit;
 
-use crate::cli::AppConfig;
-use crate::models::AnthropicRequest;
+use crate::cli::{AppConfig, CorsConfig, ModelConfig, ModelMapping, RetryConfig, ServerConfig, ThinkingPolicy};
+use crate::models::{AnthropicRequest, SystemPrompt};
 use crate::router::Router;
-use crate::providers::ProviderRegistry;
-use crate::auth::TokenStore;
+use crate::providers::{AnthropicProvider, AuthType, ProviderRegistry, ProviderResponse};
+use crate::providers::error::ProviderError;
+use crate::providers::validation::{self, ValidationMode};
+use crate::providers::streaming::{
+    end_on_error, first_content, map_events, response_events, translate_events, EarlyPingFilter, EventStream, PingFilter, TextTrimmer,
+    UsageFinalizer,
+};
+use crate::auth::{OAuthClient, OAuthConfig, TokenStore};
 use axum::{
-    extract::State,
+    extract::{ConnectInfo, Query, State},
     http::{HeaderMap, StatusCode},
     response::{
-        Html, IntoResponse, Response, sse::{Event, Sse},
+        Html, IntoResponse, Response, sse::{Event, KeepAlive, Sse},
     },
     routing::{get, post},
     Form, Json, Router as AxumRouter,
 };
+use metrics::{Metrics, UsageRecorder};
+use circuit_breaker::CircuitBreaker;
+use provider_health::ProviderHealth;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use stream_limit::StreamLimiter;
 use tokio::net::TcpListener;
 use tracing::{error, info};
 use futures::stream::StreamExt;
@@ -28,15 +45,24 @@ pub struct AppState {
     pub provider_registry: Arc<ProviderRegistry>,
     pub token_store: TokenStore,
     pub config_path: std::path::PathBuf,
+    pub stream_limiter: StreamLimiter,
+    pub pending_reauths: oauth_handlers::PendingReauths,
+    pub metrics: Metrics,
+    pub provider_health: ProviderHealth,
+    pub circuit_breaker: CircuitBreaker,
 }
 
-/// Start the HTTP server
-pub async fn start_server(config: AppConfig, config_path: std::path::PathBuf) -> anyhow::Result<()> {
+/// How often unpersisted OAuth token changes are flushed to disk
+const TOKEN_FLUSH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Build the shared application state: router, token store and provider registry
+fn build_state(config: AppConfig, config_path: std::path::PathBuf) -> anyhow::Result<Arc<AppState>> {
     let router = Router::new(config.clone());
 
     // Initialize OAuth token store FIRST (needed by provider registry)
     let token_store = TokenStore::default()
-        .map_err(|e| anyhow::anyhow!("Failed to initialize token store: {}", e))?;
+        .map_err(|e| anyhow::anyhow!("Failed to initialize token store: {}", e))?
+        .with_max_concurrent_refreshes(config.server.max_concurrent_oauth_refreshes);
 
     let existing_tokens = token_store.list_providers();
     if !existing_tokens.is_empty() {
@@ -54,23 +80,42 @@ pub async fn start_server(config: AppConfig, config_path: std::path::PathBuf) ->
         provider_registry.list_models().len()
     );
 
-    let state = Arc::new(AppState {
+    Ok(Arc::new(AppState {
         config: config.clone(),
         router,
         provider_registry,
         token_store,
         config_path,
-    });
+        stream_limiter: StreamLimiter::new(config.server.max_streams_per_client),
+        pending_reauths: Default::default(),
+        metrics: Metrics::new(),
+        provider_health: ProviderHealth::new(&config.providers),
+        circuit_breaker: CircuitBreaker::new(config.router.circuit_breaker.clone()),
+    }))
+}
 
-    // Build router
-    let app = AxumRouter::new()
-        .route("/", get(serve_admin))
+/// All HTTP routes served by the main listener
+fn build_router(metrics: Metrics, server: &ServerConfig) -> AxumRouter<Arc<AppState>> {
+    // Model API routes, counted in the request metrics
+    let api = AxumRouter::new()
         .route("/v1/messages", post(handle_messages))
         .route("/v1/messages/count_tokens", post(handle_count_tokens))
         .route("/v1/chat/completions", post(handle_openai_chat_completions))
+        .route("/v1/complete", post(handle_legacy_complete))
+        .route("/v1/embeddings", post(handle_embeddings))
+        .route_layer(axum::middleware::from_fn_with_state(server.admin_key.clone(), request_span))
+        .route_layer(axum::middleware::from_fn_with_state(metrics, metrics::track_requests));
+
+    let router = AxumRouter::new()
+        .route("/", get(serve_admin))
+        .merge(api)
         .route("/health", get(health_check))
+        .route("/healthz/providers", get(check_providers))
+        .route("/api/metrics", get(get_metrics))
         .route("/api/models", get(get_models))
         .route("/api/providers", get(get_providers))
+        .route("/api/providers/disabled", get(get_disabled_providers))
+        .route("/api/providers/enable", post(enable_provider))
         .route("/api/models-config", get(get_models_config))
         .route("/api/config", get(get_config))
         .route("/api/config", post(update_config))
@@ -84,17 +129,167 @@ pub async fn start_server(config: AppConfig, config_path: std::path::PathBuf) ->
         .route("/auth/callback", get(oauth_handlers::oauth_callback))  // OpenAI Codex uses this path
         .route("/api/oauth/tokens", get(oauth_handlers::oauth_list_tokens))
         .route("/api/oauth/tokens/delete", post(oauth_handlers::oauth_delete_token))
-        .route("/api/oauth/tokens/refresh", post(oauth_handlers::oauth_refresh_token));
+        .route("/api/oauth/tokens/refresh", post(oauth_handlers::oauth_refresh_token))
+        .route("/api/oauth/reauth", post(oauth_handlers::oauth_reauth));
+
+    match cors_layer(&server.cors) {
+        Some(layer) => router.layer(layer),
+        None => router,
+    }
+}
+
+/// CORS layer for the configured origins; None (no CORS headers at all) when none are set
+fn cors_layer(cors: &CorsConfig) -> Option<tower_http::cors::CorsLayer> {
+    use tower_http::cors::{AllowHeaders, AllowOrigin, Any, CorsLayer};
+
+    if cors.is_disabled() {
+        return None;
+    }
+    let origins = if cors.allowed_origins.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(parse_cors_values(&cors.allowed_origins, "origin"))
+    };
+    let headers = if cors.allowed_headers.iter().any(|h| h == "*") {
+        AllowHeaders::any()
+    } else {
+        AllowHeaders::list(parse_cors_values(&cors.allowed_headers, "header"))
+    };
+    let methods: Vec<String> = cors.allowed_methods.iter().map(|m| m.to_uppercase()).collect();
+    let methods: Vec<axum::http::Method> = parse_cors_values(&methods, "method");
+    Some(
+        CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods(methods)
+            .allow_headers(headers)
+            .expose_headers(Any),
+    )
+}
+
+/// Parse CORS config entries, skipping (with a warning) any that aren't valid
+fn parse_cors_values<T: std::str::FromStr>(values: &[String], kind: &str) -> Vec<T> {
+    values
+        .iter()
+        .filter_map(|v| match v.parse() {
+            Ok(value) => Some(value),
+            Err(_) => {
+                tracing::warn!("⚠️ Ignoring invalid CORS {} '{}'", kind, v);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Log filter directive enabling this crate's debug logs inside requests sent with
+/// `x-ccm-debug: true` (see `request_span`), whatever the global level
+pub fn debug_log_directive() -> tracing_subscriber::filter::Directive {
+    format!("{}[ccm_request{{ccm_debug=true}}]=debug", env!("CARGO_CRATE_NAME"))
+        .parse()
+        .expect("valid directive")
+}
+
+/// Handle the request inside a `ccm_request` span, flagged for debug logging when the
+/// client sent `x-ccm-debug: true`. Only honoured with a matching `x-ccm-admin-key`,
+/// since debug logs include full request bodies.
+async fn request_span(
+    State(admin_key): State<Option<String>>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    use tracing::Instrument;
+
+    let debug_requested = request
+        .headers()
+        .get("x-ccm-debug")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.eq_ignore_ascii_case("true") || v == "1");
+    if debug_requested && !admin_key_valid(request.headers(), admin_key.as_deref()) {
+        return AppError::Forbidden("x-ccm-debug requires a valid x-ccm-admin-key".to_string()).into_response();
+    }
+    next.run(request)
+        .instrument(tracing::info_span!("ccm_request", ccm_debug = debug_requested))
+        .await
+}
+
+/// Serve a single `/v1/messages` request read from stdin and write the response
+/// (JSON, or SSE when streaming) to stdout. Fails if the response is not a success.
+pub async fn serve_once(config: AppConfig, config_path: std::path::PathBuf) -> anyhow::Result<()> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tower::ServiceExt;
+
+    let mut input = Vec::new();
+    tokio::io::stdin().read_to_end(&mut input).await?;
+
+    let state = build_state(config, config_path)?;
+    let token_store = state.token_store.clone();
+
+    let request = axum::http::Request::post("/v1/messages")
+        .header("content-type", "application/json")
+        .body(axum::body::Body::from(input))?;
+    let response = build_router(state.metrics.clone(), &state.config.server).with_state(state).oneshot(request).await?;
+    let status = response.status();
+
+    let mut stdout = tokio::io::stdout();
+    let mut body = response.into_body().into_data_stream();
+    let mut ends_with_newline = true;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        if let Some(last) = chunk.last() {
+            ends_with_newline = *last == b'\n';
+        }
+        stdout.write_all(&chunk).await?;
+        stdout.flush().await?;
+    }
+    if !ends_with_newline {
+        stdout.write_all(b"\n").await?;
+    }
+    stdout.flush().await?;
+
+    if let Err(e) = token_store.flush() {
+        error!("⚠️ Failed to persist OAuth tokens: {}", e);
+    }
+
+    if !status.is_success() {
+        anyhow::bail!("Request failed with status {}", status);
+    }
+    Ok(())
+}
+
+/// Keep Gemini OAuth tokens refreshed ahead of expiry (one task per token)
+fn spawn_token_refreshers(config: &AppConfig, token_store: &TokenStore) {
+    let mut provider_ids: Vec<String> = config
+        .providers
+        .iter()
+        .filter(|p| p.is_enabled() && p.provider_type == "gemini" && p.auth_type == AuthType::OAuth)
+        .filter_map(|p| p.oauth_provider.clone())
+        .collect();
+    provider_ids.sort();
+    provider_ids.dedup();
+    for provider_id in provider_ids {
+        info!("🔄 Refreshing OAuth token for '{}' in the background", provider_id);
+        OAuthClient::new(OAuthConfig::gemini(), token_store.clone()).spawn_refresh_task(provider_id);
+    }
+}
+
+/// Start the HTTP server
+pub async fn start_server(config: AppConfig, config_path: std::path::PathBuf) -> anyhow::Result<()> {
+    let state = build_state(config.clone(), config_path)?;
+    state.token_store.spawn_flush_task(TOKEN_FLUSH_INTERVAL);
+    spawn_token_refreshers(&config, &state.token_store);
+
+    if config.server.self_test {
+        self_test::run(&config.providers, &state.provider_registry).await;
+    }
+
+    // Build router
+    let app = build_router(state.metrics.clone(), &config.server);
 
     // Clone state before moving it
     let oauth_state = state.clone();
     let app = app.with_state(state);
 
-    // Bind to main address
-    let addr = format!("{}:{}", config.server.host, config.server.port);
-    let listener = TcpListener::bind(&addr).await?;
-
-    info!("🚀 Server listening on {}", addr);
+    // Bind every listen address
+    let listeners = bind_listeners(&config.server.listen_addrs()).await?;
 
     // Start OAuth callback server on port 1455 (required for OpenAI Codex)
     // This is necessary because OpenAI's OAuth app only allows localhost:1455/auth/callback
@@ -119,25 +314,156 @@ pub async fn start_server(config: AppConfig, config_path: std::path::PathBuf) ->
         }
     });
 
-    // Start main server
-    axum::serve(listener, app).await?;
+    // Start main server on all listeners (with peer addresses for per-client limits)
+    serve_listeners(listeners, app).await
+}
 
+/// Bind each address. IPv6 wildcards go first: on dual-stack hosts `[::]` usually
+/// accepts IPv4 as well, in which case a `0.0.0.0` bind on the same port is redundant.
+async fn bind_listeners(addrs: &[String]) -> anyhow::Result<Vec<TcpListener>> {
+    let mut resolved = Vec::new();
+    for addr in addrs {
+        let socket_addr = tokio::net::lookup_host(addr)
+            .await?
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Could not resolve listen address {}", addr))?;
+        resolved.push(socket_addr);
+    }
+    resolved.sort_by_key(|a| !(a.is_ipv6() && a.ip().is_unspecified()));
+
+    let mut listeners: Vec<TcpListener> = Vec::new();
+    for addr in resolved {
+        match TcpListener::bind(addr).await {
+            Ok(listener) => {
+                info!("🚀 Server listening on {}", listener.local_addr()?);
+                listeners.push(listener);
+            }
+            Err(e)
+                if e.kind() == std::io::ErrorKind::AddrInUse
+                    && addr.is_ipv4()
+                    && addr.ip().is_unspecified()
+                    && listeners.iter().any(|l| {
+                        l.local_addr().is_ok_and(|l| l.ip().is_unspecified() && l.port() == addr.port())
+                    }) =>
+            {
+                info!("🚀 {} is already served by the dual-stack IPv6 listener", addr);
+            }
+            Err(e) => return Err(anyhow::anyhow!("Failed to bind {}: {}", addr, e)),
+        }
+    }
+    Ok(listeners)
+}
+
+/// Serve the same app on every listener until one of them fails
+async fn serve_listeners(listeners: Vec<TcpListener>, app: AxumRouter) -> anyhow::Result<()> {
+    let servers = listeners.into_iter().map(|listener| {
+        let app = app.clone();
+        async move { axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await }
+    });
+    futures::future::try_join_all(servers).await?;
     Ok(())
 }
 
+/// Current request, fallback, latency and token counters as JSON
+async fn get_metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.metrics.snapshot())
+}
+
 /// Serve Admin UI
 async fn serve_admin() -> impl IntoResponse {
     Html(include_str!("admin.html"))
 }
 
+/// Query parameters for /health
+#[derive(Debug, Default, serde::Deserialize)]
+struct HealthQuery {
+    /// Send a 1-token probe request to every enabled provider
+    #[serde(default)]
+    probe: bool,
+}
+
 /// Health check endpoint
-async fn health_check() -> impl IntoResponse {
+/// With `?probe=true`, also probes each provider using its configured probe model/prompt
+async fn health_check(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<HealthQuery>,
+) -> impl IntoResponse {
+    if !query.probe {
+        return Json(serde_json::json!({
+            "status": "ok",
+            "service": "claude-code-mux"
+        }));
+    }
+
+    let probes = state
+        .config
+        .providers
+        .iter()
+        .filter(|p| p.is_enabled())
+        .map(|config| {
+            let provider = state.provider_registry.get_provider(&config.name);
+            let request = config.probe_request();
+            let name = config.name.clone();
+            async move {
+                let (Some(provider), Some(request)) = (provider, request) else {
+                    return (name, serde_json::json!({ "status": "skipped" }));
+                };
+                let model = request.model.clone();
+                let started = std::time::Instant::now();
+                let result = provider.send_message(request).await;
+                let latency_ms = started.elapsed().as_millis() as u64;
+                let status = match result {
+                    Ok(_) => serde_json::json!({ "status": "ok", "model": model, "latency_ms": latency_ms }),
+                    Err(e) => serde_json::json!({ "status": "error", "model": model, "error": e.to_string() }),
+                };
+                (name, status)
+            }
+        });
+
+    let results = futures::future::join_all(probes).await;
+    let healthy = results.iter().all(|(_, r)| r["status"] != "error");
+
     Json(serde_json::json!({
-        "status": "ok",
-        "service": "claude-code-mux"
+        "status": if healthy { "ok" } else { "degraded" },
+        "service": "claude-code-mux",
+        "providers": results.into_iter().collect::<serde_json::Map<_, _>>()
     }))
 }
 
+/// Run every enabled provider's `health_check`; 503 when any of them fails
+async fn check_providers(State(state): State<Arc<AppState>>) -> Response {
+    let checks = state
+        .config
+        .providers
+        .iter()
+        .filter(|p| p.is_enabled())
+        .filter_map(|config| Some((config.name.clone(), state.provider_registry.get_provider(&config.name)?)))
+        .map(|(name, provider)| async move {
+            let started = std::time::Instant::now();
+            let status = match provider.health_check().await {
+                Ok(()) => serde_json::json!({ "status": "ok", "latency_ms": started.elapsed().as_millis() as u64 }),
+                Err(e) => {
+                    error!("❌ Health check failed for provider '{}': {}", name, e);
+                    serde_json::json!({ "status": "error", "error": e.to_string() })
+                }
+            };
+            (name, status)
+        });
+
+    let results = futures::future::join_all(checks).await;
+    let healthy = results.iter().all(|(_, r)| r["status"] == "ok");
+    let status = if healthy { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+
+    (
+        status,
+        Json(serde_json::json!({
+            "status": if healthy { "ok" } else { "degraded" },
+            "providers": results.into_iter().collect::<serde_json::Map<_, _>>()
+        })),
+    )
+        .into_response()
+}
+
 /// REMOVED: This endpoint was for LiteLLM integration which has been removed.
 /// Models are now managed through the provider registry and config.
 async fn get_models(State(_state): State<Arc<AppState>>) -> Result<Json<serde_json::Value>, AppError> {
@@ -216,6 +542,31 @@ async fn get_providers(State(state): State<Arc<AppState>>) -> impl IntoResponse
     Json(state.config.providers.clone())
 }
 
+/// Providers taken out of routing by their failure threshold
+async fn get_disabled_providers(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(serde_json::json!({ "disabled": state.provider_health.disabled() }))
+}
+
+#[derive(serde::Deserialize)]
+struct EnableProviderRequest {
+    provider: String,
+}
+
+/// Put a provider disabled by its failure threshold back into routing
+async fn enable_provider(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<EnableProviderRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    if !state.provider_health.enable(&req.provider) {
+        return Err((StatusCode::NOT_FOUND, format!("Provider '{}' is not disabled", req.provider)));
+    }
+    info!("✅ Provider {} re-enabled", req.provider);
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "message": format!("Provider '{}' enabled", req.provider),
+    })))
+}
+
 /// Get models configuration
 async fn get_models_config(State(state): State<Arc<AppState>>) -> impl IntoResponse {
     Json(state.config.models.clone())
@@ -465,7 +816,9 @@ async fn handle_openai_chat_completions(
     Json(openai_request): Json<openai_compat::OpenAIRequest>,
 ) -> Result<Response, AppError> {
     let model = openai_request.model.clone();
-    info!("Received OpenAI-compatible request for model: {}", model);
+    let request_id = request_id(&headers);
+    info!("Received OpenAI-compatible request for model: {} ({})", model, request_id);
+    let timeout = request_timeout(&headers, &state.config)?;
 
     // 1. Transform OpenAI request to Anthropic format
     let mut anthropic_request = openai_compat::transform_openai_to_anthropic(openai_request)
@@ -485,7 +838,7 @@ async fn handle_openai_chat_completions(
     );
 
     // 3. Try model mappings with fallback (1:N mapping)
-    if let Some(model_config) = state.config.models.iter().find(|m| m.name == decision.model_name) {
+    if let Some(model_config) = state.config.find_model(&decision.model_name) {
         info!("📋 Found {} provider mappings for model: {}", model_config.mappings.len(), decision.model_name);
 
         // Check for X-Provider header to override priority
@@ -512,12 +865,18 @@ async fn handle_openai_chat_completions(
                 )));
             }
         } else {
-            // Use priority ordering
-            sorted_mappings.sort_by_key(|m| m.priority);
+            // Use priority ordering, then the global fallbacks
+            sorted_mappings = model_config.mapping_chain(&state.config.fallback_models);
         }
+        retain_thinking_matches(&mut sorted_mappings, &anthropic_request);
+        route_by_app(&state.config, &headers, &mut sorted_mappings);
 
         // Try each mapping in priority order (or just the forced one)
+        let mut last_error: Option<String> = None;
         for (idx, mapping) in sorted_mappings.iter().enumerate() {
+            if idx > 0 {
+                state.metrics.record_fallback();
+            }
             info!(
                 "🔄 Trying mapping {}/{}: provider={}, actual_model={}",
                 idx + 1,
@@ -526,13 +885,50 @@ async fn handle_openai_chat_completions(
                 mapping.actual_model
             );
 
+            if !state.provider_registry.is_model_allowed(&mapping.provider, &mapping.actual_model) {
+                info!(
+                    "🚫 Skipping provider {}: model {} is excluded by its allowed/denied models",
+                    mapping.provider, mapping.actual_model
+                );
+                last_error = Some(format!("Model {} not allowed on provider {}", mapping.actual_model, mapping.provider));
+                continue;
+            }
+
+            if state.provider_health.is_disabled(&mapping.provider) {
+                info!("⛔ Skipping provider {}: disabled after too many failures", mapping.provider);
+                last_error = Some(format!("Provider {} is disabled after too many failures", mapping.provider));
+                continue;
+            }
+
+            if !state.circuit_breaker.allow(&mapping.provider) {
+                info!("🔌 Skipping provider {}: circuit open", mapping.provider);
+                last_error = Some(format!("Provider {} circuit is open after repeated failures", mapping.provider));
+                continue;
+            }
+
             // Try to get provider from registry
             if let Some(provider) = state.provider_registry.get_provider(&mapping.provider) {
-                // Update model to actual model name
-                anthropic_request.model = mapping.actual_model.clone();
+                // Update model to actual model name, with this mapping's overrides
+                let mut attempt_request = anthropic_request.clone();
+                attempt_request.model = state.provider_registry.upstream_model(&mapping.provider, &mapping.actual_model);
+                mapping.apply_overrides(&mut attempt_request);
+                state.provider_registry.trim_tools(&mapping.provider, &mut attempt_request);
+                state.provider_registry.compact_history(&mapping.provider, &mut attempt_request).await;
+                state.provider_registry.attach_request_id(&mapping.provider, &request_id, &mut attempt_request);
+                state.provider_registry.attach_forwarded_headers(&mapping.provider, &headers, &mut attempt_request);
+                if !check_thinking_support(&state.config, &state.provider_registry, &mapping.provider, &mut attempt_request)? {
+                    last_error = Some(format!("Provider {} doesn't support thinking", mapping.provider));
+                    continue;
+                }
+                state.provider_registry.coalesce_system(&mapping.provider, &mut attempt_request);
+
+                if let Err(e) = check_request_schema(model_config.schema_validation, &mapping.provider, provider.as_ref().as_ref(), &attempt_request) {
+                    last_error = Some(e.to_string());
+                    continue;
+                }
 
                 // Check if streaming is requested
-                let is_streaming = anthropic_request.stream == Some(true);
+                let is_streaming = attempt_request.stream == Some(true);
 
                 if is_streaming {
                     // Streaming not fully implemented for OpenAI format yet
@@ -540,9 +936,17 @@ async fn handle_openai_chat_completions(
                 }
 
                 // Non-streaming request
-                match provider.send_message(anthropic_request.clone()).await {
-                    Ok(anthropic_response) => {
+                match call_provider(&state, &mapping.provider, timeout, || provider.send_message(attempt_request.clone())).await {
+                    Ok(mut anthropic_response) => {
+                        if state.provider_registry.trims_response_text(&mapping.provider) {
+                            anthropic_response.trim_text();
+                        }
+                        if let Err(e) = check_response_schema(model_config.schema_validation, &mapping.provider, &anthropic_response) {
+                            last_error = Some(e.to_string());
+                            continue;
+                        }
                         info!("✅ Request succeeded with provider: {}", mapping.provider);
+                        state.metrics.record_usage(anthropic_response.usage.input_tokens, anthropic_response.usage.output_tokens);
 
                         // Transform Anthropic response to OpenAI format
                         let openai_response = openai_compat::transform_anthropic_to_openai(
@@ -552,8 +956,12 @@ async fn handle_openai_chat_completions(
 
                         return Ok(Json(openai_response).into_response());
                     }
+                    Err(e) if !can_fall_back(&state.config, &e) => {
+                        return Err(no_fallback_error(mapping, e));
+                    }
                     Err(e) => {
                         info!("⚠️ Provider {} failed: {}, trying next fallback", mapping.provider, e);
+                        last_error = Some(e.to_string());
                         continue;
                     }
                 }
@@ -565,9 +973,10 @@ async fn handle_openai_chat_completions(
 
         error!("❌ All provider mappings failed for model: {}", decision.model_name);
         return Err(AppError::ProviderError(format!(
-            "All {} provider mappings failed for model: {}",
+            "All {} provider mappings failed for model: {}{}",
             sorted_mappings.len(),
-            decision.model_name
+            decision.model_name,
+            last_error.map(|e| format!(" (last error: {})", e)).unwrap_or_default()
         )));
     } else {
         // No model mapping found, try direct provider registry lookup (backward compatibility)
@@ -577,10 +986,12 @@ async fn handle_openai_chat_completions(
             // Update model to routed model
             anthropic_request.model = decision.model_name.clone();
 
-            // Call provider
-            let anthropic_response = provider.send_message(anthropic_request)
+            // Call provider, with the provider's own retry overrides
+            let retry = state.config.retry_for(state.provider_registry.provider_name_for_model(&decision.model_name).unwrap_or_default());
+            let anthropic_response = with_timeout(timeout, retry_on_reset(&retry, &decision.model_name, || provider.send_message(anthropic_request.clone())))
                 .await
                 .map_err(|e| AppError::ProviderError(e.to_string()))?;
+            state.metrics.record_usage(anthropic_response.usage.input_tokens, anthropic_response.usage.output_tokens);
 
             // Transform to OpenAI format
             let openai_response = openai_compat::transform_anthropic_to_openai(
@@ -599,22 +1010,594 @@ async fn handle_openai_chat_completions(
     }
 }
 
+/// Whether to send SSE pings/comments to this client: the `x-ccm-sse-pings`
+/// header wins, then `server.sse_pings` (default on)
+fn sse_pings_enabled(headers: &HeaderMap, config: &AppConfig) -> bool {
+    let header = headers
+        .get("x-ccm-sse-pings")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_ascii_lowercase());
+    match header.as_deref() {
+        Some("off" | "false" | "0" | "no") => false,
+        Some("on" | "true" | "1" | "yes") => true,
+        _ => config.server.sse_pings.unwrap_or(true),
+    }
+}
+
+/// Normalize an incoming Anthropic request before it is parsed and forwarded:
+/// an empty `tools` array is dropped, along with a `tool_choice` that has no tools
+fn normalize_request(request: &mut serde_json::Value, config: &AppConfig) {
+    if !config.server.strip_empty_tools.unwrap_or(true) {
+        return;
+    }
+    let Some(obj) = request.as_object_mut() else {
+        return;
+    };
+
+    let empty_tools = obj
+        .get("tools")
+        .is_some_and(|t| t.is_null() || t.as_array().is_some_and(|a| a.is_empty()));
+    if empty_tools {
+        obj.remove("tools");
+        tracing::debug!("🧹 Stripped empty tools array from request");
+    }
+    if !obj.contains_key("tools") && obj.remove("tool_choice").is_some() {
+        tracing::debug!("🧹 Stripped tool_choice from request without tools");
+    }
+}
+
+/// Check the fields every Anthropic request needs before routing, so a malformed
+/// request gets a 400 naming the field. A missing `max_tokens` is left to
+/// `apply_default_max_tokens` when some model could fill it in.
+fn validate_request(request: &serde_json::Value, config: &AppConfig, require_max_tokens: bool) -> Result<(), AppError> {
+    if !config.server.validate_requests.unwrap_or(true) {
+        return Ok(());
+    }
+    let invalid = |reason: &str| Err(AppError::BadRequest(format!("Invalid request format: {}", reason)));
+    let Some(obj) = request.as_object() else {
+        return invalid("body must be a JSON object");
+    };
+
+    match obj.get("model") {
+        None => return invalid("missing field `model`"),
+        Some(model) if model.as_str().is_none_or(|m| m.trim().is_empty()) => {
+            return invalid("`model` must be a non-empty string")
+        }
+        _ => {}
+    }
+    match obj.get("messages") {
+        None => return invalid("missing field `messages`"),
+        Some(messages) if !messages.is_array() => return invalid("`messages` must be an array"),
+        _ => {}
+    }
+    if !require_max_tokens {
+        return Ok(());
+    }
+    match obj.get("max_tokens") {
+        None if !config.models.iter().any(|m| m.default_max_tokens.is_some()) => invalid("missing field `max_tokens`"),
+        Some(max_tokens) if max_tokens.as_u64().is_none_or(|n| n == 0 || n > u32::MAX as u64) => {
+            invalid("`max_tokens` must be a positive integer")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Fill in `max_tokens` from the model's `default_max_tokens` when the client omitted it.
+/// Without a default the request is rejected, as the upstream would do.
+fn apply_default_max_tokens(request: &mut serde_json::Value, model_config: Option<&ModelConfig>) -> Result<(), AppError> {
+    let Some(obj) = request.as_object_mut() else {
+        return Ok(());
+    };
+    if obj.contains_key("max_tokens") {
+        return Ok(());
+    }
+    match model_config.and_then(|m| m.default_max_tokens) {
+        Some(max_tokens) => {
+            tracing::debug!("📏 Client sent no max_tokens, using model default {}", max_tokens);
+            obj.insert("max_tokens".to_string(), max_tokens.into());
+            Ok(())
+        }
+        None => Err(AppError::BadRequest("Invalid request format: missing field `max_tokens`".to_string())),
+    }
+}
+
+/// Whether a failed provider call may fall back to the next mapping
+fn can_fall_back(config: &AppConfig, error: &ProviderError) -> bool {
+    match error {
+        ProviderError::AuthError(_) => config.router.fallback_on_auth_error.unwrap_or(true),
+        ProviderError::InvalidRequest(_) => config.router.fallback_on_invalid_request.unwrap_or(true),
+        _ => true,
+    }
+}
+
+/// Client-facing error for a provider failure that ends the request without fallback
+fn no_fallback_error(mapping: &ModelMapping, error: ProviderError) -> AppError {
+    error!("❌ Provider {} failed, not falling back: {}", mapping.provider, error);
+    match error {
+        ProviderError::InvalidRequest(_) => AppError::BadRequest(error.to_string()),
+        _ => AppError::ProviderError(error.to_string()),
+    }
+}
+
+/// Drop mappings whose thinking conditions the request doesn't meet
+fn retain_thinking_matches(mappings: &mut Vec<ModelMapping>, request: &AnthropicRequest) {
+    let before = mappings.len();
+    mappings.retain(|m| m.matches_thinking(request));
+    if mappings.len() < before {
+        info!("🧠 Skipped {} mappings whose thinking conditions don't match", before - mappings.len());
+    }
+}
+
+/// Drop mappings reserved for other apps and try the calling app's own mappings
+/// (`app_ids`) first, keeping priority order within each group
+fn route_by_app(config: &AppConfig, headers: &HeaderMap, mappings: &mut Vec<ModelMapping>) {
+    let app_id = headers
+        .get(config.router.app_id_header())
+        .and_then(|v| v.to_str().ok())
+        .filter(|s| !s.is_empty());
+    mappings.retain(|m| m.matches_app(app_id));
+    mappings.sort_by_key(|m| m.app_ids.is_empty());
+    let app_mappings = mappings.iter().filter(|m| !m.app_ids.is_empty()).count();
+    if let Some(app_id) = app_id.filter(|_| app_mappings > 0) {
+        info!("📱 Trying {} mappings for app {} first", app_mappings, app_id);
+    }
+}
+
+/// Apply `router.unsupported_thinking` to a thinking request bound for a provider
+/// that can't think. Returns false when the mapping should be skipped (fallback policy).
+fn check_thinking_support(
+    config: &AppConfig,
+    registry: &ProviderRegistry,
+    provider_name: &str,
+    request: &mut AnthropicRequest,
+) -> Result<bool, AppError> {
+    if request.thinking.is_none() || registry.supports_thinking(provider_name) {
+        return Ok(true);
+    }
+
+    match config.router.unsupported_thinking {
+        ThinkingPolicy::Strip => {
+            info!("🧠 Provider {} doesn't support thinking, removing it from the request", provider_name);
+            request.thinking = None;
+            Ok(true)
+        }
+        ThinkingPolicy::Error => Err(AppError::RoutingError(format!(
+            "Thinking was requested but provider {} doesn't support it",
+            provider_name
+        ))),
+        ThinkingPolicy::Fallback => {
+            info!("🧠 Skipping provider {}: thinking requested but not supported", provider_name);
+            Ok(false)
+        }
+    }
+}
+
+/// Validate the request a provider would send upstream (no-op unless enabled for the model)
+fn check_request_schema(
+    mode: ValidationMode,
+    provider_name: &str,
+    provider: &dyn AnthropicProvider,
+    request: &AnthropicRequest,
+) -> Result<(), ProviderError> {
+    if !mode.is_enabled() {
+        return Ok(());
+    }
+    let result = mode.enforce(
+        &format!("request to {}", provider_name),
+        provider.request_schema_violations(request),
+    );
+    if let Err(ref e) = result {
+        info!("⚠️ Provider {} skipped: {}, trying next fallback", provider_name, e);
+    }
+    result
+}
+
+/// Validate a provider response against the Anthropic schema (no-op unless enabled for the model)
+fn check_response_schema(
+    mode: ValidationMode,
+    provider_name: &str,
+    response: &ProviderResponse,
+) -> Result<(), ProviderError> {
+    if !mode.is_enabled() {
+        return Ok(());
+    }
+    let violations = serde_json::to_value(response)
+        .map(|body| validation::validate_anthropic_response(&body))
+        .unwrap_or_else(|e| vec![format!("failed to serialize response: {}", e)]);
+    let result = mode.enforce(&format!("response from {}", provider_name), violations);
+    if let Err(ref e) = result {
+        info!("⚠️ Provider {} failed: {}, trying next fallback", provider_name, e);
+    }
+    result
+}
+
+/// Run a provider call, retrying on the same provider while the connection is reset
+/// before a response arrives or the host fails to resolve (exponential backoff between attempts)
+async fn retry_on_reset<T, F, Fut>(
+    retry: &RetryConfig,
+    provider_name: &str,
+    mut call: F,
+) -> Result<T, ProviderError>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, ProviderError>>,
+{
+    let mut delay = std::time::Duration::from_millis(retry.backoff_ms);
+    let mut attempt = 0;
+    let mut dns_attempt = 0;
+    loop {
+        match call().await {
+            Err(e) if e.is_connection_reset() && attempt < retry.connection_retries => {
+                attempt += 1;
+                info!(
+                    "🔁 Connection to {} reset ({}), retrying in {:?} ({}/{})",
+                    provider_name, e, delay, attempt, retry.connection_retries
+                );
+            }
+            Err(e) if e.is_dns_failure() && dns_attempt < retry.dns_retries => {
+                dns_attempt += 1;
+                info!(
+                    "🔁 DNS lookup for {} failed ({}), retrying in {:?} ({}/{})",
+                    provider_name, e, delay, dns_attempt, retry.dns_retries
+                );
+            }
+            result => return result,
+        }
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+    }
+}
+
+/// Fail with `ProviderError::Timeout` when `call` (retries included) outlasts `timeout`.
+/// For streams this bounds the wait for the response to start.
+async fn with_timeout<T, Fut>(timeout: std::time::Duration, call: Fut) -> Result<T, ProviderError>
+where
+    Fut: std::future::Future<Output = Result<T, ProviderError>>,
+{
+    tokio::time::timeout(timeout, call)
+        .await
+        .unwrap_or(Err(ProviderError::Timeout(timeout)))
+}
+
+/// Call a provider with connection-reset retries, recording latency and outcome in the metrics
+async fn call_provider<T, F, Fut>(
+    state: &AppState,
+    provider_name: &str,
+    timeout: std::time::Duration,
+    call: F,
+) -> Result<T, ProviderError>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, ProviderError>>,
+{
+    let started = std::time::Instant::now();
+    let result = with_timeout(timeout, retry_on_reset(&state.config.retry_for(provider_name), provider_name, call)).await;
+    state.metrics.record_provider(provider_name, started.elapsed(), result.is_ok());
+    record_outcome(state, provider_name, timeout, result.as_ref().err());
+    result
+}
+
+/// Record a provider call's outcome in the health tracker and circuit breaker
+fn record_outcome(state: &AppState, provider_name: &str, timeout: std::time::Duration, error: Option<&ProviderError>) {
+    // A timeout under a client's shorter `x-ccm-timeout` says nothing about the provider
+    let configured = std::time::Duration::from_millis(state.config.server.timeouts.api_timeout_ms);
+    if timeout < configured && matches!(error, Some(ProviderError::Timeout(_))) {
+        return;
+    }
+    state.provider_health.record(provider_name, error);
+    state.circuit_breaker.record(provider_name, error);
+}
+
+/// Upstream response of a passthrough provider
+enum PassthroughBody {
+    Json(bytes::Bytes),
+    Stream(crate::providers::streaming::EventStream),
+}
+
+/// Forward the client's original body to a passthrough provider, only swapping in the
+/// upstream model (after the proxy-level fixups made by the handler). Auth, betas and configured headers still apply; usage is recorded
+/// in the metrics but the response is returned untouched.
+async fn send_passthrough(
+    state: &AppState,
+    mapping: &ModelMapping,
+    provider: &dyn AnthropicProvider,
+    original_body: &serde_json::Value,
+    mut request: AnthropicRequest,
+    timeout: std::time::Duration,
+) -> Result<PassthroughBody, ProviderError> {
+    let provider_name = mapping.provider.as_str();
+    let upstream_model = state.provider_registry.upstream_model(provider_name, &mapping.actual_model);
+    let mut body = original_body.clone();
+    body["model"] = upstream_model.clone().into();
+    request.model = upstream_model;
+
+    info!("🪞 Passthrough request to provider: {}", provider_name);
+    let mut stream = call_provider(state, provider_name, timeout, || provider.send_passthrough(request.clone(), body.clone())).await?;
+    if request.stream == Some(true) {
+        let events = translate_events(stream, UsageRecorder::new(state.metrics.clone()));
+        return Ok(PassthroughBody::Stream(end_on_error(events)));
+    }
+
+    let mut buffer = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        buffer.extend_from_slice(&chunk?);
+    }
+    if let Ok(response) = serde_json::from_slice::<serde_json::Value>(&buffer) {
+        let tokens = |field: &str| response.pointer(&format!("/usage/{}", field)).and_then(|v| v.as_u64()).unwrap_or(0) as u32;
+        state.metrics.record_usage(tokens("input_tokens"), tokens("output_tokens"));
+    }
+    Ok(PassthroughBody::Json(buffer.into()))
+}
+
+/// Timeout for this request: the server's `api_timeout_ms`, or the client's
+/// `x-ccm-timeout` (seconds) capped at `max_timeout_override_ms`
+fn request_timeout(headers: &HeaderMap, config: &AppConfig) -> Result<std::time::Duration, AppError> {
+    let timeouts = &config.server.timeouts;
+    let Some(value) = headers.get("x-ccm-timeout") else {
+        return Ok(std::time::Duration::from_millis(timeouts.api_timeout_ms));
+    };
+    let seconds: u64 = value
+        .to_str()
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|&s| s > 0)
+        .ok_or_else(|| AppError::BadRequest("x-ccm-timeout must be a positive number of seconds".to_string()))?;
+    let requested = std::time::Duration::from_secs(seconds);
+    let max = std::time::Duration::from_millis(timeouts.max_timeout_override_ms);
+    if requested > max {
+        info!("⏱️ Requested timeout {:?} exceeds the maximum, using {:?}", requested, max);
+        return Ok(max);
+    }
+    Ok(requested)
+}
+
+/// Id correlating proxy and upstream logs: the client's `x-request-id`, or a new one
+fn request_id(headers: &HeaderMap) -> String {
+    headers
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| format!("req_{:016x}", rand::random::<u64>()))
+}
+
+/// Whether the client asked for the raw upstream body (`x-ccm-raw-response`).
+/// Only honoured with a matching `x-ccm-admin-key`.
+fn raw_response_requested(headers: &HeaderMap, config: &AppConfig) -> Result<bool, AppError> {
+    let requested = headers
+        .get("x-ccm-raw-response")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true" | "on"));
+    if !requested {
+        return Ok(false);
+    }
+    if admin_key_valid(headers, config.server.admin_key.as_deref()) {
+        Ok(true)
+    } else {
+        Err(AppError::Forbidden(
+            "x-ccm-raw-response requires a valid x-ccm-admin-key".to_string(),
+        ))
+    }
+}
+
+/// Whether the request carries the configured `x-ccm-admin-key` (never when none is set)
+fn admin_key_valid(headers: &HeaderMap, admin_key: Option<&str>) -> bool {
+    let sent = headers.get("x-ccm-admin-key").and_then(|v| v.to_str().ok());
+    admin_key.is_some_and(|expected| sent == Some(expected))
+}
+
+/// JSON response for a provider result, with the raw upstream body next to it when captured
+fn provider_json(mut response: ProviderResponse) -> Response {
+    match response.raw.take() {
+        Some(raw) => Json(serde_json::json!({
+            "response": response,
+            "raw_upstream_response": raw,
+        }))
+        .into_response(),
+        None => Json(response).into_response(),
+    }
+}
+
+/// Add the client's `anthropic-beta` header values to the request's `betas`,
+/// so they are merged with provider and mapping betas upstream
+fn merge_beta_header(headers: &HeaderMap, request: &mut serde_json::Value) {
+    let header_betas: Vec<serde_json::Value> = headers
+        .get_all("anthropic-beta")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|b| !b.is_empty())
+        .map(|b| b.into())
+        .collect();
+    if header_betas.is_empty() {
+        return;
+    }
+    let Some(obj) = request.as_object_mut() else {
+        return;
+    };
+    match obj.get_mut("betas").and_then(|b| b.as_array_mut()) {
+        Some(betas) => betas.extend(header_betas),
+        None => {
+            obj.insert("betas".to_string(), header_betas.into());
+        }
+    }
+}
+
+/// Handle /v1/embeddings requests (OpenAI format) with providers that offer embeddings.
+/// Tries the model's mappings in priority order; global fallbacks are chat models and not used.
+async fn handle_embeddings(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(request): Json<openai_compat::EmbeddingsRequest>,
+) -> Result<Response, AppError> {
+    let model = request.model.clone();
+    info!("Received embeddings request for model: {}", model);
+    let timeout = request_timeout(&headers, &state.config)?;
+    let inputs = request.input.into_vec();
+
+    let embed_all = |provider: Arc<Box<dyn AnthropicProvider>>, upstream_model: String| {
+        let inputs = &inputs;
+        move || {
+            let provider = provider.clone();
+            let upstream_model = upstream_model.clone();
+            async move {
+                let mut vectors = Vec::with_capacity(inputs.len());
+                for text in inputs {
+                    vectors.push(provider.embed(&upstream_model, text).await?);
+                }
+                Ok::<_, ProviderError>(vectors)
+            }
+        }
+    };
+
+    let Some(model_config) = state.config.find_model(&model) else {
+        // No model mapping found, try direct provider registry lookup
+        let provider = state
+            .provider_registry
+            .get_provider_for_model(&model)
+            .map_err(|_| AppError::RoutingError(format!("No model mapping or provider found for embeddings: {}", model)))?;
+        let vectors = with_timeout(timeout, embed_all(provider, model.clone())())
+            .await
+            .map_err(|e| AppError::ProviderError(e.to_string()))?;
+        return Ok(Json(openai_compat::EmbeddingsResponse::new(model, vectors)).into_response());
+    };
+
+    let mut mappings = model_config.mappings.clone();
+    mappings.sort_by_key(|m| m.priority);
+    route_by_app(&state.config, &headers, &mut mappings);
+    for mapping in &mappings {
+        if state.provider_health.is_disabled(&mapping.provider) {
+            info!("⛔ Skipping provider {}: disabled after too many failures", mapping.provider);
+            continue;
+        }
+        if !state.circuit_breaker.allow(&mapping.provider) {
+            info!("🔌 Skipping provider {}: circuit open", mapping.provider);
+            continue;
+        }
+        if !state.provider_registry.is_model_allowed(&mapping.provider, &mapping.actual_model) {
+            info!(
+                "🚫 Skipping provider {}: model {} is excluded by its allowed/denied models",
+                mapping.provider, mapping.actual_model
+            );
+            continue;
+        }
+        let Some(provider) = state.provider_registry.get_provider(&mapping.provider) else {
+            info!("⚠️ Provider {} not found in registry, trying next fallback", mapping.provider);
+            continue;
+        };
+        if !provider.supports_embeddings() {
+            info!("⏭️ Skipping provider {}: no embeddings support", mapping.provider);
+            continue;
+        }
+
+        let upstream_model = state.provider_registry.upstream_model(&mapping.provider, &mapping.actual_model);
+        match call_provider(&state, &mapping.provider, timeout, embed_all(provider, upstream_model)).await {
+            Ok(vectors) => {
+                info!("✅ Embeddings succeeded with provider: {}", mapping.provider);
+                return Ok(Json(openai_compat::EmbeddingsResponse::new(model, vectors)).into_response());
+            }
+            Err(e) if !can_fall_back(&state.config, &e) => {
+                return Err(no_fallback_error(mapping, e));
+            }
+            Err(e) => {
+                info!("⚠️ Provider {} failed: {}, trying next fallback", mapping.provider, e);
+            }
+        }
+    }
+
+    error!("❌ All provider mappings failed for embeddings: {}", model);
+    Err(AppError::ProviderError(format!(
+        "All {} provider mappings failed for embeddings: {}",
+        mappings.len(),
+        model
+    )))
+}
+
+/// Handle legacy /v1/complete requests by running them through the messages
+/// pipeline and converting the result back to the completion format
+async fn handle_legacy_complete(
+    State(state): State<Arc<AppState>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Json(legacy_request): Json<legacy_complete::LegacyCompletionRequest>,
+) -> Result<Response, AppError> {
+    let model = legacy_request.model.clone();
+    info!("Received legacy completion request for model: {}", model);
+
+    let anthropic_request = legacy_complete::transform_legacy_to_anthropic(legacy_request)
+        .map_err(|e| AppError::ParseError(format!("Failed to transform completion request: {}", e)))?;
+    let is_streaming = anthropic_request.stream == Some(true);
+    let request_json = serde_json::to_value(&anthropic_request)
+        .map_err(|e| AppError::ParseError(e.to_string()))?;
+
+    // The translation below needs the Messages response, not the raw upstream body
+    let mut headers = headers;
+    headers.remove("x-ccm-raw-response");
+    let response = handle_messages(State(state), peer, headers, Json(request_json)).await?;
+    if !response.status().is_success() {
+        return Ok(response);
+    }
+
+    if is_streaming {
+        let body = response.into_body().into_data_stream().map(|chunk| {
+            chunk.map_err(|e| ProviderError::ApiError { status: 502, message: e.to_string() })
+        });
+        let events = translate_events(body, legacy_complete::LegacyStreamTranslator::new(model));
+        let sse_stream = events.map(|result| {
+            result
+                .map(|event| {
+                    let mut sse_event = Event::default().data(event.data);
+                    if let Some(name) = event.event {
+                        sse_event = sse_event.event(name);
+                    }
+                    sse_event
+                })
+                .map_err(|e| {
+                    error!("Stream error: {}", e);
+                    std::io::Error::other(e.to_string())
+                })
+        });
+        return Ok(Sse::new(sse_stream).into_response());
+    }
+
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .map_err(|e| AppError::ProviderError(e.to_string()))?;
+    let messages_response: ProviderResponse = serde_json::from_slice(&body)
+        .map_err(|e| AppError::ProviderError(format!("Invalid messages response: {}", e)))?;
+
+    Ok(Json(legacy_complete::transform_anthropic_to_legacy(messages_response, model)).into_response())
+}
+
 /// Handle /v1/messages requests (both streaming and non-streaming)
 async fn handle_messages(
     State(state): State<Arc<AppState>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
     headers: HeaderMap,
-    Json(request_json): Json<serde_json::Value>,
+    Json(mut request_json): Json<serde_json::Value>,
 ) -> Result<Response, AppError> {
+    let request_started = std::time::Instant::now();
     let model = request_json
         .get("model")
         .and_then(|m| m.as_str())
         .unwrap_or("unknown");
-    info!("Received request for model: {}", model);
+    let request_id = request_id(&headers);
+    info!("Received request for model: {} ({})", model, request_id);
+    let raw_response = raw_response_requested(&headers, &state.config)?;
+    let timeout = request_timeout(&headers, &state.config)?;
 
     // DEBUG: Log request body for debugging
     if let Ok(json_str) = serde_json::to_string_pretty(&request_json) {
         tracing::debug!("📥 Incoming request body:\n{}", json_str);
     }
+    validate_request(&request_json, &state.config, true)?;
+
+    // Passthrough providers receive the body as the client sent it, apart from the
+    // proxy-level fixups below (default max_tokens, subagent tag, thinking policy)
+    let mut original_body = request_json.clone();
+    normalize_request(&mut request_json, &state.config);
+    merge_beta_header(&headers, &mut request_json);
 
     // 1. Parse request for routing decision (mutable for tag extraction)
     let mut request_for_routing: AnthropicRequest = serde_json::from_value(request_json.clone())
@@ -634,8 +1617,38 @@ async fn handle_messages(
         decision.model_name, decision.route_type
     );
 
+    let model_config = state.config.find_model(&decision.model_name);
+    apply_default_max_tokens(&mut request_json, model_config.as_deref())?;
+    apply_default_max_tokens(&mut original_body, model_config.as_deref())?;
+    // The router strips the subagent tag from system[1]
+    if let Some(SystemPrompt::Blocks(blocks)) = &request_for_routing.system {
+        if let (Some(block), Some(text)) = (blocks.get(1), original_body.pointer_mut("/system/1/text")) {
+            *text = block.text.clone().into();
+        }
+    }
+
+    // Reserve a stream slot up front so over-limit clients are rejected before any upstream call
+    let mut stream_permit = if request_for_routing.stream == Some(true) {
+        let client = stream_limit::client_id(&headers, peer.map(|ConnectInfo(addr)| addr));
+        match state.stream_limiter.try_acquire(&client) {
+            Some(permit) => Some(permit),
+            None => {
+                info!(
+                    "🚦 Rejecting stream: client {} already has {} open",
+                    client,
+                    state.stream_limiter.active_streams(&client)
+                );
+                return Err(AppError::RateLimited(
+                    "Too many concurrent streams for this client".to_string(),
+                ));
+            }
+        }
+    } else {
+        None
+    };
+
     // 3. Try model mappings with fallback (1:N mapping)
-    if let Some(model_config) = state.config.models.iter().find(|m| m.name == decision.model_name) {
+    if let Some(model_config) = model_config {
         info!("📋 Found {} provider mappings for model: {}", model_config.mappings.len(), decision.model_name);
 
         // Check for X-Provider header to override priority
@@ -662,12 +1675,18 @@ async fn handle_messages(
                 )));
             }
         } else {
-            // Use priority ordering
-            sorted_mappings.sort_by_key(|m| m.priority);
+            // Use priority ordering, then the global fallbacks
+            sorted_mappings = model_config.mapping_chain(&state.config.fallback_models);
         }
+        retain_thinking_matches(&mut sorted_mappings, &request_for_routing);
+        route_by_app(&state.config, &headers, &mut sorted_mappings);
 
         // Try each mapping in priority order (or just the forced one)
+        let mut last_error: Option<String> = None;
         for (idx, mapping) in sorted_mappings.iter().enumerate() {
+            if idx > 0 {
+                state.metrics.record_fallback();
+            }
             info!(
                 "🔄 Trying mapping {}/{}: provider={}, actual_model={}",
                 idx + 1,
@@ -676,8 +1695,79 @@ async fn handle_messages(
                 mapping.actual_model
             );
 
+            if !state.provider_registry.is_model_allowed(&mapping.provider, &mapping.actual_model) {
+                info!(
+                    "🚫 Skipping provider {}: model {} is excluded by its allowed/denied models",
+                    mapping.provider, mapping.actual_model
+                );
+                last_error = Some(format!("Model {} not allowed on provider {}", mapping.actual_model, mapping.provider));
+                continue;
+            }
+
+            if state.provider_health.is_disabled(&mapping.provider) {
+                info!("⛔ Skipping provider {}: disabled after too many failures", mapping.provider);
+                last_error = Some(format!("Provider {} is disabled after too many failures", mapping.provider));
+                continue;
+            }
+
+            if !state.circuit_breaker.allow(&mapping.provider) {
+                info!("🔌 Skipping provider {}: circuit open", mapping.provider);
+                last_error = Some(format!("Provider {} circuit is open after repeated failures", mapping.provider));
+                continue;
+            }
+
             // Try to get provider from registry
             if let Some(provider) = state.provider_registry.get_provider(&mapping.provider) {
+                if state.provider_registry.is_passthrough(&mapping.provider) {
+                    // Parsed request only supplies the endpoint and headers; never sent
+                    let mut request = request_for_routing.clone();
+                    state.provider_registry.attach_request_id(&mapping.provider, &request_id, &mut request);
+                    state.provider_registry.attach_forwarded_headers(&mapping.provider, &headers, &mut request);
+                    if !check_thinking_support(&state.config, &state.provider_registry, &mapping.provider, &mut request)? {
+                        last_error = Some(format!("Provider {} doesn't support thinking", mapping.provider));
+                        continue;
+                    }
+                    let mut body = original_body.clone();
+                    if request.thinking.is_none() {
+                        if let Some(obj) = body.as_object_mut() {
+                            obj.remove("thinking");
+                        }
+                    }
+                    let passthrough = send_passthrough(&state, mapping, provider.as_ref().as_ref(), &body, request, timeout);
+                    match passthrough.await {
+                        Ok(PassthroughBody::Json(body)) => {
+                            info!("✅ Passthrough request succeeded with provider: {}", mapping.provider);
+                            return Ok(([(axum::http::header::CONTENT_TYPE, "application/json")], body).into_response());
+                        }
+                        Ok(PassthroughBody::Stream(events)) => {
+                            info!("✅ Passthrough stream started with provider: {}", mapping.provider);
+                            let permit = stream_permit.take();
+                            let sse_stream = events.map(move |result| {
+                                let _permit = &permit;
+                                result.map(|event| {
+                                    let mut sse_event = Event::default().data(event.data);
+                                    if let Some(name) = event.event {
+                                        sse_event = sse_event.event(name);
+                                    }
+                                    sse_event
+                                }).map_err(|e| {
+                                    error!("Stream error: {}", e);
+                                    std::io::Error::other(e.to_string())
+                                })
+                            });
+                            return Ok(Sse::new(sse_stream).into_response());
+                        }
+                        Err(e) if !can_fall_back(&state.config, &e) => {
+                            return Err(no_fallback_error(mapping, e));
+                        }
+                        Err(e) => {
+                            info!("⚠️ Passthrough provider {} failed: {}, trying next fallback", mapping.provider, e);
+                            last_error = Some(e.to_string());
+                            continue;
+                        }
+                    }
+                }
+
                 // Trust the model mapping configuration - no need to validate
 
                 // Parse request as Anthropic format
@@ -688,10 +1778,26 @@ async fn handle_messages(
                 let original_model = anthropic_request.model.clone();
 
                 // Update model to actual model name
-                anthropic_request.model = mapping.actual_model.clone();
+                anthropic_request.model = state.provider_registry.upstream_model(&mapping.provider, &mapping.actual_model);
+                mapping.apply_overrides(&mut anthropic_request);
+                state.provider_registry.trim_tools(&mapping.provider, &mut anthropic_request);
+                state.provider_registry.compact_history(&mapping.provider, &mut anthropic_request).await;
+                anthropic_request.capture_raw = raw_response;
+                state.provider_registry.attach_request_id(&mapping.provider, &request_id, &mut anthropic_request);
+                state.provider_registry.attach_forwarded_headers(&mapping.provider, &headers, &mut anthropic_request);
+                if !check_thinking_support(&state.config, &state.provider_registry, &mapping.provider, &mut anthropic_request)? {
+                    last_error = Some(format!("Provider {} doesn't support thinking", mapping.provider));
+                    continue;
+                }
 
                 // Update system if modified during routing
                 anthropic_request.system = request_for_routing.system.clone();
+                state.provider_registry.coalesce_system(&mapping.provider, &mut anthropic_request);
+
+                if let Err(e) = check_request_schema(model_config.schema_validation, &mapping.provider, provider.as_ref().as_ref(), &anthropic_request) {
+                    last_error = Some(e.to_string());
+                    continue;
+                }
 
                 // Check if streaming is requested
                 let is_streaming = anthropic_request.stream == Some(true);
@@ -700,41 +1806,126 @@ async fn handle_messages(
                     // Streaming request
                     info!("🌊 Streaming request to provider: {}", mapping.provider);
 
-                    match provider.send_message_stream(anthropic_request).await {
-                        Ok(stream) => {
+                    let include_usage = anthropic_request
+                        .stream_options
+                        .as_ref()
+                        .map(|o| o.include_usage)
+                        .unwrap_or(false);
+
+                    // Re-frame the provider's Anthropic SSE bytes into events, making
+                    // sure the final message_delta carries usage
+                    let stream_requested = std::time::Instant::now();
+                    let mut started = call_provider(&state, &mapping.provider, timeout, || provider.send_message_stream(anthropic_request.clone()))
+                        .await
+                        .map(|stream| translate_events(stream, UsageFinalizer::new(include_usage)));
+                    if state.provider_registry.retries_non_streaming(&mapping.provider) {
+                        if let Ok(events) = started {
+                            // Waiting for content shares the deadline of the call itself
+                            let remaining = timeout.saturating_sub(stream_requested.elapsed());
+                            started = with_timeout(remaining, first_content(events)).await;
+                            if let Err(e) = &started {
+                                record_outcome(&state, &mapping.provider, timeout, Some(e));
+                            }
+                        }
+                        started = match started {
+                            Err(e)
+                                if !matches!(
+                                    e,
+                                    ProviderError::AuthError(_)
+                                        | ProviderError::InvalidRequest(_)
+                                        | ProviderError::ApiError { status: 400..=499, .. }
+                                ) =>
+                            {
+                                info!("⚠️ Provider {} stream failed before any content: {}, retrying non-streaming", mapping.provider, e);
+                                let mut request = anthropic_request.clone();
+                                request.stream = Some(false);
+                                call_provider(&state, &mapping.provider, timeout, || provider.send_message(request.clone()))
+                                    .await
+                                    .map(|response| {
+                                        let events: EventStream = Box::pin(futures::stream::iter(response_events(&response).into_iter().map(Ok)));
+                                        events
+                                    })
+                            }
+                            other => other,
+                        };
+                    }
+
+                    match started {
+                        Ok(mut events) => {
                             info!("✅ Streaming request started with provider: {}", mapping.provider);
 
-                            // Convert byte stream to SSE response
-                            // The provider returns raw bytes (SSE format), we pass them through
-                            let sse_stream = stream.map(|result| {
-                                result.map(|bytes| {
-                                    // Convert bytes to string for SSE event
-                                    let data = String::from_utf8_lossy(&bytes).to_string();
-                                    Event::default().data(data)
+                            if state.provider_registry.trims_response_text(&mapping.provider) {
+                                events = map_events(events, TextTrimmer::new());
+                            }
+                            events = map_events(events, UsageRecorder::new(state.metrics.clone()));
+                            if let Some(budget) = state.config.server.budget.limiter() {
+                                events = map_events(events, budget);
+                            }
+                            let pings = sse_pings_enabled(&headers, &state.config);
+                            if !pings {
+                                events = map_events(events, PingFilter);
+                            } else if let Some(ms) = state.config.server.skip_initial_ping_ms {
+                                let threshold = std::time::Duration::from_millis(ms);
+                                events = map_events(events, EarlyPingFilter::new(request_started, threshold));
+                            }
+                            let events = end_on_error(events);
+                            // The permit is released when the client disconnects or the stream ends
+                            let permit = stream_permit.take();
+                            let sse_stream = events.map(move |result| {
+                                let _permit = &permit;
+                                result.map(|event| {
+                                    let mut sse_event = Event::default().data(event.data);
+                                    if let Some(name) = event.event {
+                                        sse_event = sse_event.event(name);
+                                    }
+                                    sse_event
                                 }).map_err(|e| {
                                     error!("Stream error: {}", e);
                                     std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
                                 })
                             });
 
-                            return Ok(Sse::new(sse_stream).into_response());
+                            let sse = Sse::new(sse_stream);
+                            return Ok(match state.config.server.sse_keep_alive_secs {
+                                Some(secs) if pings => sse
+                                    .keep_alive(KeepAlive::new().interval(std::time::Duration::from_secs(secs)))
+                                    .into_response(),
+                                _ => sse.into_response(),
+                            });
+                        }
+                        Err(e) if !can_fall_back(&state.config, &e) => {
+                            return Err(no_fallback_error(mapping, e));
                         }
                         Err(e) => {
                             info!("⚠️ Provider {} streaming failed: {}, trying next fallback", mapping.provider, e);
+                            last_error = Some(e.to_string());
                             continue;
                         }
                     }
                 } else {
                     // Non-streaming request (original behavior)
-                    match provider.send_message(anthropic_request).await {
+                    match call_provider(&state, &mapping.provider, timeout, || provider.send_message(anthropic_request.clone())).await {
                         Ok(mut response) => {
+                            if state.provider_registry.trims_response_text(&mapping.provider) {
+                                response.trim_text();
+                            }
+                            if let Err(e) = check_response_schema(model_config.schema_validation, &mapping.provider, &response) {
+                                last_error = Some(e.to_string());
+                                continue;
+                            }
+
                             // Restore original model name in response
                             response.model = original_model;
                             info!("✅ Request succeeded with provider: {}, response model: {}", mapping.provider, response.model);
-                            return Ok(Json(response).into_response());
+                            state.metrics.record_usage(response.usage.input_tokens, response.usage.output_tokens);
+                            return Ok(provider_json(response));
+                        }
+                        Err(e) if !can_fall_back(&state.config, &e) => {
+                            return Err(no_fallback_error(mapping, e));
                         }
                         Err(e) => {
                             info!("⚠️ Provider {} failed: {}, trying next fallback", mapping.provider, e);
+                            last_error = Some(e.to_string());
                             continue;
                         }
                     }
@@ -747,9 +1938,10 @@ async fn handle_messages(
 
         error!("❌ All provider mappings failed for model: {}", decision.model_name);
         return Err(AppError::ProviderError(format!(
-            "All {} provider mappings failed for model: {}",
+            "All {} provider mappings failed for model: {}{}",
             sorted_mappings.len(),
-            decision.model_name
+            decision.model_name,
+            last_error.map(|e| format!(" (last error: {})", e)).unwrap_or_default()
         )));
     } else {
         // No model mapping found, try direct provider registry lookup (backward compatibility)
@@ -768,17 +1960,20 @@ async fn handle_messages(
 
             // Update system if modified during routing
             anthropic_request.system = request_for_routing.system.clone();
+            anthropic_request.capture_raw = raw_response;
 
-            // Call provider
-            let mut provider_response = provider.send_message(anthropic_request)
+            // Call provider, with the provider's own retry overrides
+            let retry = state.config.retry_for(state.provider_registry.provider_name_for_model(&decision.model_name).unwrap_or_default());
+            let mut provider_response = with_timeout(timeout, retry_on_reset(&retry, &decision.model_name, || provider.send_message(anthropic_request.clone())))
                 .await
                 .map_err(|e| AppError::ProviderError(e.to_string()))?;
+            state.metrics.record_usage(provider_response.usage.input_tokens, provider_response.usage.output_tokens);
 
             // Restore original model name in response
             provider_response.model = original_model;
 
             // Return provider response
-            return Ok(Json(provider_response).into_response());
+            return Ok(provider_json(provider_response));
         }
 
         error!("❌ No model mapping or provider found for model: {}", decision.model_name);
@@ -792,8 +1987,12 @@ async fn handle_messages(
 /// Handle /v1/messages/count_tokens requests
 async fn handle_count_tokens(
     State(state): State<Arc<AppState>>,
-    Json(request_json): Json<serde_json::Value>,
+    headers: HeaderMap,
+    Json(mut request_json): Json<serde_json::Value>,
 ) -> Result<Response, AppError> {
+    normalize_request(&mut request_json, &state.config);
+    validate_request(&request_json, &state.config, false)?;
+    merge_beta_header(&headers, &mut request_json);
     let model = request_json.get("model").and_then(|m| m.as_str()).unwrap_or("unknown");
     info!("Received count_tokens request for model: {}", model);
 
@@ -816,6 +2015,14 @@ async fn handle_count_tokens(
         stop_sequences: None,
         stream: None,
         metadata: None,
+        tool_choice: None,
+        betas: None,
+        stream_options: None,
+        api_version: None,
+        output_format: None,
+        request_id_header: None,
+        capture_raw: false,
+        forwarded_headers: vec![],
     };
     let decision = state
         .router
@@ -828,12 +2035,12 @@ async fn handle_count_tokens(
     );
 
     // 3. Try model mappings with fallback (1:N mapping)
-    if let Some(model_config) = state.config.models.iter().find(|m| m.name == decision.model_name) {
+    if let Some(model_config) = state.config.find_model(&decision.model_name) {
         info!("📋 Found {} provider mappings for token counting: {}", model_config.mappings.len(), decision.model_name);
 
-        // Sort mappings by priority
-        let mut sorted_mappings = model_config.mappings.clone();
-        sorted_mappings.sort_by_key(|m| m.priority);
+        // Sort mappings by priority, then the global fallbacks
+        let mut sorted_mappings = model_config.mapping_chain(&state.config.fallback_models);
+        route_by_app(&state.config, &headers, &mut sorted_mappings);
 
         // Try each mapping in priority order
         for (idx, mapping) in sorted_mappings.iter().enumerate() {
@@ -845,13 +2052,25 @@ async fn handle_count_tokens(
                 mapping.actual_model
             );
 
+            if !state.provider_registry.is_model_allowed(&mapping.provider, &mapping.actual_model) {
+                info!(
+                    "🚫 Skipping provider {}: model {} is excluded by its allowed/denied models",
+                    mapping.provider, mapping.actual_model
+                );
+                continue;
+            }
+
             // Try to get provider from registry
             if let Some(provider) = state.provider_registry.get_provider(&mapping.provider) {
                 // Trust the model mapping configuration - no need to validate
 
                 // Update model to actual model name
                 let mut count_request_for_provider = count_request.clone();
-                count_request_for_provider.model = mapping.actual_model.clone();
+                count_request_for_provider.model = state.provider_registry.upstream_model(&mapping.provider, &mapping.actual_model);
+                count_request_for_provider.forwarded_headers = state.provider_registry.forwarded_headers(&mapping.provider, &headers);
+                if !mapping.betas.is_empty() {
+                    count_request_for_provider.betas.get_or_insert_with(Vec::new).extend(mapping.betas.iter().cloned());
+                }
 
                 // Call provider's count_tokens
                 match provider.count_tokens(count_request_for_provider).await {
@@ -859,6 +2078,9 @@ async fn handle_count_tokens(
                         info!("✅ Token count succeeded with provider: {}", mapping.provider);
                         return Ok(Json(response).into_response());
                     }
+                    Err(e) if !can_fall_back(&state.config, &e) => {
+                        return Err(no_fallback_error(mapping, e));
+                    }
                     Err(e) => {
                         info!("⚠️ Provider {} failed: {}, trying next fallback", mapping.provider, e);
                         continue;
@@ -908,6 +2130,9 @@ pub enum AppError {
     RoutingError(String),
     ParseError(String),
     ProviderError(String),
+    RateLimited(String),
+    Forbidden(String),
+    BadRequest(String),
 }
 
 impl IntoResponse for AppError {
@@ -916,6 +2141,9 @@ impl IntoResponse for AppError {
             AppError::RoutingError(msg) => (StatusCode::BAD_REQUEST, msg),
             AppError::ParseError(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
             AppError::ProviderError(msg) => (StatusCode::BAD_GATEWAY, msg),
+            AppError::RateLimited(msg) => (StatusCode::TOO_MANY_REQUESTS, msg),
+            AppError::Forbidden(msg) => (StatusCode::FORBIDDEN, msg),
+            AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
         };
 
         let body = Json(serde_json::json!({
@@ -935,8 +2163,2093 @@ impl std::fmt::Display for AppError {
             AppError::RoutingError(msg) => write!(f, "Routing error: {}", msg),
             AppError::ParseError(msg) => write!(f, "Parse error: {}", msg),
             AppError::ProviderError(msg) => write!(f, "Provider error: {}", msg),
+            AppError::RateLimited(msg) => write!(f, "Rate limited: {}", msg),
+            AppError::Forbidden(msg) => write!(f, "Forbidden: {}", msg),
+            AppError::BadRequest(msg) => write!(f, "Bad request: {}", msg),
         }
     }
 }
 
 impl std::error::Error for AppError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(strip_empty_tools: Option<bool>) -> AppConfig {
+        let mut config: AppConfig = toml::from_str("[router]\ndefault = \"m\"").unwrap();
+        config.server.strip_empty_tools = strip_empty_tools;
+        config
+    }
+
+    #[test]
+    fn test_empty_tools_removed_before_sending() {
+        let request = || {
+            serde_json::json!({
+                "model": "claude-sonnet-4-5",
+                "max_tokens": 16,
+                "messages": [{"role": "user", "content": "hi"}],
+                "tools": [],
+                "tool_choice": {"type": "auto"}
+            })
+        };
+
+        let mut normalized = request();
+        normalize_request(&mut normalized, &config(None));
+        assert!(normalized.get("tools").is_none());
+        assert!(normalized.get("tool_choice").is_none());
+
+        // The forwarded body no longer carries either field
+        let parsed: AnthropicRequest = serde_json::from_value(normalized).unwrap();
+        let body = serde_json::to_value(&parsed).unwrap();
+        assert!(body.get("tools").is_none() && body.get("tool_choice").is_none());
+
+        // Non-empty tools are left alone
+        let mut with_tools = request();
+        with_tools["tools"] = serde_json::json!([{"name": "read", "input_schema": {"type": "object"}}]);
+        normalize_request(&mut with_tools, &config(None));
+        assert_eq!(with_tools["tools"].as_array().unwrap().len(), 1);
+        assert_eq!(with_tools["tool_choice"]["type"], "auto");
+
+        // Disabled: forwarded unchanged
+        let mut untouched = request();
+        normalize_request(&mut untouched, &config(Some(false)));
+        assert_eq!(untouched, request());
+    }
+
+    /// Read one HTTP request (headers plus Content-Length body) off the socket
+    async fn read_request(socket: &mut tokio::net::TcpStream) {
+        use tokio::io::AsyncReadExt;
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            let n = socket.read(&mut chunk).await.unwrap();
+            buf.extend_from_slice(&chunk[..n]);
+            let text = String::from_utf8_lossy(&buf);
+            if let Some(end) = text.find("\r\n\r\n") {
+                let length = text[..end]
+                    .lines()
+                    .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                    .unwrap_or(0);
+                if buf.len() >= end + 4 + length {
+                    return;
+                }
+            }
+            if n == 0 {
+                return;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connection_reset_retried_on_same_provider() {
+        use crate::providers::anthropic_compatible::AnthropicCompatibleProvider;
+        use tokio::io::AsyncWriteExt;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let body = r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"ok"}],"model":"m","stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":1,"output_tokens":1}}"#;
+
+        // Every odd connection is reset after the request arrives, every even one answers
+        tokio::spawn(async move {
+            let mut accepted = 0;
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                accepted += 1;
+                read_request(&mut socket).await;
+                if accepted % 2 == 1 {
+                    #[allow(deprecated)]
+                    socket.set_linger(Some(std::time::Duration::ZERO)).unwrap();
+                    drop(socket);
+                } else {
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    );
+                    socket.write_all(response.as_bytes()).await.unwrap();
+                    socket.shutdown().await.unwrap();
+                }
+            }
+        });
+
+        let provider = AnthropicCompatibleProvider::new("flaky".to_string(), "key".to_string(), url, vec!["m".to_string()], None, None);
+        let request: AnthropicRequest = serde_json::from_value(serde_json::json!({
+            "model": "m",
+            "max_tokens": 16,
+            "messages": [{"role": "user", "content": "hi"}]
+        }))
+        .unwrap();
+
+        let retry = RetryConfig { connection_retries: 1, dns_retries: 0, backoff_ms: 1 };
+        let response = retry_on_reset(&retry, "flaky", || provider.send_message(request.clone())).await.unwrap();
+        assert_eq!(response.id, "msg_1");
+
+        // Without retries the reset surfaces (and the router would fall back)
+        let no_retry = RetryConfig { connection_retries: 0, dns_retries: 0, backoff_ms: 1 };
+        let err = retry_on_reset(&no_retry, "flaky", || provider.send_message(request.clone())).await.unwrap_err();
+        assert!(err.is_connection_reset(), "unexpected error: {}", err);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_header_extends_request_timeout() {
+        use tokio::io::AsyncWriteExt;
+        use tower::ServiceExt;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let body = r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"ok"}],"model":"up","stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":1,"output_tokens":1}}"#;
+
+        // Answers every request after 300ms
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    read_request(&mut socket).await;
+                    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    );
+                    let _ = socket.write_all(response.as_bytes()).await;
+                    let _ = socket.shutdown().await;
+                });
+            }
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let app = app_with_config(
+            &format!(
+                r#"
+[server.timeouts]
+api_timeout_ms = 100
+max_timeout_override_ms = 60000
+
+[router]
+default = "m"
+
+[[providers]]
+name = "slow"
+provider_type = "anthropic"
+api_key = "key"
+base_url = "{}"
+models = ["up"]
+
+[[models]]
+name = "m"
+mappings = [{{ priority = 1, provider = "slow", actual_model = "up" }}]
+"#,
+                url
+            ),
+            dir.path(),
+        );
+        let send = |timeout: Option<&str>| {
+            let mut request = axum::http::Request::post("/v1/messages").header("content-type", "application/json");
+            if let Some(timeout) = timeout {
+                request = request.header("x-ccm-timeout", timeout);
+            }
+            app.clone()
+                .oneshot(request.body(axum::body::Body::from(r#"{"model":"m","max_tokens":16,"messages":[{"role":"user","content":"hi"}]}"#)).unwrap())
+        };
+
+        let response = send(None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert!(body_string(response).await.contains("timed out"));
+
+        let response = send(Some("5")).await.unwrap();
+        assert!(response.status().is_success());
+
+        let response = send(Some("soon")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Capped at the configured maximum
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ccm-timeout", "3600".parse().unwrap());
+        let config: AppConfig = toml::from_str("[server.timeouts]\nmax_timeout_override_ms = 60000\n[router]\ndefault = \"m\"").unwrap();
+        assert_eq!(request_timeout(&headers, &config).unwrap(), std::time::Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn test_dns_failure_retried_until_resolved() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// Fails the first lookup like an unresolvable host, then resolves to `addr`
+        struct FlakyResolver {
+            addr: std::net::SocketAddr,
+            lookups: AtomicUsize,
+        }
+        impl reqwest::dns::Resolve for FlakyResolver {
+            fn resolve(&self, _name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+                let lookup = self.lookups.fetch_add(1, Ordering::SeqCst);
+                let addr = self.addr;
+                Box::pin(async move {
+                    if lookup == 0 {
+                        let error = std::io::Error::other("Temporary failure in name resolution");
+                        return Err(error.into());
+                    }
+                    let addrs: reqwest::dns::Addrs = Box::new(std::iter::once(addr));
+                    Ok(addrs)
+                })
+            }
+        }
+
+        let mut upstream = mockito::Server::new_async().await;
+        upstream.mock("GET", "/").with_status(200).create_async().await;
+        let resolver = Arc::new(FlakyResolver {
+            addr: upstream.socket_address(),
+            lookups: AtomicUsize::new(0),
+        });
+        let client = reqwest::Client::builder().dns_resolver(resolver.clone()).build().unwrap();
+        let url = format!("http://upstream.internal:{}/", upstream.socket_address().port());
+        let call = || async { client.get(&url).send().await.map_err(ProviderError::from) };
+
+        let no_retry = RetryConfig { connection_retries: 0, dns_retries: 0, backoff_ms: 1 };
+        let err = retry_on_reset(&no_retry, "flaky", call).await.unwrap_err();
+        assert!(err.is_dns_failure(), "unexpected error: {}", err);
+
+        resolver.lookups.store(0, Ordering::SeqCst);
+        let retry = RetryConfig { connection_retries: 0, dns_retries: 2, backoff_ms: 1 };
+        let response = retry_on_reset(&retry, "flaky", call).await.unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(resolver.lookups.load(Ordering::SeqCst), 2);
+    }
+
+    /// Router serving one model "m" mapped to an Anthropic-compatible upstream,
+    /// with extra `provider_settings` lines for the provider
+    fn mock_app(upstream: &str, dir: &std::path::Path, provider_settings: &str) -> AxumRouter {
+        app_with_config(
+            &format!(
+                r#"
+[router]
+default = "m"
+
+[[providers]]
+name = "mock"
+provider_type = "anthropic"
+api_key = "key"
+base_url = "{}"
+models = ["up"]
+{}
+
+[[models]]
+name = "m"
+mappings = [{{ priority = 1, provider = "mock", actual_model = "up" }}]
+"#,
+                upstream, provider_settings
+            ),
+            dir,
+        )
+    }
+
+    /// Router for a TOML config, with OAuth tokens stored under `dir`
+    fn app_with_config(config: &str, dir: &std::path::Path) -> AxumRouter {
+        let config: AppConfig = toml::from_str(config).unwrap();
+        let token_store = TokenStore::new(dir.join("tokens.json")).unwrap();
+        let state = Arc::new(AppState {
+            router: Router::new(config.clone()),
+            provider_registry: Arc::new(ProviderRegistry::from_configs(&config.providers, Some(token_store.clone())).unwrap()),
+            token_store,
+            config_path: dir.join("config.toml"),
+            stream_limiter: StreamLimiter::new(None),
+            pending_reauths: Default::default(),
+            metrics: Metrics::new(),
+            provider_health: ProviderHealth::new(&config.providers),
+            circuit_breaker: CircuitBreaker::new(config.router.circuit_breaker.clone()),
+            config,
+        });
+        build_router(state.metrics.clone(), &state.config.server).with_state(state)
+    }
+
+    async fn body_string(response: Response) -> String {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_metrics_snapshot_reflects_completed_request() {
+        use tower::ServiceExt;
+
+        let mut upstream = mockito::Server::new_async().await;
+        upstream
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"ok"}],"model":"up","stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":12,"output_tokens":5}}"#)
+            .create_async()
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let app = mock_app(&upstream.url(), dir.path(), "");
+
+        let request = axum::http::Request::post("/v1/messages")
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(r#"{"model":"m","max_tokens":16,"messages":[{"role":"user","content":"hi"}]}"#))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+
+        let request = axum::http::Request::get("/api/metrics").body(axum::body::Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let snapshot: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+
+        assert_eq!(snapshot["requests_total"], 1);
+        assert_eq!(snapshot["errors_total"], 0);
+        assert_eq!(snapshot["fallbacks_total"], 0);
+        assert_eq!(snapshot["input_tokens_total"], 12);
+        assert_eq!(snapshot["output_tokens_total"], 5);
+        assert_eq!(snapshot["providers"]["mock"]["requests"], 1);
+        assert_eq!(snapshot["providers"]["mock"]["errors"], 0);
+        assert!(snapshot["providers"]["mock"]["max_latency_ms"].is_u64());
+    }
+
+    #[tokio::test]
+    async fn test_passthrough_sends_body_unmodified() {
+        use tower::ServiceExt;
+
+        // Would be rewritten by the normal pipeline: empty tools stripped, unknown fields dropped
+        let client_body = serde_json::json!({
+            "model": "m",
+            "max_tokens": 16,
+            "messages": [{ "role": "user", "content": "hi" }],
+            "tools": [],
+            "tool_choice": { "type": "auto" },
+            "x_vendor_field": { "keep": true }
+        });
+        let mut expected = client_body.clone();
+        expected["model"] = "up".into();
+        let upstream_body = r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"  ok  "}],"model":"up","stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":7,"output_tokens":2},"vendor_extra":1}"#;
+
+        let mut upstream = mockito::Server::new_async().await;
+        let messages = upstream
+            .mock("POST", "/v1/messages")
+            .match_header("x-api-key", "key")
+            .match_body(mockito::Matcher::Json(expected))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(upstream_body)
+            .create_async()
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let app = mock_app(&upstream.url(), dir.path(), "passthrough = true\ntrim_response_text = true");
+
+        let request = axum::http::Request::post("/v1/messages")
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(client_body.to_string()))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+        assert_eq!(body_string(response).await, upstream_body);
+        messages.assert_async().await;
+
+        let request = axum::http::Request::get("/api/metrics").body(axum::body::Body::empty()).unwrap();
+        let snapshot: serde_json::Value = serde_json::from_str(&body_string(app.oneshot(request).await.unwrap()).await).unwrap();
+        assert_eq!(snapshot["input_tokens_total"], 7);
+        assert_eq!(snapshot["providers"]["mock"]["requests"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_failing_provider_disabled_until_reenabled() {
+        use tower::ServiceExt;
+
+        let mut upstream = mockito::Server::new_async().await;
+        let failing = upstream
+            .mock("POST", "/bad/v1/messages")
+            .with_status(500)
+            .with_body(r#"{"error":{"message":"overloaded"}}"#)
+            .expect(3)
+            .create_async()
+            .await;
+        upstream
+            .mock("POST", "/good/v1/messages")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"ok"}],"model":"up","stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":1,"output_tokens":1}}"#)
+            .create_async()
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let app = app_with_config(
+            &format!(
+                r#"
+[router]
+default = "m"
+
+[[providers]]
+name = "bad"
+provider_type = "anthropic"
+api_key = "key"
+base_url = "{url}/bad"
+models = ["up"]
+failure_threshold = {{ max_failure_rate = 0.5, min_requests = 2 }}
+
+[[providers]]
+name = "good"
+provider_type = "anthropic"
+api_key = "key"
+base_url = "{url}/good"
+models = ["up"]
+
+[[models]]
+name = "m"
+mappings = [
+    {{ priority = 1, provider = "bad", actual_model = "up" }},
+    {{ priority = 2, provider = "good", actual_model = "up" }},
+]
+"#,
+                url = upstream.url()
+            ),
+            dir.path(),
+        );
+        let send = || {
+            app.clone().oneshot(
+                axum::http::Request::post("/v1/messages")
+                    .header("content-type", "application/json")
+                    .body(axum::body::Body::from(r#"{"model":"m","max_tokens":16,"messages":[{"role":"user","content":"hi"}]}"#))
+                    .unwrap(),
+            )
+        };
+
+        // Two failures out of two calls cross the threshold; the third request skips "bad"
+        for _ in 0..3 {
+            assert!(send().await.unwrap().status().is_success());
+        }
+        let request = axum::http::Request::get("/api/providers/disabled").body(axum::body::Body::empty()).unwrap();
+        let disabled = body_string(app.clone().oneshot(request).await.unwrap()).await;
+        assert_eq!(disabled, r#"{"disabled":["bad"]}"#);
+
+        let enable = |provider: &str| {
+            axum::http::Request::post("/api/providers/enable")
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(format!(r#"{{"provider":"{}"}}"#, provider)))
+                .unwrap()
+        };
+        assert!(app.clone().oneshot(enable("bad")).await.unwrap().status().is_success());
+        assert_eq!(app.clone().oneshot(enable("good")).await.unwrap().status(), StatusCode::NOT_FOUND);
+
+        // Back in routing
+        assert!(send().await.unwrap().status().is_success());
+        failing.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_legacy_completion_round_trip() {
+        use tower::ServiceExt;
+
+        let mut upstream = mockito::Server::new_async().await;
+        let messages = upstream
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                "model": "up",
+                "max_tokens": 32,
+                "system": "You are terse.",
+                "stop_sequences": ["END"],
+                "messages": [
+                    {"role": "user", "content": "Say hi"},
+                    {"role": "assistant", "content": "Hi"},
+                    {"role": "user", "content": "Again"}
+                ]
+            })))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":" Hi again"}],"model":"up","stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":9,"output_tokens":3}}"#)
+            .create_async()
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let app = mock_app(&upstream.url(), dir.path(), "");
+        let legacy = serde_json::json!({
+            "model": "m",
+            "prompt": "You are terse.\n\nHuman: Say hi\n\nAssistant: Hi\n\nHuman: Again\n\nAssistant:",
+            "max_tokens_to_sample": 32,
+            "stop_sequences": ["END"]
+        });
+
+        let request = axum::http::Request::post("/v1/complete")
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(legacy.to_string()))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+        let completion: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(completion["type"], "completion");
+        assert_eq!(completion["completion"], " Hi again");
+        assert_eq!(completion["stop_reason"], "stop_sequence");
+        assert_eq!(completion["stop"], "\n\nHuman:");
+        assert_eq!(completion["model"], "m");
+        messages.assert_async().await;
+
+        // Raw upstream bodies can't be translated, so the raw response header is ignored
+        let request = axum::http::Request::post("/v1/complete")
+            .header("content-type", "application/json")
+            .header("x-ccm-raw-response", "true")
+            .body(axum::body::Body::from(legacy.to_string()))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+        let completion: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(completion["completion"], " Hi again");
+
+        // Streaming: text deltas become `completion` events, then a final one with the stop reason
+        upstream
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_2\",\"usage\":{\"input_tokens\":9,\"output_tokens\":0}}}\n\n",
+                "event: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+                "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\" Hi\"}}\n\n",
+                "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\" again\"}}\n\n",
+                "event: content_block_stop\ndata: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+                "event: message_delta\ndata: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"max_tokens\",\"stop_sequence\":null},\"usage\":{\"output_tokens\":2}}\n\n",
+                "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n",
+            ))
+            .create_async()
+            .await;
+
+        let mut streaming = legacy.clone();
+        streaming["stream"] = true.into();
+        let request = axum::http::Request::post("/v1/complete")
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(streaming.to_string()))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+
+        let events: Vec<serde_json::Value> = crate::providers::streaming::parse_sse_events(&body_string(response).await)
+            .into_iter()
+            .inspect(|e| assert_eq!(e.event.as_deref(), Some("completion")))
+            .filter_map(|e| e.json())
+            .collect();
+        let text: String = events.iter().filter_map(|e| e["completion"].as_str()).collect();
+        assert_eq!(text, " Hi again");
+        assert_eq!(events.last().unwrap()["stop_reason"], "max_tokens");
+        assert!(events.iter().all(|e| e["id"] == "msg_2" && e["model"] == "m"));
+    }
+
+    #[test]
+    fn test_thinking_request_to_non_thinking_provider() {
+        let mut config: AppConfig = toml::from_str(
+            r#"
+[router]
+default = "m"
+
+[[providers]]
+name = "claude"
+provider_type = "anthropic"
+api_key = "key"
+models = []
+
+[[providers]]
+name = "plain"
+provider_type = "openai"
+api_key = "key"
+models = []
+"#,
+        )
+        .unwrap();
+        let registry = ProviderRegistry::from_configs(&config.providers, None).unwrap();
+        let request: AnthropicRequest = serde_json::from_value(serde_json::json!({
+            "model": "m",
+            "max_tokens": 2048,
+            "thinking": {"type": "enabled", "budget_tokens": 1024},
+            "messages": [{"role": "user", "content": "hi"}]
+        }))
+        .unwrap();
+
+        // Thinking-capable providers are never affected
+        let mut to_claude = request.clone();
+        assert!(check_thinking_support(&config, &registry, "claude", &mut to_claude).unwrap());
+        assert!(to_claude.thinking.is_some());
+
+        // strip (default): sent without thinking
+        let mut stripped = request.clone();
+        assert!(check_thinking_support(&config, &registry, "plain", &mut stripped).unwrap());
+        assert!(stripped.thinking.is_none());
+
+        // error: request rejected
+        config.router.unsupported_thinking = ThinkingPolicy::Error;
+        let mut rejected = request.clone();
+        let err = check_thinking_support(&config, &registry, "plain", &mut rejected).unwrap_err();
+        assert!(matches!(err, AppError::RoutingError(_)));
+
+        // fallback: mapping skipped, request left intact for the next one
+        config.router.unsupported_thinking = ThinkingPolicy::Fallback;
+        let mut skipped = request.clone();
+        assert!(!check_thinking_support(&config, &registry, "plain", &mut skipped).unwrap());
+        assert!(skipped.thinking.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_thinking_selects_mapping() {
+        use tower::ServiceExt;
+
+        let mut upstream = mockito::Server::new_async().await;
+        let reply = |model: &str| {
+            format!(
+                r#"{{"id":"msg_1","type":"message","role":"assistant","content":[{{"type":"text","text":"ok"}}],"model":"{}","stop_reason":"end_turn","stop_sequence":null,"usage":{{"input_tokens":1,"output_tokens":1}}}}"#,
+                model
+            )
+        };
+        let thinking = upstream
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({ "model": "pro-up" })))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(reply("pro-up"))
+            .expect(1)
+            .create_async()
+            .await;
+        let fast = upstream
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({ "model": "flash-up" })))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(reply("flash-up"))
+            .expect(1)
+            .create_async()
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let app = app_with_config(
+            &format!(
+                r#"
+[router]
+default = "claude-opus"
+
+[[providers]]
+name = "mock"
+provider_type = "anthropic"
+api_key = "key"
+base_url = "{}"
+models = ["pro-up", "flash-up"]
+
+[[models]]
+name = "claude-opus"
+mappings = [
+    {{ priority = 1, provider = "mock", actual_model = "pro-up", when_thinking = true }},
+    {{ priority = 2, provider = "mock", actual_model = "flash-up", when_thinking = false }},
+]
+"#,
+                upstream.url()
+            ),
+            dir.path(),
+        );
+        let send = |body: serde_json::Value| {
+            let request = axum::http::Request::post("/v1/messages")
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(body.to_string()))
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+
+        let response = send(serde_json::json!({
+            "model": "claude-opus",
+            "max_tokens": 4096,
+            "thinking": { "type": "enabled", "budget_tokens": 2048 },
+            "messages": [{ "role": "user", "content": "plan this" }]
+        }))
+        .await
+        .unwrap();
+        assert!(response.status().is_success());
+
+        let response = send(serde_json::json!({
+            "model": "claude-opus",
+            "max_tokens": 4096,
+            "messages": [{ "role": "user", "content": "quick answer" }]
+        }))
+        .await
+        .unwrap();
+        assert!(response.status().is_success());
+
+        thinking.assert_async().await;
+        fast.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_debug_header_enables_debug_logs_for_one_request() {
+        use tower::ServiceExt;
+
+        #[derive(Clone, Default)]
+        struct Capture(Arc<std::sync::Mutex<Vec<u8>>>);
+        impl std::io::Write for Capture {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        // Global level info, as with RUST_LOG=info
+        let capture = Capture::default();
+        let writer = capture.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_env_filter(tracing_subscriber::EnvFilter::new("info").add_directive(debug_log_directive()))
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut upstream = mockito::Server::new_async().await;
+        upstream
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"ok"}],"model":"up","stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":1,"output_tokens":1}}"#)
+            .expect(2)
+            .create_async()
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let config = format!(
+            r#"
+[server]
+admin_key = "secret"
+
+[router]
+default = "m"
+
+[[providers]]
+name = "mock"
+provider_type = "anthropic"
+api_key = "key"
+base_url = "{}"
+models = ["up"]
+
+[[models]]
+name = "m"
+mappings = [{{ priority = 1, provider = "mock", actual_model = "up" }}]
+"#,
+            upstream.url()
+        );
+        let app = app_with_config(&config, dir.path());
+        let send = |text: &str, debug: Option<&str>| {
+            let mut request = axum::http::Request::post("/v1/messages").header("content-type", "application/json");
+            if let Some(admin_key) = debug {
+                request = request.header("x-ccm-debug", "true").header("x-ccm-admin-key", admin_key);
+            }
+            let body = serde_json::json!({ "model": "m", "max_tokens": 16, "messages": [{ "role": "user", "content": text }] });
+            app.clone().oneshot(request.body(axum::body::Body::from(body.to_string())).unwrap())
+        };
+
+        assert!(send("quiet request", None).await.unwrap().status().is_success());
+        assert!(send("noisy request", Some("secret")).await.unwrap().status().is_success());
+        // Debug logging without the admin key is refused before reaching upstream
+        let response = send("sneaky request", Some("guess")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let logs = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(logs.matches("Incoming request body").count(), 1, "{}", logs);
+        assert!(logs.contains("noisy request"));
+        assert!(!logs.contains("quiet request"));
+        assert!(!logs.contains("sneaky request"));
+        // Info logs are unaffected
+        assert_eq!(logs.matches("Received request for model").count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_embeddings_served_by_gemini() {
+        use tower::ServiceExt;
+
+        let mut upstream = mockito::Server::new_async().await;
+        for (text, values) in [("first", "[0.1,0.2]"), ("second", "[0.3,0.4]")] {
+            upstream
+                .mock("POST", "/models/text-embedding-004:embedContent")
+                .match_query(mockito::Matcher::UrlEncoded("key".into(), "gkey".into()))
+                .match_body(mockito::Matcher::PartialJson(serde_json::json!({
+                    "model": "models/text-embedding-004",
+                    "content": { "parts": [{ "text": text }] }
+                })))
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(format!(r#"{{"embedding":{{"values":{}}}}}"#, values))
+                .expect(1)
+                .create_async()
+                .await;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let app = app_with_config(
+            &format!(
+                r#"
+[router]
+default = "embed"
+
+[[providers]]
+name = "chat-only"
+provider_type = "anthropic"
+api_key = "key"
+base_url = "{url}"
+models = ["up"]
+
+[[providers]]
+name = "gemini"
+provider_type = "gemini"
+api_key = "gkey"
+base_url = "{url}"
+models = ["text-embedding-004"]
+
+[[models]]
+name = "embed"
+mappings = [
+    {{ priority = 1, provider = "chat-only", actual_model = "up" }},
+    {{ priority = 2, provider = "gemini", actual_model = "text-embedding-004" }},
+]
+"#,
+                url = upstream.url()
+            ),
+            dir.path(),
+        );
+
+        // The Anthropic provider has no embeddings, so the Gemini mapping answers
+        let request = axum::http::Request::post("/v1/embeddings")
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(r#"{"model":"embed","input":["first","second"]}"#))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(body["object"], "list");
+        assert_eq!(body["model"], "embed");
+        assert_eq!(body["data"][0]["index"], 0);
+        assert_eq!(body["data"][0]["embedding"], serde_json::json!([0.1, 0.2]));
+        assert_eq!(body["data"][1]["embedding"], serde_json::json!([0.3, 0.4]));
+
+        // The chat-only provider was skipped, not called and counted as failing
+        let request = axum::http::Request::get("/api/metrics").body(axum::body::Body::empty()).unwrap();
+        let snapshot: serde_json::Value = serde_json::from_str(&body_string(app.oneshot(request).await.unwrap()).await).unwrap();
+        assert!(snapshot["providers"].get("chat-only").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_provider_health_checks_report_each_provider() {
+        use tower::ServiceExt;
+
+        let mut upstream = mockito::Server::new_async().await;
+        upstream
+            .mock("POST", "/models/gemini-2.5-flash:countTokens")
+            .match_query(mockito::Matcher::UrlEncoded("key".to_string(), "good".to_string()))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"totalTokens":1}"#)
+            .create_async()
+            .await;
+        upstream
+            .mock("POST", "/models/gemini-2.5-flash:countTokens")
+            .match_query(mockito::Matcher::UrlEncoded("key".to_string(), "revoked".to_string()))
+            .with_status(403)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"error":{"code":403,"message":"API key not valid","status":"PERMISSION_DENIED"}}"#)
+            .create_async()
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let app = app_with_config(
+            &format!(
+                r#"
+[router]
+default = "m"
+
+[[providers]]
+name = "claude"
+provider_type = "anthropic"
+api_key = "key"
+base_url = "{url}"
+models = ["up"]
+
+[[providers]]
+name = "gemini-ok"
+provider_type = "gemini"
+api_key = "good"
+base_url = "{url}"
+models = ["gemini-2.5-flash"]
+
+[[providers]]
+name = "gemini-bad"
+provider_type = "gemini"
+api_key = "revoked"
+base_url = "{url}"
+models = ["gemini-2.5-flash"]
+
+[[models]]
+name = "m"
+mappings = [{{ priority = 1, provider = "claude", actual_model = "up" }}]
+"#,
+                url = upstream.url()
+            ),
+            dir.path(),
+        );
+
+        let request = axum::http::Request::get("/healthz/providers").body(axum::body::Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let body: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["providers"]["claude"]["status"], "ok");
+        assert_eq!(body["providers"]["gemini-ok"]["status"], "ok");
+        assert_eq!(body["providers"]["gemini-bad"]["status"], "error");
+        assert!(body["providers"]["gemini-bad"]["error"].as_str().unwrap().contains("API key not valid"));
+    }
+
+    #[tokio::test]
+    async fn test_cors_allows_only_configured_origins() {
+        use tower::ServiceExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let config = |cors: &str| format!("{}\n[router]\ndefault = \"m\"\n", cors);
+        let preflight = |app: AxumRouter, origin: &'static str| async move {
+            let request = axum::http::Request::builder()
+                .method("OPTIONS")
+                .uri("/v1/messages")
+                .header("origin", origin)
+                .header("access-control-request-method", "POST")
+                .header("access-control-request-headers", "content-type,x-api-key")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            let response = app.oneshot(request).await.unwrap();
+            let header = |name: &str| response.headers().get(name).map(|v| v.to_str().unwrap().to_string());
+            (header("access-control-allow-origin"), header("access-control-allow-headers"))
+        };
+
+        let app = app_with_config(
+            &config("[server.cors]\nallowed_origins = [\"https://tools.example\"]\n"),
+            dir.path(),
+        );
+        let (origin, headers) = preflight(app.clone(), "https://tools.example").await;
+        assert_eq!(origin.as_deref(), Some("https://tools.example"));
+        assert!(headers.unwrap().contains("x-api-key"));
+        assert_eq!(preflight(app.clone(), "https://evil.example").await.0, None);
+
+        // Actual requests from the allowed origin carry the header too
+        let request = axum::http::Request::get("/health")
+            .header("origin", "https://tools.example")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.headers()["access-control-allow-origin"], "https://tools.example");
+
+        // Restrictive by default: no origin is allowed
+        let app = app_with_config(&config(""), dir.path());
+        assert_eq!(preflight(app, "https://tools.example").await.0, None);
+    }
+
+    #[tokio::test]
+    async fn test_organization_header_reaches_upstream() {
+        use tower::ServiceExt;
+
+        let mut upstream = mockito::Server::new_async().await;
+        let body = r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"ok"}],"model":"up","stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":1,"output_tokens":1}}"#;
+        let payload = r#"{"model":"m","max_tokens":16,"messages":[{"role":"user","content":"hi"}]}"#;
+        let dir = tempfile::tempdir().unwrap();
+        let app = mock_app(&upstream.url(), dir.path(), r#"organization_id = "org-config""#);
+
+        // The provider's organization is sent by default
+        let configured = upstream
+            .mock("POST", "/v1/messages")
+            .match_header("anthropic-organization-id", "org-config")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(body)
+            .create_async()
+            .await;
+        let request = axum::http::Request::post("/v1/messages")
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(payload))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+        configured.assert_async().await;
+
+        // A client-sent organization replaces it
+        upstream.reset();
+        let forwarded = upstream
+            .mock("POST", "/v1/messages")
+            .match_header("anthropic-organization-id", "org-client")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(body)
+            .create_async()
+            .await;
+        let request = axum::http::Request::post("/v1/messages")
+            .header("content-type", "application/json")
+            .header("anthropic-organization-id", "org-client")
+            .body(axum::body::Body::from(payload))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+        forwarded.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_count_tokens_uses_client_organization() {
+        use tower::ServiceExt;
+
+        let mut upstream = mockito::Server::new_async().await;
+        let counted = upstream
+            .mock("POST", "/v1/messages/count_tokens")
+            .match_header("anthropic-organization-id", "org-client")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"input_tokens":7}"#)
+            .create_async()
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let app = app_with_config(
+            &format!(
+                r#"
+[router]
+default = "m"
+
+[[providers]]
+name = "anthropic"
+provider_type = "anthropic"
+api_key = "key"
+base_url = "{}"
+models = ["up"]
+organization_id = "org-config"
+
+[[models]]
+name = "m"
+mappings = [{{ priority = 1, provider = "anthropic", actual_model = "up" }}]
+"#,
+                upstream.url()
+            ),
+            dir.path(),
+        );
+
+        // Counted against the same organization the messages call would use
+        let request = axum::http::Request::post("/v1/messages/count_tokens")
+            .header("content-type", "application/json")
+            .header("anthropic-organization-id", "org-client")
+            .body(axum::body::Body::from(r#"{"model":"m","messages":[{"role":"user","content":"hi"}]}"#))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(body_string(response).await, r#"{"input_tokens":7}"#);
+        counted.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_request_id_header_reaches_upstream() {
+        use tower::ServiceExt;
+
+        let mut upstream = mockito::Server::new_async().await;
+        let body = r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"ok"}],"model":"up","stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":1,"output_tokens":1}}"#;
+        let request = || {
+            axum::http::Request::post("/v1/messages")
+                .header("content-type", "application/json")
+                .header("x-request-id", "trace-123")
+        };
+        let payload = r#"{"model":"m","max_tokens":16,"messages":[{"role":"user","content":"hi"}]}"#;
+
+        // Configured: the client's id is forwarded under the provider's header name
+        let forwarded = upstream
+            .mock("POST", "/v1/messages")
+            .match_header("x-correlation-id", "trace-123")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(body)
+            .create_async()
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let app = mock_app(&upstream.url(), dir.path(), r#"request_id_header = "x-correlation-id""#);
+        let response = app.oneshot(request().body(axum::body::Body::from(payload)).unwrap()).await.unwrap();
+        assert!(response.status().is_success());
+        forwarded.assert_async().await;
+
+        // Not configured: nothing is forwarded
+        upstream.reset();
+        let not_forwarded = upstream
+            .mock("POST", "/v1/messages")
+            .match_header("x-correlation-id", mockito::Matcher::Missing)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(body)
+            .create_async()
+            .await;
+        let app = mock_app(&upstream.url(), dir.path(), "");
+        let response = app.oneshot(request().body(axum::body::Body::from(payload)).unwrap()).await.unwrap();
+        assert!(response.status().is_success());
+        not_forwarded.assert_async().await;
+
+        // Without a client id one is generated
+        let generated = request_id(&HeaderMap::new());
+        assert!(generated.starts_with("req_") && generated.len() == 20);
+    }
+
+    #[tokio::test]
+    async fn test_default_max_tokens_applied_when_absent() {
+        use tower::ServiceExt;
+
+        let mut upstream = mockito::Server::new_async().await;
+        let defaulted = upstream
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({ "max_tokens": 4096 })))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"ok"}],"model":"up","stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":1,"output_tokens":1}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let app = app_with_config(
+            &format!(
+                r#"
+[router]
+default = "m"
+
+[[providers]]
+name = "mock"
+provider_type = "anthropic"
+api_key = "key"
+base_url = "{}"
+models = ["up"]
+
+[[models]]
+name = "m"
+default_max_tokens = 4096
+mappings = [{{ priority = 1, provider = "mock", actual_model = "up" }}]
+
+[[models]]
+name = "no-default"
+mappings = [{{ priority = 1, provider = "mock", actual_model = "up" }}]
+"#,
+                upstream.url()
+            ),
+            dir.path(),
+        );
+        let send = |model: &str| {
+            axum::http::Request::builder()
+                .method("POST")
+                .uri("/v1/messages")
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(
+                    serde_json::json!({ "model": model, "messages": [{ "role": "user", "content": "hi" }] }).to_string(),
+                ))
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(send("m")).await.unwrap();
+        assert!(response.status().is_success());
+        defaulted.assert_async().await;
+
+        // No default configured: still rejected
+        let response = app.oneshot(send("no-default")).await.unwrap();
+        assert!(!response.status().is_success());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("missing field `max_tokens`"));
+    }
+
+    #[tokio::test]
+    async fn test_missing_required_fields_rejected_before_routing() {
+        use tower::ServiceExt;
+
+        let mut upstream = mockito::Server::new_async().await;
+        let untouched = upstream.mock("POST", "/v1/messages").expect(0).create_async().await;
+        let dir = tempfile::tempdir().unwrap();
+        let app = mock_app(&upstream.url(), dir.path(), "");
+        let send = |body: serde_json::Value| {
+            axum::http::Request::post("/v1/messages")
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(body.to_string()))
+                .unwrap()
+        };
+        let messages = serde_json::json!([{ "role": "user", "content": "hi" }]);
+
+        for (body, error) in [
+            (serde_json::json!({ "model": "m", "messages": messages }), "missing field `max_tokens`"),
+            (serde_json::json!({ "max_tokens": 16, "messages": messages }), "missing field `model`"),
+            (serde_json::json!({ "model": "m", "max_tokens": "16", "messages": messages }), "`max_tokens` must be a positive integer"),
+        ] {
+            let response = app.clone().oneshot(send(body)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            assert!(body_string(response).await.contains(error));
+        }
+        untouched.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_configured_client_headers_forwarded() {
+        use tower::ServiceExt;
+        let mut upstream = mockito::Server::new_async().await;
+        let forwarded = upstream
+            .mock("POST", "/v1/messages")
+            .match_header("x-beta-features", "fast-mode")
+            .match_header("x-session-token", mockito::Matcher::Missing)
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"ok"}],"model":"up","stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":1,"output_tokens":1}}"#)
+            .create_async()
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        // Non-x- names are ignored even when listed
+        let app = mock_app(&upstream.url(), dir.path(), r#"forward_headers = ["X-Beta-Features", "authorization"]"#);
+
+        let request = axum::http::Request::builder()
+            .method("POST")
+            .uri("/v1/messages")
+            .header("content-type", "application/json")
+            .header("x-beta-features", "fast-mode")
+            .header("x-session-token", "secret")
+            .body(axum::body::Body::from(r#"{"model":"m","max_tokens":16,"messages":[{"role":"user","content":"hi"}]}"#))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+        forwarded.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_serves_on_ipv6_and_ipv4_listeners() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app_with_config("[router]\ndefault = \"m\"", dir.path());
+
+        let listeners = bind_listeners(&["[::1]:0".to_string(), "127.0.0.1:0".to_string()]).await.unwrap();
+        let addrs: Vec<SocketAddr> = listeners.iter().map(|l| l.local_addr().unwrap()).collect();
+        assert!(addrs[0].is_ipv6());
+        assert!(addrs[1].is_ipv4());
+        tokio::spawn(serve_listeners(listeners, app));
+
+        // Both addresses are served by the same router
+        for addr in addrs {
+            let response = reqwest::get(format!("http://{}/health", addr)).await.unwrap();
+            assert!(response.status().is_success());
+            let body: serde_json::Value = response.json().await.unwrap();
+            assert_eq!(body["status"], "ok");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_raw_upstream_response_returned_for_admin() {
+        use tower::ServiceExt;
+
+        let mut upstream = mockito::Server::new_async().await;
+        upstream
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"ok"}],"model":"up","stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":1,"output_tokens":1},"vendor_trace":"abc"}"#)
+            .create_async()
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let config = format!(
+            r#"
+[server]
+admin_key = "secret"
+
+[router]
+default = "m"
+
+[[providers]]
+name = "mock"
+provider_type = "anthropic"
+api_key = "key"
+base_url = "{}"
+models = ["up"]
+
+[[models]]
+name = "m"
+mappings = [{{ priority = 1, provider = "mock", actual_model = "up" }}]
+"#,
+            upstream.url()
+        );
+        let send = |headers: &[(&str, &str)]| {
+            let mut request = axum::http::Request::post("/v1/messages").header("content-type", "application/json");
+            for (name, value) in headers {
+                request = request.header(*name, *value);
+            }
+            let request = request
+                .body(axum::body::Body::from(
+                    r#"{"model":"m","max_tokens":16,"messages":[{"role":"user","content":"hi"}]}"#,
+                ))
+                .unwrap();
+            app_with_config(&config, dir.path()).oneshot(request)
+        };
+
+        // Admin: transformed response plus the untouched upstream body
+        let response = send(&[("x-ccm-raw-response", "true"), ("x-ccm-admin-key", "secret")]).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(body["response"]["model"], "m");
+        assert_eq!(body["raw_upstream_response"]["model"], "up");
+        assert_eq!(body["raw_upstream_response"]["vendor_trace"], "abc");
+
+        // Wrong key: refused
+        let response = send(&[("x-ccm-raw-response", "true"), ("x-ccm-admin-key", "guess")]).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // Not requested: the usual response
+        let response = send(&[]).await.unwrap();
+        let body: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(body["content"][0]["text"], "ok");
+        assert!(body.get("raw_upstream_response").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_client_beta_header_merged_with_provider_betas() {
+        use tower::ServiceExt;
+
+        let mut upstream = mockito::Server::new_async().await;
+        // Provider betas first, then the client's, without duplicates
+        let merged = upstream
+            .mock("POST", "/v1/messages")
+            .match_header(
+                "anthropic-beta",
+                "context-1m-2025-08-07,files-api-2025-04-14,interleaved-thinking-2025-05-14",
+            )
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"ok"}],"model":"up","stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":1,"output_tokens":1}}"#)
+            .create_async()
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let app = mock_app(
+            &upstream.url(),
+            dir.path(),
+            r#"betas = ["context-1m-2025-08-07", "files-api-2025-04-14"]"#,
+        );
+        let request = axum::http::Request::post("/v1/messages")
+            .header("content-type", "application/json")
+            .header("anthropic-beta", "files-api-2025-04-14, interleaved-thinking-2025-05-14")
+            .body(axum::body::Body::from(
+                r#"{"model":"m","max_tokens":16,"messages":[{"role":"user","content":"hi"}]}"#,
+            ))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+        merged.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_unauthenticated_oauth_mapping_falls_back() {
+        use tower::ServiceExt;
+
+        let mut upstream = mockito::Server::new_async().await;
+        let url = upstream.url();
+        let config = |fallback_on_auth_error: bool| {
+            format!(
+                r#"
+[router]
+default = "m"
+fallback_on_auth_error = {}
+
+[[providers]]
+name = "claude-max"
+provider_type = "anthropic"
+auth_type = "oauth"
+oauth_provider = "anthropic-max"
+models = []
+
+[[providers]]
+name = "backup"
+provider_type = "anthropic"
+api_key = "key"
+base_url = "{}"
+models = []
+
+[[models]]
+name = "m"
+mappings = [
+    {{ priority = 1, provider = "claude-max", actual_model = "claude-sonnet-4-5" }},
+    {{ priority = 2, provider = "backup", actual_model = "up" }},
+]
+"#,
+                fallback_on_auth_error,
+                url
+            )
+        };
+        let request = || {
+            axum::http::Request::post("/v1/messages")
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(r#"{"model":"m","max_tokens":16,"messages":[{"role":"user","content":"hi"}]}"#))
+                .unwrap()
+        };
+        let backup = upstream
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"ok"}],"model":"up","stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":1,"output_tokens":1}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        // No token stored for claude-max: the backup mapping serves the request
+        let dir = tempfile::tempdir().unwrap();
+        let response = app_with_config(&config(true), dir.path()).oneshot(request()).await.unwrap();
+        assert!(response.status().is_success());
+
+        // Disabled: the auth error fails the request without trying the backup
+        let response = app_with_config(&config(false), dir.path()).oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert!(body_string(response).await.contains("no token found"));
+        backup.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_invalid_request_fallback_configurable() {
+        use tower::ServiceExt;
+
+        let mut upstream = mockito::Server::new_async().await;
+        upstream
+            .mock("POST", "/models/gemini-2.5-pro:generateContent")
+            .match_query(mockito::Matcher::Any)
+            .with_status(400)
+            .with_body(r#"{"error":{"code":400,"status":"INVALID_ARGUMENT","message":"Unsupported mime type"}}"#)
+            .create_async()
+            .await;
+        let backup = upstream
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"ok"}],"model":"up","stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":1,"output_tokens":1}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let config = |fallback: bool| {
+            format!(
+                r#"
+[router]
+default = "m"
+fallback_on_invalid_request = {fallback}
+
+[[providers]]
+name = "gemini"
+provider_type = "gemini"
+api_key = "gkey"
+base_url = "{url}"
+models = ["gemini-2.5-pro"]
+
+[[providers]]
+name = "backup"
+provider_type = "anthropic"
+api_key = "key"
+base_url = "{url}"
+models = ["up"]
+
+[[models]]
+name = "m"
+mappings = [
+    {{ priority = 1, provider = "gemini", actual_model = "gemini-2.5-pro" }},
+    {{ priority = 2, provider = "backup", actual_model = "up" }},
+]
+"#,
+                url = upstream.url()
+            )
+        };
+        let request = || {
+            axum::http::Request::post("/v1/messages")
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(r#"{"model":"m","max_tokens":16,"messages":[{"role":"user","content":"hi"}]}"#))
+                .unwrap()
+        };
+        let dir = tempfile::tempdir().unwrap();
+
+        // Default: the next mapping gets a chance
+        let response = app_with_config(&config(true), dir.path()).oneshot(request()).await.unwrap();
+        assert!(response.status().is_success());
+
+        // Disabled: the request fails as a client error without trying the backup
+        let response = app_with_config(&config(false), dir.path()).oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(body_string(response).await.contains("Unsupported mime type"));
+        backup.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_skips_failing_provider() {
+        use tower::ServiceExt;
+
+        let mut down = mockito::Server::new_async().await;
+        let mut backup = mockito::Server::new_async().await;
+        let down_mock = down
+            .mock("POST", "/v1/messages")
+            .with_status(503)
+            .with_body(r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#)
+            .expect(2)
+            .create_async()
+            .await;
+        let backup_mock = backup
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"ok"}],"model":"up","stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":1,"output_tokens":1}}"#)
+            .expect(4)
+            .create_async()
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let app = app_with_config(
+            &format!(
+                r#"
+[router]
+default = "m"
+circuit_breaker = {{ failure_threshold = 2, cooldown_secs = 60 }}
+
+[[providers]]
+name = "down"
+provider_type = "anthropic"
+api_key = "key"
+base_url = "{}"
+models = ["up"]
+
+[[providers]]
+name = "backup"
+provider_type = "anthropic"
+api_key = "key"
+base_url = "{}"
+models = ["up"]
+
+[[models]]
+name = "m"
+mappings = [
+    {{ priority = 1, provider = "down", actual_model = "up" }},
+    {{ priority = 2, provider = "backup", actual_model = "up" }},
+]
+"#,
+                down.url(),
+                backup.url()
+            ),
+            dir.path(),
+        );
+
+        // Two failures open the circuit; later requests go straight to the backup
+        for _ in 0..4 {
+            let request = axum::http::Request::post("/v1/messages")
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(r#"{"model":"m","max_tokens":16,"messages":[{"role":"user","content":"hi"}]}"#))
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert!(response.status().is_success());
+        }
+        down_mock.assert_async().await;
+        backup_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_model_group_alias_tries_members_in_order() {
+        use tower::ServiceExt;
+
+        let mut first = mockito::Server::new_async().await;
+        let mut second = mockito::Server::new_async().await;
+        let config = format!(
+            r#"
+[router]
+default = "fast"
+
+[[providers]]
+name = "primary"
+provider_type = "anthropic"
+api_key = "key"
+base_url = "{}"
+models = []
+
+[[providers]]
+name = "secondary"
+provider_type = "anthropic"
+api_key = "key"
+base_url = "{}"
+models = []
+
+[[models]]
+name = "sonnet"
+mappings = [{{ priority = 1, provider = "primary", actual_model = "sonnet-up" }}]
+
+[[models]]
+name = "glm"
+mappings = [{{ priority = 1, provider = "secondary", actual_model = "glm-up" }}]
+
+[[model_groups]]
+name = "smart"
+members = ["sonnet", "glm"]
+"#,
+            first.url(),
+            second.url()
+        );
+        let failing = first
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({ "model": "sonnet-up" })))
+            .with_status(500)
+            .with_body(r#"{"error":{"message":"overloaded"}}"#)
+            .expect_at_least(1)
+            .create_async()
+            .await;
+        let serving = second
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({ "model": "glm-up" })))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"ok"}],"model":"glm-up","stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":1,"output_tokens":1}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let request = axum::http::Request::post("/v1/messages")
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(r#"{"model":"smart","max_tokens":16,"messages":[{"role":"user","content":"hi"}]}"#))
+            .unwrap();
+        let response = app_with_config(&config, dir.path()).oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+        assert!(body_string(response).await.contains(r#""text":"ok""#));
+        failing.assert_async().await;
+        serving.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_response_text_trimmed_only_when_enabled() {
+        use tower::ServiceExt;
+
+        let mut upstream = mockito::Server::new_async().await;
+        upstream
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({"stream": true})))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"usage\":{\"input_tokens\":1,\"output_tokens\":0}}}\n\n",
+                "event: content_block_start\ndata: {\"type\":\"content_block_start\",\"index\":0,\"content_block\":{\"type\":\"text\",\"text\":\"\"}}\n\n",
+                "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"\\n \"}}\n\n",
+                "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\" {\\\"a\\\": \"}}\n\n",
+                "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"1} \"}}\n\n",
+                "event: content_block_delta\ndata: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"\\n\\n\"}}\n\n",
+                "event: content_block_stop\ndata: {\"type\":\"content_block_stop\",\"index\":0}\n\n",
+                "event: message_delta\ndata: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\",\"stop_sequence\":null},\"usage\":{\"output_tokens\":4}}\n\n",
+                "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n",
+            ))
+            .create_async()
+            .await;
+        upstream
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({"stream": false})))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"\n  {\"a\": 1}  \n"}],"model":"up","stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":1,"output_tokens":4}}"#)
+            .create_async()
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let request = |stream: bool| {
+            axum::http::Request::post("/v1/messages")
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(
+                    serde_json::json!({"model": "m", "max_tokens": 16, "stream": stream, "messages": [{"role": "user", "content": "hi"}]}).to_string(),
+                ))
+                .unwrap()
+        };
+        let streamed_text = |body: String| -> String {
+            crate::providers::streaming::parse_sse_events(&body)
+                .iter()
+                .filter_map(|e| e.json())
+                .filter_map(|e| e.pointer("/delta/text").and_then(|t| t.as_str()).map(|t| t.to_string()))
+                .collect()
+        };
+
+        for (settings, expected_text, expected_streamed) in [
+            ("trim_response_text = true", "{\"a\": 1}", "{\"a\": 1}"),
+            ("", "\n  {\"a\": 1}  \n", "\n  {\"a\": 1} \n\n"),
+        ] {
+            let app = mock_app(&upstream.url(), dir.path(), settings);
+
+            let response = app.clone().oneshot(request(false)).await.unwrap();
+            let body: serde_json::Value = serde_json::from_str(&body_string(response).await).unwrap();
+            assert_eq!(body["content"][0]["text"], expected_text, "{}", settings);
+
+            let response = app.oneshot(request(true)).await.unwrap();
+            assert_eq!(streamed_text(body_string(response).await), expected_streamed, "{}", settings);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_early_stream_failure_retried_non_streaming() {
+        use tower::ServiceExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        // Upstream 503 on the stream, and a stream whose first event is an error
+        let failures = [
+            (503, "application/json", r#"{"type":"error","error":{"type":"overloaded_error","message":"busy"}}"#),
+            (
+                200,
+                "text/event-stream",
+                concat!(
+                    "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"usage\":{\"input_tokens\":1,\"output_tokens\":0}}}\n\n",
+                    "event: error\ndata: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"busy\"}}\n\n",
+                ),
+            ),
+        ];
+        for (status, content_type, failure) in failures {
+            let mut upstream = mockito::Server::new_async().await;
+            upstream
+                .mock("POST", "/v1/messages")
+                .match_body(mockito::Matcher::PartialJson(serde_json::json!({"stream": true})))
+                .with_status(status)
+                .with_header("content-type", content_type)
+                .with_body(failure)
+                .create_async()
+                .await;
+            let retry = upstream
+                .mock("POST", "/v1/messages")
+                .match_body(mockito::Matcher::PartialJson(serde_json::json!({"stream": false})))
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(r#"{"id":"msg_2","type":"message","role":"assistant","content":[{"type":"text","text":"ok"},{"type":"tool_use","id":"t1","name":"f","input":{"a":1}}],"model":"up","stop_reason":"tool_use","stop_sequence":null,"usage":{"input_tokens":3,"output_tokens":5}}"#)
+                .expect(1)
+                .create_async()
+                .await;
+
+            let app = mock_app(&upstream.url(), dir.path(), "non_streaming_retry = true");
+            let response = app
+                .oneshot(
+                    axum::http::Request::post("/v1/messages")
+                        .header("content-type", "application/json")
+                        .body(axum::body::Body::from(
+                            serde_json::json!({"model": "m", "max_tokens": 16, "stream": true, "messages": [{"role": "user", "content": "hi"}]}).to_string(),
+                        ))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let events: Vec<serde_json::Value> = crate::providers::streaming::parse_sse_events(&body_string(response).await)
+                .iter()
+                .filter_map(|e| e.json())
+                .collect();
+            retry.assert_async().await;
+
+            let types: Vec<&str> = events.iter().filter_map(|e| e["type"].as_str()).collect();
+            assert_eq!(
+                types,
+                vec![
+                    "message_start",
+                    "content_block_start",
+                    "content_block_delta",
+                    "content_block_stop",
+                    "content_block_start",
+                    "content_block_delta",
+                    "content_block_stop",
+                    "message_delta",
+                    "message_stop"
+                ],
+                "status {}",
+                status
+            );
+            assert_eq!(events[2]["delta"]["text"], "ok");
+            assert_eq!(events[4]["content_block"]["name"], "f");
+            assert_eq!(events[5]["delta"]["partial_json"], r#"{"a":1}"#);
+            assert_eq!(events[7]["delta"]["stop_reason"], "tool_use");
+            assert_eq!(events[7]["usage"]["output_tokens"], 5);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_app_id_routes_same_model_differently() {
+        use tower::ServiceExt;
+
+        let mut upstream = mockito::Server::new_async().await;
+        for provider in ["x", "y", "shared"] {
+            upstream
+                .mock("POST", format!("/{}/v1/messages", provider).as_str())
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(format!(
+                    r#"{{"id":"msg_1","type":"message","role":"assistant","content":[{{"type":"text","text":"{}"}}],"model":"up","stop_reason":"end_turn","stop_sequence":null,"usage":{{"input_tokens":1,"output_tokens":1}}}}"#,
+                    provider
+                ))
+                .create_async()
+                .await;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let providers: String = ["x", "y", "shared"]
+            .iter()
+            .map(|name| {
+                format!(
+                    "[[providers]]\nname = \"{name}\"\nprovider_type = \"anthropic\"\napi_key = \"key\"\nbase_url = \"{}/{name}\"\nmodels = [\"up\"]\n\n",
+                    upstream.url()
+                )
+            })
+            .collect();
+        let app = app_with_config(
+            &format!(
+                r#"
+[router]
+default = "opus"
+app_id_header = "x-app"
+
+{providers}
+[[models]]
+name = "opus"
+mappings = [
+    {{ priority = 1, provider = "shared", actual_model = "up" }},
+    {{ priority = 2, provider = "x", actual_model = "up", app_ids = ["a"] }},
+    {{ priority = 2, provider = "y", actual_model = "up", app_ids = ["b"] }},
+]
+"#
+            ),
+            dir.path(),
+        );
+        let send = |app_id: Option<&str>| {
+            let mut request = axum::http::Request::post("/v1/messages").header("content-type", "application/json");
+            if let Some(app_id) = app_id {
+                request = request.header("x-app", app_id);
+            }
+            app.clone().oneshot(
+                request
+                    .body(axum::body::Body::from(r#"{"model":"opus","max_tokens":16,"messages":[{"role":"user","content":"hi"}]}"#))
+                    .unwrap(),
+            )
+        };
+
+        // App mappings are tried ahead of the shared one; other apps' mappings never are
+        for (app_id, expected) in [(Some("a"), "x"), (Some("b"), "y"), (Some("c"), "shared"), (None, "shared")] {
+            let body: serde_json::Value = serde_json::from_str(&body_string(send(app_id).await.unwrap()).await).unwrap();
+            assert_eq!(body["content"][0]["text"], expected, "app {:?}", app_id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_weighted_selection_splits_load_and_fails_over() {
+        use tower::ServiceExt;
+
+        let mut upstream = mockito::Server::new_async().await;
+        let ok = r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"ok"}],"model":"up","stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":1,"output_tokens":1}}"#;
+        let key1 = upstream
+            .mock("POST", "/key1/v1/messages")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(ok)
+            .expect_at_least(1)
+            .create_async()
+            .await;
+        let key2 = upstream
+            .mock("POST", "/key2/v1/messages")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(ok)
+            .expect_at_least(1)
+            .create_async()
+            .await;
+        let down = upstream
+            .mock("POST", "/down/v1/messages")
+            .with_status(500)
+            .with_body("down")
+            .expect_at_least(1)
+            .create_async()
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let providers: String = ["key1", "key2", "down"]
+            .iter()
+            .map(|name| {
+                format!(
+                    "[[providers]]\nname = \"{name}\"\nprovider_type = \"anthropic\"\napi_key = \"key\"\nbase_url = \"{}/{name}\"\nmodels = [\"up\"]\n\n",
+                    upstream.url()
+                )
+            })
+            .collect();
+        let app = app_with_config(
+            &format!(
+                r#"
+[router]
+default = "m"
+
+{providers}
+[[models]]
+name = "m"
+selection = "weighted"
+mappings = [
+    {{ priority = 1, provider = "key1", actual_model = "up" }},
+    {{ priority = 2, provider = "key2", actual_model = "up" }},
+    {{ priority = 3, provider = "down", actual_model = "up", weight = 2 }},
+]
+"#
+            ),
+            dir.path(),
+        );
+
+        // Traffic reaches all three mappings; requests that pick "down" fail over
+        for _ in 0..40 {
+            let request = axum::http::Request::post("/v1/messages")
+                .header("content-type", "application/json")
+                .body(axum::body::Body::from(r#"{"model":"m","max_tokens":16,"messages":[{"role":"user","content":"hi"}]}"#))
+                .unwrap();
+            assert_eq!(app.clone().oneshot(request).await.unwrap().status(), StatusCode::OK);
+        }
+        key1.assert_async().await;
+        key2.assert_async().await;
+        down.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_stalled_stream_times_out_before_non_streaming_retry() {
+        use tower::ServiceExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let config = |url: &str| {
+            format!(
+                r#"
+[server.timeouts]
+api_timeout_ms = 300
+
+[router]
+default = "m"
+circuit_breaker = {{ failure_threshold = 1, cooldown_secs = 60 }}
+
+[[providers]]
+name = "mock"
+provider_type = "anthropic"
+api_key = "key"
+base_url = "{}"
+models = ["up"]
+non_streaming_retry = true
+
+[[models]]
+name = "m"
+mappings = [{{ priority = 1, provider = "mock", actual_model = "up" }}]
+"#,
+                url
+            )
+        };
+        let send = |app: AxumRouter| {
+            app.oneshot(
+                axum::http::Request::post("/v1/messages")
+                    .header("content-type", "application/json")
+                    .body(axum::body::Body::from(
+                        serde_json::json!({"model": "m", "max_tokens": 16, "stream": true, "messages": [{"role": "user", "content": "hi"}]}).to_string(),
+                    ))
+                    .unwrap(),
+            )
+        };
+
+        // 200 and then nothing on the stream; 404 for the non-streaming retry
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let first = counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0;
+                tokio::spawn(async move {
+                    use tokio::io::AsyncWriteExt;
+                    read_request(&mut socket).await;
+                    if first {
+                        let start = "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"usage\":{\"input_tokens\":1,\"output_tokens\":0}}}\n\n";
+                        let head = "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\ntransfer-encoding: chunked\r\n\r\n";
+                        let _ = socket.write_all(format!("{}{:x}\r\n{}\r\n", head, start.len(), start).as_bytes()).await;
+                        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                    } else {
+                        let body = r#"{"type":"error","error":{"type":"not_found_error","message":"no"}}"#;
+                        let response = format!(
+                            "HTTP/1.1 404 Not Found\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                            body.len(),
+                            body
+                        );
+                        let _ = socket.write_all(response.as_bytes()).await;
+                    }
+                    let _ = socket.shutdown().await;
+                });
+            }
+        });
+        // The wait for content times out and counts against the provider
+        let app = app_with_config(&config(&url), dir.path());
+        let started = std::time::Instant::now();
+        assert!(!send(app.clone()).await.unwrap().status().is_success());
+        assert!(started.elapsed() < std::time::Duration::from_secs(2));
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 2);
+        // The circuit opened on the stall, so the next request never reaches upstream
+        assert!(!send(app).await.unwrap().status().is_success());
+        assert_eq!(requests.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        // A 4xx on the stream is the request's fault: no non-streaming retry
+        let mut upstream = mockito::Server::new_async().await;
+        upstream
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({"stream": true})))
+            .with_status(404)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"type":"error","error":{"type":"not_found_error","message":"no"}}"#)
+            .create_async()
+            .await;
+        let retry = upstream
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::PartialJson(serde_json::json!({"stream": false})))
+            .expect(0)
+            .create_async()
+            .await;
+        let app = app_with_config(&config(&upstream.url()), dir.path());
+        assert!(!send(app).await.unwrap().status().is_success());
+        retry.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_passthrough_applies_proxy_fixups() {
+        use tower::ServiceExt;
+
+        // No max_tokens, a subagent tag and thinking the provider can't handle
+        let client_body = serde_json::json!({
+            "model": "m",
+            "system": [
+                { "type": "text", "text": "You are Claude Code." },
+                { "type": "text", "text": "<CCM-SUBAGENT-MODEL>m</CCM-SUBAGENT-MODEL>You review code." }
+            ],
+            "messages": [{ "role": "user", "content": "hi" }],
+            "thinking": { "type": "enabled", "budget_tokens": 1024 },
+            "x_vendor_field": { "keep": true }
+        });
+        let expected = serde_json::json!({
+            "model": "up",
+            "max_tokens": 4096,
+            "system": [
+                { "type": "text", "text": "You are Claude Code." },
+                { "type": "text", "text": "You review code." }
+            ],
+            "messages": [{ "role": "user", "content": "hi" }],
+            "x_vendor_field": { "keep": true }
+        });
+
+        let mut upstream = mockito::Server::new_async().await;
+        let messages = upstream
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::Json(expected))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"ok"}],"model":"up","stop_reason":"end_turn","stop_sequence":null,"usage":{"input_tokens":1,"output_tokens":1}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let app = app_with_config(
+            &format!(
+                r#"
+[router]
+default = "m"
+
+[[providers]]
+name = "mock"
+provider_type = "anthropic"
+api_key = "key"
+base_url = "{}"
+models = ["up"]
+passthrough = true
+supports_thinking = false
+
+[[models]]
+name = "m"
+default_max_tokens = 4096
+mappings = [{{ priority = 1, provider = "mock", actual_model = "up" }}]
+"#,
+                upstream.url()
+            ),
+            dir.path(),
+        );
+
+        let request = axum::http::Request::post("/v1/messages")
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(client_body.to_string()))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+        messages.assert_async().await;
+    }
+}
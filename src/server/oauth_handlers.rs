@@ -7,7 +7,12 @@ use axum::{
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
-use crate::auth::{OAuthClient, OAuthConfig, TokenStore};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::auth::{OAuthClient, OAuthConfig, OAuthToken, TokenStore};
+use crate::providers::{AuthType, ProviderConfig};
 
 use super::AppState;
 
@@ -133,25 +138,44 @@ pub async fn oauth_exchange(
         OAuthConfig::anthropic()
     };
 
-    let oauth_client = OAuthClient::new(config.clone(), state.token_store.clone());
+    // For Gemini providers, call loadCodeAssist to get project ID
+    let is_gemini = req.oauth_type.as_deref() == Some("gemini") ||
+                    req.provider_id.to_lowercase().contains("gemini") ||
+                    req.provider_id.to_lowercase().contains("google");
+
+    tracing::info!("🔍 Checking if Gemini provider: is_gemini={}, oauth_type={:?}, provider_id={}",
+        is_gemini, req.oauth_type, req.provider_id);
+
+    let token = exchange_and_store(&state, config, is_gemini, &req.code, &req.verifier, &req.provider_id).await?;
+
+    Ok(Json(OAuthExchangeResponse {
+        success: true,
+        message: "OAuth authentication successful! Token saved.".to_string(),
+        provider_id: req.provider_id,
+        expires_at: token.expires_at.to_rfc3339(),
+    }))
+}
+
+/// Exchange an authorization code and save the token (plus Gemini project ID)
+async fn exchange_and_store(
+    state: &AppState,
+    config: OAuthConfig,
+    is_gemini: bool,
+    code: &str,
+    verifier: &str,
+    provider_id: &str,
+) -> Result<OAuthToken, (StatusCode, String)> {
+    let oauth_client = OAuthClient::new(config, state.token_store.clone());
 
     // Exchange code for tokens
     let mut token = oauth_client
-        .exchange_code(&req.code, &req.verifier, &req.provider_id)
+        .exchange_code(code, verifier, provider_id)
         .await
         .map_err(|e| (
             StatusCode::INTERNAL_SERVER_ERROR,
             format!("Failed to exchange code: {}", e)
         ))?;
 
-    // For Gemini providers, call loadCodeAssist to get project ID
-    let is_gemini = req.oauth_type.as_deref() == Some("gemini") ||
-                    req.provider_id.to_lowercase().contains("gemini") ||
-                    req.provider_id.to_lowercase().contains("google");
-
-    tracing::info!("🔍 Checking if Gemini provider: is_gemini={}, oauth_type={:?}, provider_id={}",
-        is_gemini, req.oauth_type, req.provider_id);
-
     if is_gemini {
         tracing::info!("🔍 Gemini provider detected, calling loadCodeAssist to get project ID");
 
@@ -175,12 +199,118 @@ pub async fn oauth_exchange(
         }
     }
 
-    Ok(Json(OAuthExchangeResponse {
-        success: true,
-        message: "OAuth authentication successful! Token saved.".to_string(),
-        provider_id: req.provider_id,
-        expires_at: token.expires_at.to_rfc3339(),
-    }))
+    Ok(token)
+}
+
+/// How long a re-authentication started from the admin API stays valid
+const REAUTH_TTL: Duration = Duration::from_secs(600);
+
+/// Re-authentication started via /api/oauth/reauth, keyed by OAuth `state`
+#[derive(Debug, Clone)]
+pub struct PendingReauth {
+    pub provider_id: String,
+    pub oauth_type: String,
+    pub verifier: String,
+    pub created_at: Instant,
+}
+
+pub type PendingReauths = Arc<Mutex<HashMap<String, PendingReauth>>>;
+
+/// Request to re-authenticate a configured provider
+#[derive(Debug, Deserialize)]
+pub struct OAuthReauthRequest {
+    /// Provider name from config
+    pub provider: String,
+}
+
+/// Response with the authorize URL for re-authentication
+#[derive(Debug, Serialize)]
+pub struct OAuthReauthResponse {
+    pub url: String,
+    /// PKCE verifier (for completing manually via /api/oauth/exchange)
+    pub verifier: String,
+    /// Token ID the new token will be stored under
+    pub provider_id: String,
+    pub oauth_type: String,
+    pub instructions: String,
+}
+
+/// OAuth flow type for a provider config ("max", "openai-codex" or "gemini")
+fn oauth_type_for(provider: &ProviderConfig) -> &'static str {
+    match provider.provider_type.as_str() {
+        "openai" => "openai-codex",
+        "gemini" => "gemini",
+        _ => "max",
+    }
+}
+
+/// Start an authorize flow for a configured OAuth provider and remember it so
+/// the callback server can finish the exchange
+fn begin_reauth(
+    providers: &[ProviderConfig],
+    pending: &PendingReauths,
+    token_store: &TokenStore,
+    provider_name: &str,
+) -> Result<OAuthReauthResponse, (StatusCode, String)> {
+    let provider = providers
+        .iter()
+        .find(|p| p.name == provider_name)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("Provider '{}' not found", provider_name)))?;
+
+    let provider_id = match (&provider.auth_type, &provider.oauth_provider) {
+        (AuthType::OAuth, Some(id)) => id.clone(),
+        _ => return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Provider '{}' does not use OAuth", provider_name)
+        )),
+    };
+
+    let oauth_type = oauth_type_for(provider);
+    let config = match oauth_type {
+        "openai-codex" => OAuthConfig::openai_codex(),
+        "gemini" => OAuthConfig::gemini(),
+        _ => OAuthConfig::anthropic(),
+    };
+
+    let auth_url = OAuthClient::new(config, token_store.clone()).get_authorization_url();
+    let oauth_state = url::Url::parse(&auth_url.url)
+        .ok()
+        .and_then(|u| u.query_pairs().find(|(k, _)| k == "state").map(|(_, v)| v.into_owned()))
+        .unwrap_or_else(|| auth_url.verifier.verifier.clone());
+
+    {
+        let mut pending = pending.lock().unwrap();
+        pending.retain(|_, p| p.created_at.elapsed() < REAUTH_TTL);
+        pending.insert(oauth_state, PendingReauth {
+            provider_id: provider_id.clone(),
+            oauth_type: oauth_type.to_string(),
+            verifier: auth_url.verifier.verifier.clone(),
+            created_at: Instant::now(),
+        });
+    }
+
+    tracing::info!("🔁 Re-authentication started for provider {} ({})", provider_name, provider_id);
+
+    let instructions = match oauth_type {
+        "max" => "Visit the URL above, then paste the code shown into the admin panel to finish.",
+        _ => "Visit the URL above; the token is saved automatically when the browser returns to the callback page.",
+    };
+
+    Ok(OAuthReauthResponse {
+        url: auth_url.url,
+        verifier: auth_url.verifier.verifier,
+        provider_id,
+        oauth_type: oauth_type.to_string(),
+        instructions: instructions.to_string(),
+    })
+}
+
+/// Re-authenticate a configured OAuth provider (e.g. after its token was revoked)
+pub async fn oauth_reauth(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<OAuthReauthRequest>,
+) -> Result<Json<OAuthReauthResponse>, (StatusCode, String)> {
+    begin_reauth(&state.config.providers, &state.pending_reauths, &state.token_store, &req.provider).map(Json)
 }
 
 /// List all OAuth tokens
@@ -269,14 +399,62 @@ pub struct OAuthCallbackQuery {
     pub error_description: Option<String>,
 }
 
-/// OAuth callback handler - displays the authorization code to the user
+/// OAuth callback handler - displays the authorization code to the user,
+/// or completes the exchange directly for re-authentication started via the admin API
 pub async fn oauth_callback(
+    State(state): State<Arc<AppState>>,
     Query(params): Query<OAuthCallbackQuery>,
 ) -> Html<String> {
     // Check for errors
     if let Some(error) = params.error {
         let error_desc = params.error_description.unwrap_or_else(|| "Unknown error".to_string());
-        return Html(format!(r#"
+        return error_page(&error, &error_desc);
+    }
+
+    let pending = params.state.as_ref().and_then(|s| {
+        state
+            .pending_reauths
+            .lock()
+            .unwrap()
+            .remove(s)
+            .filter(|p| p.created_at.elapsed() < REAUTH_TTL)
+    });
+
+    if let (Some(pending), Some(code)) = (pending, params.code.as_deref()) {
+        let config = match pending.oauth_type.as_str() {
+            "openai-codex" => OAuthConfig::openai_codex(),
+            "gemini" => OAuthConfig::gemini(),
+            _ => OAuthConfig::anthropic(),
+        };
+        let is_gemini = pending.oauth_type == "gemini";
+
+        return match exchange_and_store(&state, config, is_gemini, code, &pending.verifier, &pending.provider_id).await {
+            Ok(_) => {
+                tracing::info!("✅ Re-authentication completed for {}", pending.provider_id);
+                Html(format!(r#"<!DOCTYPE html>
+<html>
+<head><meta charset="UTF-8"><title>Re-authentication Complete</title></head>
+<body style="font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif; text-align: center; padding-top: 4rem;">
+    <div style="font-size: 4rem;">✅</div>
+    <h1>Re-authentication Complete</h1>
+    <p>A new token for <strong>{}</strong> has been saved. You can close this window.</p>
+</body>
+</html>
+"#, pending.provider_id))
+            }
+            Err((_, message)) => error_page("exchange_failed", &message),
+        };
+    }
+
+    // Extract code (state is not used for token exchange, verifier is stored in frontend)
+    let code = params.code.unwrap_or_else(|| "No code received".to_string());
+
+    success_page(&code)
+}
+
+/// Error page shown by the OAuth callback
+fn error_page(error: &str, error_desc: &str) -> Html<String> {
+    Html(format!(r#"
 <!DOCTYPE html>
 <html>
 <head>
@@ -329,12 +507,11 @@ pub async fn oauth_callback(
     </div>
 </body>
 </html>
-"#));
-    }
-
-    // Extract code (state is not used for token exchange, verifier is stored in frontend)
-    let code = params.code.unwrap_or_else(|| "No code received".to_string());
+"#))
+}
 
+/// Page showing the authorization code for the user to copy
+fn success_page(code: &str) -> Html<String> {
     Html(format!(r#"
 <!DOCTYPE html>
 <html>
@@ -477,3 +654,39 @@ pub async fn oauth_callback(
 </html>
 "#))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reauth_returns_authorize_url_with_pkce() {
+        let providers: Vec<ProviderConfig> = serde_json::from_value(serde_json::json!([{
+            "name": "gemini-oauth",
+            "provider_type": "gemini",
+            "auth_type": "oauth",
+            "oauth_provider": "gemini-oauth-token",
+            "models": []
+        }]))
+        .unwrap();
+        let pending: PendingReauths = Arc::new(Mutex::new(HashMap::new()));
+        let token_store = TokenStore::new(std::env::temp_dir().join("ccm-reauth-test-tokens.json")).unwrap();
+
+        let response = begin_reauth(&providers, &pending, &token_store, "gemini-oauth").unwrap();
+        assert_eq!(response.provider_id, "gemini-oauth-token");
+        assert_eq!(response.oauth_type, "gemini");
+
+        let url = url::Url::parse(&response.url).unwrap();
+        let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(params.get("code_challenge_method").map(String::as_str), Some("S256"));
+        assert!(params.get("code_challenge").is_some_and(|c| !c.is_empty()));
+
+        // The callback can find the pending flow by its state
+        let state = params.get("state").unwrap();
+        let pending = pending.lock().unwrap();
+        assert_eq!(pending.get(state).unwrap().verifier, response.verifier);
+
+        // Non-OAuth providers are rejected
+        assert!(begin_reauth(&providers, &Arc::new(Mutex::new(HashMap::new())), &token_store, "missing").is_err());
+    }
+}
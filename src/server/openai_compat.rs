@@ -88,6 +88,64 @@ pub struct OpenAIUsage {
     pub total_tokens: u32,
 }
 
+/// OpenAI Embeddings request format
+#[derive(Debug, Deserialize)]
+pub struct EmbeddingsRequest {
+    pub model: String,
+    pub input: EmbeddingsInput,
+}
+
+/// One text or a batch of texts
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum EmbeddingsInput {
+    Single(String),
+    Batch(Vec<String>),
+}
+
+impl EmbeddingsInput {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            EmbeddingsInput::Single(text) => vec![text],
+            EmbeddingsInput::Batch(texts) => texts,
+        }
+    }
+}
+
+/// OpenAI Embeddings response format
+#[derive(Debug, Serialize)]
+pub struct EmbeddingsResponse {
+    pub object: String,
+    pub data: Vec<Embedding>,
+    pub model: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Embedding {
+    pub object: String,
+    pub index: usize,
+    pub embedding: Vec<f32>,
+}
+
+impl EmbeddingsResponse {
+    pub fn new(model: String, vectors: Vec<Vec<f32>>) -> Self {
+        let data = vectors
+            .into_iter()
+            .enumerate()
+            .map(|(index, embedding)| Embedding {
+                object: "embedding".to_string(),
+                index,
+                embedding,
+            })
+            .collect();
+        Self {
+            object: "list".to_string(),
+            data,
+            model,
+        }
+    }
+}
+
 /// Transform OpenAI request to Anthropic format
 pub fn transform_openai_to_anthropic(openai_req: OpenAIRequest) -> Result<AnthropicRequest, String> {
     let mut messages = Vec::new();
@@ -212,6 +270,14 @@ pub fn transform_openai_to_anthropic(openai_req: OpenAIRequest) -> Result<Anthro
         metadata: None,
         system: system_prompt,
         tools: None, // TODO: Transform tools if needed
+        tool_choice: None,
+        betas: None,
+        stream_options: None,
+        api_version: None,
+        output_format: None,
+        request_id_header: None,
+        capture_raw: false,
+        forwarded_headers: vec![],
     })
 }
 
@@ -0,0 +1,132 @@
+use crate::providers::error::ProviderError;
+use crate::providers::{FailureThreshold, ProviderConfig};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Disables providers whose failure rate goes over their `failure_threshold`.
+/// A disabled provider stays out of routing until re-enabled through the admin API.
+#[derive(Clone, Default)]
+pub struct ProviderHealth {
+    thresholds: Arc<HashMap<String, FailureThreshold>>,
+    outcomes: Arc<Mutex<HashMap<String, Outcomes>>>,
+}
+
+#[derive(Default)]
+struct Outcomes {
+    /// (finished at, succeeded) for calls inside the window
+    recent: VecDeque<(Instant, bool)>,
+    disabled: bool,
+}
+
+impl ProviderHealth {
+    pub fn new(providers: &[ProviderConfig]) -> Self {
+        let thresholds = providers
+            .iter()
+            .filter_map(|p| Some((p.name.clone(), p.failure_threshold.clone()?)))
+            .collect();
+        Self {
+            thresholds: Arc::new(thresholds),
+            outcomes: Default::default(),
+        }
+    }
+
+    /// Record one upstream call, disabling the provider if it crosses its threshold.
+    /// Errors caused by the request rather than the provider (e.g. a 400) aren't counted.
+    pub fn record(&self, provider: &str, error: Option<&ProviderError>) {
+        let Some(threshold) = self.thresholds.get(provider) else {
+            return;
+        };
+        if error.is_some_and(|e| !e.is_upstream_fault()) {
+            return;
+        }
+        let success = error.is_none();
+        let mut outcomes = self.outcomes.lock().unwrap();
+        let entry = outcomes.entry(provider.to_string()).or_default();
+        if entry.disabled {
+            return;
+        }
+
+        let now = Instant::now();
+        let window = Duration::from_secs(threshold.window_secs);
+        entry.recent.push_back((now, success));
+        while entry.recent.front().is_some_and(|(at, _)| now.duration_since(*at) > window) {
+            entry.recent.pop_front();
+        }
+
+        let calls = entry.recent.len();
+        let failures = entry.recent.iter().filter(|(_, ok)| !ok).count();
+        if calls >= threshold.min_requests && failures as f64 / calls as f64 > threshold.max_failure_rate {
+            entry.disabled = true;
+            entry.recent.clear();
+            tracing::warn!(
+                "⛔ Disabling provider {}: {}/{} calls failed in the last {}s (re-enable via /api/providers/enable)",
+                provider,
+                failures,
+                calls,
+                threshold.window_secs
+            );
+        }
+    }
+
+    /// Whether the provider was disabled for failing too often
+    pub fn is_disabled(&self, provider: &str) -> bool {
+        self.outcomes.lock().unwrap().get(provider).is_some_and(|o| o.disabled)
+    }
+
+    /// Put a disabled provider back into routing. Returns false if it wasn't disabled.
+    pub fn enable(&self, provider: &str) -> bool {
+        match self.outcomes.lock().unwrap().get_mut(provider) {
+            Some(entry) if entry.disabled => {
+                *entry = Outcomes::default();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Names of all currently disabled providers
+    pub fn disabled(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .outcomes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, o)| o.disabled)
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_upstream_faults_disable() {
+        let config: ProviderConfig = serde_json::from_value(serde_json::json!({
+            "name": "p",
+            "provider_type": "anthropic",
+            "api_key": "key",
+            "models": [],
+            "failure_threshold": { "max_failure_rate": 0.5, "min_requests": 2 }
+        }))
+        .unwrap();
+        let health = ProviderHealth::new(&[config]);
+        let bad_request = ProviderError::ApiError { status: 400, message: "bad".to_string() };
+        let server_error = ProviderError::ApiError { status: 500, message: "down".to_string() };
+
+        // A burst of client errors says nothing about the provider
+        for _ in 0..10 {
+            health.record("p", Some(&bad_request));
+            health.record("p", Some(&ProviderError::InvalidRequest("bad".to_string())));
+        }
+        assert!(!health.is_disabled("p"));
+
+        health.record("p", Some(&server_error));
+        health.record("p", Some(&server_error));
+        assert!(health.is_disabled("p"));
+    }
+}
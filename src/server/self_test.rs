@@ -0,0 +1,196 @@
+use crate::models::{AnthropicRequest, Tool};
+use crate::providers::{AnthropicProvider, ProviderConfig, ProviderRegistry};
+use futures::stream::StreamExt;
+use serde::Serialize;
+use tracing::{info, warn};
+
+/// Outcome of probing one capability
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", content = "error", rename_all = "lowercase")]
+pub enum Capability {
+    Ok,
+    Failed(String),
+    /// Not probed (no probe model, or auth already failed)
+    Skipped,
+}
+
+impl Capability {
+    fn symbol(&self) -> &'static str {
+        match self {
+            Capability::Ok => "✅",
+            Capability::Failed(_) => "❌",
+            Capability::Skipped => "⏭️",
+        }
+    }
+}
+
+/// What a provider was found to support at startup
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderCapabilities {
+    pub provider: String,
+    pub auth: Capability,
+    pub streaming: Capability,
+    pub tools: Capability,
+}
+
+/// Probe every enabled provider for auth, streaming and tool support, then log the matrix.
+/// Each probe is a 1-token request built from the provider's probe model/prompt.
+pub async fn run(providers: &[ProviderConfig], registry: &ProviderRegistry) -> Vec<ProviderCapabilities> {
+    info!("🩺 Running provider self-test...");
+    let probes = providers
+        .iter()
+        .filter(|p| p.is_enabled())
+        .map(|config| probe_provider(config, registry));
+    let results = futures::future::join_all(probes).await;
+
+    for caps in &results {
+        let line = format!(
+            "{:<20} auth {}  streaming {}  tools {}",
+            caps.provider,
+            caps.auth.symbol(),
+            caps.streaming.symbol(),
+            caps.tools.symbol()
+        );
+        let failures: Vec<String> = [("auth", &caps.auth), ("streaming", &caps.streaming), ("tools", &caps.tools)]
+            .iter()
+            .filter_map(|(name, cap)| match cap {
+                Capability::Failed(e) => Some(format!("{}: {}", name, e)),
+                _ => None,
+            })
+            .collect();
+        if failures.is_empty() {
+            info!("🩺 {}", line);
+        } else {
+            warn!("🩺 {} ({})", line, failures.join("; "));
+        }
+    }
+    results
+}
+
+async fn probe_provider(config: &ProviderConfig, registry: &ProviderRegistry) -> ProviderCapabilities {
+    let skipped = |provider: &str| ProviderCapabilities {
+        provider: provider.to_string(),
+        auth: Capability::Skipped,
+        streaming: Capability::Skipped,
+        tools: Capability::Skipped,
+    };
+    let (Some(provider), Some(request)) = (registry.get_provider(&config.name), config.probe_request()) else {
+        return skipped(&config.name);
+    };
+    let provider = provider.as_ref().as_ref();
+
+    // A plain request doubles as the auth check; nothing else can work without it
+    let auth = match provider.send_message(request.clone()).await {
+        Ok(_) => Capability::Ok,
+        Err(e) => Capability::Failed(e.to_string()),
+    };
+    if auth != Capability::Ok {
+        return ProviderCapabilities { auth, ..skipped(&config.name) };
+    }
+
+    let (streaming, tools) = futures::join!(probe_streaming(provider, &request), probe_tools(provider, &request));
+    ProviderCapabilities {
+        provider: config.name.clone(),
+        auth,
+        streaming,
+        tools,
+    }
+}
+
+async fn probe_streaming(provider: &dyn AnthropicProvider, request: &AnthropicRequest) -> Capability {
+    let mut request = request.clone();
+    request.stream = Some(true);
+    let mut stream = match provider.send_message_stream(request).await {
+        Ok(stream) => stream,
+        Err(e) => return Capability::Failed(e.to_string()),
+    };
+    let mut received = false;
+    while let Some(chunk) = stream.next().await {
+        match chunk {
+            Ok(bytes) => received |= !bytes.is_empty(),
+            Err(e) => return Capability::Failed(e.to_string()),
+        }
+    }
+    if received {
+        Capability::Ok
+    } else {
+        Capability::Failed("stream ended without any events".to_string())
+    }
+}
+
+async fn probe_tools(provider: &dyn AnthropicProvider, request: &AnthropicRequest) -> Capability {
+    let mut request = request.clone();
+    request.tools = Some(vec![Tool {
+        r#type: None,
+        name: Some("get_time".to_string()),
+        description: Some("Get the current time".to_string()),
+        input_schema: Some(serde_json::json!({ "type": "object", "properties": {} })),
+    }]);
+    match provider.send_message(request).await {
+        Ok(_) => Capability::Ok,
+        Err(e) => Capability::Failed(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::Matcher;
+
+    const MESSAGE: &str = r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"hi"}],"model":"up","stop_reason":"max_tokens","stop_sequence":null,"usage":{"input_tokens":1,"output_tokens":1}}"#;
+
+    #[tokio::test]
+    async fn test_probe_records_capabilities() {
+        let mut upstream = mockito::Server::new_async().await;
+        // Tools are rejected, streaming and plain requests work
+        upstream
+            .mock("POST", "/v1/messages")
+            .match_body(Matcher::Regex(r#""tools""#.to_string()))
+            .with_status(400)
+            .with_body(r#"{"error":{"message":"tools not supported"}}"#)
+            .create_async()
+            .await;
+        upstream
+            .mock("POST", "/v1/messages")
+            .match_body(Matcher::PartialJson(serde_json::json!({ "stream": true })))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body("event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n")
+            .create_async()
+            .await;
+        upstream
+            .mock("POST", "/v1/messages")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(MESSAGE)
+            .create_async()
+            .await;
+
+        let config = |name: &str, api_key: &str| -> ProviderConfig {
+            serde_json::from_value(serde_json::json!({
+                "name": name,
+                "provider_type": "anthropic",
+                "api_key": api_key,
+                "base_url": upstream.url(),
+                "models": ["up"]
+            }))
+            .unwrap()
+        };
+        let mut no_model = config("unprobed", "key");
+        no_model.models.clear();
+        let providers = vec![config("mock", "key"), no_model];
+        let registry = ProviderRegistry::from_configs(&providers, None).unwrap();
+
+        let results = run(&providers, &registry).await;
+        assert_eq!(results.len(), 2);
+
+        let mock = &results[0];
+        assert_eq!(mock.provider, "mock");
+        assert_eq!(mock.auth, Capability::Ok);
+        assert_eq!(mock.streaming, Capability::Ok);
+        assert!(matches!(&mock.tools, Capability::Failed(e) if e.contains("tools not supported")));
+
+        // Nothing to probe with
+        assert_eq!(results[1].auth, Capability::Skipped);
+    }
+}
@@ -0,0 +1,120 @@
+use axum::http::HeaderMap;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+/// Tracks open streaming responses per client and enforces a concurrency cap
+#[derive(Clone)]
+pub struct StreamLimiter {
+    /// Maximum concurrent streams per client (None = unlimited)
+    max_per_client: Option<usize>,
+    active: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl StreamLimiter {
+    pub fn new(max_per_client: Option<usize>) -> Self {
+        Self {
+            max_per_client,
+            active: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Reserve a stream slot for `client`, or None if the client is at its limit.
+    /// The slot is released when the returned permit is dropped.
+    pub fn try_acquire(&self, client: &str) -> Option<StreamPermit> {
+        let mut active = self.active.lock().unwrap();
+        // Only clients with open streams have an entry, so the map doesn't grow with every client seen
+        let count = active.get(client).copied().unwrap_or(0);
+        if self.max_per_client.is_some_and(|max| count >= max) {
+            return None;
+        }
+        active.insert(client.to_string(), count + 1);
+
+        Some(StreamPermit {
+            client: client.to_string(),
+            active: self.active.clone(),
+        })
+    }
+
+    /// Number of streams currently open for `client`
+    pub fn active_streams(&self, client: &str) -> usize {
+        self.active.lock().unwrap().get(client).copied().unwrap_or(0)
+    }
+}
+
+/// An open stream slot; held for the lifetime of the SSE response
+pub struct StreamPermit {
+    client: String,
+    active: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl Drop for StreamPermit {
+    fn drop(&mut self) {
+        let mut active = self.active.lock().unwrap();
+        if let Some(count) = active.get_mut(&self.client) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                active.remove(&self.client);
+            }
+        }
+    }
+}
+
+/// Identify the client for per-client limits: the API key it presents,
+/// falling back to the peer IP address
+pub fn client_id(headers: &HeaderMap, peer: Option<SocketAddr>) -> String {
+    let key = headers
+        .get("x-api-key")
+        .or_else(|| headers.get("authorization"))
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim_start_matches("Bearer ").trim())
+        .filter(|v| !v.is_empty());
+
+    match (key, peer) {
+        // Don't keep raw keys around as map keys
+        (Some(key), _) => {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            key.hash(&mut hasher);
+            format!("key:{:016x}", hasher.finish())
+        }
+        (None, Some(addr)) => format!("ip:{}", addr.ip()),
+        (None, None) => "anonymous".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_beyond_limit_rejected() {
+        let limiter = StreamLimiter::new(Some(2));
+
+        let first = limiter.try_acquire("client-a").unwrap();
+        let _second = limiter.try_acquire("client-a").unwrap();
+        assert!(limiter.try_acquire("client-a").is_none());
+
+        // Other clients are unaffected
+        assert!(limiter.try_acquire("client-b").is_some());
+
+        // Closing a stream frees a slot
+        drop(first);
+        assert_eq!(limiter.active_streams("client-a"), 1);
+        assert!(limiter.try_acquire("client-a").is_some());
+    }
+
+    #[test]
+    fn test_idle_clients_not_tracked() {
+        let limiter = StreamLimiter::new(Some(1));
+        let permits: Vec<_> = (0..3).map(|i| limiter.try_acquire(&format!("client-{}", i)).unwrap()).collect();
+        assert_eq!(limiter.active.lock().unwrap().len(), 3);
+        drop(permits);
+        assert!(limiter.active.lock().unwrap().is_empty());
+
+        // A rejected client leaves nothing behind either
+        let closed = StreamLimiter::new(Some(0));
+        assert!(closed.try_acquire("client-a").is_none());
+        assert!(closed.active.lock().unwrap().is_empty());
+    }
+}
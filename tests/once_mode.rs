@@ -0,0 +1,84 @@
+//! `ccm start --once`: one request in on stdin, one response out on stdout
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn write_config(dir: &std::path::Path, upstream: &str) -> std::path::PathBuf {
+    let path = dir.join("config.toml");
+    std::fs::write(
+        &path,
+        format!(
+            r#"
+[router]
+default = "mux-model"
+
+[[providers]]
+name = "mock"
+provider_type = "anthropic"
+auth_type = "apikey"
+api_key = "test-key"
+base_url = "{upstream}"
+models = ["upstream-model"]
+
+[[models]]
+name = "mux-model"
+mappings = [{{ priority = 1, provider = "mock", actual_model = "upstream-model" }}]
+"#
+        ),
+    )
+    .unwrap();
+    path
+}
+
+fn run_once(config: &std::path::Path, home: &std::path::Path, request: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ccm"))
+        .arg("--config")
+        .arg(config)
+        .args(["start", "--once"])
+        .env("HOME", home)
+        .env("RUST_LOG", "off")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(request.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_once_pipes_request_through_pipeline() {
+    let mut server = mockito::Server::new();
+    let ok = server
+        .mock("POST", "/v1/messages")
+        .match_header("x-api-key", "test-key")
+        .match_body(mockito::Matcher::PartialJson(serde_json::json!({ "model": "upstream-model" })))
+        .with_status(200)
+        .with_header("content-type", "application/json")
+        .with_body(
+            r#"{"id":"msg_1","type":"message","role":"assistant","content":[{"type":"text","text":"pong"}],
+                "model":"upstream-model","stop_reason":"end_turn","stop_sequence":null,
+                "usage":{"input_tokens":3,"output_tokens":1}}"#,
+        )
+        .create();
+
+    let dir = tempfile::tempdir().unwrap();
+    let config = write_config(dir.path(), &server.url());
+    let request = r#"{"model":"mux-model","max_tokens":16,"messages":[{"role":"user","content":"ping"}]}"#;
+
+    let output = run_once(&config, dir.path(), request);
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+    let response: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(response["content"][0]["text"], "pong");
+    // The client sees the model it asked for
+    assert_eq!(response["model"], "mux-model");
+    ok.assert();
+
+    // Upstream failure: error body on stdout, non-zero exit
+    server.reset();
+    server.mock("POST", "/v1/messages").with_status(500).with_body("boom").create();
+    let output = run_once(&config, dir.path(), request);
+    assert!(!output.status.success());
+    let error: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(error["error"]["type"], "error");
+}